@@ -9,13 +9,27 @@ use futures_util::StreamExt;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use tokio::process::Command;
+use tokio::sync::{broadcast, mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
 
 use crate::config::MycelConfig;
 use crate::context::Context;
-use crate::intent::{ActionType, Intent};
+use crate::egress::{EgressPolicy, HttpClient};
+use crate::error::Error;
+use crate::events::SystemEvent;
+use crate::executor::CodeExecutor;
+use crate::intent::{ActionType, Intent, IntentCategory};
 use crate::mcp::{self, McpManager};
+use crate::models::{
+    CompatibilityResult, HardwareInfo, ModelBackend, ModelCompatibility, ModelManager,
+    ModelManagerConfig,
+};
+use crate::policy::{ActionPolicy, PolicyEvaluator};
 
 /// Strip markdown code blocks and extract JSON from a string
 /// Handles cases like: ```json\n{...}\n``` or just ```\n{...}\n```
@@ -60,14 +74,75 @@ fn strip_markdown_code_blocks(text: &str) -> String {
 
 /// Strip markdown formatting from plain text responses
 /// Removes markdown code blocks, bold/italic markers, headers, etc.
-fn strip_markdown_formatting(text: &str) -> String {
-    let mut cleaned = text.trim().to_string();
+///
+/// With `preserve_code`, fenced code blocks are kept as they are, fences
+/// included, and only the text around them is cleaned. Chat answers want
+/// that; without it a snippet loses its fences and everything outside its
+/// outermost braces.
+fn strip_markdown_formatting(text: &str, preserve_code: bool) -> String {
+    if !preserve_code {
+        return strip_prose_markdown(&strip_markdown_code_blocks(text))
+            .trim()
+            .to_string();
+    }
+
+    let mut stripper = MarkdownStripper::default();
+    let mut output = stripper.push(text.trim());
+    output.push_str(&stripper.finish());
+    output.trim().to_string()
+}
+
+/// Line-at-a-time `strip_markdown_formatting(.., true)` for streamed text.
+/// Each line is held back until it is complete, since a chunk can end in
+/// the middle of a link or a fence.
+#[derive(Default)]
+struct MarkdownStripper {
+    pending: String,
+    in_code: bool,
+}
+
+impl MarkdownStripper {
+    /// Cleaned text for the lines `chunk` completes
+    fn push(&mut self, chunk: &str) -> String {
+        self.pending.push_str(chunk);
+        let Some(end) = self.pending.rfind('\n') else {
+            return String::new();
+        };
+        let complete: String = self.pending.drain(..=end).collect();
+        complete
+            .lines()
+            .map(|line| self.strip_line(line) + "\n")
+            .collect()
+    }
+
+    /// Cleaned text for the last, unterminated line
+    fn finish(&mut self) -> String {
+        let rest = std::mem::take(&mut self.pending);
+        if rest.is_empty() {
+            rest
+        } else {
+            self.strip_line(&rest)
+        }
+    }
 
-    // Remove code blocks (```...```)
-    cleaned = strip_markdown_code_blocks(&cleaned);
+    fn strip_line(&mut self, line: &str) -> String {
+        let fence = line.trim_start().starts_with("```");
+        let stripped = if self.in_code || fence {
+            line.to_string()
+        } else {
+            strip_prose_markdown(line)
+        };
+        if fence {
+            self.in_code = !self.in_code;
+        }
+        stripped
+    }
+}
 
+/// Remove headers, bold markers and links from text without code blocks
+fn strip_prose_markdown(text: &str) -> String {
     // Remove markdown headers (# ## ### etc.) - remove # and following space
-    let lines: Vec<&str> = cleaned.lines().collect();
+    let lines: Vec<&str> = text.lines().collect();
     let cleaned_lines: Vec<String> = lines
         .iter()
         .map(|line| {
@@ -79,7 +154,7 @@ fn strip_markdown_formatting(text: &str) -> String {
             l
         })
         .collect();
-    cleaned = cleaned_lines.join("\n");
+    let mut cleaned = cleaned_lines.join("\n");
 
     // Remove bold markers (**text** -> text)
     cleaned = cleaned.replace("**", "");
@@ -97,24 +172,18 @@ fn strip_markdown_formatting(text: &str) -> String {
         if chars[i] == '[' {
             // Look for matching ]
             let link_start = i + 1;
-            let mut link_end = None;
-            for j in (i + 1)..chars.len() {
-                if chars[j] == ']' {
-                    link_end = Some(j);
-                    break;
-                }
-            }
+            let link_end = chars[link_start..]
+                .iter()
+                .position(|&c| c == ']')
+                .map(|pos| link_start + pos);
             if let Some(end) = link_end {
                 // Check if next char is (
                 if end + 1 < chars.len() && chars[end + 1] == '(' {
                     // Find matching )
-                    let mut url_end = None;
-                    for k in (end + 2)..chars.len() {
-                        if chars[k] == ')' {
-                            url_end = Some(k);
-                            break;
-                        }
-                    }
+                    let url_end = chars[end + 2..]
+                        .iter()
+                        .position(|&c| c == ')')
+                        .map(|pos| end + 2 + pos);
                     if let Some(url_end_pos) = url_end {
                         // Extract link text
                         let link_text: String = chars[link_start..end].iter().collect();
@@ -129,15 +198,298 @@ fn strip_markdown_formatting(text: &str) -> String {
         i += 1;
     }
 
-    result.trim().to_string()
+    result
+}
+
+/// Send `text` down a response stream unless it is empty; false once the
+/// receiver has gone
+async fn send_text(tx: &tokio::sync::mpsc::Sender<Result<String>>, text: String) -> bool {
+    text.is_empty() || tx.send(Ok(text)).await.is_ok()
+}
+
+/// What `process_with_tools` came back with
+#[derive(Debug)]
+pub enum ToolsReply {
+    /// The final response
+    Text(String),
+    /// A tool call that must be confirmed by the user before it runs;
+    /// `message` asks them to
+    Confirm {
+        call: mcp::ToolCall,
+        message: String,
+    },
+}
+
+/// Progress of an agentic tool loop, sent as it happens so a client can
+/// show each step instead of a long silence
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum AgentStep {
+    /// What the model said alongside a round of tool calls
+    Thinking {
+        text: String,
+    },
+    ToolCallStarted {
+        tool: String,
+        arguments: std::collections::HashMap<String, serde_json::Value>,
+    },
+    /// A call's result, or why it wasn't run (`success` is false)
+    ToolResult {
+        tool: String,
+        result: String,
+        success: bool,
+    },
+    FinalAnswer {
+        text: String,
+    },
+}
+
+/// Where a router sends the steps of its agentic loops
+pub type StepSender = mpsc::UnboundedSender<AgentStep>;
+
+/// What generated code would do, shown before the user confirms it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodePlan {
+    /// One line on what the code does
+    pub summary: String,
+    /// Files, network access, packages and services it touches
+    #[serde(default)]
+    pub side_effects: Vec<String>,
+}
+
+impl CodePlan {
+    /// Lines added to a confirmation prompt
+    pub fn describe(&self) -> String {
+        let effects = if self.side_effects.is_empty() {
+            "none".to_string()
+        } else {
+            self.side_effects.join("; ")
+        };
+        format!("plan: {}\nside effects: {}", self.summary.trim(), effects)
+    }
+}
+
+/// Prompt timed by [`AiRouter::benchmark`]; fixed so runs are comparable
+pub const BENCHMARK_PROMPT: &str =
+    "Explain in three sentences what an operating system kernel does.";
+
+/// How fast the models answer on this machine, to help choose `prefer_cloud`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkReport {
+    pub hardware: HardwareInfo,
+    /// One entry per available backend, local first
+    pub results: Vec<BackendBenchmark>,
+}
+
+/// Averages over the completed runs against one backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendBenchmark {
+    pub provider: crate::ipc::LlmProvider,
+    pub model: String,
+    /// Runs that completed
+    pub runs: usize,
+    /// Until the first streamed text. The cloud path isn't streamed yet,
+    /// so there it equals `total_ms`.
+    pub first_token_ms: u64,
+    pub total_ms: u64,
+    /// Estimated at ~4 characters per token
+    pub tokens_per_sec: f64,
+    /// Why the runs stopped early, if they did
+    pub error: Option<String>,
+}
+
+/// Timings of one benchmark run
+struct BenchmarkRun {
+    first_token: Duration,
+    total: Duration,
+    tokens: usize,
+}
+
+/// What first-run setup still needs, for a setup wizard
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetupStatus {
+    /// Ollama answered at `ollama_url`
+    pub ollama_running: bool,
+    /// The configured local model is installed in Ollama
+    pub model_available: bool,
+    /// An OpenRouter API key is set
+    pub cloud_configured: bool,
+    /// Generated code can run: `bash` is installed and the work directory
+    /// can be created
+    pub sandbox_available: bool,
+    /// Configured local model name
+    pub local_model: String,
+    /// What the `pull_recommended_model` step pulls when no model is named
+    pub recommended_model: Option<String>,
+}
+
+/// Generations remembered for `GetRoutingLog`
+const ROUTING_LOG_CAPACITY: usize = 200;
+
+/// Where one generation went, and how it went
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingDecision {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// `local` or `cloud` - the backend that produced (or failed) the answer
+    pub source: String,
+    /// The first backend tried failed and the other one was used
+    pub fell_back: bool,
+    /// Time to the answer; for streams, time until the stream opened
+    pub latency_ms: u64,
+    pub model: String,
+    pub success: bool,
 }
 
 /// Main AI router that handles all LLM interactions
 #[derive(Clone)]
 pub struct AiRouter {
-    config: MycelConfig,
-    http_client: Client,
-    local_available: bool,
+    /// Live configuration (replaced on reload)
+    config: Arc<RwLock<MycelConfig>>,
+    http_client: HttpClient,
+    /// Whether Ollama answered when last probed
+    local_available: Arc<AtomicBool>,
+    model_manager: Arc<ModelManager>,
+    /// Ollama model currently used for local generation (switchable at runtime)
+    local_model: Arc<RwLock<String>>,
+    /// Policy applied to tool calls before they reach an MCP server
+    policy: Arc<RwLock<PolicyEvaluator>>,
+    /// Inference and download progress is reported here
+    event_bus: broadcast::Sender<SystemEvent>,
+    /// Stops calling a cloud provider that keeps failing
+    cloud_circuit: Arc<Mutex<CircuitState>>,
+    /// Aborts in-flight model requests (shutdown, or a cancelled request)
+    cancel: CancellationToken,
+    /// Caps cloud requests in flight (`cloud_max_concurrency`)
+    cloud_permits: Arc<Semaphore>,
+    /// Caps local generations in flight (`local_max_concurrency`)
+    local_permits: Arc<Semaphore>,
+    /// Most recent routing decisions, oldest first
+    routing_log: Arc<Mutex<std::collections::VecDeque<RoutingDecision>>>,
+    /// Where `process_with_tools_loop` reports its steps, if anywhere
+    steps: Option<StepSender>,
+}
+
+fn cancelled(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref(), Some(Error::Cancelled))
+}
+
+fn is_cancelled<T>(result: &Result<T>) -> bool {
+    matches!(result, Err(e) if cancelled(e))
+}
+
+/// Consecutive cloud failures that open the circuit
+const CLOUD_FAILURE_THRESHOLD: u32 = 3;
+/// How long the cloud is skipped once the circuit opens
+const CLOUD_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Circuit breaker for the cloud provider.
+///
+/// Closed: calls go through. Open: calls are skipped until the cooldown ends.
+/// After that one probe call is let through; success closes the circuit,
+/// failure re-opens it.
+#[derive(Debug)]
+struct CircuitState {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probe_in_flight: bool,
+    threshold: u32,
+    cooldown: Duration,
+}
+
+impl CircuitState {
+    fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            consecutive_failures: 0,
+            opened_at: None,
+            probe_in_flight: false,
+            threshold,
+            cooldown,
+        }
+    }
+
+    /// Whether a call may go out now (claims the probe slot when half-open)
+    fn allow(&mut self, now: Instant) -> bool {
+        match self.opened_at {
+            None => true,
+            Some(opened) if now.duration_since(opened) < self.cooldown => false,
+            Some(_) if self.probe_in_flight => false,
+            Some(_) => {
+                info!("Cloud circuit half-open, probing provider");
+                self.probe_in_flight = true;
+                true
+            }
+        }
+    }
+
+    /// Give back the probe slot of a call that ended without an outcome
+    fn abandon_probe(&mut self) {
+        self.probe_in_flight = false;
+    }
+
+    fn record(&mut self, success: bool, now: Instant) {
+        if success {
+            if self.opened_at.is_some() {
+                info!("Cloud circuit closed, provider recovered");
+            }
+            self.consecutive_failures = 0;
+            self.opened_at = None;
+            self.probe_in_flight = false;
+            return;
+        }
+
+        self.consecutive_failures += 1;
+        if self.probe_in_flight {
+            warn!(
+                "Cloud probe failed, circuit re-opened for {:?}",
+                self.cooldown
+            );
+            self.probe_in_flight = false;
+            self.opened_at = Some(now);
+        } else if self.opened_at.is_none() && self.consecutive_failures >= self.threshold {
+            warn!(
+                "Cloud failed {} times in a row, circuit opened for {:?}",
+                self.consecutive_failures, self.cooldown
+            );
+            self.opened_at = Some(now);
+        }
+    }
+}
+
+/// A call the circuit let through. Dropped without `record` (cancelled, or
+/// its future dropped mid-flight), a probe gives its slot back.
+struct CircuitCall {
+    circuit: Arc<Mutex<CircuitState>>,
+    probe: bool,
+    recorded: bool,
+}
+
+impl CircuitCall {
+    /// Admit a call, or `None` while the circuit is open
+    fn admit(circuit: &Arc<Mutex<CircuitState>>, now: Instant) -> Option<Self> {
+        let mut state = circuit.lock().unwrap();
+        if !state.allow(now) {
+            return None;
+        }
+        Some(Self {
+            circuit: Arc::clone(circuit),
+            // Only the probe gets through while the circuit is still open
+            probe: state.opened_at.is_some(),
+            recorded: false,
+        })
+    }
+
+    fn record(mut self, success: bool, now: Instant) {
+        self.circuit.lock().unwrap().record(success, now);
+        self.recorded = true;
+    }
+}
+
+impl Drop for CircuitCall {
+    fn drop(&mut self) {
+        if self.probe && !self.recorded {
+            self.circuit.lock().unwrap().abandon_probe();
+        }
+    }
 }
 
 use std::pin::Pin;
@@ -145,18 +497,23 @@ use std::pin::Pin;
 #[derive(Deserialize)]
 struct OllamaStreamResponse {
     response: Option<String>,
-    #[allow(dead_code)]
+    /// Missing on error lines
+    #[serde(default)]
     done: bool,
     error: Option<String>,
 }
 
 impl AiRouter {
     /// Create a new AI router with both local and cloud capabilities
-    pub async fn new(config: &MycelConfig) -> Result<Self> {
-        let http_client = Client::builder()
-            .timeout(std::time::Duration::from_secs(300)) // 5 min for slow CPU inference
-            .connect_timeout(std::time::Duration::from_secs(30))
-            .build()?;
+    pub async fn new(
+        config: &MycelConfig,
+        event_bus: broadcast::Sender<SystemEvent>,
+    ) -> Result<Self> {
+        // Deadlines are set per request (local vs cloud), not client-wide
+        let http_client = HttpClient::new(
+            Client::builder().connect_timeout(std::time::Duration::from_secs(30)),
+            EgressPolicy::from_config(config),
+        )?;
 
         // Check if local model (Ollama) is available
         let mut local_available = Self::check_local_availability(&http_client, config).await;
@@ -171,34 +528,127 @@ impl AiRouter {
 
         if local_available {
             info!("🧠 Local LLM online - this is the kernel's brain");
+        } else if config.openrouter_api_key.is_empty() {
+            warn!(
+                "⚠️  {}. Only requests that need no model (status, peers, history) will work.",
+                Error::NoBackend
+            );
         } else {
             warn!("⚠️  Local LLM not available! Running in degraded cloud-only mode. Start Ollama for full capability.");
         }
 
+        let model_manager = ModelManager::new(ModelManagerConfig::from_mycel_config(config))
+            .await?
+            .with_event_bus(event_bus.clone());
+
         Ok(Self {
-            config: config.clone(),
+            config: Arc::new(RwLock::new(config.clone())),
             http_client,
-            local_available,
+            local_available: Arc::new(AtomicBool::new(local_available)),
+            model_manager: Arc::new(model_manager),
+            local_model: Arc::new(RwLock::new(config.local_model.clone())),
+            policy: Arc::new(RwLock::new(PolicyEvaluator::new(config.policy.clone()))),
+            event_bus,
+            cloud_circuit: Arc::new(Mutex::new(CircuitState::new(
+                CLOUD_FAILURE_THRESHOLD,
+                CLOUD_COOLDOWN,
+            ))),
+            cancel: CancellationToken::new(),
+            cloud_permits: Arc::new(Semaphore::new(config.cloud_max_concurrency)),
+            local_permits: Arc::new(Semaphore::new(config.local_max_concurrency)),
+            routing_log: Arc::default(),
+            steps: None,
         })
     }
 
     /// Create a cloud-only router
-    pub async fn cloud_only(config: &MycelConfig) -> Result<Self> {
-        let http_client = Client::builder()
-            .timeout(std::time::Duration::from_secs(300)) // 5 min for slow CPU inference
-            .connect_timeout(std::time::Duration::from_secs(30))
-            .build()?;
+    pub async fn cloud_only(
+        config: &MycelConfig,
+        event_bus: broadcast::Sender<SystemEvent>,
+    ) -> Result<Self> {
+        // Deadlines are set per request (local vs cloud), not client-wide
+        let http_client = HttpClient::new(
+            Client::builder().connect_timeout(std::time::Duration::from_secs(30)),
+            EgressPolicy::from_config(config),
+        )?;
+
+        let model_manager = ModelManager::new(ModelManagerConfig::from_mycel_config(config))
+            .await?
+            .with_event_bus(event_bus.clone());
 
         Ok(Self {
-            config: config.clone(),
+            config: Arc::new(RwLock::new(config.clone())),
             http_client,
-            local_available: false,
+            local_available: Arc::new(AtomicBool::new(false)),
+            model_manager: Arc::new(model_manager),
+            local_model: Arc::new(RwLock::new(config.local_model.clone())),
+            policy: Arc::new(RwLock::new(PolicyEvaluator::new(config.policy.clone()))),
+            event_bus,
+            cloud_circuit: Arc::new(Mutex::new(CircuitState::new(
+                CLOUD_FAILURE_THRESHOLD,
+                CLOUD_COOLDOWN,
+            ))),
+            cancel: CancellationToken::new(),
+            cloud_permits: Arc::new(Semaphore::new(config.cloud_max_concurrency)),
+            local_permits: Arc::new(Semaphore::new(config.local_max_concurrency)),
+            routing_log: Arc::default(),
+            steps: None,
         })
     }
 
-    async fn check_local_availability(client: &Client, config: &MycelConfig) -> bool {
+    /// A router whose model requests abort with [`Error::Cancelled`] once `cancel` fires
+    pub fn with_cancellation(&self, cancel: CancellationToken) -> Self {
+        Self {
+            cancel,
+            ..self.clone()
+        }
+    }
+
+    /// A router whose agentic loops report each step to `steps`
+    pub fn with_steps(&self, steps: StepSender) -> Self {
+        Self {
+            steps: Some(steps),
+            ..self.clone()
+        }
+    }
+
+    /// Report an agentic loop step, if anyone is listening
+    fn step(&self, step: AgentStep) {
+        if let Some(steps) = &self.steps {
+            let _ = steps.send(step);
+        }
+    }
+
+    /// Token that aborts this router's model requests; a child of it cancels
+    /// one request without the others
+    pub fn cancellation(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Run `request` unless the cancellation token fires first
+    async fn cancellable<T>(
+        &self,
+        request: impl std::future::Future<Output = Result<T>>,
+    ) -> Result<T> {
+        tokio::select! {
+            biased;
+            _ = self.cancel.cancelled() => Err(Error::Cancelled.into()),
+            result = request => result,
+        }
+    }
+
+    /// Wait for a slot under `permits`, or until the request is cancelled
+    async fn permit(&self, permits: &Arc<Semaphore>) -> Result<OwnedSemaphorePermit> {
+        let acquire = async { Ok(Arc::clone(permits).acquire_owned().await?) };
+        self.cancellable(acquire).await
+    }
+
+    async fn check_local_availability(client: &HttpClient, config: &MycelConfig) -> bool {
         let url = format!("{}/api/tags", config.ollama_url);
-        client.get(&url).send().await.is_ok()
+        let Ok(request) = client.get(&url) else {
+            return false;
+        };
+        request.timeout(Duration::from_secs(5)).send().await.is_ok()
     }
 
     /// Try to start Ollama if it's not running
@@ -258,17 +708,28 @@ impl AiRouter {
         prompt: &str,
         force_cloud: bool,
     ) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
-        if self.local_available && !force_cloud {
+        if !self.has_backend() {
+            return Err(Error::NoBackend.into());
+        }
+        let start = Instant::now();
+        let mut fell_back = false;
+        if self.is_local_available() && !force_cloud {
             match self.local_generate_stream(prompt).await {
-                Ok(stream) => return Ok(Box::pin(stream)),
+                Ok(stream) => {
+                    self.record_decision("local", false, start.elapsed(), true);
+                    return Ok(Box::pin(stream));
+                }
+                Err(e) if cancelled(&e) => return Err(e),
                 Err(e) => {
                     warn!("Local LLM streaming failed, escalating to cloud: {}", e);
+                    fell_back = true;
                 }
             }
         }
 
-        let stream = self.cloud_generate_stream(prompt).await?;
-        Ok(Box::pin(stream))
+        let result = self.cloud_generate_stream(prompt).await;
+        self.record_decision("cloud", fell_back, start.elapsed(), result.is_ok());
+        Ok(Box::pin(result?))
     }
 
     /// Process user input with MCP tools available and streaming final response
@@ -279,7 +740,7 @@ impl AiRouter {
         mcp_manager: &McpManager,
     ) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
         // Get available tools
-        let tools_prompt = mcp_manager.get_tools_prompt().await;
+        let tools_prompt = self.tools_prompt(input, mcp_manager).await;
 
         // If no tools available, just stream directly
         if tools_prompt.is_empty() {
@@ -290,7 +751,7 @@ impl AiRouter {
 
         // Build the enhanced prompt with tools
         let prompt = format!(
-            r#"You are Mycel OS - an AI-native operating system assistant.
+            r#"{preamble}
 
 {tools_prompt}
 
@@ -304,57 +765,183 @@ HOW TO RESPOND:
 - After tool results, summarize what you found
 - For simple questions, answer directly without tools
 
-cwd: {cwd}
+{preferences}cwd: {cwd}
 user: {input}
 
 Reply (use <tool_call>{{...}}</tool_call> for tools):"#,
+            preamble = self.preamble(
+                context,
+                "You are Mycel OS - an AI-native operating system assistant."
+            ),
             tools_prompt = tools_prompt,
+            preferences = self.preferences_section(context),
             cwd = context.working_directory,
             input = input
         );
 
-        // Get initial response from LLM (non-streaming for tool handling)
-        let response = self.smart_generate(&prompt, false).await?;
-
-        // Parse for tool calls
-        let parsed = mcp::parse_tool_calls(&response);
+        // Stream the first response, watching for tool calls as it arrives
+        let mut stream = self.smart_generate_stream(&prompt, false).await?;
+
+        let router = self.clone();
+        let mcp_manager = mcp_manager.clone();
+        let input = input.to_string();
+        let (tx, rx) = tokio::sync::mpsc::channel::<Result<String>>(32);
+
+        tokio::spawn(async move {
+            // Only `<tool_call>` blocks can be picked out as they stream; the
+            // other formats need the whole response first
+            let format = router.config().mcp.tool_call_format;
+            let mut parser = matches!(
+                format,
+                mcp::ToolCallFormatHint::Auto | mcp::ToolCallFormatHint::XmlTags
+            )
+            .then(mcp::ToolCallStreamParser::new);
+            let mut buffered = String::new();
+            let mut stripper = MarkdownStripper::default();
+            let mut tool_blocks = Vec::new();
+
+            while let Some(chunk) = stream.next().await {
+                let text = match chunk {
+                    Ok(text) => text,
+                    Err(e) => {
+                        let _ = tx.send(Err(e)).await;
+                        return;
+                    }
+                };
+                let Some(parser) = parser.as_mut() else {
+                    buffered.push_str(&text);
+                    continue;
+                };
+                for segment in parser.push(&text) {
+                    match segment {
+                        // Text after a tool call is the model guessing at results; drop it
+                        mcp::StreamSegment::Text(text) if tool_blocks.is_empty() => {
+                            if !send_text(&tx, stripper.push(&text)).await {
+                                return;
+                            }
+                        }
+                        mcp::StreamSegment::Text(_) => {}
+                        mcp::StreamSegment::ToolCall(block) => {
+                            if tool_blocks.is_empty() {
+                                send_text(&tx, stripper.finish()).await;
+                            }
+                            tool_blocks.push(block);
+                        }
+                    }
+                }
+            }
+            // Frees its local slot before the continuation asks for one
+            drop(stream);
+            match parser {
+                Some(parser) if tool_blocks.is_empty() => {
+                    if let Some(mcp::StreamSegment::Text(text)) = parser.finish() {
+                        send_text(&tx, stripper.push(&text)).await;
+                    }
+                    send_text(&tx, stripper.finish()).await;
+                }
+                Some(_) => {}
+                None if router.parse_tool_calls(&buffered).tool_calls.is_empty() => {
+                    send_text(&tx, stripper.push(&buffered)).await;
+                    send_text(&tx, stripper.finish()).await;
+                }
+                None => tool_blocks.push(buffered),
+            }
 
-        if !parsed.has_tool_calls() {
-            // No tool calls - stream the response directly
-            let cleaned = strip_markdown_formatting(&response);
-            return Ok(Box::pin(futures::stream::once(futures::future::ready(Ok(
-                cleaned,
-            )))));
-        }
+            let calls: Vec<mcp::ToolCall> = tool_blocks
+                .iter()
+                .flat_map(|block| router.parse_tool_calls(block).tool_calls)
+                .collect();
+            if calls.is_empty() {
+                return;
+            }
 
-        // Process tool calls
-        let mut tool_results = Vec::new();
-        for call in &parsed.tool_calls {
-            if mcp_manager.requires_confirmation(&call.name).await {
-                tool_results.push(format!("Tool '{}' requires confirmation.", call.name));
-            } else {
-                match mcp_manager.process_tool_call(call).await {
-                    Ok(result) => tool_results.push(result),
-                    Err(e) => tool_results.push(format!("Tool error: {}", e)),
+            let (tool_results, held) = router.run_tool_calls(&calls, &mcp_manager).await;
+            // The stream can't wait for an answer, so tell the user how to give one
+            for notice in held {
+                if !send_text(&tx, format!("{}\n\n", notice)).await {
+                    return;
                 }
             }
-        }
 
-        // Build continuation prompt with tool results
-        let continuation_prompt = format!(
-            r#"User asked: {}
+            // Build continuation prompt with tool results
+            let continuation_prompt = format!(
+                r#"User asked: {}
 
 Tool results:
 {}
 
 Provide a helpful response based on these results. Include relevant details, commands, or next steps."#,
-            input,
-            tool_results.join("\n\n")
-        );
+                input,
+                tool_results.join("\n\n")
+            );
 
-        // Stream the final response
-        self.smart_generate_stream(&continuation_prompt, false)
-            .await
+            // Resume streaming with the final response
+            match router
+                .smart_generate_stream(&continuation_prompt, false)
+                .await
+            {
+                Ok(mut continuation) => {
+                    let mut stripper = MarkdownStripper::default();
+                    while let Some(chunk) = continuation.next().await {
+                        let sent = match chunk {
+                            Ok(text) => send_text(&tx, stripper.push(&text)).await,
+                            Err(e) => tx.send(Err(e)).await.is_ok(),
+                        };
+                        if !sent {
+                            return;
+                        }
+                    }
+                    send_text(&tx, stripper.finish()).await;
+                }
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                }
+            }
+        });
+
+        Ok(Box::pin(futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|item| (item, rx))
+        })))
+    }
+
+    /// Execute tool calls (after policy and confirmation checks), returning
+    /// one result or notice per call for the model, and for the user a
+    /// notice per call held for their confirmation
+    async fn run_tool_calls(
+        &self,
+        calls: &[mcp::ToolCall],
+        mcp_manager: &McpManager,
+    ) -> (Vec<String>, Vec<String>) {
+        let mut tool_results = Vec::new();
+        let mut held = Vec::new();
+        for call in calls {
+            if let Some(notice) = self.tool_policy_notice(call, mcp_manager) {
+                tool_results.push(notice);
+            } else if let Some(reason) = self.confirmation_reason(call, mcp_manager).await {
+                match mcp_manager.hold_for_confirmation(call).await {
+                    Ok(confirmation) => {
+                        tool_results.push(format!(
+                            "Tool '{}' is waiting for the user's confirmation and hasn't run.",
+                            call.name
+                        ));
+                        held.push(format!(
+                            "{}\ntool: {} {}\napprove or reject confirmation {} to continue.",
+                            reason,
+                            call.name,
+                            serde_json::to_string(&call.arguments).unwrap_or_default(),
+                            confirmation.id
+                        ));
+                    }
+                    Err(e) => tool_results.push(format!("Tool error: {}", e)),
+                }
+            } else {
+                match mcp_manager.process_tool_call(call).await {
+                    Ok(result) => tool_results.push(result),
+                    Err(e) => tool_results.push(format!("Tool error: {}", e)),
+                }
+            }
+        }
+        (tool_results, held)
     }
 
     /// Generate using local Ollama with streaming
@@ -364,43 +951,36 @@ Provide a helpful response based on these results. Include relevant details, com
     ) -> Result<impl Stream<Item = Result<String>> + Send> {
         debug!("🧠 Streaming with local LLM (kernel brain)");
 
-        let request = OllamaRequest {
-            model: self.config.local_model.clone(),
-            prompt: prompt.to_string(),
-            stream: true,
+        let request = self.ollama_request(prompt, true);
+
+        let permit = self.permit(&self.local_permits).await?;
+        let url = format!("{}/api/generate", self.config().ollama_url);
+        let timeout = self.local_timeout();
+        let send = async {
+            self.http_client
+                .post(&url)?
+                .timeout(timeout)
+                .json(&request)
+                .send()
+                .await
+                .map_err(|e| request_error(e, "local model", timeout))
         };
-
-        let url = format!("{}/api/generate", self.config.ollama_url);
-        let response = self.http_client.post(&url).json(&request).send().await?;
+        let response = self.cancellable(send).await?;
 
         if !response.status().is_success() {
             let error_text = response.text().await?;
             return Err(anyhow!("Ollama API error: {}", error_text));
         }
 
-        let stream = response.bytes_stream().map(|result| {
-            match result {
-                Ok(bytes) => {
-                    // Ollama sends multiple JSON objects, one per line
-                    let text = String::from_utf8_lossy(&bytes);
-                    let mut combined = String::new();
-                    for line in text.lines() {
-                        if let Ok(chunk) = serde_json::from_str::<OllamaStreamResponse>(line) {
-                            if let Some(err) = chunk.error {
-                                return Err(anyhow!("Ollama error: {}", err));
-                            }
-                            if let Some(resp) = chunk.response {
-                                combined.push_str(&resp);
-                            }
-                        }
-                    }
-                    Ok(combined)
-                }
-                Err(e) => Err(anyhow!("Stream error: {}", e)),
-            }
-        });
+        let stream = ollama_stream(response.bytes_stream());
 
-        Ok(stream)
+        // Dropping the body on cancel closes the connection to Ollama. The
+        // slot is held until the stream is dropped.
+        Ok(stream
+            .take_until(self.cancel.clone().cancelled_owned())
+            .inspect(move |_| {
+                let _held = &permit;
+            }))
     }
 
     /// Generate using cloud API with streaming
@@ -410,8 +990,8 @@ Provide a helpful response based on these results. Include relevant details, com
     ) -> Result<impl Stream<Item = Result<String>> + Send> {
         debug!("☁️  Streaming with cloud LLM via OpenRouter");
 
-        if self.config.openrouter_api_key.is_empty() {
-            return Err(anyhow!("OpenRouter API key not configured"));
+        if self.config().openrouter_api_key.is_empty() {
+            return Err(Error::CloudUnconfigured.into());
         }
 
         // Mock streaming for cloud by returning the whole thing as one chunk
@@ -422,21 +1002,94 @@ Provide a helpful response based on these results. Include relevant details, com
 
     pub fn build_basic_prompt(&self, input: &str, context: &Context) -> String {
         format!(
-            r#"You are Mycel OS, an AI assistant. Answer the user's question or help with their task.
+            r#"{}
 
-Current directory: {}
+{}{}{}Current directory: {}
 User: {}
 
 Respond directly and helpfully:"#,
-            context.working_directory, input
+            self.preamble(
+                context,
+                "You are Mycel OS, an AI assistant. Answer the user's question or help with their task."
+            ),
+            history_section(context),
+            files_section(context),
+            self.preferences_section(context),
+            context.working_directory,
+            input
         )
     }
 
+    /// Tools prompt for `input`, narrowed to the most relevant tools when
+    /// `mcp.tool_selection_top_k` is set
+    async fn tools_prompt(&self, input: &str, mcp_manager: &McpManager) -> String {
+        mcp_manager
+            .get_tools_prompt_for(
+                input,
+                move |text: String| async move { self.embed(&text).await },
+            )
+            .await
+    }
+
+    /// Embed text with the local embedding model (Ollama `/api/embeddings`)
+    pub async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        if !self.is_local_available() {
+            return Err(anyhow!("Local embedding model unavailable"));
+        }
+
+        let url = format!("{}/api/embeddings", self.config().ollama_url);
+        let timeout = self.local_timeout();
+        let response = self
+            .http_client
+            .post(&url)?
+            .timeout(timeout)
+            .json(&serde_json::json!({
+                "model": self.config().embedding_model,
+                "prompt": text,
+            }))
+            .send()
+            .await
+            .map_err(|e| request_error(e, "local model", timeout))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response.text().await?;
+            return Err(anyhow!(
+                "Ollama embeddings error ({}): {}",
+                status,
+                error_text
+            ));
+        }
+
+        let body: OllamaEmbeddingResponse = response.json().await?;
+        if body.embedding.is_empty() {
+            return Err(anyhow!("Ollama returned an empty embedding"));
+        }
+        Ok(body.embedding)
+    }
+
     /// Main interface - processes user input (legacy non-streaming)
     pub async fn process(&self, input: &str, context: &Context) -> Result<String> {
-        // 1. Parse the user's intent using the AI
-        // This ensures the AI understands the request rather than relying on brittle string matching
-        let intent = self.parse_intent(input, context).await?;
+        // 1. Work out the user's intent: obvious inputs are routed by keyword,
+        // anything else is parsed by the AI
+        let fast_route = if self.config().intent_fast_path {
+            IntentCategory::fast_route(input)
+        } else {
+            None
+        };
+        let intent = match fast_route {
+            Some(action_type) => {
+                debug!(?action_type, "Intent routed by keyword fast-path");
+                Intent {
+                    action: input.to_string(),
+                    action_type,
+                    confidence: 1.0,
+                    parameters: serde_json::Value::Null,
+                    requires_cloud: false,
+                }
+            }
+            None => self.parse_intent(input, context).await?,
+        };
 
         info!(
             action = %intent.action,
@@ -473,24 +1126,25 @@ Respond directly and helpfully:"#,
     }
 
     /// Process user input with MCP tools available
-    /// This method injects available tools into the prompt and handles tool calls
+    /// This method injects available tools into the prompt and handles tool calls.
+    /// A call that needs confirmation stops processing and is handed back.
     pub async fn process_with_tools(
         &self,
         input: &str,
         context: &Context,
         mcp_manager: &McpManager,
-    ) -> Result<String> {
+    ) -> Result<ToolsReply> {
         // Get available tools
-        let tools_prompt = mcp_manager.get_tools_prompt().await;
+        let tools_prompt = self.tools_prompt(input, mcp_manager).await;
 
         // If no tools available, fall back to regular processing
         if tools_prompt.is_empty() {
-            return self.process(input, context).await;
+            return Ok(ToolsReply::Text(self.process(input, context).await?));
         }
 
         // Build the enhanced prompt with tools
         let prompt = format!(
-            r#"You are Mycel OS, an AI assistant with system access.
+            r#"{preamble}
 
 {tools_prompt}
 
@@ -499,11 +1153,18 @@ IMPORTANT:
 - Use tools only when the user asks for system info, file operations, or commands.
 - Be concise and helpful.
 
-Current directory: {cwd}
+{history}{files}{preferences}Current directory: {cwd}
 User: {input}
 
 Respond:"#,
+            preamble = self.preamble(
+                context,
+                "You are Mycel OS, an AI assistant with system access."
+            ),
             tools_prompt = tools_prompt,
+            history = history_section(context),
+            files = files_section(context),
+            preferences = self.preferences_section(context),
             cwd = context.working_directory,
             input = input
         );
@@ -512,11 +1173,17 @@ Respond:"#,
         let response = self.smart_generate(&prompt, false).await?;
 
         // Parse for tool calls
-        let parsed = mcp::parse_tool_calls(&response);
+        let parsed = self.parse_tool_calls(&response);
 
         if !parsed.has_tool_calls() {
             // No tool calls - return the response directly
-            return Ok(strip_markdown_formatting(&response));
+            return Ok(ToolsReply::Text(strip_markdown_formatting(&response, true)));
+        }
+
+        // Nothing runs until the user has answered for the first call that
+        // needs confirmation
+        if let Some(reply) = self.confirm_first(&parsed.tool_calls, mcp_manager).await? {
+            return Ok(reply);
         }
 
         // Process tool calls
@@ -527,14 +1194,8 @@ Respond:"#,
                 call.name, call.arguments
             );
 
-            // Check if confirmation is required
-            if mcp_manager.requires_confirmation(&call.name).await {
-                // Return information about what would be done
-                let tool_info = format!(
-                    "Tool '{}' requires confirmation.\nArguments: {:?}\n\nTo proceed, confirm the action.",
-                    call.name, call.arguments
-                );
-                tool_results.push(tool_info);
+            if let Some(notice) = self.tool_policy_notice(call, mcp_manager) {
+                tool_results.push(notice);
             } else {
                 // Execute the tool
                 match mcp_manager.process_tool_call(call).await {
@@ -546,7 +1207,8 @@ Respond:"#,
 
         // Build continuation prompt with tool results
         let continuation_prompt = format!(
-            r#"You are Mycel OS. Continue the conversation with tool results.
+            r#"{}
+Continue the conversation with tool results.
 
 Previous context:
 User asked: {}
@@ -555,7 +1217,8 @@ You responded: {}
 Tool results:
 {}
 
-Now provide a concise final response to the user based on these tool results. Be terse."#,
+Now provide a final response to the user based on these tool results."#,
+            self.preamble(context, "You are Mycel OS. Be terse and concise."),
             input,
             parsed.prefix_text.trim(),
             tool_results.join("\n\n")
@@ -563,36 +1226,60 @@ Now provide a concise final response to the user based on these tool results. Be
 
         // Get final response
         let final_response = self.smart_generate(&continuation_prompt, false).await?;
-        Ok(strip_markdown_formatting(&final_response))
+        Ok(ToolsReply::Text(strip_markdown_formatting(
+            &final_response,
+            true,
+        )))
     }
 
-    /// Process with tools but allow multiple tool call rounds (agentic loop)
+    /// Process with tools but allow multiple tool call rounds (agentic loop).
+    /// A router made `with_steps` reports each round as it goes. A call
+    /// that needs confirmation ends the loop and is handed back.
     pub async fn process_with_tools_loop(
         &self,
         input: &str,
         context: &Context,
         mcp_manager: &McpManager,
         max_iterations: usize,
-    ) -> Result<String> {
-        let tools_prompt = mcp_manager.get_tools_prompt().await;
+        provider: crate::ipc::LlmProvider,
+    ) -> Result<ToolsReply> {
+        let reply = self
+            .tools_loop(input, context, mcp_manager, max_iterations, provider)
+            .await?;
+        if let ToolsReply::Text(answer) = &reply {
+            self.step(AgentStep::FinalAnswer {
+                text: answer.clone(),
+            });
+        }
+        Ok(reply)
+    }
+
+    async fn tools_loop(
+        &self,
+        input: &str,
+        context: &Context,
+        mcp_manager: &McpManager,
+        max_iterations: usize,
+        provider: crate::ipc::LlmProvider,
+    ) -> Result<ToolsReply> {
+        let tools_prompt = self.tools_prompt(input, mcp_manager).await;
 
         if tools_prompt.is_empty() {
-            return self.process(input, context).await;
+            let answer = if provider == crate::ipc::LlmProvider::Auto {
+                self.process(input, context).await?
+            } else {
+                self.generate_with_provider(&self.build_basic_prompt(input, context), provider)
+                    .await?
+            };
+            return Ok(ToolsReply::Text(answer));
         }
 
-        let mut conversation = format!(
-            r#"You are Mycel OS. You ARE the operating system.
+        let head = format!(
+            r#"{preamble}
 
 {tools_prompt}
 
-EVOLUTION RULES:
-- If the user asks for a capability you don't have, USE 'evolve_os_add_capability' to write a new MCP server.
-- You can write servers in JavaScript (Node.js) or Python.
-- Always provide complete, production-ready code for new servers.
-- You can also publish these to the global registry using 'near_publish_capability'.
-
-GENERAL RULES:
-- TERSE responses only.
+{evolution_rules}GENERAL RULES:
 - Use tools when helpful.
 - After tool results, either use another tool or give a final response.
 - When done, just respond normally without tool calls.
@@ -601,18 +1288,29 @@ cwd: {cwd}
 user: {input}
 
 Reply:"#,
+            preamble = self.preamble(context, TERSE_OS_PREAMBLE),
             tools_prompt = tools_prompt,
+            evolution_rules = if mcp_manager.evolution_enabled() {
+                EVOLUTION_RULES
+            } else {
+                ""
+            },
             cwd = context.working_directory,
             input = input
         );
+        let mut conversation = LoopPrompt::new(head, self.config().local_max_tokens);
+
+        let mut guard = ToolLoopGuard::default();
 
         for iteration in 0..max_iterations {
-            let response = self.smart_generate(&conversation, false).await?;
-            let parsed = mcp::parse_tool_calls(&response);
+            let response = self
+                .generate_with_provider(&conversation.render(), provider)
+                .await?;
+            let parsed = self.parse_tool_calls(&response);
 
             if !parsed.has_tool_calls() {
                 // No more tool calls - we're done
-                return Ok(strip_markdown_formatting(&response));
+                return Ok(ToolsReply::Text(strip_markdown_formatting(&response, true)));
             }
 
             debug!(
@@ -621,33 +1319,96 @@ Reply:"#,
                 parsed.tool_calls.len()
             );
 
+            if guard.is_repeat(&parsed.tool_calls) {
+                warn!(
+                    "Tool loop repeated the same calls at iteration {}, forcing a final response",
+                    iteration + 1
+                );
+                let prompt = format!(
+                    "{}\n\nYou are repeating the same tool calls. Do not call any more tools. Give your final response now:",
+                    conversation.render()
+                );
+                let response = self.generate_with_provider(&prompt, provider).await?;
+                let parsed = self.parse_tool_calls(&response);
+                if parsed.has_tool_calls() {
+                    let text = parsed.prefix_text.trim();
+                    if text.is_empty() {
+                        return Ok(ToolsReply::Text(
+                            "Stopped: the same tools kept being called without progress."
+                                .to_string(),
+                        ));
+                    }
+                    return Ok(ToolsReply::Text(strip_markdown_formatting(text, true)));
+                }
+                return Ok(ToolsReply::Text(strip_markdown_formatting(&response, true)));
+            }
+
+            // Nothing in this round runs until the user has answered for
+            // the first call that needs confirmation
+            if let Some(reply) = self.confirm_first(&parsed.tool_calls, mcp_manager).await? {
+                return Ok(reply);
+            }
+
+            let thinking = parsed.prefix_text.trim();
+            if !thinking.is_empty() {
+                self.step(AgentStep::Thinking {
+                    text: thinking.to_string(),
+                });
+            }
+
             // Process all tool calls
             let mut tool_results = Vec::new();
             for call in &parsed.tool_calls {
-                if mcp_manager.requires_confirmation(&call.name).await {
-                    tool_results.push(format!(
-                        "Tool '{}' requires user confirmation. Cannot proceed automatically.",
-                        call.name
-                    ));
+                self.step(AgentStep::ToolCallStarted {
+                    tool: call.name.clone(),
+                    arguments: call.arguments.clone(),
+                });
+                let (result, success) = if let Some(notice) =
+                    self.tool_policy_notice(call, mcp_manager)
+                {
+                    (notice, false)
+                } else if guard.is_failing(call) {
+                    (
+                        format!(
+                            "Tool '{}' keeps failing with the same error. Not retrying it; try something else.",
+                            call.name
+                        ),
+                        false,
+                    )
                 } else {
                     match mcp_manager.process_tool_call(call).await {
-                        Ok(result) => tool_results.push(result),
-                        Err(e) => tool_results.push(format!("Tool error: {}", e)),
+                        Ok(result) => (result, true),
+                        Err(e) => {
+                            let error = e.to_string();
+                            guard.record_error(call, &error);
+                            (format!("Tool error: {}", error), false)
+                        }
                     }
-                }
+                };
+                self.step(AgentStep::ToolResult {
+                    tool: call.name.clone(),
+                    result: result.clone(),
+                    success,
+                });
+                tool_results.push(result);
             }
 
             // Add to conversation
-            conversation.push_str(&format!(
-                "\n\nAssistant: {}\n\nTool results:\n{}\n\nContinue (use more tools or give final response):",
-                parsed.prefix_text.trim(),
-                tool_results.join("\n\n")
-            ));
+            conversation.push(
+                parsed.tool_calls.iter().map(|c| c.name.clone()).collect(),
+                format!(
+                    "\n\nAssistant: {}\n\nTool results:\n{}\n\nContinue (use more tools or give final response):",
+                    parsed.prefix_text.trim(),
+                    tool_results.join("\n\n")
+                ),
+            );
         }
 
         // Max iterations reached
         warn!("MCP tool loop reached max iterations ({})", max_iterations);
-        Ok("Max tool iterations reached. Please try a simpler query.".to_string())
+        Ok(ToolsReply::Text(
+            "Max tool iterations reached. Please try a simpler query.".to_string(),
+        ))
     }
 
     /// Parse user input into a structured intent (legacy, kept for compatibility)
@@ -704,7 +1465,7 @@ action_type:
     /// Generate a simple text response
     pub async fn generate_response(&self, input: &str, context: &Context) -> Result<String> {
         let prompt = format!(
-            r#"You are Mycel OS - an AI operating system assistant.
+            r#"{}
 
 Your capabilities: run commands, file operations, package management, system info.
 
@@ -714,24 +1475,74 @@ Be HELPFUL and SPECIFIC:
 - Include relevant file paths, commands, or configuration details
 - If something needs clarification, ask
 
-cwd: {}
+{}cwd: {}
 user: {}
 
 Reply:"#,
-            context.working_directory, input
+            self.preamble(
+                context,
+                "You are Mycel OS - an AI operating system assistant."
+            ),
+            self.preferences_section(context),
+            context.working_directory,
+            input
         );
 
         let response = self.smart_generate(&prompt, false).await?;
         // Strip any markdown formatting that might have been added
-        Ok(strip_markdown_formatting(&response))
+        Ok(strip_markdown_formatting(&response, true))
     }
 
-    /// Generate code to accomplish a task
-    pub async fn generate_code(&self, intent: &Intent, context: &Context) -> Result<String> {
+    /// Describe in plain language what a piece of code will do when run
+    pub async fn explain_code(&self, code: &str) -> Result<String> {
         let prompt = format!(
-            r#"You are the OS kernel. Generate code to execute the user's intent.
+            r#"Explain what this code will do if it runs, for a user deciding whether to allow it.
 
-Task: {}
+Rules:
+1. Plain language, 1-3 sentences.
+2. Mention files, packages, services or network access it touches.
+3. Say if it changes or deletes anything.
+
+Code:
+{}
+
+Explanation:"#,
+            code
+        );
+
+        let response = self.smart_generate(&prompt, false).await?;
+        Ok(strip_markdown_formatting(&response, false))
+    }
+
+    /// Summarize what code would do and what it touches, for a
+    /// confirmation prompt. Never cached: it is only asked for code that
+    /// is about to wait on the user.
+    pub async fn explain_plan(&self, code: &str) -> Result<CodePlan> {
+        let prompt = format!(
+            r#"Describe what this code will do if it runs, for a user deciding whether to allow it.
+
+Reply with JSON only:
+{{"summary": "one line", "side_effects": ["each file written or deleted, network access, package installed or service changed"]}}
+
+Use an empty list when it only reads or prints.
+
+Code:
+{}"#,
+            code
+        );
+
+        let response = self.smart_generate(&prompt, false).await?;
+        let cleaned_response = strip_markdown_code_blocks(&response);
+        serde_json::from_str(&cleaned_response)
+            .map_err(|e| anyhow!("Failed to parse code plan: {}", e))
+    }
+
+    /// Generate code to accomplish a task
+    pub async fn generate_code(&self, intent: &Intent, context: &Context) -> Result<String> {
+        let prompt = format!(
+            r#"You are the OS kernel. Generate code to execute the user's intent.
+
+Task: {}
 Current Directory: {}
 
 Rules:
@@ -769,80 +1580,191 @@ cwd: {}
     /// Request from cloud AI (for complex tasks)
     pub async fn cloud_request(&self, input: &str, context: &Context) -> Result<String> {
         let prompt = format!(
-            r#"You are Mycel OS. Terse responses only. No fluff.
+            r#"{}
 
 cwd: {}
 user: {}
 
-Reply (1-2 sentences max):"#,
-            context.working_directory, input
+Reply:"#,
+            self.preamble(
+                context,
+                "You are Mycel OS. Terse responses only. No fluff. Reply in 1-2 sentences max."
+            ),
+            context.working_directory,
+            input
         );
 
         let response = self.cloud_generate(&prompt).await?;
-        Ok(strip_markdown_formatting(&response))
+        Ok(strip_markdown_formatting(&response, true))
     }
 
     /// Smart routing between local and cloud
     async fn smart_generate(&self, prompt: &str, force_cloud: bool) -> Result<String> {
+        if self.cancel.is_cancelled() {
+            return Err(Error::Cancelled.into());
+        }
+        if !self.has_backend() {
+            return Err(Error::NoBackend.into());
+        }
         let start = std::time::Instant::now();
 
+        // Read the config once: a second read while the first guard is
+        // alive can deadlock behind a queued `apply_config` write
+        let (prefer_cloud, has_api) = {
+            let config = self.config();
+            (config.prefer_cloud, !config.openrouter_api_key.is_empty())
+        };
+
         // If prefer_cloud is set and we have a cloud API, use cloud first
-        let use_cloud_first = force_cloud || (self.config.prefer_cloud && self.has_cloud_api());
+        let use_cloud_first = force_cloud || (prefer_cloud && has_api);
 
         info!(
             "AI routing: prefer_cloud={}, has_api={}, using_cloud={}",
-            self.config.prefer_cloud,
-            self.has_cloud_api(),
-            use_cloud_first
+            prefer_cloud, has_api, use_cloud_first
         );
 
-        let result = if use_cloud_first {
+        let planned = if use_cloud_first || !self.is_local_available() {
+            "cloud"
+        } else {
+            "local"
+        };
+        let _ = self.event_bus.send(SystemEvent::InferenceStarted {
+            source: planned.to_string(),
+        });
+
+        let (result, source, fell_back) = if use_cloud_first {
             // Cloud first mode
             match self.cloud_generate(prompt).await {
-                Ok(response) => Ok(response),
+                Ok(response) => (Ok(response), "cloud", false),
+                Err(e) if cancelled(&e) => (Err(e), "cloud", false),
                 Err(e) => {
-                    if self.local_available {
+                    if self.is_local_available() {
                         warn!("Cloud failed, falling back to local: {}", e);
-                        self.local_generate(prompt).await
+                        (self.local_generate(prompt).await, "local", true)
                     } else {
-                        Err(e)
+                        (Err(e), "cloud", false)
                     }
                 }
             }
         } else {
             // Local first mode
-            if self.local_available {
+            if self.is_local_available() {
                 match self.local_generate(prompt).await {
-                    Ok(response) => Ok(response),
+                    Ok(response) => (Ok(response), "local", false),
+                    Err(e) if cancelled(&e) => (Err(e), "local", false),
                     Err(e) => {
                         warn!("Local LLM failed, escalating to cloud: {}", e);
-                        self.cloud_generate(prompt).await
+                        (self.cloud_generate(prompt).await, "cloud", true)
                     }
                 }
             } else {
-                self.cloud_generate(prompt).await
+                (self.cloud_generate(prompt).await, "cloud", false)
             }
         };
 
         let elapsed = start.elapsed();
-        let source = if use_cloud_first { "cloud" } else { "local" };
         info!("AI response time: {:?} ({})", elapsed, source);
+        self.record_decision(source, fell_back, elapsed, result.is_ok());
+
+        if result.is_ok() {
+            let _ = self.event_bus.send(SystemEvent::InferenceCompleted {
+                source: source.to_string(),
+                duration_ms: elapsed.as_millis() as u64,
+            });
+        }
 
         result
     }
 
-    /// Generate using local Ollama - the primary brain of Mycel OS    /// Generate using local Ollama - the primary brain of Mycel OS
+    /// Remember where a generation went, dropping the oldest past
+    /// `ROUTING_LOG_CAPACITY`
+    fn record_decision(&self, source: &str, fell_back: bool, elapsed: Duration, success: bool) {
+        let model = if source == "local" {
+            self.local_model()
+        } else {
+            self.config().cloud_model.clone()
+        };
+        let decision = RoutingDecision {
+            timestamp: chrono::Utc::now(),
+            source: source.to_string(),
+            fell_back,
+            latency_ms: elapsed.as_millis() as u64,
+            model,
+            success,
+        };
+        let mut log = self.routing_log.lock().unwrap_or_else(|e| e.into_inner());
+        if log.len() == ROUTING_LOG_CAPACITY {
+            log.pop_front();
+        }
+        log.push_back(decision);
+    }
+
+    /// The last `limit` routing decisions, newest first
+    pub fn routing_log(&self, limit: usize) -> Vec<RoutingDecision> {
+        let log = self.routing_log.lock().unwrap_or_else(|e| e.into_inner());
+        log.iter().rev().take(limit).cloned().collect()
+    }
+
+    /// Generate using local Ollama - the primary brain of Mycel OS
     async fn local_generate(&self, prompt: &str) -> Result<String> {
-        debug!("🧠 Generating with local LLM (kernel brain)");
+        let _permit = self.permit(&self.local_permits).await?;
+        self.cancellable(self.local_request(prompt)).await
+    }
 
-        let request = OllamaRequest {
-            model: self.config.local_model.clone(),
+    /// Generation request for the local model, with `local_keep_alive`
+    fn ollama_request(&self, prompt: &str, stream: bool) -> OllamaRequest {
+        OllamaRequest {
+            model: self.local_model(),
             prompt: prompt.to_string(),
-            stream: false,
-        };
+            stream,
+            keep_alive: self
+                .config()
+                .local_keep_alive
+                .as_deref()
+                .and_then(crate::config::keep_alive_value),
+        }
+    }
+
+    /// Ask Ollama to drop the local model from memory now. The next request
+    /// loads it again, which takes a while for large models.
+    pub async fn unload_model(&self) -> Result<String> {
+        if !self.is_local_available() {
+            return Err(Error::LocalUnavailable.into());
+        }
+        let model = self.local_model();
+        let url = format!("{}/api/generate", self.config().ollama_url);
+        let timeout = self.local_timeout();
+        let response = self
+            .http_client
+            .post(&url)?
+            .timeout(timeout)
+            .json(&serde_json::json!({"model": model, "keep_alive": 0}))
+            .send()
+            .await
+            .map_err(|e| request_error(e, "local model", timeout))?;
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            return Err(anyhow!("Ollama API error: {}", error_text));
+        }
+        info!(model = %model, "Unloaded local model");
+        Ok(model)
+    }
+
+    async fn local_request(&self, prompt: &str) -> Result<String> {
+        debug!("🧠 Generating with local LLM (kernel brain)");
 
-        let url = format!("{}/api/generate", self.config.ollama_url);
-        let response = self.http_client.post(&url).json(&request).send().await?;
+        let request = self.ollama_request(prompt, false);
+
+        let url = format!("{}/api/generate", self.config().ollama_url);
+        let timeout = self.local_timeout();
+        let response = self
+            .http_client
+            .post(&url)?
+            .timeout(timeout)
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| request_error(e, "local model", timeout))?;
 
         // Save status code before consuming response
         let status = response.status();
@@ -854,7 +1776,10 @@ Reply (1-2 sentences max):"#,
         }
 
         // Try to parse as success response
-        let ollama_response: OllamaResponse = response.json().await?;
+        let ollama_response: OllamaResponse = response
+            .json()
+            .await
+            .map_err(|e| request_error(e, "local model", timeout))?;
 
         // Check if it's an error response (Ollama sometimes returns 200 with error field)
         if let Some(error) = ollama_response.error {
@@ -869,21 +1794,39 @@ Reply (1-2 sentences max):"#,
 
     /// Generate using cloud API via OpenRouter
     async fn cloud_generate(&self, prompt: &str) -> Result<String> {
-        if self.config.openrouter_api_key.is_empty() {
+        if self.config().openrouter_api_key.is_empty() {
+            return Err(Error::CloudUnconfigured.into());
+        }
+
+        // Queue here so a burst of chats can't fan out into a burst of calls
+        let _permit = self.permit(&self.cloud_permits).await?;
+        let Some(call) = CircuitCall::admit(&self.cloud_circuit, Instant::now()) else {
             return Err(anyhow!(
-                "No cloud API configured. Set OPENROUTER_API_KEY environment variable."
+                "Cloud provider skipped: too many recent failures (circuit open)"
             ));
-        }
+        };
 
-        self.openrouter_generate(prompt).await
+        let result = self.cancellable(self.openrouter_generate(prompt)).await;
+        // A cancelled call has no outcome; dropping `call` frees a probe slot
+        if !is_cancelled(&result) {
+            call.record(result.is_ok(), Instant::now());
+        }
+        result
     }
 
     /// Generate using OpenRouter API
     async fn openrouter_generate(&self, prompt: &str) -> Result<String> {
-        info!("☁️  Generating with cloud LLM: {}", self.config.cloud_model);
+        let (model, api_key) = {
+            let config = self.config();
+            (
+                config.cloud_model.clone(),
+                config.openrouter_api_key.clone(),
+            )
+        };
+        info!("☁️  Generating with cloud LLM: {}", model);
 
         let request = OpenRouterRequest {
-            model: self.config.cloud_model.clone(),
+            model,
             messages: vec![OpenRouterMessage {
                 role: "user".to_string(),
                 content: prompt.to_string(),
@@ -891,26 +1834,29 @@ Reply (1-2 sentences max):"#,
             max_tokens: Some(4096),
         };
 
+        let timeout = self.cloud_timeout();
         let response = self
             .http_client
-            .post("https://openrouter.ai/api/v1/chat/completions")
-            .header(
-                "Authorization",
-                format!("Bearer {}", self.config.openrouter_api_key),
-            )
+            .post("https://openrouter.ai/api/v1/chat/completions")?
+            .timeout(timeout)
+            .header("Authorization", format!("Bearer {}", api_key))
             .header("HTTP-Referer", "https://mycel-os.dev")
             .header("X-Title", "Mycel OS")
             .header("Content-Type", "application/json")
             .json(&request)
             .send()
-            .await?;
+            .await
+            .map_err(|e| request_error(e, "cloud model", timeout))?;
 
         if !response.status().is_success() {
             let error_text = response.text().await?;
             return Err(anyhow!("OpenRouter API error: {}", error_text));
         }
 
-        let response: OpenRouterResponse = response.json().await?;
+        let response: OpenRouterResponse = response
+            .json()
+            .await
+            .map_err(|e| request_error(e, "cloud model", timeout))?;
 
         response
             .choices
@@ -924,23 +1870,21 @@ Reply (1-2 sentences max):"#,
         &self,
         prompt: &str,
         provider: crate::ipc::LlmProvider,
-    ) -> Result<String> {
+    ) -> crate::error::Result<String> {
         use crate::ipc::LlmProvider;
         let start = std::time::Instant::now();
 
         let result = match provider {
             LlmProvider::Auto => self.smart_generate(prompt, false).await,
             LlmProvider::Local => {
-                if !self.local_available {
-                    return Err(anyhow!("Local LLM (Ollama) is not available"));
+                if !self.is_local_available() {
+                    return Err(Error::LocalUnavailable);
                 }
                 self.local_generate(prompt).await
             }
             LlmProvider::Cloud => {
                 if !self.has_cloud_api() {
-                    return Err(anyhow!(
-                        "Cloud LLM is not configured. Set OPENROUTER_API_KEY."
-                    ));
+                    return Err(Error::CloudUnconfigured);
                 }
                 self.cloud_generate(prompt).await
             }
@@ -953,58 +1897,71 @@ Reply (1-2 sentences max):"#,
             LlmProvider::Cloud => "cloud",
         };
         info!("AI response time: {:?} ({})", elapsed, source);
+        // smart_generate already recorded where an `Auto` request went
+        if provider != LlmProvider::Auto {
+            self.record_decision(source, false, elapsed, result.is_ok());
+        }
 
-        result
+        Ok(result?)
     }
 
-    /// Process with tools using a specific provider
+    /// Process with tools using a specific provider. A call that needs
+    /// confirmation stops processing and is handed back.
     pub async fn process_with_tools_provider(
         &self,
         input: &str,
         context: &Context,
         mcp_manager: &McpManager,
         provider: crate::ipc::LlmProvider,
-    ) -> Result<String> {
-        let tools_prompt = mcp_manager.get_tools_prompt().await;
+    ) -> Result<ToolsReply> {
+        let tools_prompt = self.tools_prompt(input, mcp_manager).await;
 
         if tools_prompt.is_empty() {
-            return self
-                .generate_with_provider(&self.build_basic_prompt(input, context), provider)
-                .await;
+            return Ok(ToolsReply::Text(
+                self.generate_with_provider(&self.build_basic_prompt(input, context), provider)
+                    .await?,
+            ));
         }
 
         let prompt = format!(
-            r#"You are Mycel OS. You ARE the operating system.
+            r#"{preamble}
 
 {tools_prompt}
 
 RULES:
-- TERSE responses only.
 - Use tools when helpful for the task.
 - For simple questions, just respond directly.
 - After getting tool results, provide a final response.
 
-cwd: {cwd}
+{history}{files}{preferences}cwd: {cwd}
 user: {input}
 
 Reply:"#,
+            preamble = self.preamble(context, TERSE_OS_PREAMBLE),
             tools_prompt = tools_prompt,
+            history = history_section(context),
+            files = files_section(context),
+            preferences = self.preferences_section(context),
             cwd = context.working_directory,
             input = input
         );
 
         let response = self.generate_with_provider(&prompt, provider).await?;
-        let parsed = mcp::parse_tool_calls(&response);
+        let parsed = self.parse_tool_calls(&response);
 
         if !parsed.has_tool_calls() {
-            return Ok(strip_markdown_formatting(&response));
+            return Ok(ToolsReply::Text(strip_markdown_formatting(&response, true)));
+        }
+
+        if let Some(reply) = self.confirm_first(&parsed.tool_calls, mcp_manager).await? {
+            return Ok(reply);
         }
 
         // Process tool calls
         let mut tool_results = Vec::new();
         for call in &parsed.tool_calls {
-            if mcp_manager.requires_confirmation(&call.name).await {
-                tool_results.push(format!("Tool '{}' requires user confirmation.", call.name));
+            if let Some(notice) = self.tool_policy_notice(call, mcp_manager) {
+                tool_results.push(notice);
             } else {
                 match mcp_manager.process_tool_call(call).await {
                     Ok(result) => tool_results.push(result),
@@ -1031,26 +1988,696 @@ Provide a concise final response based on these tool results."#,
         let final_response = self
             .generate_with_provider(&continuation_prompt, provider)
             .await?;
-        Ok(strip_markdown_formatting(&final_response))
+        Ok(ToolsReply::Text(strip_markdown_formatting(
+            &final_response,
+            true,
+        )))
+    }
+
+    /// Run a tool call past the policy layer.
+    ///
+    /// Returns a notice for the model when policy denies the call or wants
+    /// confirmation first, or `None` when the call may proceed.
+    fn tool_policy_notice(&self, call: &mcp::ToolCall, mcp_manager: &McpManager) -> Option<String> {
+        let risk = mcp_manager.assess_risk_level(&call.name, &call.arguments);
+        let decision = self
+            .policy
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .evaluate_tool_call(&call.name, &call.arguments, risk);
+        match decision {
+            ActionPolicy::Allow => None,
+            ActionPolicy::Deny { reason } => {
+                warn!(tool = %call.name, reason = %reason, "Tool call denied by policy");
+                Some(format!("Tool '{}' was denied: {}", call.name, reason))
+            }
+            ActionPolicy::RequiresConfirmation { message, .. } => Some(format!(
+                "Tool '{}' requires user confirmation. {}",
+                call.name, message
+            )),
+        }
+    }
+
+    /// The first of `calls` that needs the user's confirmation, as a reply
+    /// asking for it
+    async fn confirm_first(
+        &self,
+        calls: &[mcp::ToolCall],
+        mcp_manager: &McpManager,
+    ) -> Result<Option<ToolsReply>> {
+        for call in calls {
+            if let Some(reason) = self.confirmation_reason(call, mcp_manager).await {
+                let message = format!(
+                    "{}\ntool: {} {}",
+                    reason,
+                    call.name,
+                    serde_json::to_string(&call.arguments)?
+                );
+                return Ok(Some(ToolsReply::Confirm {
+                    call: call.clone(),
+                    message,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Why `call` needs the user's confirmation before it runs, if it does:
+    /// policy asks for it, or the server lists the tool in
+    /// `requires_confirmation`. Denied calls never do; they aren't run at all.
+    async fn confirmation_reason(
+        &self,
+        call: &mcp::ToolCall,
+        mcp_manager: &McpManager,
+    ) -> Option<String> {
+        let risk = mcp_manager.assess_risk_level(&call.name, &call.arguments);
+        let decision = self
+            .policy
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .evaluate_tool_call(&call.name, &call.arguments, risk);
+        match decision {
+            ActionPolicy::RequiresConfirmation { message, .. } => Some(message),
+            ActionPolicy::Deny { .. } => None,
+            ActionPolicy::Allow => mcp_manager
+                .requires_confirmation(&call.name)
+                .await
+                .then(|| format!("tool '{}' requires confirmation", call.name)),
+        }
     }
 
     /// Check if local LLM is available
     pub fn is_local_available(&self) -> bool {
-        self.local_available
+        self.local_available.load(Ordering::Relaxed)
+    }
+
+    /// Probe Ollama again, for when it may have come up or gone away since
+    /// startup (say, after setup pulled a model)
+    pub async fn refresh_local_availability(&self) -> bool {
+        let config = self.config().clone();
+        let available = Self::check_local_availability(&self.http_client, &config).await;
+        if self.local_available.swap(available, Ordering::Relaxed) != available {
+            info!(available, "Local LLM availability changed");
+        }
+        available
+    }
+
+    /// Tool calls in `response`, in the formats `mcp.tool_call_format` allows
+    fn parse_tool_calls(&self, response: &str) -> mcp::tool_parser::ParsedResponse {
+        mcp::parse_tool_calls_as(response, self.config().mcp.tool_call_format)
+    }
+
+    /// The current configuration; don't hold on to it across an await or
+    /// an `apply_config`
+    fn config(&self) -> std::sync::RwLockReadGuard<'_, MycelConfig> {
+        self.config.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The user's preferences for a prompt, limited to
+    /// `prompt_preference_keys` when that is set
+    fn preferences_section(&self, context: &Context) -> String {
+        /// Preferences included when no keys are configured
+        const MAX_PREFERENCES: usize = 8;
+        /// Keep one long value from crowding out the rest of the prompt
+        const MAX_VALUE_CHARS: usize = 200;
+
+        let keys = self.config().prompt_preference_keys.clone();
+        let mut preferences: Vec<(&String, &String)> = context
+            .user_preferences
+            .iter()
+            .filter(|(key, value)| {
+                !value.trim().is_empty() && (keys.is_empty() || keys.contains(key))
+            })
+            .collect();
+        if preferences.is_empty() {
+            return String::new();
+        }
+        preferences.sort();
+        preferences.truncate(MAX_PREFERENCES.max(keys.len()));
+
+        let mut section = String::from("User preferences:\n");
+        for (key, value) in preferences {
+            let value: String = value.trim().chars().take(MAX_VALUE_CHARS).collect();
+            section.push_str(&format!("- {}: {}\n", key, value));
+        }
+        section
+    }
+
+    /// Opening of a chat prompt: the configured `system_prompt` with `{cwd}`
+    /// and `{user}` filled in, or `default` under the configured persona name
+    fn preamble(&self, context: &Context, default: &str) -> String {
+        let config = self.config();
+        match config.system_prompt.as_deref().map(str::trim) {
+            Some(custom) if !custom.is_empty() => custom
+                .replace("{cwd}", &context.working_directory)
+                .replace("{user}", context.user_name.as_deref().unwrap_or("the user")),
+            _ => match config.persona_name.as_deref().map(str::trim) {
+                Some(name) if !name.is_empty() => default.replace("Mycel OS", name),
+                _ => default.to_string(),
+            },
+        }
+    }
+
+    /// Apply a reloaded configuration: models, cloud settings and policy
+    /// take effect on the next request
+    pub fn apply_config(&self, config: &MycelConfig) {
+        // Only override a runtime model switch when the file itself changed
+        if config.local_model != self.config().local_model {
+            if let Ok(mut model) = self.local_model.write() {
+                *model = config.local_model.clone();
+            }
+        }
+
+        if let Ok(mut policy) = self.policy.write() {
+            *policy = PolicyEvaluator::new(config.policy.clone());
+        }
+        if let Ok(mut current) = self.config.write() {
+            *current = config.clone();
+        }
     }
 
     /// Check if cloud API is available
-    fn has_cloud_api(&self) -> bool {
-        !self.config.openrouter_api_key.is_empty()
+    pub fn has_cloud_api(&self) -> bool {
+        !self.config().openrouter_api_key.is_empty()
+    }
+
+    /// Whether any model, local or cloud, can generate
+    pub fn has_backend(&self) -> bool {
+        self.is_local_available() || self.has_cloud_api()
+    }
+
+    /// Deadline for one request to the local model
+    fn local_timeout(&self) -> Duration {
+        Duration::from_secs(self.config().local_timeout_secs)
+    }
+
+    /// Deadline for one request to the cloud provider
+    fn cloud_timeout(&self) -> Duration {
+        Duration::from_secs(self.config().cloud_timeout_secs)
+    }
+
+    /// Name of the model currently used for local generation
+    pub fn local_model(&self) -> String {
+        self.local_model
+            .read()
+            .map(|m| m.clone())
+            .unwrap_or_else(|_| self.config().local_model.clone())
+    }
+
+    /// List models from a backend along with their hardware compatibility
+    pub async fn list_models(&self, backend: ModelBackend) -> Result<Vec<ModelCompatibility>> {
+        let models = self.model_manager.list_available(backend).await?;
+        Ok(self.model_manager.annotate(models))
+    }
+
+    /// Models recommended for this machine's hardware
+    pub async fn recommend_models(&self) -> Result<Vec<ModelCompatibility>> {
+        let models = self.model_manager.get_recommended().await?;
+        Ok(self.model_manager.annotate(models))
+    }
+
+    /// What first-run setup still needs. Ollama is asked again rather than
+    /// trusting the startup check, since the user may have started it since.
+    pub async fn setup_status(&self, executor: &CodeExecutor) -> SetupStatus {
+        let local_model = self.local_model();
+        let installed = self
+            .model_manager
+            .list_available(ModelBackend::Ollama)
+            .await;
+        // Ollama lists untagged pulls as `name:latest`
+        let model_available = installed.as_ref().is_ok_and(|models| {
+            models
+                .iter()
+                .any(|m| m.id == local_model || m.id == format!("{}:latest", local_model))
+        });
+        let recommended_model = self
+            .model_manager
+            .get_recommended()
+            .await
+            .ok()
+            .and_then(|models| models.into_iter().next())
+            .map(|m| m.id);
+
+        SetupStatus {
+            ollama_running: installed.is_ok(),
+            model_available,
+            cloud_configured: self.has_cloud_api(),
+            sandbox_available: executor.is_available().await,
+            local_model,
+            recommended_model,
+        }
+    }
+
+    /// Pull `id`, or the top recommendation for this machine, into Ollama
+    /// and return the pulled model's name
+    pub async fn pull_recommended_model(&self, id: Option<&str>) -> Result<String> {
+        let recommended = self.model_manager.get_recommended().await?;
+        let model = match id {
+            Some(id) => recommended.iter().find(|m| m.id == id).ok_or_else(|| {
+                let names: Vec<&str> = recommended.iter().map(|m| m.id.as_str()).collect();
+                anyhow!(
+                    "'{}' is not recommended for this machine; choose one of: {}",
+                    id,
+                    names.join(", ")
+                )
+            })?,
+            None => recommended
+                .first()
+                .ok_or_else(|| anyhow!("No model is recommended for this machine"))?,
+        };
+
+        self.model_manager.download(model).await?;
+        Ok(model.id.clone())
+    }
+
+    /// Time `runs` generations of [`BENCHMARK_PROMPT`] on the local model
+    /// and, if configured, the cloud one. Nothing is stored; cancelling the
+    /// router stops the benchmark with [`Error::Cancelled`].
+    pub async fn benchmark(&self, runs: usize) -> Result<BenchmarkReport> {
+        use crate::ipc::LlmProvider;
+
+        let mut results = Vec::new();
+        if self.is_local_available() {
+            let model = self.local_model();
+            results.push(
+                self.benchmark_backend(LlmProvider::Local, model, runs)
+                    .await?,
+            );
+        }
+        if self.has_cloud_api() {
+            let model = self.config().cloud_model.clone();
+            results.push(
+                self.benchmark_backend(LlmProvider::Cloud, model, runs)
+                    .await?,
+            );
+        }
+        if results.is_empty() {
+            return Err(anyhow!("No model is available to benchmark"));
+        }
+
+        Ok(BenchmarkReport {
+            hardware: self.model_manager.hardware().clone(),
+            results,
+        })
+    }
+
+    async fn benchmark_backend(
+        &self,
+        provider: crate::ipc::LlmProvider,
+        model: String,
+        runs: usize,
+    ) -> Result<BackendBenchmark> {
+        let mut completed = Vec::new();
+        let mut error = None;
+        for _ in 0..runs.max(1) {
+            match self.benchmark_run(provider).await {
+                Ok(run) => completed.push(run),
+                Err(e) if cancelled(&e) => return Err(e),
+                Err(e) => {
+                    error = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+
+        let n = completed.len().max(1) as u32;
+        let first_token: Duration = completed.iter().map(|r| r.first_token).sum();
+        let total: Duration = completed.iter().map(|r| r.total).sum();
+        let tokens: usize = completed.iter().map(|r| r.tokens).sum();
+        let tokens_per_sec = if total.is_zero() {
+            0.0
+        } else {
+            tokens as f64 / total.as_secs_f64()
+        };
+        info!(?provider, model = %model, runs = completed.len(), "Benchmarked");
+
+        Ok(BackendBenchmark {
+            provider,
+            model,
+            runs: completed.len(),
+            first_token_ms: (first_token / n).as_millis() as u64,
+            total_ms: (total / n).as_millis() as u64,
+            tokens_per_sec,
+            error,
+        })
+    }
+
+    async fn benchmark_run(&self, provider: crate::ipc::LlmProvider) -> Result<BenchmarkRun> {
+        let start = Instant::now();
+        let mut stream: Pin<Box<dyn Stream<Item = Result<String>> + Send>> = match provider {
+            crate::ipc::LlmProvider::Cloud => {
+                Box::pin(self.cloud_generate_stream(BENCHMARK_PROMPT).await?)
+            }
+            _ => Box::pin(self.local_generate_stream(BENCHMARK_PROMPT).await?),
+        };
+
+        let mut first_token = None;
+        let mut text = String::new();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            if first_token.is_none() && !chunk.is_empty() {
+                first_token = Some(start.elapsed());
+            }
+            text.push_str(&chunk);
+        }
+        // A cancelled local stream just ends
+        if self.cancel.is_cancelled() {
+            return Err(Error::Cancelled.into());
+        }
+
+        let total = start.elapsed();
+        Ok(BenchmarkRun {
+            first_token: first_token.unwrap_or(total),
+            total,
+            tokens: text.chars().count().div_ceil(4),
+        })
+    }
+
+    /// Switch the local model to an installed Ollama model.
+    ///
+    /// Fails without switching if the model isn't installed or the hardware
+    /// can't run it.
+    pub async fn switch_model(&self, id: &str) -> Result<CompatibilityResult> {
+        let models = self
+            .model_manager
+            .list_available(ModelBackend::Ollama)
+            .await?;
+        let model = models
+            .iter()
+            .find(|m| m.id == id)
+            .ok_or_else(|| anyhow!("Model '{}' is not installed in Ollama", id))?;
+
+        let compatibility = self.model_manager.check_compatibility(model);
+        if let CompatibilityResult::Incompatible { reason } = &compatibility {
+            return Err(anyhow!("Model incompatible with hardware: {}", reason));
+        }
+
+        if let Ok(mut active) = self.local_model.write() {
+            *active = id.to_string();
+        }
+        info!(model = id, "Switched local model");
+
+        Ok(compatibility)
+    }
+}
+
+/// Default preamble for the terse, tool-driven prompts
+const TERSE_OS_PREAMBLE: &str =
+    "You are Mycel OS. You ARE the operating system. TERSE responses only.";
+
+/// Agentic-loop instructions for the `evolve_os_*` meta-tools
+const EVOLUTION_RULES: &str = "EVOLUTION RULES:
+- If the user asks for a capability you don't have, USE 'evolve_os_add_capability' to write a new MCP server.
+- You can write servers in JavaScript (Node.js) or Python.
+- Always provide complete, production-ready code for new servers.
+- You can also publish these to the global registry using 'near_publish_capability'.
+
+";
+
+/// Same call failing the same way this many times in a row stops retries
+const MAX_IDENTICAL_TOOL_ERRORS: u32 = 2;
+
+/// Spots an agentic tool loop that has stopped making progress
+///
+/// Small models often call the same tool with the same arguments every
+/// iteration; this lets the loop bail out after the first repeat instead of
+/// burning the whole iteration budget.
+#[derive(Debug, Default)]
+struct ToolLoopGuard {
+    /// Sorted call signatures from the previous iteration
+    last_calls: Option<Vec<String>>,
+    /// Last error and how many times in a row it occurred, per call signature
+    errors: std::collections::HashMap<String, (String, u32)>,
+}
+
+impl ToolLoopGuard {
+    fn signature(call: &mcp::ToolCall) -> String {
+        McpManager::cache_key(&call.name, &call.arguments)
+    }
+
+    /// Record an iteration's calls; true if they match the previous iteration's
+    fn is_repeat(&mut self, calls: &[mcp::ToolCall]) -> bool {
+        let mut signatures: Vec<String> = calls.iter().map(Self::signature).collect();
+        signatures.sort();
+        let repeat = self.last_calls.as_ref() == Some(&signatures);
+        self.last_calls = Some(signatures);
+        repeat
+    }
+
+    /// Record a failed call
+    fn record_error(&mut self, call: &mcp::ToolCall, error: &str) {
+        let entry = self
+            .errors
+            .entry(Self::signature(call))
+            .or_insert_with(|| (error.to_string(), 0));
+        if entry.0 == error {
+            entry.1 += 1;
+        } else {
+            *entry = (error.to_string(), 1);
+        }
+    }
+
+    /// Whether a call has failed identically often enough to stop retrying it
+    fn is_failing(&self, call: &mcp::ToolCall) -> bool {
+        self.errors
+            .get(&Self::signature(call))
+            .map(|(_, count)| *count >= MAX_IDENTICAL_TOOL_ERRORS)
+            .unwrap_or(false)
     }
 }
 
+/// Rough characters per token, for budgeting prompts without a tokenizer
+const CHARS_PER_TOKEN: usize = 4;
+
+/// The newest tool step is never cut below this, even over budget
+const MIN_STEP_CHARS: usize = 1000;
+
+/// Prompt for the agentic tool loop: a pinned head (preamble, tools and the
+/// user's question) followed by one step per iteration.
+///
+/// Once the prompt outgrows the budget the oldest steps are dropped and
+/// replaced by a note naming the tools they called, so a long run never
+/// pushes the user's question out of the model's context window.
+struct LoopPrompt {
+    head: String,
+    /// Tools called in each step, and the step's text
+    steps: std::collections::VecDeque<(Vec<String>, String)>,
+    dropped_steps: usize,
+    dropped_tools: Vec<String>,
+    budget_chars: usize,
+}
+
+impl LoopPrompt {
+    fn new(head: String, max_tokens: u32) -> Self {
+        Self {
+            head,
+            steps: std::collections::VecDeque::new(),
+            dropped_steps: 0,
+            dropped_tools: Vec::new(),
+            budget_chars: max_tokens as usize * CHARS_PER_TOKEN,
+        }
+    }
+
+    fn push(&mut self, tools: Vec<String>, step: String) {
+        self.steps.push_back((tools, step));
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        while self.steps.len() > 1 && self.len() > self.budget_chars {
+            let Some((tools, _)) = self.steps.pop_front() else {
+                break;
+            };
+            self.dropped_steps += 1;
+            for tool in tools {
+                if !self.dropped_tools.contains(&tool) {
+                    self.dropped_tools.push(tool);
+                }
+            }
+        }
+
+        // A single oversized step keeps its start; tool output leads with the essentials
+        let excess = self.len().saturating_sub(self.budget_chars);
+        if let Some((_, step)) = self.steps.back_mut().filter(|_| excess > 0) {
+            let mut cut = step.len().saturating_sub(excess).max(MIN_STEP_CHARS);
+            if cut < step.len() {
+                while !step.is_char_boundary(cut) {
+                    cut -= 1;
+                }
+                step.truncate(cut);
+                step.push_str("\n[truncated]");
+            }
+        }
+    }
+
+    fn omitted_note(&self) -> String {
+        if self.dropped_steps == 0 {
+            return String::new();
+        }
+        format!(
+            "\n\n[{} earlier tool step(s) omitted to fit the context window; they called: {}]",
+            self.dropped_steps,
+            self.dropped_tools.join(", ")
+        )
+    }
+
+    fn len(&self) -> usize {
+        self.head.len()
+            + self.omitted_note().len()
+            + self.steps.iter().map(|(_, s)| s.len()).sum::<usize>()
+    }
+
+    fn render(&self) -> String {
+        let mut prompt = self.head.clone();
+        prompt.push_str(&self.omitted_note());
+        for (_, step) in &self.steps {
+            prompt.push_str(step);
+        }
+        prompt
+    }
+}
+
+/// Text chunks from an Ollama streaming body, one per read.
+///
+/// Ollama sends one JSON object per line, and a line may be split across
+/// reads. The stream ends with an `Err` if a line carries an `error` (e.g.
+/// the context overflowed mid-response) or the body ends before a line with
+/// `done: true`, so partial output is never mistaken for a full answer.
+fn ollama_stream<S, B, E>(body: S) -> impl Stream<Item = Result<String>>
+where
+    S: Stream<Item = std::result::Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    let body = Box::pin(body.fuse());
+    futures::stream::unfold(
+        (body, Vec::new(), false),
+        |(mut body, mut pending, finished)| async move {
+            if finished {
+                return None;
+            }
+            loop {
+                let at_end = match body.next().await {
+                    Some(Ok(bytes)) => {
+                        pending.extend_from_slice(bytes.as_ref());
+                        false
+                    }
+                    Some(Err(e)) => {
+                        let err = anyhow!("Stream error: {}", e);
+                        return Some((Err(err), (body, pending, true)));
+                    }
+                    None => true,
+                };
+
+                // Only whole lines, unless nothing more is coming
+                let cut = if at_end {
+                    pending.len()
+                } else {
+                    pending
+                        .iter()
+                        .rposition(|&b| b == b'\n')
+                        .map_or(0, |i| i + 1)
+                };
+                let lines: Vec<u8> = pending.drain(..cut).collect();
+
+                let mut text = String::new();
+                let mut done = false;
+                for line in String::from_utf8_lossy(&lines).lines() {
+                    let Ok(chunk) = serde_json::from_str::<OllamaStreamResponse>(line) else {
+                        continue;
+                    };
+                    if let Some(err) = chunk.error {
+                        let err = anyhow!("Ollama error: {}", err);
+                        return Some((Err(err), (body, pending, true)));
+                    }
+                    text.push_str(chunk.response.as_deref().unwrap_or_default());
+                    if chunk.done {
+                        done = true;
+                        break;
+                    }
+                }
+
+                if done {
+                    return Some((Ok(text), (body, pending, true)));
+                }
+                if !text.is_empty() {
+                    return Some((Ok(text), (body, pending, false)));
+                }
+                if at_end {
+                    let err = anyhow!("Ollama stream ended before the response was complete");
+                    return Some((Err(err), (body, pending, true)));
+                }
+            }
+        },
+    )
+}
+
+/// Name deadline overruns explicitly so callers (and the cloud fallback log)
+/// see why a backend failed
+fn request_error(err: reqwest::Error, backend: &str, timeout: Duration) -> anyhow::Error {
+    if err.is_timeout() {
+        anyhow!("{} timed out after {}s", backend, timeout.as_secs())
+    } else {
+        err.into()
+    }
+}
+
+/// Earlier conversation turns from the context, formatted for a prompt
+fn history_section(context: &Context) -> String {
+    /// Keep long turns from crowding out the rest of the prompt
+    const MAX_TURN_CHARS: usize = 500;
+
+    if context.conversation_history.is_empty() {
+        return String::new();
+    }
+
+    let clip = |text: &str| -> String {
+        if text.chars().count() > MAX_TURN_CHARS {
+            let clipped: String = text.chars().take(MAX_TURN_CHARS).collect();
+            format!("{}...", clipped)
+        } else {
+            text.to_string()
+        }
+    };
+
+    let mut section = String::from("Relevant earlier conversation:\n");
+    for turn in &context.conversation_history {
+        section.push_str(&format!(
+            "User: {}\nAssistant: {}\n",
+            clip(&turn.user),
+            clip(&turn.assistant)
+        ));
+    }
+    section.push('\n');
+    section
+}
+
+/// Files the user works with most, formatted for a prompt
+fn files_section(context: &Context) -> String {
+    if context.frequently_used.is_empty() {
+        return String::new();
+    }
+    format!(
+        "Frequently used files: {}\n",
+        context.frequently_used.join(", ")
+    )
+}
+
+#[derive(Deserialize)]
+struct OllamaEmbeddingResponse {
+    #[serde(default)]
+    embedding: Vec<f32>,
+}
+
 // Request/Response types for Ollama
 #[derive(Serialize)]
 struct OllamaRequest {
     model: String,
     prompt: String,
     stream: bool,
+    /// How long the model stays loaded afterwards; Ollama's default if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<serde_json::Value>,
 }
 
 #[derive(Deserialize)]
@@ -1117,10 +2744,977 @@ pub struct UiSpec {
     pub data_bindings: Vec<String>,
 }
 
+/// Model stand-ins, shared with tests in other modules
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+
+    /// Ollama stand-in answering each `/api/generate` with the next of
+    /// `replies`; returns its URL and the prompts it was sent
+    pub(crate) async fn fake_ollama(replies: Vec<String>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let prompts = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&prompts);
+        let replies = Mutex::new(replies.into_iter());
+        let url = fake_json_server(move |request| {
+            let prompt = request["prompt"].as_str().unwrap_or_default();
+            seen.lock().unwrap().push(prompt.to_string());
+            serde_json::json!({
+                "response": replies.lock().unwrap().next().unwrap_or_default(),
+                "done": true,
+            })
+        })
+        .await;
+        (url, prompts)
+    }
+
+    /// HTTP server answering each JSON request body with `respond(body)`;
+    /// returns its URL
+    pub(crate) async fn fake_json_server(
+        respond: impl Fn(serde_json::Value) -> serde_json::Value + Send + 'static,
+    ) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                // Headers, then as much body as Content-Length announces
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                let body = loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break Vec::new();
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    let Some(end) = text.find("\r\n\r\n") else {
+                        continue;
+                    };
+                    let length = text[..end]
+                        .lines()
+                        .find_map(|l| {
+                            let l = l.to_lowercase();
+                            l.strip_prefix("content-length:")?.trim().parse().ok()
+                        })
+                        .unwrap_or(0);
+                    if request.len() >= end + 4 + length {
+                        break request[end + 4..end + 4 + length].to_vec();
+                    }
+                };
+
+                let request: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+                let reply = respond(request).to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    reply.len(),
+                    reply
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    /// Router that generates with the fake Ollama at `ollama_url`
+    pub(crate) async fn local_router(ollama_url: String) -> AiRouter {
+        let config = MycelConfig {
+            ollama_url,
+            openrouter_api_key: String::new(),
+            ..Default::default()
+        };
+        let router = AiRouter::cloud_only(&config, broadcast::channel(16).0)
+            .await
+            .unwrap();
+        router.local_available.store(true, Ordering::Relaxed);
+        router
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::testing::fake_ollama;
     use super::*;
 
+    #[tokio::test]
+    async fn test_refresh_local_availability() {
+        let ollama = super::testing::fake_json_server(|_| serde_json::json!({"models": []})).await;
+        let config = MycelConfig {
+            ollama_url: "http://127.0.0.1:9".to_string(),
+            openrouter_api_key: String::new(),
+            ..Default::default()
+        };
+        let router = AiRouter::cloud_only(&config, broadcast::channel(1).0)
+            .await
+            .unwrap();
+        assert!(!router.refresh_local_availability().await);
+        assert!(!router.has_backend());
+
+        // Ollama comes up later, at the configured address
+        router.apply_config(&MycelConfig {
+            ollama_url: ollama,
+            ..config
+        });
+        assert!(router.refresh_local_availability().await);
+        assert!(router.is_local_available() && router.has_backend());
+    }
+
+    #[tokio::test]
+    async fn test_custom_system_prompt() {
+        let config = MycelConfig {
+            system_prompt: Some("You are Sage, a patient tutor for {user} in {cwd}.".to_string()),
+            ..Default::default()
+        };
+        let (tx, _) = broadcast::channel(1);
+        let router = AiRouter::cloud_only(&config, tx).await.unwrap();
+        let context = Context {
+            session_id: "test".to_string(),
+            working_directory: "/home/ada/notes".to_string(),
+            recent_files: vec![],
+            conversation_history: vec![],
+            timestamp: chrono::Utc::now(),
+            user_name: Some("Ada".to_string()),
+            user_preferences: std::collections::HashMap::new(),
+            frequently_used: vec![],
+            pending_command: None,
+            pending_tool_call: None,
+        };
+
+        let prompt = router.build_basic_prompt("explain inodes", &context);
+        assert!(prompt.starts_with("You are Sage, a patient tutor for Ada in /home/ada/notes."));
+        assert!(!prompt.contains("Mycel OS"));
+        assert!(prompt.contains("User: explain inodes"));
+
+        router.apply_config(&MycelConfig {
+            persona_name: Some("Jarvis".to_string()),
+            ..Default::default()
+        });
+        let prompt = router.build_basic_prompt("hi", &context);
+        assert!(prompt.starts_with("You are Jarvis, an AI assistant."));
+    }
+
+    #[tokio::test]
+    async fn test_preferences_appear_in_prompt() {
+        let dir = std::env::temp_dir().join(format!("mycel-prefs-{}", uuid::Uuid::new_v4()));
+        let config = MycelConfig {
+            context_path: dir.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let contexts = crate::context::ContextManager::new(&config).await.unwrap();
+        contexts
+            .set_user_preference("language", "always use python")
+            .await
+            .unwrap();
+        contexts
+            .set_user_preference("editor", "helix")
+            .await
+            .unwrap();
+        let context = contexts.get_context("s").await.unwrap();
+
+        let (tx, _) = broadcast::channel(1);
+        let router = AiRouter::cloud_only(&config, tx).await.unwrap();
+        let prompt = router.build_basic_prompt("sort a csv", &context);
+        assert!(
+            prompt.contains("User preferences:\n- editor: helix\n- language: always use python\n"),
+            "{}",
+            prompt
+        );
+
+        // Only the configured keys are included
+        router.apply_config(&MycelConfig {
+            prompt_preference_keys: vec!["language".to_string()],
+            ..config
+        });
+        let prompt = router.build_basic_prompt("sort a csv", &context);
+        assert!(prompt.contains("- language: always use python"));
+        assert!(!prompt.contains("helix"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_tool_loop_stops_repeated_calls() {
+        // A model that answers every iteration with the same call, and with
+        // text once told to stop
+        let call = r#"<tool_call>{"name": "system_info", "arguments": {}}</tool_call>"#;
+        let (url, prompts) = fake_ollama(vec![
+            call.to_string(),
+            call.to_string(),
+            "All checked.".to_string(),
+        ])
+        .await;
+        let router = testing::local_router(url).await;
+
+        let dir = mcp::testing::temp_dir("mycel-repeat");
+        let server = mcp::testing::write_counting_server(&dir);
+        let mcp_config = crate::config::McpConfig {
+            servers: vec![server.clone()],
+            ..Default::default()
+        };
+        let manager = mcp::testing::TestManager::start(&dir, mcp_config, &[server]).await;
+
+        let context = Context {
+            session_id: "test".to_string(),
+            working_directory: "/tmp".to_string(),
+            recent_files: vec![],
+            conversation_history: vec![],
+            timestamp: chrono::Utc::now(),
+            user_name: None,
+            user_preferences: std::collections::HashMap::new(),
+            frequently_used: vec![],
+            pending_command: None,
+            pending_tool_call: None,
+        };
+        let reply = router
+            .process_with_tools_loop(
+                "check the system",
+                &context,
+                &manager,
+                10,
+                crate::ipc::LlmProvider::Auto,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(reply, ToolsReply::Text(text) if text == "All checked."));
+
+        // The repeat ran no tool and was answered by a forced final prompt,
+        // well inside the iteration budget
+        let prompts = prompts.lock().unwrap().clone();
+        assert_eq!(prompts.len(), 3);
+        assert!(prompts[2].contains("You are repeating the same tool calls"));
+        assert_eq!(prompts[2].matches("hits=").count(), 1);
+    }
+
+    #[test]
+    fn test_tool_loop_guard_allows_new_arguments() {
+        let call = |path: &str| {
+            mcp::parse_tool_calls(&format!(
+                r#"<tool_call>{{"name": "list_dir", "arguments": {{"path": "{}"}}}}</tool_call>"#,
+                path
+            ))
+            .tool_calls
+        };
+
+        // Different arguments are progress, not a repeat
+        let mut guard = ToolLoopGuard::default();
+        assert!(!guard.is_repeat(&call("/tmp")));
+        assert!(!guard.is_repeat(&call("/home")));
+        assert!(guard.is_repeat(&call("/home")));
+    }
+
+    #[test]
+    fn test_tool_loop_guard_stops_identical_errors() {
+        let call = mcp::parse_tool_calls(
+            r#"<tool_call>{"name": "read_file", "arguments": {"path": "/nope"}}</tool_call>"#,
+        )
+        .tool_calls
+        .remove(0);
+
+        let mut guard = ToolLoopGuard::default();
+        guard.record_error(&call, "not found");
+        assert!(!guard.is_failing(&call));
+        guard.record_error(&call, "permission denied");
+        assert!(!guard.is_failing(&call));
+        guard.record_error(&call, "permission denied");
+        assert!(guard.is_failing(&call));
+    }
+
+    #[test]
+    fn test_loop_prompt_keeps_question_within_budget() {
+        let head = "You are Mycel.\n\nuser: which directory is using the most disk?\n\nReply:";
+        let mut prompt = LoopPrompt::new(head.to_string(), 1024);
+
+        for i in 0..50 {
+            let tool = if i % 2 == 0 { "du" } else { "find" };
+            let output = format!("result {} {}", i, "x".repeat(600));
+            prompt.push(
+                vec![tool.to_string()],
+                format!("\n\nAssistant: step {}\n\nTool results:\n{}", i, output),
+            );
+        }
+
+        let rendered = prompt.render();
+        assert!(rendered.starts_with(head));
+        assert!(rendered.len() <= 1024 * CHARS_PER_TOKEN);
+        assert!(rendered.contains("result 49"));
+        assert!(!rendered.contains("result 0 "));
+        assert!(rendered.contains("omitted to fit the context window; they called: du, find"));
+
+        // A single step too big for the budget is cut, not dropped
+        let mut prompt = LoopPrompt::new(head.to_string(), 512);
+        prompt.push(vec!["cat".to_string()], "y".repeat(5000));
+        let rendered = prompt.render();
+        assert!(rendered.starts_with(head));
+        assert!(rendered.ends_with("[truncated]"));
+        assert!(rendered.len() < 512 * CHARS_PER_TOKEN + 20);
+    }
+
+    #[test]
+    fn test_cloud_circuit_breaker() {
+        let start = Instant::now();
+        let cooldown = Duration::from_secs(60);
+        let mut circuit = CircuitState::new(3, cooldown);
+
+        // Stays closed below the threshold
+        circuit.record(false, start);
+        circuit.record(false, start);
+        assert!(circuit.allow(start));
+
+        // Opens on the third consecutive failure
+        circuit.record(false, start);
+        assert!(!circuit.allow(start));
+        assert!(!circuit.allow(start + Duration::from_secs(59)));
+
+        // After the cooldown exactly one probe goes out
+        let later = start + cooldown;
+        assert!(circuit.allow(later));
+        assert!(!circuit.allow(later));
+
+        // A failed probe re-opens it
+        circuit.record(false, later);
+        assert!(!circuit.allow(later + Duration::from_secs(1)));
+
+        // A successful probe closes it
+        let much_later = later + cooldown;
+        assert!(circuit.allow(much_later));
+        circuit.record(true, much_later);
+        assert!(circuit.allow(much_later));
+        assert!(circuit.allow(much_later));
+    }
+
+    #[test]
+    fn test_dropped_probe_frees_its_slot() {
+        let start = Instant::now();
+        let cooldown = Duration::from_secs(60);
+        let circuit = Arc::new(Mutex::new(CircuitState::new(1, cooldown)));
+        circuit.lock().unwrap().record(false, start);
+
+        // The probe's future is dropped before it records an outcome
+        let later = start + cooldown;
+        let probe = CircuitCall::admit(&circuit, later).unwrap();
+        assert!(CircuitCall::admit(&circuit, later).is_none());
+        drop(probe);
+
+        // The next call may probe again, and its outcome closes the circuit
+        let probe = CircuitCall::admit(&circuit, later).unwrap();
+        probe.record(true, later);
+        assert!(circuit.lock().unwrap().opened_at.is_none());
+        assert!(CircuitCall::admit(&circuit, later).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_local_request_times_out() {
+        // An endpoint that accepts connections and never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                held.push(socket);
+            }
+        });
+
+        let config = MycelConfig {
+            ollama_url: format!("http://{}", addr),
+            local_timeout_secs: 1,
+            ..MycelConfig::default()
+        };
+        let (tx, _) = broadcast::channel(4);
+        let router = AiRouter::cloud_only(&config, tx).await.unwrap();
+
+        let started = Instant::now();
+        let err = router.local_generate("hello").await.unwrap_err();
+        assert_eq!(err.to_string(), "local model timed out after 1s");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_generation_returns_promptly() {
+        // An endpoint that accepts connections and never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                held.push(socket);
+            }
+        });
+
+        let config = MycelConfig {
+            ollama_url: format!("http://{}", addr),
+            ..MycelConfig::default()
+        };
+        let (tx, _) = broadcast::channel(4);
+        let cancel = CancellationToken::new();
+        let router = AiRouter::cloud_only(&config, tx)
+            .await
+            .unwrap()
+            .with_cancellation(cancel.clone());
+
+        let trigger = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            trigger.cancel();
+        });
+
+        let started = Instant::now();
+        let err = router.local_generate("hello").await.unwrap_err();
+        assert!(cancelled(&err), "unexpected error: {}", err);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // Already cancelled: fails straight away instead of escalating
+        let err = router.smart_generate("hello", false).await.unwrap_err();
+        assert!(cancelled(&err), "unexpected error: {}", err);
+    }
+
+    #[tokio::test]
+    async fn test_ollama_stream_surfaces_errors() {
+        async fn collect(reads: &[&str]) -> Vec<Result<String>> {
+            let reads: Vec<std::io::Result<Vec<u8>>> =
+                reads.iter().map(|r| Ok(r.as_bytes().to_vec())).collect();
+            ollama_stream(futures::stream::iter(reads)).collect().await
+        }
+
+        // A line split across reads is reassembled
+        let chunks = collect(&[
+            "{\"response\":\"Hel\",\"done\":false}\n{\"respo",
+            "nse\":\"lo\",\"done\":false}\n{\"response\":\"\",\"done\":true}\n",
+        ])
+        .await;
+        let text: String = chunks.into_iter().map(Result::unwrap).collect();
+        assert_eq!(text, "Hello");
+
+        // An error after partial output fails the stream
+        let chunks = collect(&[
+            "{\"response\":\"Hel\",\"done\":false}\n",
+            "{\"error\":\"context window exceeded\"}\n",
+        ])
+        .await;
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].as_ref().unwrap(), "Hel");
+        let err = chunks[1].as_ref().unwrap_err();
+        assert!(
+            err.to_string().contains("context window exceeded"),
+            "{}",
+            err
+        );
+
+        // So does a body that ends without `done: true`
+        let chunks = collect(&["{\"response\":\"Hel\",\"done\":false}\n"]).await;
+        let err = chunks.last().unwrap().as_ref().unwrap_err();
+        assert!(err.to_string().contains("before the response was complete"));
+    }
+
+    #[test]
+    fn test_strip_markdown_preserves_code_blocks() {
+        let answer = "## Reading config\n\nUse **serde**:\n\n```rust\nfn load() -> Config {\n    toml::from_str(&text)?\n}\n```\n\nSee [the docs](https://serde.rs).";
+        assert_eq!(
+            strip_markdown_formatting(answer, true),
+            "Reading config\n\nUse serde:\n\n```rust\nfn load() -> Config {\n    toml::from_str(&text)?\n}\n```\n\nSee the docs."
+        );
+
+        // A lone snippet keeps its fences
+        let snippet = "```json\n{\"debug\": true}\n```";
+        assert_eq!(strip_markdown_formatting(snippet, true), snippet);
+
+        // Without preserve_code the block is unwrapped, as JSON replies need
+        assert_eq!(
+            strip_markdown_formatting(snippet, false),
+            "{\"debug\": true}"
+        );
+    }
+
+    #[test]
+    fn test_markdown_stripper_matches_whole_text() {
+        let answer = "## Reading config\n\nUse **serde**:\n\n```rust\n# [derive]\n```\n\nSee [the docs](https://serde.rs).";
+
+        // Chunks that split a header, a link and a fence
+        let mut stripper = MarkdownStripper::default();
+        let mut streamed = String::new();
+        for chunk in answer.as_bytes().chunks(5) {
+            streamed.push_str(&stripper.push(std::str::from_utf8(chunk).unwrap()));
+        }
+        streamed.push_str(&stripper.finish());
+
+        assert_eq!(streamed, strip_markdown_formatting(answer, true));
+        assert!(streamed.contains("```rust\n# [derive]\n```"));
+    }
+
+    #[tokio::test]
+    async fn test_no_backend_is_reported_clearly() {
+        let config = MycelConfig {
+            openrouter_api_key: String::new(),
+            ..Default::default()
+        };
+        let (tx, _) = broadcast::channel(16);
+        let router = AiRouter::cloud_only(&config, tx).await.unwrap();
+        assert!(!router.has_backend());
+
+        let err = router
+            .generate_with_provider("hello", crate::ipc::LlmProvider::Auto)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::NoBackend));
+        assert_eq!(err.code(), Some("no_backend"));
+        assert!(err.to_string().contains("start Ollama"));
+
+        let err = router
+            .smart_generate_stream("hello", false)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(Error::find(&err), Some(Error::NoBackend)));
+    }
+
+    #[tokio::test]
+    async fn test_cloud_calls_queue_for_a_permit() {
+        let config = MycelConfig {
+            openrouter_api_key: "test-key".to_string(),
+            cloud_max_concurrency: 2,
+            ..Default::default()
+        };
+        let (tx, _) = broadcast::channel(16);
+        let router = AiRouter::cloud_only(&config, tx).await.unwrap();
+
+        // With both slots taken the next call waits instead of going out
+        let first = router.permit(&router.cloud_permits).await.unwrap();
+        let _second = router.permit(&router.cloud_permits).await.unwrap();
+        let queued =
+            tokio::time::timeout(Duration::from_millis(100), router.cloud_generate("hello")).await;
+        assert!(queued.is_err(), "call went out without a permit");
+
+        // A freed slot lets the next one through
+        drop(first);
+        let third = tokio::time::timeout(
+            Duration::from_millis(100),
+            router.permit(&router.cloud_permits),
+        )
+        .await;
+        assert!(third.unwrap().is_ok());
+
+        // Waiting for a slot can be cancelled
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let err = router
+            .with_cancellation(cancel)
+            .cloud_generate("hello")
+            .await
+            .unwrap_err();
+        assert!(cancelled(&err));
+    }
+
+    #[tokio::test]
+    async fn test_forced_cloud_generation_is_logged() {
+        let config = MycelConfig {
+            openrouter_api_key: "test-key".to_string(),
+            cloud_model: "test/cloud-model".to_string(),
+            ..Default::default()
+        };
+        let (tx, _) = broadcast::channel(16);
+        let router = AiRouter::cloud_only(&config, tx).await.unwrap();
+        // An open circuit fails the call at once instead of going out
+        for _ in 0..CLOUD_FAILURE_THRESHOLD {
+            router
+                .cloud_circuit
+                .lock()
+                .unwrap()
+                .record(false, Instant::now());
+        }
+
+        assert!(router.smart_generate("hello", true).await.is_err());
+        let log = router.routing_log(10);
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].source, "cloud");
+        assert_eq!(log[0].model, "test/cloud-model");
+        assert!(!log[0].fell_back);
+        assert!(!log[0].success);
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_is_sent_to_ollama() {
+        let (tx, _) = broadcast::channel(1);
+        let router = AiRouter::cloud_only(&MycelConfig::default(), tx)
+            .await
+            .unwrap();
+        let request = serde_json::to_value(router.ollama_request("hi", false)).unwrap();
+        assert!(request.get("keep_alive").is_none());
+
+        // Numbers go over as seconds (negative: stay loaded), durations as text
+        for (setting, sent) in [
+            ("-1", serde_json::json!(-1)),
+            ("300", serde_json::json!(300)),
+            ("5m", serde_json::json!("5m")),
+            ("1h30m", serde_json::json!("1h30m")),
+        ] {
+            router.apply_config(&MycelConfig {
+                local_keep_alive: Some(setting.to_string()),
+                ..Default::default()
+            });
+            let request = serde_json::to_value(router.ollama_request("hi", true)).unwrap();
+            assert_eq!(request["keep_alive"], sent, "{}", setting);
+        }
+
+        let config = MycelConfig {
+            local_keep_alive: Some("five minutes".to_string()),
+            ..Default::default()
+        };
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("local_keep_alive"));
+    }
+
+    #[tokio::test]
+    async fn test_setup_status_reports_missing_model_and_key() {
+        // Ollama is up but has nothing installed
+        let (url, _) = fake_ollama(vec![String::new()]).await;
+        let code_path = std::env::temp_dir().join(format!("mycel-setup-{}", uuid::Uuid::new_v4()));
+        let config = MycelConfig {
+            ollama_url: url,
+            openrouter_api_key: String::new(),
+            code_path: code_path.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let (tx, _) = broadcast::channel(16);
+        let router = AiRouter::cloud_only(&config, tx).await.unwrap();
+        let executor = CodeExecutor::new(&config).unwrap();
+
+        let status = router.setup_status(&executor).await;
+        assert!(status.ollama_running);
+        assert!(!status.model_available);
+        assert!(!status.cloud_configured);
+        assert_eq!(status.local_model, config.local_model);
+        assert!(status.recommended_model.is_some());
+
+        // Nothing answering at all
+        let config = MycelConfig {
+            ollama_url: "http://127.0.0.1:9".to_string(),
+            openrouter_api_key: "sk-or-test".to_string(),
+            ..config
+        };
+        let router = AiRouter::cloud_only(&config, broadcast::channel(1).0)
+            .await
+            .unwrap();
+        let status = router.setup_status(&executor).await;
+        assert!(!status.ollama_running);
+        assert!(!status.model_available);
+        assert!(status.cloud_configured);
+        let _ = std::fs::remove_dir_all(&code_path);
+    }
+
+    #[tokio::test]
+    async fn test_benchmark_times_local_model() {
+        let reply = "A kernel manages memory, processes and devices.".to_string();
+        let (url, prompts) = fake_ollama(vec![reply.clone(), reply]).await;
+        let config = MycelConfig {
+            ollama_url: url,
+            openrouter_api_key: String::new(),
+            ..Default::default()
+        };
+        let (tx, _) = broadcast::channel(16);
+        let router = AiRouter::cloud_only(&config, tx).await.unwrap();
+        router.local_available.store(true, Ordering::Relaxed);
+
+        let report = router.benchmark(2).await.unwrap();
+        assert_eq!(report.results.len(), 1);
+        let local = &report.results[0];
+        assert_eq!(local.provider, crate::ipc::LlmProvider::Local);
+        assert_eq!(local.runs, 2);
+        assert!(local.error.is_none());
+        assert!(local.tokens_per_sec > 0.0);
+        assert!(local.first_token_ms <= local.total_ms);
+        assert_eq!(*prompts.lock().unwrap(), vec![BENCHMARK_PROMPT; 2]);
+
+        // Once cancelled, nothing more is sent
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let err = router
+            .with_cancellation(cancel)
+            .benchmark(2)
+            .await
+            .unwrap_err();
+        assert!(cancelled(&err));
+        assert_eq!(prompts.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_plan_summary_is_added_to_confirmation() {
+        let reply = "```json\n{\"summary\": \"Deletes the build directory\", \"side_effects\": [\"removes ./build\"]}\n```";
+        let (url, prompts) = fake_ollama(vec![reply.to_string()]).await;
+        let config = MycelConfig {
+            ollama_url: url,
+            openrouter_api_key: String::new(),
+            ..Default::default()
+        };
+        let (tx, _) = broadcast::channel(16);
+        let router = AiRouter::cloud_only(&config, tx).await.unwrap();
+        router.local_available.store(true, Ordering::Relaxed);
+
+        let plan = router.explain_plan("rm -rf ./build").await.unwrap();
+        assert!(prompts.lock().unwrap()[0].contains("rm -rf ./build"));
+        assert_eq!(
+            plan.describe(),
+            "plan: Deletes the build directory\nside effects: removes ./build"
+        );
+
+        let read_only = CodePlan {
+            summary: "Lists files".to_string(),
+            side_effects: Vec::new(),
+        };
+        assert!(read_only.describe().ends_with("side effects: none"));
+    }
+
+    #[tokio::test]
+    async fn test_agentic_loop_takes_several_tool_rounds() {
+        let call = |round: u32| {
+            format!(
+                r#"<tool_call>{{"name": "system_info", "arguments": {{"round": {}}}}}</tool_call>"#,
+                round
+            )
+        };
+        let (url, prompts) =
+            fake_ollama(vec![call(1), call(2), "**Checked twice.**".to_string()]).await;
+
+        let config = MycelConfig {
+            ollama_url: url,
+            ..Default::default()
+        };
+        let (tx, _) = broadcast::channel(16);
+        let router = AiRouter::cloud_only(&config, tx).await.unwrap();
+        router.local_available.store(true, Ordering::Relaxed);
+
+        let dir = mcp::testing::temp_dir("mycel-agentic");
+        let server = mcp::testing::write_counting_server(&dir);
+        let mcp_config = crate::config::McpConfig {
+            servers: vec![server.clone()],
+            ..Default::default()
+        };
+        let manager = mcp::testing::TestManager::start(&dir, mcp_config, &[server]).await;
+
+        let context = Context {
+            session_id: "test".to_string(),
+            working_directory: "/tmp".to_string(),
+            recent_files: vec![],
+            conversation_history: vec![],
+            timestamp: chrono::Utc::now(),
+            user_name: None,
+            user_preferences: std::collections::HashMap::new(),
+            frequently_used: vec![],
+            pending_command: None,
+            pending_tool_call: None,
+        };
+        let reply = router
+            .process_with_tools_loop(
+                "check the system twice",
+                &context,
+                &manager,
+                5,
+                crate::ipc::LlmProvider::Auto,
+            )
+            .await
+            .unwrap();
+
+        assert!(matches!(reply, ToolsReply::Text(text) if text == "Checked twice."));
+        let prompts = prompts.lock().unwrap().clone();
+        assert_eq!(prompts.len(), 3);
+        assert!(prompts[1].contains("hits=1"));
+        assert!(prompts[2].contains("hits=2"));
+    }
+
+    #[tokio::test]
+    async fn test_tools_stream_honours_tool_call_format() {
+        let (url, prompts) = fake_ollama(vec![
+            r#"{"tool_calls": [{"function": {"name": "system_info", "arguments": {}}}]}"#
+                .to_string(),
+            "All good.".to_string(),
+        ])
+        .await;
+
+        let mut config = MycelConfig {
+            ollama_url: url,
+            ..Default::default()
+        };
+        config.mcp.tool_call_format = mcp::ToolCallFormatHint::OpenAiToolCalls;
+        let (tx, _) = broadcast::channel(16);
+        let router = AiRouter::cloud_only(&config, tx).await.unwrap();
+        router.local_available.store(true, Ordering::Relaxed);
+
+        let dir = mcp::testing::temp_dir("mycel-stream");
+        let server = mcp::testing::write_counting_server(&dir);
+        let mcp_config = crate::config::McpConfig {
+            servers: vec![server.clone()],
+            ..Default::default()
+        };
+        let manager = mcp::testing::TestManager::start(&dir, mcp_config, &[server]).await;
+
+        let context = Context {
+            session_id: "test".to_string(),
+            working_directory: "/tmp".to_string(),
+            recent_files: vec![],
+            conversation_history: vec![],
+            timestamp: chrono::Utc::now(),
+            user_name: None,
+            user_preferences: std::collections::HashMap::new(),
+            frequently_used: vec![],
+            pending_command: None,
+            pending_tool_call: None,
+        };
+        let stream = router
+            .process_with_tools_stream("how is the system?", &context, &manager)
+            .await
+            .unwrap();
+        let chunks: Vec<String> = stream.map(|chunk| chunk.unwrap()).collect().await;
+
+        // The JSON call is run, not shown, and the answer uses its result
+        assert_eq!(chunks.concat(), "All good.");
+        assert!(prompts.lock().unwrap()[1].contains("hits=1"));
+    }
+
+    #[tokio::test]
+    async fn test_tools_stream_holds_calls_for_confirmation() {
+        let (url, prompts) = fake_ollama(vec![
+            r#"<tool_call>{"name": "system_info", "arguments": {}}</tool_call>"#.to_string(),
+            "Waiting on you.".to_string(),
+        ])
+        .await;
+
+        let config = MycelConfig {
+            ollama_url: url,
+            ..Default::default()
+        };
+        let (tx, _) = broadcast::channel(16);
+        let router = AiRouter::cloud_only(&config, tx).await.unwrap();
+        router.local_available.store(true, Ordering::Relaxed);
+
+        let dir = mcp::testing::temp_dir("mycel-stream");
+        let mut server = mcp::testing::write_counting_server(&dir);
+        server.requires_confirmation = vec!["system_info".to_string()];
+        let mcp_config = crate::config::McpConfig {
+            servers: vec![server.clone()],
+            ..Default::default()
+        };
+        let manager = mcp::testing::TestManager::start(&dir, mcp_config, &[server]).await;
+
+        let context = Context {
+            session_id: "test".to_string(),
+            working_directory: "/tmp".to_string(),
+            recent_files: vec![],
+            conversation_history: vec![],
+            timestamp: chrono::Utc::now(),
+            user_name: None,
+            user_preferences: std::collections::HashMap::new(),
+            frequently_used: vec![],
+            pending_command: None,
+            pending_tool_call: None,
+        };
+        let stream = router
+            .process_with_tools_stream("how is the system?", &context, &manager)
+            .await
+            .unwrap();
+        let chunks: Vec<String> = stream.map(|chunk| chunk.unwrap()).collect().await;
+        let output = chunks.concat();
+
+        // Nothing ran; the call waits under the id the user was shown
+        let pending = manager.pending_confirmations().await;
+        assert_eq!(pending.len(), 1);
+        assert!(output.contains(&pending[0].id), "{}", output);
+        assert!(output.ends_with("Waiting on you."), "{}", output);
+        assert!(!prompts.lock().unwrap()[1].contains("hits="));
+
+        let result = manager
+            .resolve_confirmation(&pending[0].id, true)
+            .await
+            .unwrap();
+        assert!(result.contains("hits=1"), "{}", result);
+    }
+
+    #[tokio::test]
+    async fn test_agentic_loop_streams_steps_in_order() {
+        let (url, _) = fake_ollama(vec![
+            r#"Checking packages first. <tool_call>{"name": "system_info", "arguments": {"round": 1}}</tool_call>"#
+                .to_string(),
+            r#"<tool_call>{"name": "system_info", "arguments": {"round": 2}}</tool_call>"#
+                .to_string(),
+            "Done.".to_string(),
+        ])
+        .await;
+
+        let config = MycelConfig {
+            ollama_url: url,
+            ..Default::default()
+        };
+        let (tx, _) = broadcast::channel(16);
+        let router = AiRouter::cloud_only(&config, tx).await.unwrap();
+        router.local_available.store(true, Ordering::Relaxed);
+        let (steps_tx, mut steps_rx) = mpsc::unbounded_channel();
+        let router = router.with_steps(steps_tx);
+
+        let dir = mcp::testing::temp_dir("mycel-steps");
+        let server = mcp::testing::write_counting_server(&dir);
+        let mcp_config = crate::config::McpConfig {
+            servers: vec![server.clone()],
+            ..Default::default()
+        };
+        let manager = mcp::testing::TestManager::start(&dir, mcp_config, &[server]).await;
+
+        let context = Context {
+            session_id: "test".to_string(),
+            working_directory: "/tmp".to_string(),
+            recent_files: vec![],
+            conversation_history: vec![],
+            timestamp: chrono::Utc::now(),
+            user_name: None,
+            user_preferences: std::collections::HashMap::new(),
+            frequently_used: vec![],
+            pending_command: None,
+            pending_tool_call: None,
+        };
+        let reply = router
+            .process_with_tools_loop(
+                "check the system twice",
+                &context,
+                &manager,
+                5,
+                crate::ipc::LlmProvider::Auto,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(reply, ToolsReply::Text(text) if text == "Done."));
+
+        let started = |round: u32| AgentStep::ToolCallStarted {
+            tool: "system_info".to_string(),
+            arguments: std::collections::HashMap::from([(
+                "round".to_string(),
+                serde_json::json!(round),
+            )]),
+        };
+        let result = |hits: u32| AgentStep::ToolResult {
+            tool: "system_info".to_string(),
+            result: format!("Tool 'system_info' result:\nhits={}", hits),
+            success: true,
+        };
+        let mut steps = Vec::new();
+        while let Ok(step) = steps_rx.try_recv() {
+            steps.push(step);
+        }
+        assert_eq!(
+            steps,
+            [
+                AgentStep::Thinking {
+                    text: "Checking packages first.".to_string()
+                },
+                started(1),
+                result(1),
+                started(2),
+                result(2),
+                AgentStep::FinalAnswer {
+                    text: "Done.".to_string()
+                },
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn test_ollama_available() {
         // This test requires Ollama to be running.
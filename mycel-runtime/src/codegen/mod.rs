@@ -8,6 +8,7 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use uuid::Uuid;
 
 /// A generated code artifact
@@ -45,6 +46,8 @@ impl CodeArtifact {
             CodeLanguage::Shell => "sh",
             CodeLanguage::Html => "html",
             CodeLanguage::Css => "css",
+            CodeLanguage::Go => "go",
+            CodeLanguage::Ruby => "rb",
             CodeLanguage::Unknown => "txt",
         };
 
@@ -74,21 +77,42 @@ pub enum CodeLanguage {
     Shell,
     Html,
     Css,
+    Go,
+    Ruby,
     Unknown,
 }
 
 impl CodeLanguage {
-    /// Detect language from code content
+    /// Detect language from code content. This is the only detector: the
+    /// executor runs what it says, so code is classified the same way for
+    /// policy checks, artifacts and execution.
     pub fn detect(code: &str) -> Self {
         let code_lower = code.to_lowercase();
 
         // Check for shebangs first
-        if code.starts_with("#!/usr/bin/env python") || code.starts_with("#!/usr/bin/python") {
+        let shebang = |interpreters: &[&str]| {
+            interpreters.iter().any(|name| {
+                code.starts_with(&format!("#!/usr/bin/env {}", name))
+                    || code.starts_with(&format!("#!/usr/bin/{}", name))
+                    || code.starts_with(&format!("#!/bin/{}", name))
+            })
+        };
+        if shebang(&["python"]) {
             return Self::Python;
         }
-        if code.starts_with("#!/bin/bash") || code.starts_with("#!/bin/sh") {
+        if shebang(&["bash", "sh"]) {
             return Self::Shell;
         }
+        if shebang(&["ruby"]) {
+            return Self::Ruby;
+        }
+        if shebang(&["node"]) {
+            return Self::JavaScript;
+        }
+
+        if code.contains("package main") || code.contains("func main()") {
+            return Self::Go;
+        }
 
         // Check for language-specific patterns
         if code_lower.contains("import ")
@@ -96,6 +120,9 @@ impl CodeLanguage {
         {
             return Self::Python;
         }
+        if code.contains("require '") || has_ruby_puts(code) {
+            return Self::Ruby;
+        }
         if code_lower.contains("fn ") && code_lower.contains("let ") && code_lower.contains("->") {
             return Self::Rust;
         }
@@ -108,9 +135,17 @@ impl CodeLanguage {
         if code_lower.contains("const ")
             || code_lower.contains("function ")
             || code_lower.contains("=>")
+            || code_lower.contains("console.log")
         {
             return Self::JavaScript;
         }
+        // Looser Python hints, once the other languages have had their turn
+        if code_lower.contains("import ")
+            || code_lower.contains("def ")
+            || code_lower.contains("print(")
+        {
+            return Self::Python;
+        }
         if code_lower.contains("<!doctype") || code_lower.contains("<html") {
             return Self::Html;
         }
@@ -124,6 +159,22 @@ impl CodeLanguage {
         Self::Unknown
     }
 
+    /// Language named by a code fence tag such as ```` ```py ````
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        match tag.trim().to_lowercase().as_str() {
+            "python" | "python3" | "py" => Some(Self::Python),
+            "javascript" | "js" | "node" => Some(Self::JavaScript),
+            "typescript" | "ts" => Some(Self::TypeScript),
+            "rust" | "rs" => Some(Self::Rust),
+            "shell" | "sh" | "bash" | "zsh" => Some(Self::Shell),
+            "html" => Some(Self::Html),
+            "css" => Some(Self::Css),
+            "go" | "golang" => Some(Self::Go),
+            "ruby" | "rb" => Some(Self::Ruby),
+            _ => None,
+        }
+    }
+
     /// Get the file extension for this language
     pub fn extension(&self) -> &'static str {
         match self {
@@ -134,6 +185,8 @@ impl CodeLanguage {
             Self::Shell => "sh",
             Self::Html => "html",
             Self::Css => "css",
+            Self::Go => "go",
+            Self::Ruby => "rb",
             Self::Unknown => "txt",
         }
     }
@@ -144,7 +197,246 @@ impl CodeLanguage {
             Self::Python => Some("python3"),
             Self::JavaScript => Some("node"),
             Self::Shell => Some("bash"),
+            Self::Go => Some("go"),
+            Self::Ruby => Some("ruby"),
             _ => None,
         }
     }
 }
+
+/// Whether a line opens with Ruby's `puts` statement. Matching the word at
+/// the start keeps `inputs ` and `outputs ` in Python from counting, and a
+/// Python variable named `puts` is an assignment, not a call.
+fn has_ruby_puts(code: &str) -> bool {
+    code.lines().any(|line| {
+        line.trim_start().strip_prefix("puts").is_some_and(|rest| {
+            rest.starts_with([' ', '\t', '(', '"', '\'']) && !rest.trim_start().starts_with('=')
+        })
+    })
+}
+
+/// Code from the first fenced block in `text`, with the language its tag
+/// names. Prose around the block is dropped; text without a fence is
+/// returned trimmed, and an unclosed fence runs to the end.
+pub fn extract_code_block(text: &str) -> (Option<CodeLanguage>, String) {
+    let mut lines = text.lines();
+    let Some(tag) = lines
+        .by_ref()
+        .find_map(|line| line.trim_start().strip_prefix("```"))
+    else {
+        return (None, text.trim().to_string());
+    };
+
+    let code: Vec<&str> = lines
+        .take_while(|line| !line.trim_start().starts_with("```"))
+        .collect();
+    (CodeLanguage::from_tag(tag), code.join("\n"))
+}
+
+/// Name of the artifact index file inside `code_path`
+const ARTIFACT_INDEX: &str = "artifacts.json";
+
+/// Index entry for a stored artifact (the code itself lives at `saved_path`)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ArtifactRecord {
+    pub id: String,
+    pub language: CodeLanguage,
+    pub description: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub saved_path: Option<PathBuf>,
+    pub executed: bool,
+}
+
+impl From<&CodeArtifact> for ArtifactRecord {
+    fn from(artifact: &CodeArtifact) -> Self {
+        Self {
+            id: artifact.id.clone(),
+            language: artifact.language,
+            description: artifact.description.clone(),
+            created_at: artifact.created_at,
+            saved_path: artifact.saved_path.clone(),
+            executed: artifact.executed,
+        }
+    }
+}
+
+/// Keeps track of generated code artifacts in a JSON index in `code_path`
+#[derive(Debug, Clone)]
+pub struct ArtifactStore {
+    base_path: String,
+    records: Arc<Mutex<Vec<ArtifactRecord>>>,
+}
+
+impl ArtifactStore {
+    /// Open the store, loading an existing index if there is one
+    pub fn open(base_path: &str) -> Result<Self> {
+        let index = PathBuf::from(base_path).join(ARTIFACT_INDEX);
+        let records = if index.exists() {
+            serde_json::from_str(&std::fs::read_to_string(&index)?)?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            base_path: base_path.to_string(),
+            records: Arc::new(Mutex::new(records)),
+        })
+    }
+
+    /// Save the artifact's code (if not saved yet) and add it to the index
+    pub fn add(&self, artifact: &mut CodeArtifact) -> Result<ArtifactRecord> {
+        if artifact.saved_path.is_none() {
+            artifact.save(&self.base_path)?;
+        }
+
+        let record = ArtifactRecord::from(&*artifact);
+        let mut records = self.lock();
+        records.push(record.clone());
+        self.persist(&records)?;
+        Ok(record)
+    }
+
+    /// All artifacts, newest first
+    pub fn list(&self) -> Vec<ArtifactRecord> {
+        let mut records = self.lock().clone();
+        records.sort_by_key(|r| std::cmp::Reverse(r.created_at));
+        records
+    }
+
+    pub fn get(&self, id: &str) -> Option<ArtifactRecord> {
+        self.lock().iter().find(|r| r.id == id).cloned()
+    }
+
+    /// Flag an artifact as executed. Returns false if the id is unknown.
+    pub fn mark_executed(&self, id: &str) -> Result<bool> {
+        let mut records = self.lock();
+        let Some(record) = records.iter_mut().find(|r| r.id == id) else {
+            return Ok(false);
+        };
+        record.executed = true;
+        self.persist(&records)?;
+        Ok(true)
+    }
+
+    /// Read back the code of a stored artifact
+    pub fn read_code(&self, id: &str) -> Result<Option<String>> {
+        match self.get(id).and_then(|r| r.saved_path) {
+            Some(path) => Ok(Some(std::fs::read_to_string(path)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Most recent artifact whose code matches exactly
+    pub fn find_by_code(&self, code: &str) -> Option<ArtifactRecord> {
+        self.list().into_iter().find(|r| {
+            r.saved_path
+                .as_ref()
+                .and_then(|p| std::fs::read_to_string(p).ok())
+                .is_some_and(|saved| saved == code)
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<ArtifactRecord>> {
+        self.records.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn persist(&self, records: &[ArtifactRecord]) -> Result<()> {
+        std::fs::create_dir_all(&self.base_path)?;
+        let index = PathBuf::from(&self.base_path).join(ARTIFACT_INDEX);
+        std::fs::write(index, serde_json::to_string_pretty(records)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store() -> (String, ArtifactStore) {
+        let dir = std::env::temp_dir().join(format!("mycel-artifacts-{}", Uuid::new_v4()));
+        let dir = dir.to_string_lossy().to_string();
+        let store = ArtifactStore::open(&dir).unwrap();
+        (dir, store)
+    }
+
+    #[test]
+    fn test_artifact_add_list_round_trip() {
+        let (dir, store) = temp_store();
+
+        let mut first = CodeArtifact::new(
+            CodeLanguage::Shell,
+            "ls -la".to_string(),
+            "list files".to_string(),
+        );
+        let mut second = CodeArtifact::new(
+            CodeLanguage::Python,
+            "print('hi')".to_string(),
+            "greet".to_string(),
+        );
+        second.created_at = first.created_at + chrono::Duration::seconds(1);
+        store.add(&mut first).unwrap();
+        store.add(&mut second).unwrap();
+
+        // Reopening reads the index back from disk
+        let reopened = ArtifactStore::open(&dir).unwrap();
+        let listed = reopened.list();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].id, second.id);
+        assert_eq!(listed[1].description, "list files");
+        assert_eq!(
+            reopened.read_code(&first.id).unwrap().as_deref(),
+            Some("ls -la")
+        );
+        assert_eq!(
+            reopened.get(&second.id).unwrap().language,
+            CodeLanguage::Python
+        );
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_extract_tagged_block() {
+        let (language, code) = extract_code_block("```python\nprint('hi')\n```");
+        assert_eq!(language, Some(CodeLanguage::Python));
+        assert_eq!(code, "print('hi')");
+
+        let (language, code) = extract_code_block("```\nls -la\n```");
+        assert_eq!(language, None);
+        assert_eq!(code, "ls -la");
+    }
+
+    #[test]
+    fn test_extract_block_ignores_prose_and_later_blocks() {
+        let text = "Here you go:\n```bash\ndf -h\nfree -m\n```\nOr in Python:\n```py\nimport os\n```\nDone.";
+        let (language, code) = extract_code_block(text);
+        assert_eq!(language, Some(CodeLanguage::Shell));
+        assert_eq!(code, "df -h\nfree -m");
+    }
+
+    #[test]
+    fn test_extract_without_fence() {
+        let (language, code) = extract_code_block("  uptime\n");
+        assert_eq!(language, None);
+        assert_eq!(code, "uptime");
+    }
+
+    #[test]
+    fn test_artifact_mark_executed() {
+        let (dir, store) = temp_store();
+
+        let mut artifact =
+            CodeArtifact::new(CodeLanguage::Shell, "uptime".to_string(), String::new());
+        store.add(&mut artifact).unwrap();
+        assert!(!store.get(&artifact.id).unwrap().executed);
+
+        assert!(store.mark_executed(&artifact.id).unwrap());
+        assert!(!store.mark_executed("missing").unwrap());
+        assert_eq!(store.find_by_code("uptime").unwrap().id, artifact.id);
+
+        let reopened = ArtifactStore::open(&dir).unwrap();
+        assert!(reopened.get(&artifact.id).unwrap().executed);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -14,20 +14,22 @@ use serde::{Deserialize, Serialize};
 use tracing::{debug, info, warn};
 
 use super::patterns::{Pattern, PatternId};
+use crate::egress::{EgressPolicy, HttpClient};
 
 /// Bittensor client for Mycel OS
 #[derive(Clone)]
 pub struct BittensorClient {
     config: BittensorConfig,
-    http_client: reqwest::Client,
+    http_client: HttpClient,
     wallet: Option<BittensorWallet>,
 }
 
 impl BittensorClient {
     pub async fn new(config: &BittensorConfig) -> Result<Self> {
-        let http_client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(60))
-            .build()?;
+        let http_client = HttpClient::new(
+            reqwest::Client::builder().timeout(std::time::Duration::from_secs(60)),
+            config.egress.clone(),
+        )?;
 
         // Load wallet if configured
         let wallet = if let Some(ref wallet_path) = config.wallet_path {
@@ -67,7 +69,7 @@ impl BittensorClient {
             .get(format!(
                 "{}/metagraph/{}",
                 self.config.api_url, self.config.subnet_uid
-            ))
+            ))?
             .send()
             .await?
             .json::<Metagraph>()
@@ -211,7 +213,7 @@ impl BittensorClient {
             .get(format!(
                 "{}/models/{}/weights",
                 self.config.api_url, model_id
-            ))
+            ))?
             .send()
             .await?
             .json::<ModelWeights>()
@@ -229,7 +231,7 @@ impl BittensorClient {
 
         let response = self
             .http_client
-            .get(format!("{}/balance/{}", self.config.api_url, wallet.hotkey))
+            .get(format!("{}/balance/{}", self.config.api_url, wallet.hotkey))?
             .send()
             .await?
             .json::<BalanceResponse>()
@@ -250,7 +252,7 @@ impl BittensorClient {
             .get(format!(
                 "{}/rewards/{}/{}",
                 self.config.api_url, self.config.subnet_uid, wallet.hotkey
-            ))
+            ))?
             .send()
             .await?
             .json::<RewardsSummary>()
@@ -293,8 +295,14 @@ impl BittensorClient {
                 timeout: timeout_secs,
             };
 
-            match client
-                .post(&url)
+            let builder = match client.post(&url) {
+                Ok(builder) => builder,
+                Err(e) => {
+                    debug!("Miner {} skipped: {}", neuron.uid, e);
+                    continue;
+                }
+            };
+            match builder
                 .timeout(std::time::Duration::from_secs(timeout_secs))
                 .json(&request)
                 .send()
@@ -332,8 +340,10 @@ impl BittensorClient {
             let axon = validator.axon_info.as_ref().unwrap();
             let url = format!("http://{}:{}/validator", axon.ip, axon.port);
 
-            // Fire and forget
-            let _ = self.http_client.post(&url).json(&message).send().await;
+            // Fire and forget; validators egress rules forbid are skipped
+            if let Ok(request) = self.http_client.post(&url) {
+                let _ = request.json(&message).send().await;
+            }
         }
 
         Ok(())
@@ -427,6 +437,10 @@ pub struct BittensorConfig {
 
     /// Verify connection on start
     pub verify_on_start: bool,
+
+    /// Hosts the client may reach
+    #[serde(default)]
+    pub egress: EgressPolicy,
 }
 
 impl Default for BittensorConfig {
@@ -442,6 +456,7 @@ impl Default for BittensorConfig {
             max_inference_tokens: 2048,
             min_semantic_similarity: 0.7,
             verify_on_start: true,
+            egress: EgressPolicy::default(),
         }
     }
 }
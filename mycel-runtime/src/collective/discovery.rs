@@ -13,7 +13,10 @@ use tracing::debug;
 
 use super::bittensor::BittensorClient;
 use super::near::NearClient;
-use super::patterns::{Pattern, PatternSource, PatternStore, RankedPattern};
+use super::patterns::{
+    context_domain, sort_ranked, Pattern, PatternMatcher, PatternSource, PatternStore,
+    RankedPattern,
+};
 use crate::context::Context;
 
 /// Pattern discovery across local and network sources
@@ -87,7 +90,10 @@ impl PatternDiscovery {
         let deduped = self.deduplicate(all_patterns);
 
         // Rank patterns
-        let ranked = self.rank_patterns(deduped, context);
+        let ranked = {
+            let store = self.local_store.read().await;
+            self.rank_patterns(&store, deduped, context)
+        };
 
         // Cache results
         self.cache.set(&cache_key, ranked.clone()).await;
@@ -98,16 +104,13 @@ impl PatternDiscovery {
     async fn search_local(&self, context: &Context) -> Result<Vec<DiscoveredPattern>> {
         let store = self.local_store.read().await;
 
-        // Infer domain from context
-        let domain = self.infer_domain(context);
-
-        // Search by domain and recent activity
-        let patterns = store.search(Some(&domain), "");
-
-        Ok(patterns
+        // Every local pattern is a candidate; ranking drops the ones that don't apply
+        Ok(store
+            .rank(&PatternMatcher::new(context, ""))
             .into_iter()
+            .map(|r| r.pattern)
             .map(|p| DiscoveredPattern {
-                pattern: p.clone(),
+                pattern: p,
                 source: PatternSource::Local,
                 source_score: 1.0, // Local patterns get a boost
                 fetch_time_ms: 0,
@@ -120,7 +123,7 @@ impl PatternDiscovery {
         near: &NearClient,
         context: &Context,
     ) -> Result<Vec<DiscoveredPattern>> {
-        let domain = self.infer_domain(context);
+        let domain = context_domain(context, "");
 
         let query = super::near::PatternQuery {
             domain: Some(domain),
@@ -227,125 +230,22 @@ impl PatternDiscovery {
         seen.into_values().collect()
     }
 
+    /// Score patterns against the context, using local outcome history
+    /// where the store has it
     fn rank_patterns(
         &self,
+        store: &PatternStore,
         patterns: Vec<DiscoveredPattern>,
         context: &Context,
     ) -> Vec<RankedPattern> {
+        let matcher = PatternMatcher::new(context, "");
         let mut ranked: Vec<RankedPattern> = patterns
-            .into_iter()
-            .map(|dp| {
-                let relevance_score = self.compute_relevance(&dp.pattern, context);
-
-                // Combined score factors:
-                // - Relevance to context (40%)
-                // - Quality/reputation score (30%)
-                // - Source preference (local > network) (15%)
-                // - Success rate (15%)
-
-                let source_bonus = match dp.source {
-                    PatternSource::Local => 0.15,
-                    PatternSource::Network => 0.0,
-                    PatternSource::Builtin => 0.1,
-                };
-
-                let combined_score = relevance_score * 0.4
-                    + (dp.pattern.quality_score as f64) * 0.3
-                    + source_bonus
-                    + (dp.pattern.success_rate as f64) * 0.15;
-
-                RankedPattern {
-                    pattern: dp.pattern,
-                    relevance_score,
-                    combined_score,
-                }
-            })
-            .collect();
-
-        // Sort by combined score descending
-        ranked.sort_by(|a, b| {
-            b.combined_score
-                .partial_cmp(&a.combined_score)
-                .unwrap_or(std::cmp::Ordering::Equal)
-        });
-
-        ranked
-    }
-
-    fn compute_relevance(&self, pattern: &Pattern, context: &Context) -> f64 {
-        let mut score = 0.0;
-
-        // Domain match
-        let context_domain = self.infer_domain(context);
-        if pattern.domain == context_domain {
-            score += 0.4;
-        }
-
-        // Keyword overlap between trigger and recent activity
-        let trigger_lower = pattern.trigger.to_lowercase();
-        let trigger_words: std::collections::HashSet<_> =
-            trigger_lower.split_whitespace().collect();
-
-        let context_words: std::collections::HashSet<_> = context
-            .recent_files
             .iter()
-            .flat_map(|f| {
-                f.to_lowercase()
-                    .split('/')
-                    .map(String::from)
-                    .collect::<Vec<_>>()
-            })
+            .filter_map(|dp| store.rank_pattern(&dp.pattern, &matcher))
             .collect();
 
-        let overlap = trigger_words
-            .iter()
-            .filter(|w| context_words.iter().any(|cw| cw.contains(*w)))
-            .count();
-
-        if !trigger_words.is_empty() {
-            score += (overlap as f64 / trigger_words.len() as f64) * 0.3;
-        }
-
-        // User preference match
-        if let Some(pref_domain) = context.user_preferences.get("preferred_domain") {
-            if &pattern.domain == pref_domain {
-                score += 0.2;
-            }
-        }
-
-        // Recency bonus for recently successful patterns
-        // (Would need access to usage history)
-        score += 0.1; // Default
-
-        score
-    }
-
-    fn infer_domain(&self, context: &Context) -> String {
-        // Infer domain from working directory and recent files
-        let wd = context.working_directory.to_lowercase();
-
-        if wd.contains("code") || wd.contains("src") || wd.contains("dev") {
-            return "coding".to_string();
-        }
-        if wd.contains("doc") || wd.contains("writing") {
-            return "writing".to_string();
-        }
-        if wd.contains("data") || wd.contains("analytics") {
-            return "analysis".to_string();
-        }
-
-        // Check recent files
-        for file in &context.recent_files {
-            let ext = file.split('.').last().unwrap_or("");
-            match ext {
-                "py" | "rs" | "js" | "ts" => return "coding".to_string(),
-                "md" | "txt" | "doc" => return "writing".to_string(),
-                "csv" | "json" | "xlsx" => return "analysis".to_string(),
-                _ => {}
-            }
-        }
-
-        "general".to_string()
+        sort_ranked(&mut ranked);
+        ranked
     }
 
     fn compute_context_embedding(&self, _context: &Context) -> Result<Vec<f32>> {
@@ -362,6 +262,9 @@ impl PatternDiscovery {
         let mut hasher = DefaultHasher::new();
         context.working_directory.hash(&mut hasher);
         context.recent_files.hash(&mut hasher);
+        if let Some(turn) = context.conversation_history.last() {
+            turn.user.hash(&mut hasher);
+        }
 
         format!("{:x}", hasher.finish())
     }
@@ -73,6 +73,23 @@ impl CollectiveIntelligence {
         self.discovery.discover(context).await
     }
 
+    /// Find a pattern whose trigger matches `input`, if one ranks well enough
+    /// to answer it directly
+    pub async fn match_pattern(
+        &self,
+        input: &str,
+        context: &Context,
+    ) -> Result<Option<patterns::RankedPattern>> {
+        let trigger = privacy::generalize_query(input.trim());
+        let matcher = patterns::PatternMatcher::new(context, input);
+        let ranked = self.pattern_store.read().await.rank(&matcher);
+
+        Ok(ranked.into_iter().find(|r| {
+            r.combined_score >= self.config.min_match_score
+                && r.pattern.trigger.trim().eq_ignore_ascii_case(&trigger)
+        }))
+    }
+
     /// Apply a pattern to the current context
     pub async fn apply_pattern(
         &self,
@@ -82,6 +99,7 @@ impl CollectiveIntelligence {
         // Record usage attempt
         let mut store = self.pattern_store.write().await;
         store.record_usage(&pattern.id);
+        store.save().await?;
 
         // If pattern is from network, handle payment
         if let Some(ref near) = self.near_client {
@@ -159,6 +177,7 @@ impl CollectiveIntelligence {
         {
             let mut store = self.pattern_store.write().await;
             store.record_outcome(pattern_id, success, rating);
+            store.save().await?;
         }
 
         // Report to NEAR
@@ -236,13 +255,31 @@ pub struct CollectiveConfig {
 
     pub federated_learning_enabled: bool,
     pub privacy_config: privacy::PrivacyConfig,
+
+    /// Minimum ranking score for a pattern to answer an input directly
+    pub min_match_score: f64,
 }
 
 impl CollectiveConfig {
-    pub fn from_mycel_config(_config: &MycelConfig) -> Self {
-        // Extract collective config from main config
-        // For now, use defaults
-        Self::default()
+    pub fn from_mycel_config(config: &MycelConfig) -> Self {
+        let mut collective = Self {
+            pattern_store_path: std::path::Path::new(&config.context_path)
+                .join("patterns")
+                .to_string_lossy()
+                .to_string(),
+            ..Self::default()
+        };
+
+        // A linked NEAR account enables the on-chain registry and reputation
+        if let Some(ref account) = config.near_account {
+            collective.near_enabled = true;
+            collective.near_config = near::NearConfig::for_account(account);
+        }
+        let egress = crate::egress::EgressPolicy::from_config(config);
+        collective.near_config.egress = egress.clone();
+        collective.bittensor_config.egress = egress;
+
+        collective
     }
 }
 
@@ -258,6 +295,7 @@ impl Default for CollectiveConfig {
             min_share_quality: 0.8,
             federated_learning_enabled: false,
             privacy_config: privacy::PrivacyConfig::default(),
+            min_match_score: 0.5,
         }
     }
 }
@@ -274,6 +312,28 @@ pub struct Interaction {
     pub user_rating: Option<u8>,
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_from_mycel_config() {
+        let mut config = MycelConfig {
+            context_path: "/tmp/mycel-ctx".to_string(),
+            ..MycelConfig::default()
+        };
+
+        let collective = CollectiveConfig::from_mycel_config(&config);
+        assert!(!collective.near_enabled);
+        assert_eq!(collective.pattern_store_path, "/tmp/mycel-ctx/patterns");
+
+        config.near_account = Some("alice.testnet".to_string());
+        let collective = CollectiveConfig::from_mycel_config(&config);
+        assert!(collective.near_enabled);
+        assert_eq!(collective.near_config.account_id, "alice.testnet");
+    }
+}
+
 /// Stats about collective intelligence participation
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CollectiveStats {
@@ -4,30 +4,40 @@
 //! - Pattern registry
 //! - Reputation system
 //!
-//! Note: This module is scaffolded - blockchain integration is deferred.
+//! Calls go to NEAR JSON-RPC directly: views use `query`, and contract calls
+//! are signed locally with the account's ed25519 key and sent with
+//! `broadcast_tx_commit`.
 #![allow(dead_code)]
 #![allow(clippy::needless_borrow)]
 #![allow(clippy::unnecessary_lazy_evaluations)]
 //! - Micropayments
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use base64::Engine;
+use ed25519_dalek::{Signer, SigningKey};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tracing::{debug, info, warn};
 
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
 use super::patterns::{Pattern, PatternId};
+use crate::egress::{EgressPolicy, HttpClient};
+
+/// Gas attached to contract calls (30 TGas)
+const CALL_GAS: u64 = 30_000_000_000_000;
 
 /// NEAR client for Clay OS
 #[derive(Clone)]
 pub struct NearClient {
     config: NearConfig,
-    // In production, this would be the actual NEAR SDK client
-    // For now, we'll use HTTP calls to a NEAR RPC
-    http_client: reqwest::Client,
+    http_client: HttpClient,
+    /// Signing key for `account_id`; without one only view calls work
+    signer: Option<Arc<NearSigner>>,
+    /// Local record of registered patterns, used for queries
     mock_ledger: Arc<RwLock<MockLedger>>,
 }
 
@@ -39,14 +49,24 @@ struct MockLedger {
 
 impl NearClient {
     pub async fn new(config: &NearConfig) -> Result<Self> {
-        let http_client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(30))
-            .build()?;
+        let http_client = HttpClient::new(
+            reqwest::Client::builder().timeout(std::time::Duration::from_secs(30)),
+            config.egress.clone(),
+        )?;
+
+        let signer = NearSigner::load(config)?.map(Arc::new);
+        if signer.is_none() {
+            warn!(
+                "No NEAR key found for '{}' - contract calls are disabled, views still work",
+                config.account_id
+            );
+        }
 
         // Verify connection to NEAR
         let client = Self {
             config: config.clone(),
             http_client,
+            signer,
             mock_ledger: Arc::new(RwLock::new(MockLedger::default())),
         };
 
@@ -66,7 +86,7 @@ impl NearClient {
     async fn verify_connection(&self) -> Result<()> {
         let response = self
             .http_client
-            .post(&self.config.rpc_url)
+            .post(&self.config.rpc_url)?
             .json(&serde_json::json!({
                 "jsonrpc": "2.0",
                 "id": "clay-verify",
@@ -102,7 +122,7 @@ impl NearClient {
         // Compute pattern hash
         let pattern_hash = self.compute_pattern_hash(pattern);
 
-        // Update mock ledger
+        // Record locally so query_patterns can find it
         {
             let mut ledger = self.mock_ledger.write().await;
             let entry = PatternEntry {
@@ -118,8 +138,15 @@ impl NearClient {
             ledger.patterns.insert(pattern.id.clone(), entry);
         }
 
-        // Call registry contract (simulate)
-        let _result = self
+        if self.signer.is_none() {
+            warn!(
+                "Pattern {} registered locally only (no NEAR key)",
+                pattern.id
+            );
+            return Ok(pattern.id.clone());
+        }
+
+        let result = self
             .call_contract(
                 &self.config.registry_contract,
                 "register_pattern",
@@ -127,15 +154,18 @@ impl NearClient {
                     "pattern_hash": pattern_hash,
                     "metadata_cid": metadata_cid,
                     "domain": pattern.domain,
-                    "price_per_use": pattern.suggested_price.unwrap_or(0),
+                    // u128 doesn't fit in a JSON number; NEAR contracts take it as a string
+                    "price_per_use": pattern.suggested_price.unwrap_or(0).to_string(),
                 }),
                 Some(self.config.registration_deposit),
             )
-            .await
-            .unwrap_or_else(|_| serde_json::Value::Null); // Ignore error for mock
+            .await?;
 
-        // Extract pattern ID from result or use original
-        let pattern_id = pattern.id.clone();
+        // Use the ID assigned by the registry if it returned one
+        let pattern_id = result
+            .as_str()
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| pattern.id.clone());
 
         info!("Pattern registered with ID: {}", pattern_id);
         Ok(pattern_id)
@@ -232,7 +262,7 @@ impl NearClient {
     pub async fn get_balance(&self) -> Result<u128> {
         let response = self
             .http_client
-            .post(&self.config.rpc_url)
+            .post(&self.config.rpc_url)?
             .json(&serde_json::json!({
                 "jsonrpc": "2.0",
                 "id": "clay-balance",
@@ -265,27 +295,46 @@ impl NearClient {
         args: serde_json::Value,
         deposit: Option<u128>,
     ) -> Result<serde_json::Value> {
-        // In production, this would sign and send a transaction
-        // For now, we'll simulate the call
+        let args = serde_json::to_vec(&args)?;
+        let signed_tx = self
+            .sign_transaction(contract_id, method, &args, deposit.unwrap_or(0))
+            .await?;
 
-        let args_base64 =
-            base64::engine::general_purpose::STANDARD.encode(serde_json::to_string(&args)?);
+        let response = self
+            .rpc("broadcast_tx_commit", serde_json::json!([signed_tx]))
+            .await?;
+
+        let status = &response["status"];
+        if let Some(failure) = status.get("Failure") {
+            return Err(anyhow!(
+                "NEAR call {}.{} failed: {}",
+                contract_id,
+                method,
+                failure
+            ));
+        }
+
+        let value = status["SuccessValue"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Unexpected NEAR transaction status: {}", status))?;
+        if value.is_empty() {
+            return Ok(serde_json::Value::Null);
+        }
 
+        let bytes = base64::engine::general_purpose::STANDARD.decode(value)?;
+        Ok(serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null))
+    }
+
+    /// Send a JSON-RPC request and return its `result`
+    async fn rpc(&self, method: &str, params: serde_json::Value) -> Result<serde_json::Value> {
         let response = self
             .http_client
-            .post(&self.config.rpc_url)
+            .post(&self.config.rpc_url)?
             .json(&serde_json::json!({
                 "jsonrpc": "2.0",
-                "id": "clay-call",
-                "method": "broadcast_tx_commit",
-                "params": {
-                    "signed_tx": self.sign_transaction(
-                        contract_id,
-                        method,
-                        &args_base64,
-                        deposit.unwrap_or(0),
-                    ).await?
-                }
+                "id": "clay-rpc",
+                "method": method,
+                "params": params,
             }))
             .send()
             .await?
@@ -293,10 +342,14 @@ impl NearClient {
             .await?;
 
         if let Some(error) = response.get("error") {
-            return Err(anyhow!("NEAR call failed: {}", error));
+            return Err(anyhow!("NEAR {} failed: {}", method, error));
+        }
+        // Query errors are reported inside the result
+        if let Some(error) = response["result"].get("error") {
+            return Err(anyhow!("NEAR {} failed: {}", method, error));
         }
 
-        Ok(response["result"]["status"]["SuccessValue"].clone())
+        Ok(response["result"].clone())
     }
 
     async fn view_contract(
@@ -310,7 +363,7 @@ impl NearClient {
 
         let response = self
             .http_client
-            .post(&self.config.rpc_url)
+            .post(&self.config.rpc_url)?
             .json(&serde_json::json!({
                 "jsonrpc": "2.0",
                 "id": "clay-view",
@@ -348,17 +401,49 @@ impl NearClient {
         Ok(result)
     }
 
+    /// Build and sign a function-call transaction, returning it base64-encoded
     async fn sign_transaction(
         &self,
-        _contract_id: &str,
-        _method: &str,
-        _args: &str,
-        _deposit: u128,
+        contract_id: &str,
+        method: &str,
+        args: &[u8],
+        deposit: u128,
     ) -> Result<String> {
-        // In production, this would use the NEAR SDK to sign
-        // For now, return a placeholder
-        warn!("Transaction signing not implemented - using mock");
-        Ok("mock_signed_tx".to_string())
+        let signer = self.signer.as_ref().ok_or_else(|| {
+            anyhow!(
+                "No NEAR signing key for '{}'; set private_key or add ~/.near-credentials",
+                self.config.account_id
+            )
+        })?;
+
+        // Nonce and a recent block hash come from the access key
+        let access_key = self
+            .rpc(
+                "query",
+                serde_json::json!({
+                    "request_type": "view_access_key",
+                    "finality": "final",
+                    "account_id": signer.account_id,
+                    "public_key": signer.public_key_string(),
+                }),
+            )
+            .await?;
+
+        let nonce = access_key["nonce"]
+            .as_u64()
+            .ok_or_else(|| anyhow!("Access key response has no nonce"))?;
+        let block_hash: [u8; 32] = bs58::decode(
+            access_key["block_hash"]
+                .as_str()
+                .ok_or_else(|| anyhow!("Access key response has no block_hash"))?,
+        )
+        .into_vec()?
+        .try_into()
+        .map_err(|_| anyhow!("Invalid block hash length"))?;
+
+        let signed =
+            signer.sign_function_call(contract_id, nonce + 1, block_hash, method, args, deposit);
+        Ok(base64::engine::general_purpose::STANDARD.encode(signed))
     }
 
     async fn upload_to_storage(&self, pattern: &Pattern) -> Result<String> {
@@ -375,6 +460,121 @@ impl NearClient {
     }
 }
 
+/// ed25519 key used to sign transactions for a NEAR account
+struct NearSigner {
+    account_id: String,
+    key: SigningKey,
+}
+
+impl NearSigner {
+    /// Load the key from `config.private_key`, or from the near-cli
+    /// credentials file for the account (`~/.near-credentials/<network>/<account>.json`)
+    fn load(config: &NearConfig) -> Result<Option<Self>> {
+        if config.account_id.is_empty() {
+            return Ok(None);
+        }
+
+        if let Some(ref secret) = config.private_key {
+            return Self::from_secret(&config.account_id, secret).map(Some);
+        }
+
+        let Some(path) = credentials_path(&config.network_id, &config.account_id) else {
+            return Ok(None);
+        };
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let credentials: NearCredentials = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid NEAR credentials in {}", path.display()))?;
+        Self::from_secret(&config.account_id, &credentials.private_key).map(Some)
+    }
+
+    /// Parse an `ed25519:<base58>` secret (64-byte keypair or 32-byte seed)
+    fn from_secret(account_id: &str, secret: &str) -> Result<Self> {
+        let encoded = secret
+            .strip_prefix("ed25519:")
+            .ok_or_else(|| anyhow!("Only ed25519 NEAR keys are supported"))?;
+        let bytes = bs58::decode(encoded).into_vec()?;
+
+        let seed: [u8; 32] = match bytes.len() {
+            32 | 64 => bytes[..32].try_into()?,
+            n => return Err(anyhow!("Invalid NEAR secret key length: {}", n)),
+        };
+
+        Ok(Self {
+            account_id: account_id.to_string(),
+            key: SigningKey::from_bytes(&seed),
+        })
+    }
+
+    fn public_key_string(&self) -> String {
+        format!(
+            "ed25519:{}",
+            bs58::encode(self.key.verifying_key().as_bytes()).into_string()
+        )
+    }
+
+    /// Borsh-encode a single-action FunctionCall transaction and sign it,
+    /// returning the encoded `SignedTransaction`
+    fn sign_function_call(
+        &self,
+        receiver_id: &str,
+        nonce: u64,
+        block_hash: [u8; 32],
+        method: &str,
+        args: &[u8],
+        deposit: u128,
+    ) -> Vec<u8> {
+        let mut tx = Vec::new();
+        borsh_string(&mut tx, &self.account_id);
+        tx.push(0); // KeyType::ED25519
+        tx.extend_from_slice(self.key.verifying_key().as_bytes());
+        tx.extend_from_slice(&nonce.to_le_bytes());
+        borsh_string(&mut tx, receiver_id);
+        tx.extend_from_slice(&block_hash);
+        tx.extend_from_slice(&1u32.to_le_bytes()); // one action
+        tx.push(2); // Action::FunctionCall
+        borsh_string(&mut tx, method);
+        borsh_bytes(&mut tx, args);
+        tx.extend_from_slice(&CALL_GAS.to_le_bytes());
+        tx.extend_from_slice(&deposit.to_le_bytes());
+
+        let hash = Sha256::digest(&tx);
+        let signature = self.key.sign(&hash);
+
+        let mut signed = tx;
+        signed.push(0); // KeyType::ED25519
+        signed.extend_from_slice(&signature.to_bytes());
+        signed
+    }
+}
+
+fn borsh_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn borsh_string(out: &mut Vec<u8>, s: &str) {
+    borsh_bytes(out, s.as_bytes());
+}
+
+fn credentials_path(network_id: &str, account_id: &str) -> Option<PathBuf> {
+    dirs::home_dir().map(|home| {
+        home.join(".near-credentials")
+            .join(network_id)
+            .join(format!("{}.json", account_id))
+    })
+}
+
+/// near-cli credentials file format
+#[derive(Deserialize)]
+struct NearCredentials {
+    private_key: String,
+}
+
 /// NEAR configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NearConfig {
@@ -401,6 +601,25 @@ pub struct NearConfig {
 
     /// Verify connection on startup
     pub verify_on_start: bool,
+
+    /// Hosts the RPC client may reach
+    #[serde(default)]
+    pub egress: EgressPolicy,
+}
+
+impl NearConfig {
+    /// Configuration for an account, picking the network from its suffix
+    pub fn for_account(account_id: &str) -> Self {
+        let mut config = Self::default();
+        if account_id.ends_with(".near") {
+            config.network_id = "mainnet".to_string();
+            config.rpc_url = "https://rpc.mainnet.near.org".to_string();
+            config.registry_contract = "patterns.clay.near".to_string();
+            config.reputation_contract = "reputation.clay.near".to_string();
+        }
+        config.account_id = account_id.to_string();
+        config
+    }
 }
 
 impl Default for NearConfig {
@@ -414,6 +633,7 @@ impl Default for NearConfig {
             reputation_contract: "reputation.clay.testnet".to_string(),
             registration_deposit: 100_000_000_000_000_000_000_000, // 0.1 NEAR
             verify_on_start: true,
+            egress: EgressPolicy::default(),
         }
     }
 }
@@ -457,3 +677,95 @@ pub struct ReputationScore {
     pub total_rating: u64,
     pub composite: f64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signature, Verifier};
+
+    fn test_signer() -> NearSigner {
+        let secret = format!("ed25519:{}", bs58::encode([7u8; 32]).into_string());
+        NearSigner::from_secret("alice.testnet", &secret).unwrap()
+    }
+
+    #[test]
+    fn test_signed_transaction_layout() {
+        let signer = test_signer();
+        let signed =
+            signer.sign_function_call("patterns.clay.testnet", 5, [1u8; 32], "ping", b"{}", 0);
+
+        // Transaction is followed by a key-type byte and a 64-byte signature
+        let (tx, sig) = signed.split_at(signed.len() - 65);
+        assert_eq!(sig[0], 0);
+        assert_eq!(&tx[..4], &13u32.to_le_bytes());
+        assert_eq!(&tx[4..17], b"alice.testnet");
+
+        let signature = Signature::from_slice(&sig[1..]).unwrap();
+        let hash = Sha256::digest(tx);
+        assert!(signer.key.verifying_key().verify(&hash, &signature).is_ok());
+    }
+
+    #[test]
+    fn test_for_account_picks_network() {
+        assert_eq!(NearConfig::for_account("alice.near").network_id, "mainnet");
+        assert_eq!(
+            NearConfig::for_account("alice.near").rpc_url,
+            "https://rpc.mainnet.near.org"
+        );
+        assert_eq!(NearConfig::for_account("bob.testnet").network_id, "testnet");
+    }
+
+    #[test]
+    fn test_rejects_non_ed25519_key() {
+        assert!(NearSigner::from_secret("alice.testnet", "secp256k1:abc").is_err());
+    }
+
+    /// Runs against testnet only when MYCEL_NEAR_TESTNET_ACCOUNT is set
+    #[tokio::test]
+    async fn test_testnet_reputation() {
+        let Ok(account) = std::env::var("MYCEL_NEAR_TESTNET_ACCOUNT") else {
+            return;
+        };
+        let mut config = NearConfig::for_account(&account);
+        config.private_key = std::env::var("MYCEL_NEAR_TESTNET_KEY").ok();
+
+        let client = NearClient::new(&config).await.unwrap();
+        client.verify_connection().await.unwrap();
+        client.get_balance().await.unwrap();
+        let reputation = client.get_reputation().await.unwrap();
+        assert!((0.0..=1.0).contains(&reputation));
+    }
+
+    #[tokio::test]
+    async fn test_get_reputation_reads_view_result() {
+        // Stand-in RPC serving the reputation contract's view for alice
+        let rpc_url = crate::ai::testing::fake_json_server(|request| {
+            let params = &request["params"];
+            assert_eq!(params["account_id"], "reputation.clay.testnet");
+            assert_eq!(params["method_name"], "get_reputation");
+            let args = base64::engine::general_purpose::STANDARD
+                .decode(params["args_base64"].as_str().unwrap())
+                .unwrap();
+            let args: serde_json::Value = serde_json::from_slice(&args).unwrap();
+            assert_eq!(args["account"], "alice.testnet");
+
+            let score = serde_json::to_vec(&ReputationScore {
+                successful_uses: 8,
+                failed_uses: 2,
+                total_rating: 36,
+                composite: 0.75,
+            })
+            .unwrap();
+            serde_json::json!({ "jsonrpc": "2.0", "result": { "result": score } })
+        })
+        .await;
+
+        let config = NearConfig {
+            rpc_url,
+            verify_on_start: false,
+            ..NearConfig::for_account("alice.testnet")
+        };
+        let client = NearClient::new(&config).await.unwrap();
+        assert_eq!(client.get_reputation().await.unwrap(), 0.75);
+    }
+}
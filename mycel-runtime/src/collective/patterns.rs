@@ -11,11 +11,23 @@
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 
 use crate::context::Context;
 
+/// Weight of context relevance in a pattern's combined score
+const RELEVANCE_WEIGHT: f64 = 0.6;
+/// Weight of historical success in a pattern's combined score
+const SUCCESS_WEIGHT: f64 = 0.4;
+/// How many of the latest user inputs contribute keywords
+const RECENT_INPUTS: usize = 3;
+/// Words too common to say anything about what a pattern is for
+const STOP_WORDS: &[&str] = &[
+    "the", "and", "for", "with", "how", "what", "can", "you", "this", "that", "please", "from",
+    "into", "are", "was", "have", "does", "about", "some", "all", "its", "your", "our", "want",
+];
+
 /// Unique identifier for a pattern
 pub type PatternId = String;
 
@@ -229,6 +241,162 @@ pub struct RankedPattern {
     pub combined_score: f64,
 }
 
+/// Sort by combined score, best first (ties by id so results are stable)
+pub fn sort_ranked(ranked: &mut [RankedPattern]) {
+    ranked.sort_by(|a, b| {
+        b.combined_score
+            .partial_cmp(&a.combined_score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.pattern.id.cmp(&b.pattern.id))
+    });
+}
+
+/// Scores patterns against one context: its working directory, the keywords
+/// of recent inputs, and the category (domain) the user seems to be working in
+pub struct PatternMatcher {
+    keywords: HashSet<String>,
+    domain: String,
+    locations: Vec<String>,
+}
+
+impl PatternMatcher {
+    /// Build a matcher for `context`; `query` is the input being answered,
+    /// if it isn't in the history yet
+    pub fn new(context: &Context, query: &str) -> Self {
+        let recent_inputs: Vec<&str> = context
+            .conversation_history
+            .iter()
+            .rev()
+            .take(RECENT_INPUTS)
+            .map(|turn| turn.user.as_str())
+            .collect();
+
+        let mut words = keywords_of(query);
+        for input in &recent_inputs {
+            words.extend(keywords_of(input));
+        }
+        words.extend(keywords(&context.working_directory));
+        for file in &context.recent_files {
+            words.extend(keywords(file));
+        }
+
+        let latest = recent_inputs.first().copied().unwrap_or("");
+        let domain = context_domain(context, &format!("{} {}", query, latest));
+
+        let mut locations = vec![context.working_directory.to_lowercase()];
+        locations.extend(context.recent_files.iter().map(|f| f.to_lowercase()));
+
+        Self {
+            keywords: words,
+            domain,
+            locations,
+        }
+    }
+
+    /// Domain the context was classified as
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// How well `pattern` fits the context (0.0 - 1.0), or `None` if it
+    /// shares neither keywords nor a category with it
+    pub fn relevance(&self, pattern: &Pattern) -> Option<f64> {
+        let trigger = keywords(&pattern.trigger);
+        let overlap = if trigger.is_empty() {
+            0.0
+        } else {
+            trigger.intersection(&self.keywords).count() as f64 / trigger.len() as f64
+        };
+        let same_domain = pattern.domain == self.domain;
+
+        if overlap == 0.0 && !same_domain {
+            return None;
+        }
+
+        // A pattern without requirements applies anywhere
+        let requirements = if pattern.context_requirements.is_empty() {
+            1.0
+        } else {
+            let met = pattern
+                .context_requirements
+                .iter()
+                .filter(|req| {
+                    let req = req.to_lowercase();
+                    self.locations.iter().any(|loc| loc.contains(&req))
+                        || self.keywords.contains(&req)
+                })
+                .count();
+            met as f64 / pattern.context_requirements.len() as f64
+        };
+
+        let domain = if same_domain { 1.0 } else { 0.0 };
+        Some(overlap * 0.6 + domain * 0.25 + requirements * 0.15)
+    }
+}
+
+/// Combine relevance and historical success into a ranking score
+pub fn combined_score(relevance: f64, success: f64) -> f64 {
+    relevance * RELEVANCE_WEIGHT + success * SUCCESS_WEIGHT
+}
+
+/// Domain of the current task: from what the user asked when that is
+/// telling, otherwise from the working directory and recent files
+pub fn context_domain(context: &Context, input: &str) -> String {
+    let from_input = super::privacy::infer_domain(input);
+    if from_input != "general" {
+        return from_input;
+    }
+
+    let wd = context.working_directory.to_lowercase();
+    if wd.contains("code") || wd.contains("src") || wd.contains("dev") {
+        return "coding".to_string();
+    }
+    if wd.contains("doc") || wd.contains("writing") {
+        return "writing".to_string();
+    }
+    if wd.contains("data") || wd.contains("analytics") {
+        return "analysis".to_string();
+    }
+
+    for file in &context.recent_files {
+        let ext = file.rsplit('.').next().unwrap_or("");
+        match ext {
+            "py" | "rs" | "js" | "ts" => return "coding".to_string(),
+            "md" | "txt" | "doc" => return "writing".to_string(),
+            "csv" | "json" | "xlsx" => return "analysis".to_string(),
+            _ => {}
+        }
+    }
+
+    "general".to_string()
+}
+
+/// Lowercased content words of `text`, skipping `[PLACEHOLDER]`s left by
+/// query generalization
+fn keywords(text: &str) -> HashSet<String> {
+    let mut visible = String::with_capacity(text.len());
+    let mut in_placeholder = false;
+    for c in text.chars() {
+        match c {
+            '[' => in_placeholder = true,
+            ']' => in_placeholder = false,
+            _ if !in_placeholder => visible.push(c),
+            _ => {}
+        }
+    }
+
+    visible
+        .split(|c: char| !c.is_alphanumeric())
+        .map(str::to_lowercase)
+        .filter(|w| w.chars().count() >= 3 && !STOP_WORDS.contains(&w.as_str()))
+        .collect()
+}
+
+/// Keywords of a raw user input, generalized the way triggers are
+fn keywords_of(input: &str) -> HashSet<String> {
+    keywords(&super::privacy::generalize_query(input))
+}
+
 /// Local storage for patterns
 pub struct PatternStore {
     patterns: HashMap<PatternId, Pattern>,
@@ -326,6 +494,46 @@ impl PatternStore {
             .collect()
     }
 
+    /// Historical success of a pattern (0.0 - 1.0): its recorded outcomes
+    /// when there are any, otherwise the rate it arrived with, otherwise
+    /// a neutral 0.5
+    pub fn success_score(&self, pattern: &Pattern) -> f64 {
+        match self.usage_stats.get(&pattern.id) {
+            Some(stats) if stats.success_count + stats.failure_count > 0 => {
+                // Laplace smoothing keeps a single outcome from dominating
+                (stats.success_count + 1) as f64
+                    / (stats.success_count + stats.failure_count + 2) as f64
+            }
+            _ if pattern.success_rate > 0.0 => pattern.success_rate as f64,
+            _ => 0.5,
+        }
+    }
+
+    /// Score `pattern` against the matcher's context, or `None` if it doesn't apply
+    pub fn rank_pattern(
+        &self,
+        pattern: &Pattern,
+        matcher: &PatternMatcher,
+    ) -> Option<RankedPattern> {
+        let relevance_score = matcher.relevance(pattern)?;
+        Some(RankedPattern {
+            pattern: pattern.clone(),
+            relevance_score,
+            combined_score: combined_score(relevance_score, self.success_score(pattern)),
+        })
+    }
+
+    /// Stored patterns that apply to the matcher's context, best first
+    pub fn rank(&self, matcher: &PatternMatcher) -> Vec<RankedPattern> {
+        let mut ranked: Vec<RankedPattern> = self
+            .patterns
+            .values()
+            .filter_map(|p| self.rank_pattern(p, matcher))
+            .collect();
+        sort_ranked(&mut ranked);
+        ranked
+    }
+
     /// Record pattern usage
     pub fn record_usage(&mut self, pattern_id: &PatternId) {
         let stats = self
@@ -402,3 +610,96 @@ pub struct UsageStats {
     pub rating_count: u64,
     pub last_used: Option<chrono::DateTime<chrono::Utc>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::ConversationTurn;
+
+    fn pattern(trigger: &str, domain: &str) -> Pattern {
+        Pattern::new(
+            trigger.to_string(),
+            PatternSolution::PromptTemplate {
+                template: "answer".to_string(),
+                variables: Vec::new(),
+            },
+            domain.to_string(),
+            String::new(),
+        )
+    }
+
+    fn context(input: &str) -> Context {
+        Context {
+            session_id: "s".to_string(),
+            working_directory: "/home/user/projects".to_string(),
+            recent_files: vec![],
+            conversation_history: vec![ConversationTurn {
+                timestamp: chrono::Utc::now(),
+                user: input.to_string(),
+                assistant: String::new(),
+                embedding: None,
+            }],
+            timestamp: chrono::Utc::now(),
+            user_name: None,
+            user_preferences: HashMap::new(),
+            frequently_used: vec![],
+            pending_command: None,
+            pending_tool_call: None,
+        }
+    }
+
+    #[test]
+    fn test_matching_trigger_outranks_unrelated() {
+        let mut store = PatternStore::new("/nonexistent");
+        let backup = pattern("backup my photos to the server", "general");
+        let recipe = pattern("suggest a dinner recipe", "general");
+        store.patterns.insert(backup.id.clone(), backup.clone());
+        store.patterns.insert(recipe.id.clone(), recipe.clone());
+
+        let matcher = PatternMatcher::new(&context("how do I backup photos?"), "");
+        let ranked = store.rank(&matcher);
+
+        assert_eq!(ranked[0].pattern.id, backup.id);
+        if let Some(other) = ranked.iter().find(|r| r.pattern.id == recipe.id) {
+            assert!(other.combined_score < ranked[0].combined_score);
+        }
+    }
+
+    #[test]
+    fn test_successful_pattern_outranks_unsuccessful_on_equal_match() {
+        let mut store = PatternStore::new("/nonexistent");
+        let good = pattern("compress log files", "general");
+        let bad = pattern("compress log files", "general");
+        store.patterns.insert(good.id.clone(), good.clone());
+        store.patterns.insert(bad.id.clone(), bad.clone());
+        for _ in 0..4 {
+            store.record_outcome(&good.id, true, 5);
+            store.record_outcome(&bad.id, false, 1);
+        }
+
+        let matcher = PatternMatcher::new(&context(""), "compress the log files");
+        let ranked = store.rank(&matcher);
+
+        assert_eq!(ranked.len(), 2);
+        assert_eq!(ranked[0].relevance_score, ranked[1].relevance_score);
+        assert_eq!(ranked[0].pattern.id, good.id);
+    }
+
+    #[tokio::test]
+    async fn test_store_persists_patterns_and_stats() {
+        let dir = std::env::temp_dir().join(format!("mycel-patterns-{}", uuid::Uuid::new_v4()));
+        let path = dir.to_string_lossy().to_string();
+
+        let mut store = PatternStore::load_or_create(&path).await.unwrap();
+        let p = pattern("rotate nginx logs", "general");
+        store.add_pattern(p.clone()).await.unwrap();
+        store.record_outcome(&p.id, true, 5);
+        store.save().await.unwrap();
+
+        let reloaded = PatternStore::load_or_create(&path).await.unwrap();
+        assert_eq!(reloaded.pattern_count(), 1);
+        assert_eq!(reloaded.success_score(&p), store.success_score(&p));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
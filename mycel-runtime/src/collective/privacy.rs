@@ -3,7 +3,10 @@
 //! Ensures that shared patterns don't leak private information while
 //! still being useful to the collective.
 //!
-//! Note: This module is scaffolded - full implementation deferred.
+//! Federated-learning contributions get differential privacy from a
+//! clip-and-noise Gaussian mechanism (see `compute_private_gradients`).
+//!
+//! Note: Text privatization (`apply_dp_noise`) is still a pass-through.
 #![allow(dead_code)]
 #![allow(clippy::unnecessary_map_or)]
 #![allow(clippy::let_and_return)]
@@ -66,6 +69,15 @@ pub struct PrivacyConfig {
 
     /// Require human review above this sensitivity score
     pub human_review_threshold: f64,
+
+    /// Largest L2 norm one pattern may contribute to a gradient; larger
+    /// feature vectors are scaled down to it
+    #[serde(default = "default_clip_norm")]
+    pub clip_norm: f64,
+}
+
+fn default_clip_norm() -> f64 {
+    1.0
 }
 
 impl Default for PrivacyConfig {
@@ -81,6 +93,7 @@ impl Default for PrivacyConfig {
                 "legal_personal".to_string(),
             ],
             human_review_threshold: 0.8,
+            clip_norm: default_clip_norm(),
         }
     }
 }
@@ -194,32 +207,65 @@ pub fn validate_for_sharing(pattern: &Pattern, config: &PrivacyConfig) -> Result
     Ok(())
 }
 
-/// Compute private gradients for federated learning
+/// Length of a pattern's feature vector
+const FEATURE_DIM: usize = 128;
+
+/// Compute private gradients for federated learning.
+///
+/// Each pattern becomes a feature vector, clipped to `clip_norm`. The
+/// clipped vectors are summed, Gaussian noise with standard deviation
+/// `clip_norm * sqrt(2 ln(1.25 / delta)) / epsilon` is added to every
+/// coordinate, and the sum is averaged.
+///
+/// Guarantee: adding or removing one pattern moves the sum by at most
+/// `clip_norm`, so the noisy sum is (epsilon, delta)-differentially private
+/// with respect to any single pattern (the Gaussian mechanism; the bound is
+/// proven for epsilon < 1 and only approximate above it).
+///
+/// Limits:
+/// - The unit protected is one pattern, not one user; someone behind many
+///   patterns is protected less.
+/// - Each contribution spends epsilon again; nothing tracks the budget
+///   across rounds, so repeated contributions add up.
+/// - `sample_count` is sent as is and reveals how many patterns there were.
+/// - Noise comes from floating-point sampling, which is not hardened
+///   against precision attacks.
 pub fn compute_private_gradients(
-    interactions: &[&Pattern],
+    patterns: &[&Pattern],
     config: &PrivacyConfig,
 ) -> Result<super::bittensor::PrivateGradients> {
-    // This is a simplified implementation
-    // In production, would use actual gradient computation with DP-SGD
-
-    let sample_count = interactions.len();
+    if !(config.epsilon > 0.0 && config.delta > 0.0 && config.delta < 1.0) {
+        return Err(anyhow!(
+            "Differential privacy needs epsilon > 0 and 0 < delta < 1 (got {}, {})",
+            config.epsilon,
+            config.delta
+        ));
+    }
+    if config.clip_norm <= 0.0 {
+        return Err(anyhow!("clip_norm must be positive"));
+    }
+    if patterns.is_empty() {
+        return Err(anyhow!("No patterns to compute gradients from"));
+    }
+    let sample_count = patterns.len();
 
-    // Compute "gradients" (simplified as pattern embeddings)
-    let mut gradients: Vec<f32> = Vec::new();
-    for pattern in interactions {
-        let embedding = compute_pattern_embedding(pattern)?;
-        gradients.extend(embedding);
+    let mut sum = vec![0.0; FEATURE_DIM];
+    for pattern in patterns {
+        let features = clip_to_norm(pattern_features(pattern), config.clip_norm);
+        for (total, feature) in sum.iter_mut().zip(features) {
+            *total += feature;
+        }
     }
 
-    // Add Gaussian noise for differential privacy
-    let noise_scale = compute_noise_scale(config.epsilon, config.delta, sample_count);
-    let noisy_gradients: Vec<f32> = gradients
+    // Noise the sum, where one pattern's influence is bounded, then average
+    let noise_scale = compute_noise_scale(config.epsilon, config.delta, config.clip_norm);
+    let gradients: Vec<f32> = sum
         .iter()
-        .map(|g| g + sample_gaussian(0.0, noise_scale))
+        .map(|total| ((total + sample_gaussian(0.0, noise_scale)) / sample_count as f64) as f32)
         .collect();
 
     // Compress gradients
-    let compressed = compress_gradients(&noisy_gradients)?;
+    let compressed = compress_gradients(&gradients)?;
 
     // Compute hash for integrity
     let hash = sha256::digest(&compressed);
@@ -270,7 +316,7 @@ fn extract_insight(interaction: &Interaction) -> Result<ExtractedInsight> {
     })
 }
 
-fn generalize_query(query: &str) -> String {
+pub fn generalize_query(query: &str) -> String {
     // Replace specific entities with placeholders
     let mut result = query.to_string();
 
@@ -297,7 +343,7 @@ fn extract_variables(template: &str) -> Vec<String> {
         .collect()
 }
 
-fn infer_domain(query: &str) -> String {
+pub(super) fn infer_domain(query: &str) -> String {
     let query_lower = query.to_lowercase();
 
     if query_lower.contains("code")
@@ -509,29 +555,49 @@ fn compute_utility(privatized: &ExtractedInsight, original: &ExtractedInsight) -
     utility
 }
 
-fn compute_pattern_embedding(_pattern: &Pattern) -> Result<Vec<f32>> {
-    // Compute embedding for a pattern
-    // Simplified - in production would use actual embedding model
-
-    Ok(vec![0.0; 128]) // Placeholder
+/// Hashed bag of words from a pattern's domain, trigger and description.
+/// SHA-256 picks each word's slot and sign, so every device maps the same
+/// word to the same coordinate.
+fn pattern_features(pattern: &Pattern) -> Vec<f64> {
+    use sha2::{Digest, Sha256};
+
+    let mut features = vec![0.0; FEATURE_DIM];
+    let domain = format!("domain:{}", pattern.domain.to_lowercase());
+    let text = format!("{} {}", pattern.trigger, pattern.description).to_lowercase();
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .chain(std::iter::once(domain.as_str()));
+    for word in words {
+        let digest = Sha256::digest(word.as_bytes());
+        let slot = u64::from_le_bytes(digest[..8].try_into().unwrap()) as usize % FEATURE_DIM;
+        features[slot] += if digest[8] & 1 == 0 { 1.0 } else { -1.0 };
+    }
+    features
 }
 
-fn compute_noise_scale(epsilon: f64, delta: f64, n: usize) -> f32 {
-    // Compute noise scale for DP-SGD
-    // Simplified formula
-
-    let c = 1.0; // Clipping bound
-    let sigma = c * (2.0 * (1.25 / delta).ln()).sqrt() / epsilon;
+/// `features` scaled down, if needed, to an L2 norm of at most `max_norm`
+fn clip_to_norm(mut features: Vec<f64>, max_norm: f64) -> Vec<f64> {
+    let norm = features.iter().map(|f| f * f).sum::<f64>().sqrt();
+    if norm > max_norm {
+        let scale = max_norm / norm;
+        features.iter_mut().for_each(|f| *f *= scale);
+    }
+    features
+}
 
-    (sigma / (n as f64).sqrt()) as f32
+/// Standard deviation of the Gaussian mechanism for L2 sensitivity
+/// `clip_norm`
+fn compute_noise_scale(epsilon: f64, delta: f64, clip_norm: f64) -> f64 {
+    clip_norm * (2.0 * (1.25 / delta).ln()).sqrt() / epsilon
 }
 
-fn sample_gaussian(mean: f32, std: f32) -> f32 {
-    use std::f32::consts::PI;
+fn sample_gaussian(mean: f64, std: f64) -> f64 {
+    use std::f64::consts::PI;
 
-    // Box-Muller transform
-    let u1: f32 = rand::random();
-    let u2: f32 = rand::random();
+    // Box-Muller transform; u1 is in (0, 1] so its log is finite
+    let u1: f64 = 1.0 - rand::random::<f64>();
+    let u2: f64 = rand::random();
 
     mean + std * (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
 }
@@ -562,3 +628,48 @@ fn regex_replace_names(text: &str, _replacement: &str) -> String {
     // This would use NER in production
     text.to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_noise_grows_as_epsilon_shrinks() {
+        let loose = compute_noise_scale(2.0, 1e-5, 1.0);
+        let default = compute_noise_scale(1.0, 1e-5, 1.0);
+        let strict = compute_noise_scale(0.1, 1e-5, 1.0);
+        assert!(loose < default && default < strict);
+        // Noise is proportional to the sensitivity it hides
+        assert!((compute_noise_scale(1.0, 1e-5, 2.0) - 2.0 * default).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_clipping_bounds_the_norm() {
+        let norm = |v: &[f64]| v.iter().map(|f| f * f).sum::<f64>().sqrt();
+
+        let clipped = clip_to_norm(vec![3.0, 4.0], 1.0);
+        assert!((norm(&clipped) - 1.0).abs() < 1e-9);
+        assert!((clipped[0] / clipped[1] - 0.75).abs() < 1e-9);
+        // Short vectors are left alone
+        assert_eq!(clip_to_norm(vec![0.3, 0.4], 1.0), vec![0.3, 0.4]);
+
+        let pattern = Pattern::new(
+            "find large files in a directory and sort them by size".to_string(),
+            PatternSolution::PromptTemplate {
+                template: "du -ah {{dir}} | sort -h".to_string(),
+                variables: vec!["dir".to_string()],
+            },
+            "search".to_string(),
+            "Pattern for search tasks".to_string(),
+        );
+        let features = pattern_features(&pattern);
+        assert_eq!(features, pattern_features(&pattern));
+        assert!(norm(&features) > 1.0);
+        assert!(norm(&clip_to_norm(features, 1.0)) <= 1.0 + 1e-9);
+
+        let config = PrivacyConfig::default();
+        let gradients = compute_private_gradients(&[&pattern], &config).unwrap();
+        assert_eq!(gradients.compressed.len(), FEATURE_DIM);
+        assert_eq!(gradients.sample_count, 1);
+    }
+}
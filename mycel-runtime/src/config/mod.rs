@@ -1,9 +1,14 @@
 //! Configuration for Mycel Runtime
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::policy::PolicyConfig;
+
+mod secrets;
+pub use secrets::resolve_secret;
+
 /// Main configuration struct
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MycelConfig {
@@ -15,11 +20,16 @@ pub struct MycelConfig {
     #[serde(default = "default_local_model")]
     pub local_model: String,
 
+    /// Ollama model used for embeddings (semantic context retrieval)
+    #[serde(default = "default_embedding_model")]
+    pub embedding_model: String,
+
     /// Cloud model to use (OpenRouter model name, e.g. "anthropic/claude-3.5-sonnet")
     #[serde(default = "default_cloud_model")]
     pub cloud_model: String,
 
-    /// OpenRouter API key (get one at https://openrouter.ai/keys)
+    /// OpenRouter API key (get one at https://openrouter.ai/keys), or where
+    /// to find it: `env:VAR`, `file:/path` or `keyring:service`
     #[serde(default)]
     pub openrouter_api_key: String,
 
@@ -27,6 +37,26 @@ pub struct MycelConfig {
     #[serde(default)]
     pub prefer_cloud: bool,
 
+    /// Route obvious inputs by keyword instead of asking the LLM for the intent
+    #[serde(default = "default_true")]
+    pub intent_fast_path: bool,
+
+    /// Hosts outbound HTTP requests may reach, subdomains included. Unset
+    /// allows any host; an empty list blocks all but loopback (pure local
+    /// mode). Pulling a model through Ollama counts as reaching its
+    /// registry (`registry.ollama.ai`).
+    #[serde(default)]
+    pub egress_allowlist: Option<Vec<String>>,
+
+    /// Hosts outbound HTTP requests may never reach, subdomains included
+    #[serde(default)]
+    pub egress_denylist: Vec<String>,
+
+    /// Answer only: no code execution, no side-effecting tools, no
+    /// evolution and no sync (see `enable_safe_mode`)
+    #[serde(default)]
+    pub safe_mode: bool,
+
     /// Path to store context and state
     #[serde(default = "default_context_path")]
     pub context_path: String,
@@ -39,14 +69,66 @@ pub struct MycelConfig {
     #[serde(default = "default_ipc_path")]
     pub ipc_socket_path: String,
 
-    /// Maximum tokens for local model
+    /// Maximum tokens for local model; also the prompt budget for agentic
+    /// tool loops, which drop their oldest tool results to stay under it
     #[serde(default = "default_max_tokens")]
     pub local_max_tokens: u32,
 
+    /// How long Ollama keeps the local model in memory after a request,
+    /// e.g. "30s", "5m" or "-1" to keep it loaded (default: Ollama's own,
+    /// 5 minutes). Longer avoids reloading the model between requests; shorter
+    /// gives the memory back to other programs sooner.
+    #[serde(default)]
+    pub local_keep_alive: Option<String>,
+
     /// Force cloud for complex tasks (default: false - local LLM is primary)
     #[serde(default = "default_false")]
     pub force_cloud_for_complex: bool,
 
+    /// Deadline for a single local (Ollama) request in seconds (default: 300).
+    /// A timeout counts as a local failure and falls back to cloud.
+    #[serde(default = "default_local_timeout")]
+    pub local_timeout_secs: u64,
+
+    /// Deadline for a single cloud request in seconds (default: 120)
+    #[serde(default = "default_cloud_timeout")]
+    pub cloud_timeout_secs: u64,
+
+    /// Cloud requests in flight at once; the rest queue (default: 4).
+    /// Read at startup.
+    #[serde(default = "default_cloud_max_concurrency")]
+    pub cloud_max_concurrency: usize,
+
+    /// Local generations in flight at once; the rest queue (default: 2).
+    /// Read at startup.
+    #[serde(default = "default_local_max_concurrency")]
+    pub local_max_concurrency: usize,
+
+    /// Replaces the built-in "You are Mycel OS..." preamble of chat prompts.
+    /// `{cwd}` and `{user}` are filled in per request; tool instructions are
+    /// still appended.
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+
+    /// Name the assistant goes by in the built-in preamble (default: Mycel OS)
+    #[serde(default)]
+    pub persona_name: Option<String>,
+
+    /// Explain generated code and wait for a `yes` instead of running it,
+    /// even when the policy would allow it
+    #[serde(default)]
+    pub dry_run_code: bool,
+
+    /// Before asking to confirm generated code, have the model summarize
+    /// what it does and which files, network or packages it touches
+    #[serde(default)]
+    pub explain_plan: bool,
+
+    /// User preferences added to chat prompts. Empty means all of them
+    /// (a few at most, so they can't crowd out the request).
+    #[serde(default)]
+    pub prompt_preference_keys: Vec<String>,
+
     /// Execution timeout in seconds (default: 30)
     #[serde(default = "default_execution_timeout")]
     pub execution_timeout_secs: u64,
@@ -55,6 +137,11 @@ pub struct MycelConfig {
     #[serde(default = "default_execution_memory")]
     pub execution_memory_mb: u32,
 
+    /// Environment variables passed through to executed code on top of
+    /// `PATH`, `HOME` and `LANG`; everything else is stripped
+    #[serde(default)]
+    pub execution_env_allowlist: Vec<String>,
+
     /// Blockchain synchronization settings
     #[serde(default)]
     pub blockchain_sync: bool,
@@ -63,9 +150,32 @@ pub struct MycelConfig {
     #[serde(default)]
     pub near_account: Option<String>,
 
+    /// Which kinds of sync operation go to mesh peers and which to the
+    /// blockchain (conversation turns: mesh only by default)
+    #[serde(default)]
+    pub sync_policy: crate::sync::SyncPolicy,
+
+    /// Days conversation turns are kept in the sync log before compaction
+    /// drops them (default: 90)
+    #[serde(default = "default_sync_retention_days")]
+    pub sync_retention_days: u32,
+
+    /// Learn patterns from interactions and share them with the collective
+    #[serde(default)]
+    pub collective_enabled: bool,
+
+    /// Address to serve Prometheus metrics on (e.g. "127.0.0.1:9464");
+    /// off when unset
+    #[serde(default)]
+    pub metrics_bind: Option<String>,
+
     /// MCP (Model Context Protocol) configuration
     #[serde(default)]
     pub mcp: McpConfig,
+
+    /// Action policy (confirmation rules and blocked paths)
+    #[serde(default)]
+    pub policy: PolicyConfig,
 }
 
 /// MCP (Model Context Protocol) configuration
@@ -78,6 +188,79 @@ pub struct McpConfig {
     /// List of MCP servers to connect to
     #[serde(default)]
     pub servers: Vec<McpServerConfig>,
+
+    /// Substitute an empty string for undefined `$VAR` references in server
+    /// `args`/`env` instead of failing to start the server
+    #[serde(default)]
+    pub allow_undefined_env: bool,
+
+    /// Offer the self-modification meta-tools (`evolve_os_*`) to the model.
+    /// Disable for locked-down deployments such as kiosks.
+    #[serde(default = "default_true")]
+    pub evolution_enabled: bool,
+
+    /// Offer the built-in `file_read`/`file_write`/`file_list` tools, which
+    /// check every path against `policy.blocked_file_patterns`
+    #[serde(default = "default_true")]
+    pub file_tools_enabled: bool,
+
+    /// Hold capabilities the model writes for review instead of installing
+    /// them right away; they run only once confirmed
+    #[serde(default = "default_true")]
+    pub evolution_require_confirmation: bool,
+
+    /// When non-empty, only these tools are offered to the model
+    #[serde(default)]
+    pub tool_allowlist: Vec<String>,
+
+    /// Tools never offered to the model, even if allowlisted
+    #[serde(default)]
+    pub tool_denylist: Vec<String>,
+
+    /// Bundled servers to start alongside `servers`, by name
+    /// (`void-tools`, `near-identity`, `web-tools`, `filesystem`)
+    #[serde(default)]
+    pub enabled_builtins: Vec<String>,
+
+    /// Offer the model only this many tools per request, picked by embedding
+    /// similarity to the input; 0 offers every tool
+    #[serde(default)]
+    pub tool_selection_top_k: usize,
+
+    /// Seconds the result of an idempotent tool answers repeats of the
+    /// same call (default: 60); 0 calls the tool every time
+    #[serde(default = "default_tool_cache_ttl")]
+    pub tool_cache_ttl_secs: u64,
+
+    /// Keep cached results of read-only tools in
+    /// `<context_path>/tool_cache.json` across restarts
+    #[serde(default = "default_true")]
+    pub persist_tool_cache: bool,
+
+    /// Model/tool rounds an agentic chat may take before giving up
+    #[serde(default = "default_agentic_max_iterations")]
+    pub agentic_max_iterations: usize,
+
+    /// Largest JSON-RPC message accepted from a server (default: 16 MiB).
+    /// Longer ones are dropped so a misbehaving server can't grow the
+    /// reader without bound.
+    #[serde(default = "default_mcp_max_message_bytes")]
+    pub max_message_bytes: usize,
+
+    /// Tool-call format the models emit: `auto` (default) tries every
+    /// format; `xml_tags`, `openai`, `json_code_block`, `function_syntax`
+    /// or `direct_json` parses only that one
+    #[serde(default)]
+    pub tool_call_format: crate::mcp::ToolCallFormatHint,
+
+    /// Offer and run only tools declared read-only
+    #[serde(default)]
+    pub read_only_tools: bool,
+
+    /// Seconds a tool call waits for the user's confirmation before it is
+    /// cancelled (default: 300)
+    #[serde(default = "default_confirmation_ttl")]
+    pub confirmation_ttl_secs: u64,
 }
 
 impl Default for McpConfig {
@@ -85,6 +268,21 @@ impl Default for McpConfig {
         Self {
             enabled: true,
             servers: Vec::new(),
+            allow_undefined_env: false,
+            evolution_enabled: true,
+            file_tools_enabled: true,
+            evolution_require_confirmation: true,
+            tool_allowlist: Vec::new(),
+            tool_denylist: Vec::new(),
+            enabled_builtins: Vec::new(),
+            tool_selection_top_k: 0,
+            tool_cache_ttl_secs: default_tool_cache_ttl(),
+            persist_tool_cache: true,
+            agentic_max_iterations: default_agentic_max_iterations(),
+            max_message_bytes: default_mcp_max_message_bytes(),
+            tool_call_format: Default::default(),
+            read_only_tools: false,
+            confirmation_ttl_secs: default_confirmation_ttl(),
         }
     }
 }
@@ -98,23 +296,45 @@ pub struct McpServerConfig {
     /// Command to run the server
     pub command: String,
 
-    /// Arguments for the command
+    /// Arguments for the command (`$VAR` and `${VAR}` are expanded)
     #[serde(default)]
     pub args: Vec<String>,
 
-    /// Environment variables
+    /// Environment variables (values expand `$VAR` and `${VAR}`)
     #[serde(default)]
     pub env: HashMap<String, String>,
 
     /// Tools that require user confirmation before execution
     #[serde(default)]
     pub requires_confirmation: Vec<String>,
+
+    /// Seconds a tool call may take before it fails (default: 30)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_timeout_secs: Option<u64>,
+
+    /// Per-tool overrides of `tool_timeout_secs`, by tool name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tool_timeouts: HashMap<String, u64>,
+
+    /// Mark tools idempotent (safe to cache and retry) or not, by tool
+    /// name, overriding what the server declares
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub idempotent_tools: HashMap<String, bool>,
+}
+
+/// Compare two settings by their serialized form
+fn differs<T: Serialize>(a: &T, b: &T) -> bool {
+    serde_json::to_value(a).ok() != serde_json::to_value(b).ok()
 }
 
 fn default_true() -> bool {
     true
 }
 
+fn default_embedding_model() -> String {
+    "nomic-embed-text".to_string()
+}
+
 fn default_ollama_url() -> String {
     "http://localhost:11434".to_string()
 }
@@ -147,10 +367,48 @@ fn default_false() -> bool {
     false
 }
 
+/// `local_keep_alive` as Ollama takes it: a bare number is seconds (negative
+/// keeps the model loaded), otherwise a duration such as "10m" or "1h30m"
+pub fn keep_alive_value(keep_alive: &str) -> Option<serde_json::Value> {
+    let keep_alive = keep_alive.trim();
+    if let Ok(seconds) = keep_alive.parse::<i64>() {
+        return Some(serde_json::json!(seconds));
+    }
+    let units = keep_alive.strip_prefix('-').unwrap_or(keep_alive);
+    let mut rest = units;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .filter(|&n| n > 0)?;
+        rest = &rest[digits..];
+        let unit = ["ms", "s", "m", "h"]
+            .into_iter()
+            .find(|unit| rest.starts_with(unit))?;
+        rest = &rest[unit.len()..];
+    }
+    (!units.is_empty()).then(|| serde_json::json!(keep_alive))
+}
+
 fn default_max_tokens() -> u32 {
     2048
 }
 
+fn default_local_timeout() -> u64 {
+    300
+}
+
+fn default_cloud_timeout() -> u64 {
+    120
+}
+
+fn default_cloud_max_concurrency() -> usize {
+    4
+}
+
+fn default_local_max_concurrency() -> usize {
+    2
+}
+
 fn default_execution_timeout() -> u64 {
     30
 }
@@ -159,24 +417,65 @@ fn default_execution_memory() -> u32 {
     512
 }
 
+fn default_sync_retention_days() -> u32 {
+    90
+}
+
+fn default_agentic_max_iterations() -> usize {
+    5
+}
+
+fn default_confirmation_ttl() -> u64 {
+    300
+}
+
+fn default_tool_cache_ttl() -> u64 {
+    60
+}
+
+fn default_mcp_max_message_bytes() -> usize {
+    16 * 1024 * 1024
+}
+
 impl Default for MycelConfig {
     fn default() -> Self {
         Self {
             ollama_url: default_ollama_url(),
             local_model: default_local_model(),
+            embedding_model: default_embedding_model(),
             cloud_model: default_cloud_model(),
             openrouter_api_key: String::new(),
             prefer_cloud: false,
+            intent_fast_path: true,
+            egress_allowlist: None,
+            egress_denylist: Vec::new(),
+            safe_mode: false,
             context_path: default_context_path(),
             code_path: default_code_path(),
             ipc_socket_path: default_ipc_path(),
             local_max_tokens: 2048,
+            local_keep_alive: None,
             force_cloud_for_complex: false, // Local LLM is the primary brain
+            local_timeout_secs: default_local_timeout(),
+            cloud_timeout_secs: default_cloud_timeout(),
+            cloud_max_concurrency: default_cloud_max_concurrency(),
+            local_max_concurrency: default_local_max_concurrency(),
+            system_prompt: None,
+            persona_name: None,
+            dry_run_code: false,
+            explain_plan: false,
+            prompt_preference_keys: Vec::new(),
             execution_timeout_secs: default_execution_timeout(),
             execution_memory_mb: default_execution_memory(),
+            execution_env_allowlist: Vec::new(),
             blockchain_sync: false,
             near_account: None,
+            sync_policy: Default::default(),
+            sync_retention_days: default_sync_retention_days(),
+            collective_enabled: false,
+            metrics_bind: None,
             mcp: McpConfig::default(),
+            policy: PolicyConfig::default(),
         }
     }
 }
@@ -190,6 +489,8 @@ impl MycelConfig {
         } else {
             Self::default()
         };
+        config.openrouter_api_key =
+            resolve_secret("openrouter_api_key", &config.openrouter_api_key)?;
 
         // Environment variable overrides
         if let Ok(key) = std::env::var("OPENROUTER_API_KEY") {
@@ -207,6 +508,10 @@ impl MycelConfig {
             config.prefer_cloud = true;
         }
 
+        if config.safe_mode {
+            config.enable_safe_mode();
+        }
+
         // Dev mode adjustments
         if dev_mode {
             config.context_path = "./mycel-data".to_string();
@@ -214,27 +519,380 @@ impl MycelConfig {
             config.ipc_socket_path = "/tmp/mycel-dev.sock".to_string();
         }
 
+        config.validate()?;
         Ok(config)
     }
 
-    /// Save configuration to file
+    /// Make Mycel answer-only: code execution, evolution and tools with side
+    /// effects are switched off. Sync is skipped at startup by the runtime.
+    pub fn enable_safe_mode(&mut self) {
+        self.safe_mode = true;
+        self.policy.allow_code_execution = false;
+        self.mcp.evolution_enabled = false;
+        self.mcp.read_only_tools = true;
+    }
+
+    /// Check for settings that would only fail later at runtime.
+    ///
+    /// All problems are reported together; `prefer_cloud` without a cloud
+    /// key is only a warning since local inference still works.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        match reqwest::Url::parse(&self.ollama_url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            Ok(url) => problems.push(format!(
+                "ollama_url must be http(s), got scheme '{}'",
+                url.scheme()
+            )),
+            Err(e) => problems.push(format!(
+                "ollama_url '{}' is not a valid URL: {}",
+                self.ollama_url, e
+            )),
+        }
+        if self.local_model.trim().is_empty() {
+            problems.push("local_model must not be empty".to_string());
+        }
+        if let Some(keep_alive) = &self.local_keep_alive {
+            if keep_alive_value(keep_alive).is_none() {
+                problems.push(format!(
+                    "local_keep_alive must be seconds or a duration like \"5m\" (got '{}')",
+                    keep_alive
+                ));
+            }
+        }
+        if self.local_timeout_secs == 0 {
+            problems.push("local_timeout_secs must be greater than 0".to_string());
+        }
+        if self.cloud_timeout_secs == 0 {
+            problems.push("cloud_timeout_secs must be greater than 0".to_string());
+        }
+        if self.cloud_max_concurrency == 0 || self.local_max_concurrency == 0 {
+            problems.push(
+                "cloud_max_concurrency and local_max_concurrency must be greater than 0"
+                    .to_string(),
+            );
+        }
+        if self.execution_timeout_secs == 0 {
+            problems.push("execution_timeout_secs must be greater than 0".to_string());
+        }
+        if let Some(bind) = &self.metrics_bind {
+            if bind.parse::<std::net::SocketAddr>().is_err() {
+                problems.push(format!(
+                    "metrics_bind must be an address like 127.0.0.1:9464 (got '{}')",
+                    bind
+                ));
+            }
+        }
+        for name in &self.mcp.enabled_builtins {
+            if !crate::mcp::BUILTIN_SERVERS.contains(&name.as_str()) {
+                problems.push(format!(
+                    "mcp.enabled_builtins: unknown server '{}' (expected one of: {})",
+                    name,
+                    crate::mcp::BUILTIN_SERVERS.join(", ")
+                ));
+            }
+        }
+        if self.mcp.agentic_max_iterations == 0 {
+            problems.push("mcp.agentic_max_iterations must be greater than 0".to_string());
+        }
+        if self.mcp.max_message_bytes == 0 {
+            problems.push("mcp.max_message_bytes must be greater than 0".to_string());
+        }
+        if self.execution_memory_mb < 64 {
+            problems.push(format!(
+                "execution_memory_mb must be at least 64 (got {})",
+                self.execution_memory_mb
+            ));
+        }
+
+        if self.prefer_cloud && self.openrouter_api_key.is_empty() {
+            tracing::warn!(
+                "prefer_cloud is set but no cloud API key is configured; \
+                 set OPENROUTER_API_KEY or openrouter_api_key"
+            );
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Invalid configuration:\n  - {}",
+                problems.join("\n  - ")
+            ))
+        }
+    }
+
+    /// Settings that differ in `new` but only take effect after a restart
+    pub fn restart_required(&self, new: &Self) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        let mut check = |name, changed: bool| {
+            if changed {
+                fields.push(name);
+            }
+        };
+
+        check(
+            "ipc_socket_path",
+            self.ipc_socket_path != new.ipc_socket_path,
+        );
+        check("context_path", self.context_path != new.context_path);
+        check("code_path", self.code_path != new.code_path);
+        check(
+            "execution_timeout_secs",
+            self.execution_timeout_secs != new.execution_timeout_secs,
+        );
+        check(
+            "execution_memory_mb",
+            self.execution_memory_mb != new.execution_memory_mb,
+        );
+        check(
+            "execution_env_allowlist",
+            self.execution_env_allowlist != new.execution_env_allowlist,
+        );
+        check(
+            "blockchain_sync",
+            self.blockchain_sync != new.blockchain_sync,
+        );
+        check("near_account", self.near_account != new.near_account);
+        check("safe_mode", self.safe_mode != new.safe_mode);
+        check(
+            "egress_allowlist",
+            self.egress_allowlist != new.egress_allowlist,
+        );
+        check(
+            "egress_denylist",
+            self.egress_denylist != new.egress_denylist,
+        );
+        check("sync_policy", differs(&self.sync_policy, &new.sync_policy));
+        check(
+            "sync_retention_days",
+            self.sync_retention_days != new.sync_retention_days,
+        );
+        check(
+            "collective_enabled",
+            self.collective_enabled != new.collective_enabled,
+        );
+        check("metrics_bind", self.metrics_bind != new.metrics_bind);
+        check("mcp", differs(&self.mcp, &new.mcp));
+        fields
+    }
+
+    /// Copy the settings that can change while running from `new`,
+    /// returning the names of those that changed
+    pub fn apply_live(&mut self, new: &Self) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+
+        macro_rules! live {
+            ($($field:ident),*) => {$(
+                if differs(&self.$field, &new.$field) {
+                    self.$field = new.$field.clone();
+                    changed.push(stringify!($field));
+                }
+            )*};
+        }
+        live!(
+            ollama_url,
+            local_model,
+            embedding_model,
+            cloud_model,
+            openrouter_api_key,
+            prefer_cloud,
+            intent_fast_path,
+            system_prompt,
+            persona_name,
+            dry_run_code,
+            explain_plan,
+            prompt_preference_keys,
+            local_max_tokens,
+            local_keep_alive,
+            force_cloud_for_complex,
+            local_timeout_secs,
+            cloud_timeout_secs,
+            policy
+        );
+
+        changed
+    }
+
+    /// Save configuration to file, readable only by its owner since it
+    /// may hold the OpenRouter API key
     pub fn save(&self, path: &str) -> Result<()> {
+        use std::io::Write;
+
         let content = toml::to_string_pretty(self)?;
-        std::fs::write(path, content)?;
+        let mut options = std::fs::OpenOptions::new();
+        options.create(true).truncate(true).write(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path)?;
+        // The mode only applies to a new file; tighten an existing one before
+        // the key goes into it
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        }
+        file.write_all(content.as_bytes())?;
         Ok(())
     }
+
+    /// Apply a change to the config file on disk and save it.
+    ///
+    /// Works from the file's own contents rather than the running config, so
+    /// environment overrides (API keys) and dev-mode paths are never written out.
+    pub fn update_file(path: &str, update: impl FnOnce(&mut Self)) -> Result<Self> {
+        let mut config: Self = if std::path::Path::new(path).exists() {
+            toml::from_str(&std::fs::read_to_string(path)?)?
+        } else {
+            Self::default()
+        };
+        update(&mut config);
+
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        config.save(path)?;
+        Ok(config)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_apply_live_and_restart_required() {
+        let mut running = MycelConfig::default();
+        let mut new = running.clone();
+        new.local_model = "qwen2.5:7b".to_string();
+        new.prefer_cloud = true;
+        new.policy.denied_tools = vec!["shell_command".to_string()];
+        new.ipc_socket_path = "/tmp/other.sock".to_string();
+
+        assert_eq!(running.restart_required(&new), vec!["ipc_socket_path"]);
+
+        let changed = running.apply_live(&new);
+        assert_eq!(changed, vec!["local_model", "prefer_cloud", "policy"]);
+        assert_eq!(running.local_model, "qwen2.5:7b");
+        assert!(running.prefer_cloud);
+        assert_eq!(running.policy.denied_tools, vec!["shell_command"]);
+        // Restart-only settings are left alone
+        assert_ne!(running.ipc_socket_path, "/tmp/other.sock");
+        assert!(running.apply_live(&new).is_empty());
+    }
+
     #[test]
     fn test_default_config() {
         let config = MycelConfig::default();
         assert_eq!(config.local_model, "tinydolphin");
         assert_eq!(config.ollama_url, "http://localhost:11434");
         assert!(!config.force_cloud_for_complex);
+        assert!(!config.dry_run_code);
+        assert!(!config.explain_plan);
+        assert!(config.system_prompt.is_none());
+    }
+
+    #[test]
+    fn test_update_file_persists() {
+        let path = std::env::temp_dir().join(format!("mycel-config-{}.toml", uuid::Uuid::new_v4()));
+        let path = path.to_string_lossy().to_string();
+        std::fs::write(&path, "local_model = \"phi3:mini\"\n").unwrap();
+
+        MycelConfig::update_file(&path, |c| {
+            c.blockchain_sync = true;
+            c.near_account = Some("alice.near".to_string());
+        })
+        .unwrap();
+
+        let reloaded: MycelConfig =
+            toml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(reloaded.blockchain_sync);
+        assert_eq!(reloaded.near_account.as_deref(), Some("alice.near"));
+        // Existing settings are kept
+        assert_eq!(reloaded.local_model, "phi3:mini");
+        // An API key may be in there, so only the owner can read it
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_ollama_url() {
+        let config = MycelConfig {
+            ollama_url: "localhost:11434 oops".to_string(),
+            ..MycelConfig::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("ollama_url"), "{}", err);
+
+        let config = MycelConfig {
+            ollama_url: "ftp://localhost:11434".to_string(),
+            ..MycelConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_empty_local_model() {
+        let config = MycelConfig {
+            local_model: "  ".to_string(),
+            ..MycelConfig::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("local_model"), "{}", err);
+    }
+
+    #[test]
+    fn test_validate_rejects_zero_timeout() {
+        let config = MycelConfig {
+            execution_timeout_secs: 0,
+            ..MycelConfig::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("execution_timeout_secs"), "{}", err);
+    }
+
+    #[test]
+    fn test_validate_rejects_low_memory_limit() {
+        let config = MycelConfig {
+            execution_memory_mb: 16,
+            ..MycelConfig::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("execution_memory_mb"), "{}", err);
+    }
+
+    #[test]
+    fn test_validate_aggregates_problems() {
+        let config = MycelConfig {
+            execution_timeout_secs: 0,
+            execution_memory_mb: 1,
+            ..MycelConfig::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("execution_timeout_secs") && err.contains("execution_memory_mb"));
+    }
+
+    #[test]
+    fn test_validate_allows_prefer_cloud_without_key() {
+        let config = MycelConfig {
+            prefer_cloud: true,
+            openrouter_api_key: String::new(),
+            ..MycelConfig::default()
+        };
+        assert!(config.validate().is_ok());
+        assert!(MycelConfig::default().validate().is_ok());
     }
 
     #[test]
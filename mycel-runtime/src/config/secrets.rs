@@ -0,0 +1,84 @@
+//! Secret references - keep API keys out of `config.toml`
+//!
+//! A key field may hold a reference instead of the key itself, resolved when
+//! the config is loaded:
+//!
+//! - `env:VAR` - the environment variable `VAR`
+//! - `file:/path` - the contents of a file (surrounding whitespace trimmed)
+//! - `keyring:service` - the OS secret store entry for `service`, with the
+//!   config field name as the user (e.g. `openrouter_api_key`)
+//!
+//! Anything else is the key itself, as before.
+
+use anyhow::{anyhow, Context, Result};
+
+/// Resolve `value` of the config field `field` to the secret it names
+pub fn resolve_secret(field: &str, value: &str) -> Result<String> {
+    let secret = if let Some(var) = value.strip_prefix("env:") {
+        std::env::var(var)
+            .map_err(|_| anyhow!("{}: environment variable {} is not set", field, var))?
+    } else if let Some(path) = value.strip_prefix("file:") {
+        std::fs::read_to_string(path)
+            .with_context(|| format!("{}: cannot read secret file {}", field, path))?
+            .trim()
+            .to_string()
+    } else if let Some(service) = value.strip_prefix("keyring:") {
+        keyring::Entry::new(service, field)
+            .and_then(|entry| entry.get_password())
+            .with_context(|| format!("{}: no secret for {} in the OS keyring", field, service))?
+    } else {
+        return Ok(value.to_string());
+    };
+
+    if secret.is_empty() {
+        return Err(anyhow!("{}: {} resolved to an empty secret", field, value));
+    }
+    tracing::info!("Resolved {} from {} ({})", field, value, redact(&secret));
+    Ok(secret)
+}
+
+/// A secret shortened so logs can tell keys apart without revealing them
+pub fn redact(secret: &str) -> String {
+    let shown: String = secret.chars().take(4).collect();
+    if secret.chars().count() <= 8 {
+        "[redacted]".to_string()
+    } else {
+        format!("{}…[redacted]", shown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_and_file_references_resolve() {
+        let var = format!("MYCEL_TEST_KEY_{}", std::process::id());
+        std::env::set_var(&var, "sk-or-from-env");
+        assert_eq!(
+            resolve_secret("openrouter_api_key", &format!("env:{}", var)).unwrap(),
+            "sk-or-from-env"
+        );
+        std::env::remove_var(&var);
+        let err = resolve_secret("openrouter_api_key", &format!("env:{}", var)).unwrap_err();
+        assert!(err.to_string().contains("is not set"), "{}", err);
+
+        let path = std::env::temp_dir().join(format!("mycel-key-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "sk-or-from-file\n").unwrap();
+        let reference = format!("file:{}", path.display());
+        assert_eq!(
+            resolve_secret("openrouter_api_key", &reference).unwrap(),
+            "sk-or-from-file"
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(resolve_secret("openrouter_api_key", &reference).is_err());
+
+        // A plain value is the key itself
+        assert_eq!(
+            resolve_secret("openrouter_api_key", "sk-or-1").unwrap(),
+            "sk-or-1"
+        );
+        assert_eq!(redact("sk-or-v1-abcdef123456"), "sk-o…[redacted]");
+        assert_eq!(redact("short"), "[redacted]");
+    }
+}
@@ -8,26 +8,56 @@
 //! Memory management:
 //! - Sessions are cleaned up after configurable TTL (default: 24 hours)
 //! - Call cleanup_stale_sessions() periodically to reclaim memory
+//!
+//! Persistence:
+//! - Sessions are written to `<context_path>/sessions/` (one JSON file per
+//!   session) on every conversation turn and reloaded on startup
+//! - Stale session files are removed with the same TTL as in-memory sessions
+//! - `<context_path>/mycel.lock` is held while a daemon runs, so a second
+//!   one can't share (and corrupt) the same state
 
 use anyhow::Result;
 use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::config::MycelConfig;
 
 /// Default session TTL in hours
 const DEFAULT_SESSION_TTL_HOURS: i64 = 24;
 
+/// Number of past turns included in the prompt context
+pub const RELEVANT_TURNS: usize = 5;
+
+/// Number of the most recent turns always kept, whatever their relevance
+const RECENT_TURNS_KEPT: usize = 2;
+
+/// Number of files kept in `UserContext::frequently_used`
+pub const FREQUENT_FILES: usize = 5;
+
+/// Accesses needed before a file counts as frequently used
+const FREQUENT_MIN_ACCESSES: u32 = 2;
+
+/// Upper bound on tracked access counts; the least used are dropped first
+const MAX_TRACKED_FILES: usize = 200;
+
+/// How long file access counts wait before being written, so a burst of
+/// accesses is saved once
+const FILE_ACCESS_SAVE_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+
 /// Main context manager
 #[derive(Clone)]
 pub struct ContextManager {
     config: MycelConfig,
     sessions: Arc<RwLock<HashMap<String, SessionContext>>>,
     user_context: Arc<RwLock<UserContext>>,
+    /// Set while a save of recorded file accesses is scheduled
+    file_access_save_pending: Arc<AtomicBool>,
 }
 
 impl ContextManager {
@@ -35,11 +65,135 @@ impl ContextManager {
         // Load user context from disk if it exists
         let user_context = UserContext::load_or_default(&config.context_path).await?;
 
-        Ok(Self {
+        let manager = Self {
             config: config.clone(),
             sessions: Arc::new(RwLock::new(HashMap::new())),
             user_context: Arc::new(RwLock::new(user_context)),
-        })
+            file_access_save_pending: Arc::new(AtomicBool::new(false)),
+        };
+
+        match manager.load_sessions().await {
+            Ok(0) => {}
+            Ok(count) => info!(sessions = count, "Restored persisted sessions"),
+            Err(e) => warn!("Failed to load persisted sessions: {}", e),
+        }
+
+        Ok(manager)
+    }
+
+    /// Directory holding one JSON file per persisted session
+    fn sessions_dir(&self) -> PathBuf {
+        Path::new(&self.config.context_path).join("sessions")
+    }
+
+    /// File a session is persisted to.
+    ///
+    /// Session ids come from IPC clients, so anything that isn't a plain
+    /// identifier is hashed rather than used as a path component.
+    fn session_file(&self, session_id: &str) -> PathBuf {
+        let is_plain = !session_id.is_empty()
+            && session_id.len() <= 128
+            && session_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        let stem = if is_plain {
+            session_id.to_string()
+        } else {
+            sha256::digest(session_id)
+        };
+        self.sessions_dir().join(format!("{}.json", stem))
+    }
+
+    /// Write a session to disk
+    pub async fn save_session(&self, session: &SessionContext) -> Result<()> {
+        tokio::fs::create_dir_all(self.sessions_dir()).await?;
+        let content = serde_json::to_string_pretty(session)?;
+        tokio::fs::write(self.session_file(&session.id), content).await?;
+        Ok(())
+    }
+
+    /// Write every in-memory session to disk (used on shutdown)
+    pub async fn persist_sessions(&self) -> Result<usize> {
+        let snapshot: Vec<SessionContext> = self.sessions.read().await.values().cloned().collect();
+        for session in &snapshot {
+            self.save_session(session).await?;
+        }
+        Ok(snapshot.len())
+    }
+
+    /// Load persisted sessions into memory, dropping any past the TTL.
+    ///
+    /// Returns the number of sessions restored.
+    pub async fn load_sessions(&self) -> Result<usize> {
+        let dir = self.sessions_dir();
+        if !dir.exists() {
+            return Ok(0);
+        }
+
+        let cutoff = Utc::now() - Duration::hours(DEFAULT_SESSION_TTL_HOURS);
+        let mut sessions = self.sessions.write().await;
+        let mut restored = 0;
+
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().map(|e| e != "json").unwrap_or(true) {
+                continue;
+            }
+
+            let session = match tokio::fs::read_to_string(&path)
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|c| Ok(serde_json::from_str::<SessionContext>(&c)?))
+            {
+                Ok(session) => session,
+                Err(e) => {
+                    warn!(path = %path.display(), "Skipping unreadable session file: {}", e);
+                    continue;
+                }
+            };
+
+            if session.last_accessed <= cutoff {
+                let _ = tokio::fs::remove_file(&path).await;
+                continue;
+            }
+
+            sessions.insert(session.id.clone(), session);
+            restored += 1;
+        }
+
+        Ok(restored)
+    }
+
+    /// Remove persisted sessions older than `cutoff` that aren't live in memory
+    async fn cleanup_session_files(&self, cutoff: DateTime<Utc>) -> Result<usize> {
+        let dir = self.sessions_dir();
+        if !dir.exists() {
+            return Ok(0);
+        }
+
+        let sessions = self.sessions.read().await;
+        let mut removed = 0;
+
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let Ok(content) = tokio::fs::read_to_string(&path).await else {
+                continue;
+            };
+            let stale = match serde_json::from_str::<SessionContext>(&content) {
+                Ok(session) => {
+                    session.last_accessed <= cutoff && !sessions.contains_key(&session.id)
+                }
+                // Corrupt files would otherwise linger forever
+                Err(_) => true,
+            };
+            if stale && tokio::fs::remove_file(&path).await.is_ok() {
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
     }
 
     /// Get the context for a session (creates if doesn't exist)
@@ -62,7 +216,9 @@ impl ContextManager {
             timestamp: Utc::now(),
             user_name: user_ctx.name.clone(),
             user_preferences: user_ctx.preferences.clone(),
+            frequently_used: user_ctx.frequently_used.clone(),
             pending_command: session.pending_command.clone(),
+            pending_tool_call: session.pending_tool_call.clone(),
         })
     }
 
@@ -82,11 +238,95 @@ impl ContextManager {
         sessions.get(session_id).and_then(|s| s.pending_command.clone())
     }
 
+    /// The directory a session's relative paths resolve against
+    pub async fn working_directory(&self, session_id: &str) -> Option<String> {
+        let sessions = self.sessions.read().await;
+        sessions.get(session_id).map(|s| s.working_directory.clone())
+    }
+
     /// Clear the pending command for a session
     pub async fn clear_pending_command(&self, session_id: &str) -> Result<()> {
         self.set_pending_command(session_id, None).await
     }
 
+    /// Set the tool call waiting for the user's confirmation in a session
+    pub async fn set_pending_tool_call(
+        &self,
+        session_id: &str,
+        call: Option<crate::mcp::ToolCall>,
+    ) -> Result<()> {
+        let mut sessions = self.sessions.write().await;
+        if let Some(session) = sessions.get_mut(session_id) {
+            session.touch();
+            session.pending_tool_call_at = call.as_ref().map(|_| Utc::now());
+            session.pending_tool_call = call;
+        }
+        Ok(())
+    }
+
+    /// Clear the pending tool call for a session
+    pub async fn clear_pending_tool_call(&self, session_id: &str) -> Result<()> {
+        self.set_pending_tool_call(session_id, None).await
+    }
+
+    /// Whether the session's pending tool call has waited `ttl` or longer
+    pub async fn pending_tool_call_expired(
+        &self,
+        session_id: &str,
+        ttl: std::time::Duration,
+    ) -> bool {
+        let sessions = self.sessions.read().await;
+        sessions
+            .get(session_id)
+            .is_some_and(|s| s.pending_tool_call_expired(ttl))
+    }
+
+    /// Cancel pending tool calls that have waited `ttl` or longer,
+    /// returning how many
+    pub async fn expire_pending_tool_calls(&self, ttl: std::time::Duration) -> usize {
+        let mut sessions = self.sessions.write().await;
+        let mut expired = 0;
+        for session in sessions.values_mut() {
+            if session.pending_tool_call_expired(ttl) {
+                session.pending_tool_call = None;
+                session.pending_tool_call_at = None;
+                expired += 1;
+            }
+        }
+        if expired > 0 {
+            info!(expired_tool_calls = expired, "Cancelled expired tool calls");
+        }
+        expired
+    }
+
+    /// Pin a session to an LLM provider and persist the choice
+    pub async fn set_provider(
+        &self,
+        session_id: &str,
+        provider: crate::ipc::LlmProvider,
+    ) -> Result<()> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .entry(session_id.to_string())
+            .or_insert_with(|| SessionContext::new(session_id));
+        session.touch();
+        session.provider = provider;
+
+        let snapshot = session.clone();
+        drop(sessions);
+        self.save_session(&snapshot).await
+    }
+
+    /// The provider a session is pinned to
+    pub async fn session_provider(&self, session_id: &str) -> crate::ipc::LlmProvider {
+        self.sessions
+            .read()
+            .await
+            .get(session_id)
+            .map(|s| s.provider)
+            .unwrap_or_default()
+    }
+
     /// Update session context after an interaction
     pub async fn update_session(
         &self,
@@ -102,6 +342,7 @@ impl ContextManager {
                 timestamp: Utc::now(),
                 user: user_input.to_string(),
                 assistant: ai_response.to_string(),
+                embedding: None,
             };
             session.conversation_history.push(turn.clone());
 
@@ -109,29 +350,148 @@ impl ContextManager {
             if session.conversation_history.len() > 50 {
                 session.conversation_history.remove(0);
             }
+
+            let snapshot = session.clone();
+            drop(sessions);
+            if let Err(e) = self.save_session(&snapshot).await {
+                warn!(session = session_id, "Failed to persist session: {}", e);
+            }
+
             Ok(turn)
         } else {
             Err(anyhow::anyhow!("Session not found"))
         }
     }
 
+    /// Store the embedding for a turn (identified by its timestamp)
+    pub async fn attach_embedding(
+        &self,
+        session_id: &str,
+        turn_timestamp: DateTime<Utc>,
+        embedding: Vec<f32>,
+    ) -> Result<()> {
+        let mut sessions = self.sessions.write().await;
+        let Some(session) = sessions.get_mut(session_id) else {
+            return Ok(());
+        };
+        let Some(turn) = session
+            .conversation_history
+            .iter_mut()
+            .find(|t| t.timestamp == turn_timestamp)
+        else {
+            return Ok(());
+        };
+        turn.embedding = Some(embedding);
+
+        let snapshot = session.clone();
+        drop(sessions);
+        self.save_session(&snapshot).await
+    }
+
+    /// The `k` past turns most relevant to the current input.
+    ///
+    /// The last `RECENT_TURNS_KEPT` turns are always included; the remaining
+    /// slots go to older turns ranked by cosine similarity to
+    /// `query_embedding`. When there is no query embedding, or no older turn
+    /// has one, the `k` most recent turns are used instead. Results are in
+    /// chronological order.
+    pub async fn relevant_turns(
+        &self,
+        session_id: &str,
+        query_embedding: Option<&[f32]>,
+        k: usize,
+    ) -> Vec<ConversationTurn> {
+        let sessions = self.sessions.read().await;
+        let Some(session) = sessions.get(session_id) else {
+            return Vec::new();
+        };
+        let history = &session.conversation_history;
+        let older = history.len().saturating_sub(RECENT_TURNS_KEPT.min(k));
+
+        let mut scored: Vec<(usize, f32)> = match query_embedding {
+            Some(query) => history[..older]
+                .iter()
+                .enumerate()
+                .filter_map(|(i, turn)| {
+                    let embedding = turn.embedding.as_deref()?;
+                    Some((i, cosine_similarity(query, embedding)))
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let mut indices: Vec<usize> = if scored.is_empty() {
+            (history.len().saturating_sub(k)..history.len()).collect()
+        } else {
+            scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+            scored
+                .into_iter()
+                .take(k - (history.len() - older))
+                .map(|(i, _)| i)
+                .chain(older..history.len())
+                .collect()
+        };
+        indices.sort_unstable();
+
+        indices
+            .into_iter()
+            .map(|i| ConversationTurn {
+                embedding: None,
+                ..history[i].clone()
+            })
+            .collect()
+    }
+
     /// Record that a file was accessed
+    ///
+    /// Updates the session's recent files and the user-wide access counts
+    /// that feed `frequently_used`. The counts are written to disk after
+    /// `FILE_ACCESS_SAVE_DELAY`, or by `save_user_context`.
     pub async fn record_file_access(&self, session_id: &str, file_path: &str) -> Result<()> {
-        let mut sessions = self.sessions.write().await;
+        {
+            let mut sessions = self.sessions.write().await;
 
-        if let Some(session) = sessions.get_mut(session_id) {
-            session.touch();
-            // Remove if already present, then add to front
-            session.recent_files.retain(|f| f != file_path);
-            session.recent_files.insert(0, file_path.to_string());
+            if let Some(session) = sessions.get_mut(session_id) {
+                session.touch();
+                // Remove if already present, then add to front
+                session.recent_files.retain(|f| f != file_path);
+                session.recent_files.insert(0, file_path.to_string());
 
-            // Keep only last 20 files
-            session.recent_files.truncate(20);
+                // Keep only last 20 files
+                session.recent_files.truncate(20);
+            }
+        }
+
+        self.user_context
+            .write()
+            .await
+            .record_file_access(file_path);
+
+        if !self.file_access_save_pending.swap(true, Ordering::AcqRel) {
+            let manager = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(FILE_ACCESS_SAVE_DELAY).await;
+                manager
+                    .file_access_save_pending
+                    .store(false, Ordering::Release);
+                if let Err(e) = manager.save_user_context().await {
+                    warn!("Failed to save file access counts: {}", e);
+                }
+            });
         }
 
         Ok(())
     }
 
+    /// Write the user context to disk (used on shutdown)
+    pub async fn save_user_context(&self) -> Result<()> {
+        self.user_context
+            .read()
+            .await
+            .save(&self.config.context_path)
+            .await
+    }
+
     /// Change working directory for a session
     pub async fn set_working_directory(&self, session_id: &str, path: &str) -> Result<()> {
         let mut sessions = self.sessions.write().await;
@@ -175,6 +535,13 @@ impl ContextManager {
                 "Cleaned up stale sessions"
             );
         }
+        drop(sessions);
+
+        match self.cleanup_session_files(cutoff).await {
+            Ok(0) => {}
+            Ok(files) => info!(removed_files = files, "Cleaned up stale session files"),
+            Err(e) => warn!("Failed to clean up session files: {}", e),
+        }
 
         removed
     }
@@ -183,6 +550,48 @@ impl ContextManager {
     pub async fn session_count(&self) -> usize {
         self.sessions.read().await.len()
     }
+
+    /// Search conversation history across all sessions.
+    ///
+    /// A turn matches when every whitespace-separated query term appears
+    /// (case-insensitively) in either the user or assistant text. Results
+    /// are newest first. Persisted sessions are loaded at startup, so they
+    /// are covered too.
+    pub async fn search_history(&self, query: &str, limit: usize) -> Vec<HistoryMatch> {
+        let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        if terms.is_empty() || limit == 0 {
+            return Vec::new();
+        }
+
+        let sessions = self.sessions.read().await;
+        let mut matches: Vec<HistoryMatch> = sessions
+            .values()
+            .flat_map(|session| {
+                session
+                    .conversation_history
+                    .iter()
+                    .map(move |turn| (session.id.as_str(), turn))
+            })
+            .filter(|(_, turn)| {
+                let user = turn.user.to_lowercase();
+                let assistant = turn.assistant.to_lowercase();
+                terms
+                    .iter()
+                    .all(|t| user.contains(t.as_str()) || assistant.contains(t.as_str()))
+            })
+            .map(|(session_id, turn)| HistoryMatch {
+                session_id: session_id.to_string(),
+                turn: ConversationTurn {
+                    embedding: None,
+                    ..turn.clone()
+                },
+            })
+            .collect();
+
+        matches.sort_by_key(|m| std::cmp::Reverse(m.turn.timestamp));
+        matches.truncate(limit);
+        matches
+    }
 }
 
 /// The context passed to AI for each interaction
@@ -195,7 +604,13 @@ pub struct Context {
     pub timestamp: DateTime<Utc>,
     pub user_name: Option<String>,
     pub user_preferences: HashMap<String, String>,
+    /// Files the user opens most often, across sessions
+    #[serde(default)]
+    pub frequently_used: Vec<String>,
     pub pending_command: Option<String>,
+    /// Tool call waiting for the user to confirm it
+    #[serde(default)]
+    pub pending_tool_call: Option<crate::mcp::ToolCall>,
 }
 
 /// A single conversation turn
@@ -204,6 +619,30 @@ pub struct ConversationTurn {
     pub timestamp: DateTime<Utc>,
     pub user: String,
     pub assistant: String,
+    /// Embedding of the turn for semantic retrieval, once computed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<Vec<f32>>,
+}
+
+/// Cosine similarity of two vectors (0.0 if they can't be compared)
+pub(crate) fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+/// A conversation turn found by `search_history`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryMatch {
+    pub session_id: String,
+    pub turn: ConversationTurn,
 }
 
 /// Per-session context
@@ -217,6 +656,15 @@ pub struct SessionContext {
     pub conversation_history: Vec<ConversationTurn>,
     pub metadata: HashMap<String, String>,
     pub pending_command: Option<String>,
+    /// Tool call waiting for the user to confirm it
+    #[serde(default)]
+    pub pending_tool_call: Option<crate::mcp::ToolCall>,
+    /// When `pending_tool_call` was set
+    #[serde(default)]
+    pub pending_tool_call_at: Option<DateTime<Utc>>,
+    /// LLM provider this session is pinned to (Auto follows the config)
+    #[serde(default)]
+    pub provider: crate::ipc::LlmProvider,
 }
 
 impl SessionContext {
@@ -233,6 +681,9 @@ impl SessionContext {
             conversation_history: Vec::new(),
             metadata: HashMap::new(),
             pending_command: None,
+            pending_tool_call: None,
+            pending_tool_call_at: None,
+            provider: crate::ipc::LlmProvider::Auto,
         }
     }
 
@@ -240,6 +691,20 @@ impl SessionContext {
     pub fn touch(&mut self) {
         self.last_accessed = Utc::now();
     }
+
+    /// Whether a tool call is pending and has waited `ttl` or longer.
+    /// Calls restored without a timestamp count as expired.
+    fn pending_tool_call_expired(&self, ttl: std::time::Duration) -> bool {
+        if self.pending_tool_call.is_none() {
+            return false;
+        }
+        self.pending_tool_call_at.is_none_or(|at| {
+            Utc::now()
+                .signed_duration_since(at)
+                .to_std()
+                .is_ok_and(|waited| waited >= ttl)
+        })
+    }
 }
 
 /// Persistent user context
@@ -248,7 +713,14 @@ pub struct UserContext {
     pub name: Option<String>,
     pub preferences: HashMap<String, String>,
     pub learned_patterns: Vec<LearnedPattern>,
+    /// Most-accessed files, highest count first
     pub frequently_used: Vec<String>,
+    /// How many times each file has been accessed
+    #[serde(default)]
+    pub file_access_counts: HashMap<String, u32>,
+    /// When each counted file was last accessed
+    #[serde(default)]
+    pub file_last_accessed: HashMap<String, DateTime<Utc>>,
 }
 
 impl UserContext {
@@ -270,6 +742,99 @@ impl UserContext {
         tokio::fs::write(&context_file, content).await?;
         Ok(())
     }
+
+    /// Count an access to a file and refresh `frequently_used`
+    pub fn record_file_access(&mut self, file_path: &str) {
+        *self
+            .file_access_counts
+            .entry(file_path.to_string())
+            .or_insert(0) += 1;
+        self.file_last_accessed
+            .insert(file_path.to_string(), Utc::now());
+
+        // Most used first; among equals the most recently used, so the file
+        // just added isn't the one evicted
+        let mut ranked: Vec<(String, u32, Option<DateTime<Utc>>)> = self
+            .file_access_counts
+            .iter()
+            .map(|(path, count)| {
+                let last = self.file_last_accessed.get(path).copied();
+                (path.clone(), *count, last)
+            })
+            .collect();
+        ranked.sort_by(|a, b| {
+            b.1.cmp(&a.1)
+                .then_with(|| b.2.cmp(&a.2))
+                .then_with(|| a.0.cmp(&b.0))
+        });
+
+        for (path, _, _) in ranked.iter().skip(MAX_TRACKED_FILES) {
+            self.file_access_counts.remove(path);
+            self.file_last_accessed.remove(path);
+        }
+
+        self.frequently_used = ranked
+            .into_iter()
+            .filter(|(_, count, _)| *count >= FREQUENT_MIN_ACCESSES)
+            .take(FREQUENT_FILES)
+            .map(|(path, _, _)| path)
+            .collect();
+    }
+}
+
+/// Lock file claiming a context path for one daemon
+pub const LOCK_FILE: &str = "mycel.lock";
+
+/// Exclusive advisory lock on a context path, released when dropped
+pub struct InstanceLock {
+    file: std::fs::File,
+}
+
+impl InstanceLock {
+    /// Lock `<context_path>/mycel.lock`, failing if another process holds it
+    pub fn acquire(context_path: &str) -> Result<Self> {
+        use std::io::{Read, Write};
+
+        std::fs::create_dir_all(context_path)?;
+        let path = Path::new(context_path).join(LOCK_FILE);
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(std::fs::TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                let _ = file.read_to_string(&mut holder);
+                let holder = match holder.trim() {
+                    "" => String::new(),
+                    pid => format!(" (pid {})", pid),
+                };
+                anyhow::bail!(
+                    "Another Mycel instance{} is using {}; stop it first, or run with --dev to use a separate context path",
+                    holder,
+                    context_path
+                );
+            }
+            Err(std::fs::TryLockError::Error(e)) => {
+                return Err(anyhow::anyhow!("Failed to lock {}: {}", path.display(), e));
+            }
+        }
+
+        // Name the holder for the error above
+        file.set_len(0)?;
+        file.write_all(std::process::id().to_string().as_bytes())?;
+        Ok(Self { file })
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
 }
 
 /// A pattern learned from user behavior
@@ -292,6 +857,230 @@ mod tests {
         assert!(session.conversation_history.is_empty());
     }
 
+    fn temp_config() -> MycelConfig {
+        let dir = std::env::temp_dir().join(format!("mycel-ctx-{}", uuid::Uuid::new_v4()));
+        MycelConfig {
+            context_path: dir.to_string_lossy().to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_second_instance_lock_fails() {
+        let config = temp_config();
+
+        let first = InstanceLock::acquire(&config.context_path).unwrap();
+        let err = InstanceLock::acquire(&config.context_path)
+            .err()
+            .expect("second lock on the same path should fail");
+        let message = err.to_string();
+        assert!(message.contains("Another Mycel instance"), "{}", message);
+        assert!(
+            message.contains(&format!("pid {}", std::process::id())),
+            "{}",
+            message
+        );
+
+        // Released on drop
+        drop(first);
+        assert!(InstanceLock::acquire(&config.context_path).is_ok());
+
+        let _ = std::fs::remove_dir_all(&config.context_path);
+    }
+
+    #[tokio::test]
+    async fn test_sessions_survive_restart() {
+        let config = temp_config();
+
+        let manager = ContextManager::new(&config).await.unwrap();
+        manager.get_context("resume-me").await.unwrap();
+        manager
+            .update_session("resume-me", "hello", "hi there")
+            .await
+            .unwrap();
+
+        let restarted = ContextManager::new(&config).await.unwrap();
+        assert_eq!(restarted.session_count().await, 1);
+        let ctx = restarted.get_context("resume-me").await.unwrap();
+        assert_eq!(ctx.conversation_history.len(), 1);
+        assert_eq!(ctx.conversation_history[0].user, "hello");
+
+        let _ = std::fs::remove_dir_all(&config.context_path);
+    }
+
+    #[tokio::test]
+    async fn test_frequently_used_files() {
+        let config = temp_config();
+
+        let manager = ContextManager::new(&config).await.unwrap();
+        manager.get_context("files").await.unwrap();
+        for _ in 0..3 {
+            manager
+                .record_file_access("files", "/home/user/notes.md")
+                .await
+                .unwrap();
+        }
+        manager
+            .record_file_access("files", "/tmp/once.txt")
+            .await
+            .unwrap();
+
+        let ctx = manager.get_context("files").await.unwrap();
+        assert_eq!(ctx.frequently_used, vec!["/home/user/notes.md".to_string()]);
+        assert_eq!(ctx.recent_files[0], "/tmp/once.txt");
+
+        manager.save_user_context().await.unwrap();
+        let reloaded = UserContext::load_or_default(&config.context_path)
+            .await
+            .unwrap();
+        assert_eq!(reloaded.file_access_counts["/home/user/notes.md"], 3);
+        assert_eq!(reloaded.frequently_used, ctx.frequently_used);
+
+        let _ = std::fs::remove_dir_all(&config.context_path);
+    }
+
+    #[test]
+    fn test_file_access_eviction_keeps_newest_on_tie() {
+        let mut user_ctx = UserContext::default();
+        for i in 0..MAX_TRACKED_FILES {
+            user_ctx.record_file_access(&format!("/tmp/{:03}", i));
+        }
+
+        // At the cap every file has one access; the oldest makes room
+        user_ctx.record_file_access("/tmp/new");
+        assert_eq!(user_ctx.file_access_counts.len(), MAX_TRACKED_FILES);
+        assert!(user_ctx.file_access_counts.contains_key("/tmp/new"));
+        assert!(!user_ctx.file_access_counts.contains_key("/tmp/000"));
+        assert!(!user_ctx.file_last_accessed.contains_key("/tmp/000"));
+    }
+
+    #[tokio::test]
+    async fn test_session_provider_persists() {
+        use crate::ipc::LlmProvider;
+
+        let config = temp_config();
+        let manager = ContextManager::new(&config).await.unwrap();
+        assert_eq!(manager.session_provider("pinned").await, LlmProvider::Auto);
+
+        manager
+            .set_provider("pinned", LlmProvider::Cloud)
+            .await
+            .unwrap();
+        assert_eq!(manager.session_provider("pinned").await, LlmProvider::Cloud);
+
+        let restarted = ContextManager::new(&config).await.unwrap();
+        assert_eq!(
+            restarted.session_provider("pinned").await,
+            LlmProvider::Cloud
+        );
+
+        let _ = std::fs::remove_dir_all(&config.context_path);
+    }
+
+    #[tokio::test]
+    async fn test_stale_sessions_not_restored() {
+        let config = temp_config();
+        let manager = ContextManager::new(&config).await.unwrap();
+
+        let mut stale = SessionContext::new("old/../session");
+        stale.last_accessed = Utc::now() - Duration::hours(DEFAULT_SESSION_TTL_HOURS + 1);
+        manager.save_session(&stale).await.unwrap();
+        let file = manager.session_file(&stale.id);
+        assert!(file.starts_with(manager.sessions_dir()));
+        assert!(file.exists());
+
+        let restarted = ContextManager::new(&config).await.unwrap();
+        assert_eq!(restarted.session_count().await, 0);
+        assert!(!file.exists());
+
+        let _ = std::fs::remove_dir_all(&config.context_path);
+    }
+
+    #[tokio::test]
+    async fn test_search_history() {
+        let config = temp_config();
+        let manager = ContextManager::new(&config).await.unwrap();
+
+        manager.get_context("a").await.unwrap();
+        manager.get_context("b").await.unwrap();
+        manager
+            .update_session("a", "how do I restart Postgres?", "sv restart postgresql")
+            .await
+            .unwrap();
+        manager
+            .update_session("b", "list files", "ls -la")
+            .await
+            .unwrap();
+        manager
+            .update_session("b", "postgres logs", "tail /var/log/postgresql.log")
+            .await
+            .unwrap();
+
+        let results = manager.search_history("POSTGRES", 10).await;
+        assert_eq!(results.len(), 2);
+        // Newest first
+        assert_eq!(results[0].session_id, "b");
+        assert_eq!(results[1].session_id, "a");
+
+        let results = manager.search_history("restart postgres", 10).await;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].turn.assistant, "sv restart postgresql");
+
+        assert_eq!(manager.search_history("postgres", 1).await.len(), 1);
+        assert!(manager.search_history("   ", 10).await.is_empty());
+
+        let _ = std::fs::remove_dir_all(&config.context_path);
+    }
+
+    #[tokio::test]
+    async fn test_relevant_turns() {
+        let config = temp_config();
+        let manager = ContextManager::new(&config).await.unwrap();
+        manager.get_context("s").await.unwrap();
+
+        let topics = [
+            ("postgres setup", vec![1.0, 0.0, 0.0]),
+            ("weather today", vec![0.0, 1.0, 0.0]),
+            ("postgres backup", vec![0.9, 0.1, 0.0]),
+            ("music", vec![0.0, 0.0, 1.0]),
+        ];
+        for (text, _) in &topics {
+            manager.update_session("s", text, "ok").await.unwrap();
+        }
+
+        // Without embeddings: most recent turns
+        let recent = manager.relevant_turns("s", Some(&[1.0, 0.0, 0.0]), 2).await;
+        let users: Vec<_> = recent.iter().map(|t| t.user.as_str()).collect();
+        assert_eq!(users, ["postgres backup", "music"]);
+
+        let history = manager.get_context("s").await.unwrap().conversation_history;
+        for (turn, (_, embedding)) in history.iter().zip(&topics) {
+            manager
+                .attach_embedding("s", turn.timestamp, embedding.clone())
+                .await
+                .unwrap();
+        }
+
+        // With embeddings: the latest turns plus the most similar older ones,
+        // in chronological order
+        let relevant = manager.relevant_turns("s", Some(&[1.0, 0.0, 0.0]), 3).await;
+        let users: Vec<_> = relevant.iter().map(|t| t.user.as_str()).collect();
+        assert_eq!(users, ["postgres setup", "postgres backup", "music"]);
+        assert!(relevant.iter().all(|t| t.embedding.is_none()));
+
+        // A new turn whose embedding hasn't arrived yet is still kept
+        manager.update_session("s", "cooking", "ok").await.unwrap();
+        let relevant = manager.relevant_turns("s", Some(&[1.0, 0.0, 0.0]), 3).await;
+        let users: Vec<_> = relevant.iter().map(|t| t.user.as_str()).collect();
+        assert_eq!(users, ["postgres setup", "music", "cooking"]);
+
+        // No query embedding falls back to recency
+        let recent = manager.relevant_turns("s", None, 1).await;
+        assert_eq!(recent[0].user, "cooking");
+
+        let _ = std::fs::remove_dir_all(&config.context_path);
+    }
+
     #[test]
     fn test_session_touch() {
         let mut session = SessionContext::new("test");
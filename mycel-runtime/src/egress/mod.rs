@@ -0,0 +1,167 @@
+//! Egress - which hosts Mycel may make HTTP requests to
+//!
+//! Every outbound request (Ollama, OpenRouter, Hugging Face, NEAR RPC,
+//! Bittensor) goes through [`HttpClient`], which refuses hosts that
+//! `egress_allowlist`/`egress_denylist` rule out before anything is sent,
+//! including redirects to them.
+
+use reqwest::{redirect, RequestBuilder, Url};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use crate::config::MycelConfig;
+use crate::error::{Error, Result};
+
+/// Redirects followed before giving up, as reqwest does by default
+const MAX_REDIRECTS: usize = 10;
+
+/// Host rules for outbound requests. A rule matches the host and its
+/// subdomains: `openrouter.ai` also covers `api.openrouter.ai`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EgressPolicy {
+    /// Only these hosts may be reached; `None` allows any, and an empty list
+    /// blocks every host but loopback
+    #[serde(default)]
+    pub allowlist: Option<Vec<String>>,
+    /// These hosts may never be reached, loopback included
+    #[serde(default)]
+    pub denylist: Vec<String>,
+}
+
+impl EgressPolicy {
+    pub fn from_config(config: &MycelConfig) -> Self {
+        Self {
+            allowlist: config.egress_allowlist.clone(),
+            denylist: config.egress_denylist.clone(),
+        }
+    }
+
+    /// Why a request to `url` is refused, or `None` if it may go out
+    pub fn check(&self, url: &Url) -> Option<String> {
+        let Some(host) = url.host_str() else {
+            return Some(format!("{} has no host", url));
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if self.denylist.iter().any(|rule| host_matches(host, rule)) {
+            return Some(format!("{} is in egress_denylist", host));
+        }
+        // Ollama and other local services keep working in pure local mode
+        if is_loopback(host) {
+            return None;
+        }
+        match &self.allowlist {
+            Some(allowed) if !allowed.iter().any(|rule| host_matches(host, rule)) => {
+                Some(format!("{} is not in egress_allowlist", host))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// `host` is `rule` or one of its subdomains
+fn host_matches(host: &str, rule: &str) -> bool {
+    let rule = rule.trim().trim_start_matches("*.").trim_end_matches('.');
+    host.eq_ignore_ascii_case(rule)
+        || host
+            .to_ascii_lowercase()
+            .ends_with(&format!(".{}", rule.to_ascii_lowercase()))
+}
+
+fn is_loopback(host: &str) -> bool {
+    host.eq_ignore_ascii_case("localhost")
+        || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// `reqwest::Client` that checks the egress policy before each request
+#[derive(Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    policy: Arc<EgressPolicy>,
+}
+
+impl HttpClient {
+    /// Build a client from `builder`, with redirects held to `policy` too
+    pub fn new(builder: reqwest::ClientBuilder, policy: EgressPolicy) -> Result<Self> {
+        let policy = Arc::new(policy);
+        let redirects = Arc::clone(&policy);
+        let client = builder
+            .redirect(redirect::Policy::custom(move |attempt| {
+                if let Some(reason) = redirects.check(attempt.url()) {
+                    attempt.error(Error::EgressDenied(reason))
+                } else if attempt.previous().len() >= MAX_REDIRECTS {
+                    attempt.error("too many redirects")
+                } else {
+                    attempt.follow()
+                }
+            }))
+            .build()
+            .map_err(|e| Error::Other(e.into()))?;
+        Ok(Self { client, policy })
+    }
+
+    pub fn get(&self, url: impl AsRef<str>) -> Result<RequestBuilder> {
+        self.checked(url.as_ref()).map(|url| self.client.get(url))
+    }
+
+    pub fn post(&self, url: impl AsRef<str>) -> Result<RequestBuilder> {
+        self.checked(url.as_ref()).map(|url| self.client.post(url))
+    }
+
+    fn checked(&self, url: &str) -> Result<Url> {
+        let url = Url::parse(url).map_err(|e| Error::Other(e.into()))?;
+        match self.policy.check(&url) {
+            Some(reason) => Err(Error::EgressDenied(reason)),
+            None => Ok(url),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_denied_host_is_refused_before_sending() {
+        // Anything reaching this listener means the request went out
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/tags", listener.local_addr().unwrap());
+
+        let policy = EgressPolicy {
+            allowlist: Some(vec!["openrouter.ai".to_string()]),
+            denylist: vec!["127.0.0.1".to_string()],
+        };
+        let client = HttpClient::new(reqwest::Client::builder(), policy.clone()).unwrap();
+        let err = client.get(&url).unwrap_err();
+        assert!(matches!(err, Error::EgressDenied(_)));
+        assert_eq!(err.code(), Some("egress_denied"));
+        assert!(err.to_string().contains("egress_denylist"), "{}", err);
+        let accepted =
+            tokio::time::timeout(std::time::Duration::from_millis(100), listener.accept()).await;
+        assert!(accepted.is_err(), "request reached the denied host");
+
+        let allowed = |url: &str| policy.check(&Url::parse(url).unwrap()).is_none();
+        assert!(allowed("https://openrouter.ai/api/v1/chat/completions"));
+        assert!(allowed("https://api.openrouter.ai/v1"));
+        assert!(!allowed("https://huggingface.co/api/models"));
+        assert!(!allowed("https://notopenrouter.ai/"));
+        // Loopback stays reachable unless denied outright
+        assert!(allowed("http://localhost:11434/api/tags"));
+        assert!(!allowed("http://127.0.0.1:11434/api/tags"));
+
+        // An empty allowlist is pure local mode
+        let local = EgressPolicy {
+            allowlist: Some(Vec::new()),
+            denylist: Vec::new(),
+        };
+        assert!(local
+            .check(&Url::parse("http://[::1]:11434/").unwrap())
+            .is_none());
+        assert!(local
+            .check(&Url::parse("https://rpc.mainnet.near.org").unwrap())
+            .is_some());
+        assert!(EgressPolicy::default()
+            .check(&Url::parse("https://huggingface.co/").unwrap())
+            .is_none());
+    }
+}
@@ -0,0 +1,100 @@
+//! Error - structured failures callers can match on
+//!
+//! Most code uses `anyhow` internally. Failures a caller may want to react
+//! to (prompting for an API key, offering to start Ollama) are raised as
+//! [`Error`] variants, which survive being wrapped in an `anyhow::Error` and
+//! reach IPC clients as a stable `code`.
+
+use std::time::Duration;
+
+/// Result type for public APIs that return [`Error`]
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Ollama isn't reachable
+    #[error("Local LLM (Ollama) is not available")]
+    LocalUnavailable,
+    /// No OpenRouter API key is set
+    #[error("Cloud LLM is not configured. Set OPENROUTER_API_KEY.")]
+    CloudUnconfigured,
+    /// Neither Ollama nor a cloud key is available, so nothing can generate
+    #[error("No LLM backend available; start Ollama or set OPENROUTER_API_KEY")]
+    NoBackend,
+    /// An MCP server didn't answer in time
+    #[error("Request timed out after {0:?}")]
+    ToolTimeout(Duration),
+    /// Code ran past `execution_timeout_secs`
+    #[error("Execution timed out after {0} seconds")]
+    ExecutionTimeout(u64),
+    /// Configuration forbids the action
+    #[error("{0}")]
+    PolicyDenied(String),
+    /// `egress_allowlist`/`egress_denylist` forbid the request's host
+    #[error("Outbound request blocked: {0}")]
+    EgressDenied(String),
+    /// A confirmation was answered after `confirmation_ttl_secs`
+    #[error("Confirmation expired: {0}. Ask again to re-request it.")]
+    ConfirmationExpired(String),
+    /// The program needed to run code isn't installed
+    #[error("'{0}' is not installed")]
+    InterpreterMissing(String),
+    /// A model request was aborted through the router's cancellation token
+    #[error("generation cancelled")]
+    Cancelled,
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl Error {
+    /// Stable identifier for IPC clients; `None` for unstructured errors
+    pub fn code(&self) -> Option<&'static str> {
+        match self {
+            Self::LocalUnavailable => Some("local_unavailable"),
+            Self::CloudUnconfigured => Some("cloud_unconfigured"),
+            Self::NoBackend => Some("no_backend"),
+            Self::ToolTimeout(_) => Some("tool_timeout"),
+            Self::ExecutionTimeout(_) => Some("execution_timeout"),
+            Self::PolicyDenied(_) => Some("policy_denied"),
+            Self::EgressDenied(_) => Some("egress_denied"),
+            Self::ConfirmationExpired(_) => Some("confirmation_expired"),
+            Self::InterpreterMissing(_) => Some("interpreter_missing"),
+            Self::Cancelled => Some("cancelled"),
+            Self::Other(_) => None,
+        }
+    }
+
+    /// The structured error in `err`'s chain, if there is one
+    pub fn find(err: &anyhow::Error) -> Option<&Error> {
+        err.chain().find_map(|e| e.downcast_ref::<Error>())
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        err.downcast().unwrap_or_else(Error::Other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_structured_error_survives_anyhow() {
+        let wrapped: anyhow::Result<()> = Err(Error::CloudUnconfigured.into());
+        let err = wrapped.context("Chat failed").unwrap_err();
+        assert_eq!(
+            Error::find(&err).and_then(Error::code),
+            Some("cloud_unconfigured")
+        );
+
+        // Converting back recovers the variant rather than wrapping it
+        let err: Error = anyhow::Error::from(Error::LocalUnavailable).into();
+        assert!(matches!(err, Error::LocalUnavailable));
+        let err: Error = anyhow::anyhow!("disk full").into();
+        assert_eq!(err.code(), None);
+        assert_eq!(err.to_string(), "disk full");
+    }
+}
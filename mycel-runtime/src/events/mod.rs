@@ -19,4 +19,28 @@ pub enum SystemEvent {
     McpServerRestarted {
         name: String,
     },
+    /// Fired when an MCP server used up its restart attempts and was given up on
+    McpServerFailed {
+        name: String,
+        reason: String,
+    },
+    /// Progress of a model download (0-100)
+    ModelDownloadProgress {
+        model: String,
+        percent: u8,
+    },
+    /// A capability is being set up (writing code, installing dependencies, ...)
+    CapabilityInstalling {
+        name: String,
+        step: String,
+    },
+    /// An LLM request was sent ("local" or "cloud")
+    InferenceStarted {
+        source: String,
+    },
+    /// An LLM request returned successfully
+    InferenceCompleted {
+        source: String,
+        duration_ms: u64,
+    },
 }
@@ -0,0 +1,136 @@
+//! Commands on `PATH` - suggest the intended command for a mistyped one
+//!
+//! `gti` isn't a package anyone wants; it's `git` with two letters swapped.
+//! Before searching the repositories for a missing command, the names of the
+//! executables on `PATH` are checked for a near miss.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Most edits between a typed command and a suggestion
+const MAX_DISTANCE: usize = 2;
+
+/// How long a scan of `PATH` is reused, so new installs show up eventually
+const CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Cached names of the executables on `PATH`
+#[derive(Default)]
+pub struct CommandIndex {
+    cached: Mutex<Option<(Instant, Arc<Vec<String>>)>>,
+}
+
+impl CommandIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The installed command `typed` was most likely meant to be, if it
+    /// isn't installed itself
+    pub async fn closest(&self, typed: &str) -> Option<String> {
+        closest_command(typed, &self.commands().await).map(str::to_string)
+    }
+
+    async fn commands(&self) -> Arc<Vec<String>> {
+        if let Some((scanned, commands)) = self.lock().as_ref() {
+            if scanned.elapsed() < CACHE_TTL {
+                return Arc::clone(commands);
+            }
+        }
+        // Reading every PATH directory blocks, so keep it off the runtime
+        let commands = Arc::new(
+            tokio::task::spawn_blocking(scan_path)
+                .await
+                .unwrap_or_default(),
+        );
+        *self.lock() = Some((Instant::now(), Arc::clone(&commands)));
+        commands
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<(Instant, Arc<Vec<String>>)>> {
+        self.cached.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Executable file names in the `PATH` directories, sorted and deduplicated
+fn scan_path() -> Vec<String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::var_os("PATH").unwrap_or_else(|| super::DEFAULT_PATH.into());
+    let mut commands: Vec<String> = std::env::split_paths(&path)
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .metadata()
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    commands.sort();
+    commands.dedup();
+    commands
+}
+
+/// The command in `commands` fewest edits away from `typed`, alphabetically
+/// first on a tie. Short names allow fewer edits (none for two letters, one
+/// for three or four), or `ls` would match half of `/usr/bin`. `None` if
+/// `typed` is itself listed.
+fn closest_command<'a>(typed: &str, commands: &'a [String]) -> Option<&'a str> {
+    if commands.iter().any(|c| c == typed) {
+        return None;
+    }
+    let max = MAX_DISTANCE.min(typed.chars().count().saturating_sub(1) / 2);
+    commands
+        .iter()
+        .map(|c| (edit_distance(typed, c), c.as_str()))
+        .filter(|(distance, _)| *distance <= max)
+        .min()
+        .map(|(_, command)| command)
+}
+
+/// Levenshtein distance, with swapping two adjacent characters counted as
+/// one edit (optimal string alignment)
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // Rows for i - 2, i - 1 and i
+    let mut before: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+        before = std::mem::replace(&mut previous, current);
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typo_suggests_installed_command() {
+        let commands: Vec<String> = ["gdb", "git", "grep", "ls", "sl", "tig"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+
+        assert_eq!(edit_distance("gti", "git"), 1);
+        assert_eq!(closest_command("gti", &commands), Some("git"));
+        assert_eq!(closest_command("gerp", &commands), Some("grep"));
+        // Installed already, or too far from anything
+        assert_eq!(closest_command("git", &commands), None);
+        assert_eq!(closest_command("docker", &commands), None);
+        // Two letters allow no edits at all
+        assert_eq!(closest_command("lx", &commands), None);
+    }
+}
@@ -5,15 +5,44 @@
 //!
 //! Security model: The AI is trusted. Users interact through natural language,
 //! and the AI decides what code to run. The AI is responsible for safety.
+//!
+//! Children still get a predictable setting: a minimal environment (`PATH`,
+//! `HOME`, `LANG` and the configured allowlist) and `<code_path>/workdir` as
+//! their working directory, so results don't depend on how the daemon was
+//! launched and daemon secrets don't leak into generated code.
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use std::process::Stdio;
 use std::time::Duration;
 use tokio::process::Command;
 use tokio::time::timeout;
 use tracing::{debug, info};
 
+use crate::codegen::CodeLanguage;
 use crate::config::MycelConfig;
+use crate::error::Error;
+
+mod commands;
+mod packages;
+pub use commands::CommandIndex;
+pub use packages::PackageManager;
+
+/// Variables every child gets, taken from the daemon's environment
+const BASE_ENV: &[&str] = &["PATH", "HOME", "LANG"];
+
+/// Used when the daemon itself has no `PATH`
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// What running a piece of code produced
+#[derive(Debug, Clone)]
+pub struct ExecutionResult {
+    /// stdout, or stderr and stdout combined when the code failed
+    pub output: String,
+    /// The process exited with status 0
+    pub success: bool,
+    /// `None` when the process was killed by a signal
+    pub exit_code: Option<i32>,
+}
 
 /// Code executor - runs AI-generated code with full system access
 #[derive(Clone)]
@@ -30,45 +59,51 @@ impl CodeExecutor {
     }
 
     /// Execute code and return output
-    pub async fn run(&self, code: &str) -> Result<String> {
-        let language = self.detect_language(code);
-
-        info!(language = ?language, "Executing kernel-generated code");
+    pub async fn run(&self, code: &str) -> crate::error::Result<String> {
+        self.run_as(code, None).await
+    }
 
-        match language {
-            Language::Python => self.run_python(code).await,
-            Language::JavaScript => self.run_javascript(code).await,
-            Language::Shell => self.run_shell(code).await,
-        }
+    /// Execute code as `hint` (e.g. from a code fence tag), or as
+    /// [`CodeLanguage::detect`] classifies it
+    pub async fn run_as(
+        &self,
+        code: &str,
+        hint: Option<CodeLanguage>,
+    ) -> crate::error::Result<String> {
+        Ok(self.execute(code, hint).await?.output)
     }
 
-    fn detect_language(&self, code: &str) -> Language {
-        let code_lower = code.to_lowercase();
-        let first_line = code.lines().next().unwrap_or("");
+    /// Execute code, reporting whether it succeeded alongside its output
+    pub async fn run_detailed(&self, code: &str) -> crate::error::Result<ExecutionResult> {
+        self.execute(code, None).await
+    }
 
-        // Check shebang first
-        if first_line.starts_with("#!/usr/bin/python") || first_line.starts_with("#!/usr/bin/env python") {
-            return Language::Python;
-        }
-        if first_line.starts_with("#!/bin/bash") || first_line.starts_with("#!/bin/sh") {
-            return Language::Shell;
-        }
-        if first_line.starts_with("#!/usr/bin/node") || first_line.starts_with("#!/usr/bin/env node") {
-            return Language::JavaScript;
-        }
+    /// `run_detailed` as `hint`, like `run_as`
+    pub async fn run_detailed_as(
+        &self,
+        code: &str,
+        hint: Option<CodeLanguage>,
+    ) -> crate::error::Result<ExecutionResult> {
+        self.execute(code, hint).await
+    }
 
-        // Python indicators
-        if code_lower.contains("import ") || code_lower.contains("def ") || code_lower.contains("print(") {
-            return Language::Python;
-        }
+    async fn execute(
+        &self,
+        code: &str,
+        hint: Option<CodeLanguage>,
+    ) -> crate::error::Result<ExecutionResult> {
+        let language = runnable_language(code, hint)?;
 
-        // JavaScript indicators
-        if code_lower.contains("const ") || code_lower.contains("function ") || code_lower.contains("console.log") {
-            return Language::JavaScript;
-        }
+        info!(language = ?language, "Executing kernel-generated code");
 
-        // Default to shell - the AI can run any command
-        Language::Shell
+        let output = match language {
+            CodeLanguage::Python => self.run_python(code).await,
+            CodeLanguage::JavaScript => self.run_javascript(code).await,
+            CodeLanguage::Go => self.run_go(code).await,
+            CodeLanguage::Ruby => self.run_ruby(code).await,
+            _ => self.run_shell(code).await,
+        };
+        Ok(output?)
     }
 
     async fn write_to_temp_file(&self, code: &str, extension: &str) -> Result<std::path::PathBuf> {
@@ -84,7 +119,7 @@ impl CodeExecutor {
         Ok(path)
     }
 
-    async fn run_python(&self, code: &str) -> Result<String> {
+    async fn run_python(&self, code: &str) -> Result<ExecutionResult> {
         debug!("Executing Python code as kernel");
 
         let path = self.write_to_temp_file(code, "py").await?;
@@ -101,7 +136,7 @@ impl CodeExecutor {
         result
     }
 
-    async fn run_javascript(&self, code: &str) -> Result<String> {
+    async fn run_javascript(&self, code: &str) -> Result<ExecutionResult> {
         debug!("Executing JavaScript code as kernel");
 
         let path = self.write_to_temp_file(code, "js").await?;
@@ -118,7 +153,33 @@ impl CodeExecutor {
         result
     }
 
-    async fn run_shell(&self, code: &str) -> Result<String> {
+    async fn run_go(&self, code: &str) -> Result<ExecutionResult> {
+        debug!("Executing Go code as kernel");
+
+        let path = self.write_to_temp_file(code, "go").await?;
+        let path_str = path.to_string_lossy().to_string();
+
+        let mut cmd = Command::new("go");
+        cmd.arg("run").arg(&path_str);
+
+        let result = self.execute_with_timeout(cmd).await;
+
+        // Cleanup
+        let _ = tokio::fs::remove_file(path).await;
+
+        result
+    }
+
+    async fn run_ruby(&self, code: &str) -> Result<ExecutionResult> {
+        debug!("Executing Ruby code as kernel");
+
+        let mut cmd = Command::new("ruby");
+        cmd.arg("-e").arg(code);
+
+        self.execute_with_timeout(cmd).await
+    }
+
+    async fn run_shell(&self, code: &str) -> Result<ExecutionResult> {
         debug!("Executing shell code as kernel");
 
         // For shell, we still use -c because it's often simpler for one-liners
@@ -132,7 +193,44 @@ impl CodeExecutor {
         self.execute_with_timeout(cmd).await
     }
 
-    async fn execute_with_timeout(&self, mut cmd: Command) -> Result<String> {
+    /// Whether generated code can run here: `bash`, which runs anything
+    /// unlabelled, is on `PATH` and the work directory can be created
+    pub async fn is_available(&self) -> bool {
+        let path = std::env::var_os("PATH").unwrap_or_else(|| DEFAULT_PATH.into());
+        let has_shell = std::env::split_paths(&path).any(|dir| dir.join("bash").is_file());
+        has_shell && tokio::fs::create_dir_all(self.work_dir()).await.is_ok()
+    }
+
+    /// Working directory for executed code
+    fn work_dir(&self) -> std::path::PathBuf {
+        std::path::Path::new(&self.config.code_path).join("workdir")
+    }
+
+    /// Strip the inherited environment down to the base variables plus the
+    /// allowlist, and run from the work directory
+    async fn isolate(&self, cmd: &mut Command) -> Result<()> {
+        let work_dir = self.work_dir();
+        tokio::fs::create_dir_all(&work_dir).await?;
+
+        cmd.env_clear().current_dir(&work_dir);
+        let allowlist = self
+            .config
+            .execution_env_allowlist
+            .iter()
+            .map(String::as_str);
+        for name in BASE_ENV.iter().copied().chain(allowlist) {
+            if let Ok(value) = std::env::var(name) {
+                cmd.env(name, value);
+            }
+        }
+        if std::env::var_os("PATH").is_none() {
+            cmd.env("PATH", DEFAULT_PATH);
+        }
+        Ok(())
+    }
+
+    async fn execute_with_timeout(&self, mut cmd: Command) -> Result<ExecutionResult> {
+        self.isolate(&mut cmd).await?;
         let timeout_duration = Duration::from_secs(self.config.execution_timeout_secs);
 
         let output = match timeout(
@@ -141,24 +239,26 @@ impl CodeExecutor {
         )
         .await
         {
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                let program = cmd.as_std().get_program().to_string_lossy().to_string();
+                return Err(Error::InterpreterMissing(program).into());
+            }
             Ok(result) => result?,
             Err(_) => {
-                return Err(anyhow!(
-                    "Execution timed out after {} seconds",
-                    self.config.execution_timeout_secs
-                ));
+                return Err(Error::ExecutionTimeout(self.config.execution_timeout_secs).into());
             }
         };
 
         let stdout = String::from_utf8_lossy(&output.stdout);
         let stderr = String::from_utf8_lossy(&output.stderr);
 
-        if output.status.success() {
+        let success = output.status.success();
+        let text = if success {
             if stdout.is_empty() && !stderr.is_empty() {
                 // Some commands output to stderr even on success
-                Ok(stderr.to_string())
+                stderr.to_string()
             } else {
-                Ok(stdout.to_string())
+                stdout.to_string()
             }
         } else {
             // Include both stdout and stderr for debugging
@@ -168,76 +268,213 @@ impl CodeExecutor {
             }
             if !stdout.is_empty() {
                 if !result.is_empty() {
-                    result.push_str("\n");
+                    result.push('\n');
                 }
                 result.push_str(&stdout);
             }
             if result.is_empty() {
                 result = format!("Command exited with code: {:?}", output.status.code());
             }
-            Ok(result)
-        }
+            result
+        };
+
+        Ok(ExecutionResult {
+            output: text,
+            success,
+            exit_code: output.status.code(),
+        })
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum Language {
-    Python,
-    JavaScript,
-    Shell,
+/// The language `code` runs as: `hint` if given, otherwise what
+/// [`CodeLanguage::detect`] finds. Code detected as anything without a
+/// runner goes to the shell, since detection is a guess; only a hint naming
+/// such a language is refused.
+fn runnable_language(code: &str, hint: Option<CodeLanguage>) -> Result<CodeLanguage> {
+    let runnable = |language| {
+        matches!(
+            language,
+            CodeLanguage::Python
+                | CodeLanguage::JavaScript
+                | CodeLanguage::Shell
+                | CodeLanguage::Go
+                | CodeLanguage::Ruby
+        )
+    };
+    match hint {
+        Some(CodeLanguage::Unknown) | None => {
+            let detected = CodeLanguage::detect(code);
+            // Default to shell - the AI can run any command
+            Ok(if runnable(detected) {
+                detected
+            } else {
+                CodeLanguage::Shell
+            })
+        }
+        Some(language) if runnable(language) => Ok(language),
+        Some(other) => anyhow::bail!(
+            "Cannot run {:?} code; only shell, Python, JavaScript, Go and Ruby can be executed",
+            other
+        ),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn test_executor() -> CodeExecutor {
-        let config = crate::config::MycelConfig::default();
-        CodeExecutor::new(&config).unwrap()
+    #[tokio::test]
+    async fn test_children_get_clean_env_and_cwd() {
+        let code_path = std::env::temp_dir().join(format!("mycel-exec-{}", uuid::Uuid::new_v4()));
+        let config = crate::config::MycelConfig {
+            code_path: code_path.to_string_lossy().to_string(),
+            execution_env_allowlist: vec!["MYCEL_TEST_ALLOWED".to_string()],
+            ..Default::default()
+        };
+        let executor = CodeExecutor::new(&config).unwrap();
+
+        std::env::set_var("MYCEL_TEST_SECRET", "hunter2");
+        std::env::set_var("MYCEL_TEST_ALLOWED", "visible");
+        let output = executor
+            .run_shell("echo \"[$MYCEL_TEST_SECRET] [$MYCEL_TEST_ALLOWED]\"; pwd")
+            .await
+            .unwrap()
+            .output;
+
+        let mut lines = output.lines();
+        assert_eq!(lines.next(), Some("[] [visible]"));
+        let cwd = std::path::PathBuf::from(lines.next().unwrap());
+        assert_eq!(
+            cwd.canonicalize().unwrap(),
+            code_path.join("workdir").canonicalize().unwrap()
+        );
+
+        let _ = std::fs::remove_dir_all(code_path);
+    }
+
+    #[tokio::test]
+    async fn test_failing_code_is_not_reported_as_success() {
+        let code_path = std::env::temp_dir().join(format!("mycel-exec-{}", uuid::Uuid::new_v4()));
+        let config = crate::config::MycelConfig {
+            code_path: code_path.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let executor = CodeExecutor::new(&config).unwrap();
+
+        let failed = executor
+            .run_detailed("echo oops >&2; exit 3")
+            .await
+            .unwrap();
+        assert!(!failed.success);
+        assert_eq!(failed.exit_code, Some(3));
+        // stderr may also carry noise from the shell's startup
+        assert!(failed.output.trim().ends_with("oops"), "{}", failed.output);
+
+        let passed = executor.run_detailed("echo fine").await.unwrap();
+        assert!(passed.success);
+        assert_eq!(passed.exit_code, Some(0));
+        assert_eq!(passed.output.trim(), "fine");
+
+        let _ = std::fs::remove_dir_all(code_path);
     }
 
     #[test]
     fn test_detect_python() {
-        let executor = test_executor();
         assert!(matches!(
-            executor.detect_language("import os\nprint('hello')"),
-            Language::Python
+            runnable_language("import os\nprint('hello')", None).unwrap(),
+            CodeLanguage::Python
         ));
         assert!(matches!(
-            executor.detect_language("def foo():\n    pass"),
-            Language::Python
+            runnable_language("def foo():\n    pass", None).unwrap(),
+            CodeLanguage::Python
         ));
     }
 
     #[test]
     fn test_detect_javascript() {
-        let executor = test_executor();
         assert!(matches!(
-            executor.detect_language("const x = 1;"),
-            Language::JavaScript
+            runnable_language("const x = 1;", None).unwrap(),
+            CodeLanguage::JavaScript
         ));
         assert!(matches!(
-            executor.detect_language("console.log('hi')"),
-            Language::JavaScript
+            runnable_language("console.log('hi')", None).unwrap(),
+            CodeLanguage::JavaScript
         ));
     }
 
     #[test]
     fn test_detect_shell() {
-        let executor = test_executor();
         assert!(matches!(
-            executor.detect_language("#!/bin/bash\necho hello"),
-            Language::Shell
+            runnable_language("#!/bin/bash\necho hello", None).unwrap(),
+            CodeLanguage::Shell
         ));
     }
 
+    #[test]
+    fn test_detect_go() {
+        let code = "package main\n\nimport \"fmt\"\n\nfunc main() {\n\tfmt.Println(\"hi\")\n}";
+        assert!(matches!(
+            runnable_language(code, None).unwrap(),
+            CodeLanguage::Go
+        ));
+    }
+
+    #[test]
+    fn test_detect_ruby() {
+        assert!(matches!(
+            runnable_language("puts 'hello'", None).unwrap(),
+            CodeLanguage::Ruby
+        ));
+        assert!(matches!(
+            runnable_language("require 'json'\nputs JSON.generate({a: 1})", None).unwrap(),
+            CodeLanguage::Ruby
+        ));
+
+        // Python whose names merely contain "puts" stays Python
+        for code in [
+            "inputs = load()\noutputs = [x * 2 for x in inputs ]\nprint(outputs )",
+            "import sys\n\ndef main():\n    puts = sys.argv\n    print(puts)",
+        ] {
+            assert_eq!(CodeLanguage::detect(code), CodeLanguage::Python, "{}", code);
+        }
+    }
+
     #[test]
     fn test_simple_command_is_shell() {
-        let executor = test_executor();
         // Simple commands like "ls" default to shell
         assert!(matches!(
-            executor.detect_language("ls -la"),
-            Language::Shell
+            runnable_language("ls -la", None).unwrap(),
+            CodeLanguage::Shell
         ));
     }
+
+    #[test]
+    fn test_shebang_detected_alike_for_policy_and_execution() {
+        for (script, language) in [
+            ("#!/usr/bin/env python3\nprint('hi')", CodeLanguage::Python),
+            ("#!/usr/bin/env node\nlet x = 1", CodeLanguage::JavaScript),
+            ("#!/usr/bin/env bash\nimport_data", CodeLanguage::Shell),
+            ("#!/bin/sh\nprint() { echo \"$1\"; }", CodeLanguage::Shell),
+            ("#!/usr/bin/ruby\nx = 1", CodeLanguage::Ruby),
+        ] {
+            assert_eq!(CodeLanguage::detect(script), language, "{}", script);
+            assert_eq!(
+                runnable_language(script, None).unwrap(),
+                language,
+                "{}",
+                script
+            );
+        }
+    }
+
+    #[test]
+    fn test_unrunnable_language_is_refused() {
+        // Only when a fence names it; a detected one may be a wrong guess
+        let code = "fn main() {\n    let x = 1;\n}\nfn id(x: u8) -> u8 { x }";
+        assert_eq!(CodeLanguage::detect(code), CodeLanguage::Rust);
+        let err = runnable_language(code, Some(CodeLanguage::Rust)).unwrap_err();
+        assert!(err.to_string().contains("Cannot run Rust code"), "{}", err);
+        assert!(runnable_language("echo hi", Some(CodeLanguage::Html)).is_err());
+        assert_eq!(runnable_language(code, None).unwrap(), CodeLanguage::Shell);
+    }
 }
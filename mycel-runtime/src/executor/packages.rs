@@ -0,0 +1,228 @@
+//! Package managers - find which package provides a missing command
+//!
+//! The daemon runs on Void but is developed on Debian containers and used on
+//! Fedora and Arch, so searches and install hints go through whichever
+//! manager the host actually has.
+
+/// Search results considered when looking for a package
+const MAX_SEARCH_LINES: usize = 200;
+
+/// Related packages listed when nothing matches exactly
+const MAX_SUGGESTIONS: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageManager {
+    Xbps,
+    Apt,
+    Dnf,
+    Pacman,
+}
+
+impl PackageManager {
+    /// Checked in this order, so Void wins on hosts with more than one
+    const ALL: [PackageManager; 4] = [Self::Xbps, Self::Apt, Self::Dnf, Self::Pacman];
+
+    /// The first manager whose search tool is on `PATH`
+    pub fn detect() -> Option<Self> {
+        let path = std::env::var_os("PATH")?;
+        let dirs: Vec<_> = std::env::split_paths(&path).collect();
+        Self::ALL
+            .into_iter()
+            .find(|pm| dirs.iter().any(|dir| dir.join(pm.search_tool()).is_file()))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Xbps => "xbps",
+            Self::Apt => "apt",
+            Self::Dnf => "dnf",
+            Self::Pacman => "pacman",
+        }
+    }
+
+    fn search_tool(&self) -> &'static str {
+        match self {
+            Self::Xbps => "xbps-query",
+            Self::Apt => "apt-cache",
+            Self::Dnf => "dnf",
+            Self::Pacman => "pacman",
+        }
+    }
+
+    /// Shell command searching the repositories for `query`
+    pub fn search_command(&self, query: &str) -> String {
+        let search = match self {
+            Self::Xbps => "xbps-query -Rs",
+            Self::Apt => "apt-cache search",
+            Self::Dnf => "dnf -q search",
+            Self::Pacman => "pacman -Ss",
+        };
+        format!(
+            "{} {} 2>/dev/null | head -{}",
+            search,
+            shell_quote(query),
+            MAX_SEARCH_LINES
+        )
+    }
+
+    /// Command the user should run to install `package`
+    pub fn install_command(&self, package: &str) -> String {
+        let install = match self {
+            Self::Xbps => "sudo xbps-install",
+            Self::Apt => "sudo apt install",
+            Self::Dnf => "sudo dnf install",
+            Self::Pacman => "sudo pacman -S",
+        };
+        format!("{} {}", install, package)
+    }
+
+    /// Package name on one line of search output, if the line names one
+    fn package_name<'a>(&self, line: &'a str) -> Option<&'a str> {
+        match self {
+            // "[-] htop-3.3.0_1    Interactive process viewer"
+            Self::Xbps => {
+                let pkgver = line.strip_prefix('[')?.split_whitespace().nth(1)?;
+                pkgver.rsplit_once('-').map(|(name, _)| name)
+            }
+            // "htop - interactive processes viewer"
+            Self::Apt => line.split(" - ").next().map(str::trim),
+            // "htop.x86_64 : Interactive process viewer", under "=== ... ===" headers
+            Self::Dnf => {
+                let (name, _) = line.split_once(" : ")?;
+                Some(name.trim().rsplit_once('.').map_or(name.trim(), |(n, _)| n))
+            }
+            // "extra/htop 3.3.0-1", with indented description lines
+            Self::Pacman => {
+                if line.starts_with(char::is_whitespace) {
+                    return None;
+                }
+                let first = line.split_whitespace().next()?;
+                Some(first.rsplit_once('/').map_or(first, |(_, name)| name))
+            }
+        }
+    }
+
+    /// Reply for a missing `cmd`, given the output of `search_command(cmd)`
+    pub fn suggestion(&self, cmd: &str, search_output: &str) -> String {
+        let lines: Vec<&str> = search_output
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .collect();
+
+        if let Some(line) = lines.iter().find(|l| self.package_name(l) == Some(cmd)) {
+            return format!(
+                "'{}' not installed. found: {}\ninstall? run: {}",
+                cmd,
+                line.trim(),
+                self.install_command(cmd)
+            );
+        }
+
+        let related: Vec<&str> = lines
+            .iter()
+            .filter(|l| self.package_name(l).is_some())
+            .take(MAX_SUGGESTIONS)
+            .map(|l| l.trim())
+            .collect();
+        if related.is_empty() {
+            return format!(
+                "'{}' not found and no package available. check spelling or install manually.",
+                cmd
+            );
+        }
+
+        format!(
+            "'{}' not installed. related packages:\n{}\ninstall with: {}",
+            cmd,
+            related.join("\n"),
+            self.install_command("<package>")
+        )
+    }
+}
+
+/// Single-quote `s` for `sh`
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_per_manager() {
+        let expected = [
+            (
+                PackageManager::Xbps,
+                "xbps-query -Rs 'htop'",
+                "sudo xbps-install htop",
+            ),
+            (
+                PackageManager::Apt,
+                "apt-cache search 'htop'",
+                "sudo apt install htop",
+            ),
+            (
+                PackageManager::Dnf,
+                "dnf -q search 'htop'",
+                "sudo dnf install htop",
+            ),
+            (
+                PackageManager::Pacman,
+                "pacman -Ss 'htop'",
+                "sudo pacman -S htop",
+            ),
+        ];
+        for (pm, search, install) in expected {
+            assert!(
+                pm.search_command("htop").starts_with(search),
+                "{}: {}",
+                pm.name(),
+                pm.search_command("htop")
+            );
+            assert_eq!(pm.install_command("htop"), install);
+        }
+
+        assert!(PackageManager::Apt
+            .search_command("it's")
+            .starts_with(r"apt-cache search 'it'\''s'"));
+    }
+
+    #[test]
+    fn test_suggestion_finds_exact_package() {
+        let outputs = [
+            (
+                PackageManager::Xbps,
+                "[-] htop-vim-1.0_1  Plugin\n[-] htop-3.3.0_1      Interactive process viewer",
+            ),
+            (
+                PackageManager::Apt,
+                "htop-vim - Plugin\nhtop - interactive processes viewer",
+            ),
+            (
+                PackageManager::Dnf,
+                "=== Name Exactly Matched: htop ===\nhtop.x86_64 : Interactive process viewer",
+            ),
+            (
+                PackageManager::Pacman,
+                "extra/htop 3.3.0-1\n    Interactive process viewer",
+            ),
+        ];
+        for (pm, output) in outputs {
+            let reply = pm.suggestion("htop", output);
+            assert!(
+                reply.ends_with(&format!("install? run: {}", pm.install_command("htop"))),
+                "{}: {}",
+                pm.name(),
+                reply
+            );
+        }
+
+        let reply = PackageManager::Pacman.suggestion("htpo", "extra/htop 3.3.0-1\n    Viewer");
+        assert!(reply.contains("related packages:\nextra/htop 3.3.0-1\n"));
+        assert!(reply.ends_with("sudo pacman -S <package>"));
+        assert!(PackageManager::Apt
+            .suggestion("htpo", "")
+            .contains("no package available"));
+    }
+}
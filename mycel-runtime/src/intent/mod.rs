@@ -151,6 +151,35 @@ impl IntentCategory {
 
         Self::Unknown
     }
+
+    /// Route an input from keywords alone, without asking the LLM.
+    ///
+    /// Returns `None` when the keywords aren't conclusive: unknown inputs,
+    /// creation requests ("write a poem" vs "create a file"), and inputs that
+    /// mention a question word without being phrased as a question.
+    pub fn fast_route(input: &str) -> Option<ActionType> {
+        match Self::from_action(input) {
+            Self::Information if is_question(input) => Some(ActionType::SimpleResponse),
+            Self::Analysis => Some(ActionType::SimpleResponse),
+            Self::Action | Self::Configuration | Self::Navigation | Self::Transformation => {
+                Some(ActionType::GenerateCode)
+            }
+            Self::Information | Self::Creation | Self::Unknown => None,
+        }
+    }
+}
+
+/// Whether the input is phrased as a question
+fn is_question(input: &str) -> bool {
+    const QUESTION_WORDS: &[&str] = &["what", "who", "when", "where", "why", "how", "which"];
+
+    let input = input.trim().to_lowercase();
+    let first_word = input
+        .split(|c: char| !c.is_alphanumeric())
+        .find(|w| !w.is_empty())
+        .unwrap_or("");
+
+    input.ends_with('?') || input.starts_with("tell me") || QUESTION_WORDS.contains(&first_word)
 }
 
 #[cfg(test)]
@@ -186,4 +215,27 @@ mod tests {
             IntentCategory::Transformation
         ));
     }
+
+    #[test]
+    fn test_fast_route() {
+        let cases = [
+            ("what is a kernel?", Some(ActionType::SimpleResponse)),
+            ("who wrote Dune", Some(ActionType::SimpleResponse)),
+            ("tell me about void linux", Some(ActionType::SimpleResponse)),
+            ("explain how pipes work", Some(ActionType::SimpleResponse)),
+            ("run the backup script", Some(ActionType::GenerateCode)),
+            ("open firefox", Some(ActionType::GenerateCode)),
+            ("set the volume to 50%", Some(ActionType::GenerateCode)),
+            ("show disk usage", Some(ActionType::GenerateCode)),
+            ("convert image.png to jpg", Some(ActionType::GenerateCode)),
+            // Inconclusive: left to the LLM
+            ("write a poem about rust", None),
+            ("hello there", None),
+            ("somewhat confused", None),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(IntentCategory::fast_route(input), expected, "{}", input);
+        }
+    }
 }
@@ -18,8 +18,11 @@ use std::time::{Duration, Instant};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{UnixListener, UnixStream};
 use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 
+use crate::error::Error;
+use crate::models::{CompatibilityResult, ModelBackend, ModelCompatibility};
 use crate::MycelRuntime;
 
 /// Maximum message size in bytes (1MB)
@@ -65,25 +68,26 @@ impl RateLimiter {
 /// IPC Server for Mycel Runtime
 pub struct IpcServer {
     listener: UnixListener,
+    socket_path: String,
     runtime: Arc<MycelRuntime>,
     auth_token: String,
 }
 
 impl IpcServer {
     pub async fn new(runtime: &MycelRuntime) -> Result<Self> {
-        let socket_path = &runtime.config.ipc_socket_path;
+        let socket_path = runtime.config.read().await.ipc_socket_path.clone();
 
         // Remove existing socket if present
-        let _ = std::fs::remove_file(socket_path);
+        let _ = std::fs::remove_file(&socket_path);
 
-        let listener = UnixListener::bind(socket_path)?;
+        let listener = UnixListener::bind(&socket_path)?;
 
         // Set socket permissions to 0600 (owner read/write only)
         #[cfg(unix)]
         {
             use std::os::unix::fs::PermissionsExt;
             let permissions = std::fs::Permissions::from_mode(0o600);
-            std::fs::set_permissions(socket_path, permissions)?;
+            std::fs::set_permissions(&socket_path, permissions)?;
             info!("IPC socket permissions set to 0600");
         }
 
@@ -94,6 +98,7 @@ impl IpcServer {
 
         Ok(Self {
             listener,
+            socket_path,
             runtime: Arc::new(runtime.clone()),
             auth_token,
         })
@@ -104,9 +109,14 @@ impl IpcServer {
         &self.auth_token
     }
 
-    pub async fn run(&self) -> Result<()> {
+    /// Accept connections until `shutdown` is cancelled
+    pub async fn run(&self, shutdown: CancellationToken) -> Result<()> {
         loop {
-            match self.listener.accept().await {
+            let accepted = tokio::select! {
+                _ = shutdown.cancelled() => break,
+                accepted = self.listener.accept() => accepted,
+            };
+            match accepted {
                 Ok((stream, _)) => {
                     let runtime = Arc::clone(&self.runtime);
                     let auth_token = self.auth_token.clone();
@@ -121,6 +131,10 @@ impl IpcServer {
                 }
             }
         }
+
+        info!("IPC server stopped accepting connections");
+        let _ = std::fs::remove_file(&self.socket_path);
+        Ok(())
     }
 }
 
@@ -150,12 +164,10 @@ async fn handle_connection(
                 // Check message size limit
                 if n > MAX_MESSAGE_SIZE {
                     warn!("Message exceeds size limit ({} bytes)", n);
-                    let error_response = IpcResponse::Error {
-                        message: format!(
-                            "Message too large: {} bytes (max: {} bytes)",
-                            n, MAX_MESSAGE_SIZE
-                        ),
-                    };
+                    let error_response = IpcResponse::error(format!(
+                        "Message too large: {} bytes (max: {} bytes)",
+                        n, MAX_MESSAGE_SIZE
+                    ));
                     let response_json = serde_json::to_string(&error_response)? + "\n";
                     let mut w = writer.lock().await;
                     w.write_all(response_json.as_bytes()).await?;
@@ -171,12 +183,10 @@ async fn handle_connection(
                 // Check rate limit
                 if !rate_limiter.check() {
                     warn!("Rate limit exceeded for session {}", session_id);
-                    let error_response = IpcResponse::Error {
-                        message: format!(
-                            "Rate limit exceeded: max {} requests per minute",
-                            RATE_LIMIT_REQUESTS
-                        ),
-                    };
+                    let error_response = IpcResponse::error(format!(
+                        "Rate limit exceeded: max {} requests per minute",
+                        RATE_LIMIT_REQUESTS
+                    ));
                     let response_json = serde_json::to_string(&error_response)? + "\n";
                     let mut w = writer.lock().await;
                     w.write_all(response_json.as_bytes()).await?;
@@ -203,9 +213,8 @@ async fn handle_connection(
                                         info!("Client authenticated for session {}", session_id);
                                     } else {
                                         warn!("Invalid auth token for session {}", session_id);
-                                        let error_response = IpcResponse::Error {
-                                            message: "Invalid authentication token".to_string(),
-                                        };
+                                        let error_response =
+                                            IpcResponse::error("Invalid authentication token");
                                         let response_json =
                                             serde_json::to_string(&error_response)? + "\n";
                                         let mut w = writer.lock().await;
@@ -224,9 +233,9 @@ async fn handle_connection(
                                     continue;
                                 }
                                 _ => {
-                                    let error_response = IpcResponse::Error {
-                                        message: "Authentication required. Send Authenticate request first.".to_string(),
-                                    };
+                                    let error_response = IpcResponse::error(
+                                        "Authentication required. Send Authenticate request first.",
+                                    );
                                     let response_json =
                                         serde_json::to_string(&error_response)? + "\n";
                                     let mut w = writer.lock().await;
@@ -239,11 +248,56 @@ async fn handle_connection(
 
                         // Process request
                         match &request {
-                            IpcRequest::Chat { message, provider } => {
-                                match runtime
-                                    .process_input_with_provider(message, &session_id, *provider)
-                                    .await
-                                {
+                            IpcRequest::Chat {
+                                message,
+                                provider,
+                                dry_run,
+                                agentic,
+                            } => {
+                                // A provider in the request wins over the session's pinned one
+                                let provider = match provider {
+                                    LlmProvider::Auto => {
+                                        runtime.context_manager.session_provider(&session_id).await
+                                    }
+                                    explicit => *explicit,
+                                };
+                                // Relay tool progress and agentic steps while the
+                                // request runs
+                                let (progress_tx, mut progress_rx) =
+                                    tokio::sync::mpsc::unbounded_channel();
+                                let (steps_tx, mut steps_rx) =
+                                    tokio::sync::mpsc::unbounded_channel();
+                                let turn = MycelRuntime {
+                                    ai_router: runtime.ai_router.with_steps(steps_tx),
+                                    ..MycelRuntime::clone(&runtime)
+                                };
+                                let work = turn.process_input_with_provider(
+                                    message,
+                                    &session_id,
+                                    provider,
+                                    *dry_run,
+                                    *agentic,
+                                    Some(progress_tx),
+                                );
+                                tokio::pin!(work);
+                                let result = loop {
+                                    tokio::select! {
+                                        result = &mut work => break result,
+                                        Some(update) = progress_rx.recv() => {
+                                            send_tool_progress(&writer, update).await?;
+                                        }
+                                        Some(step) = steps_rx.recv() => {
+                                            send_agent_step(&writer, step).await?;
+                                        }
+                                    }
+                                };
+                                while let Ok(update) = progress_rx.try_recv() {
+                                    send_tool_progress(&writer, update).await?;
+                                }
+                                while let Ok(step) = steps_rx.try_recv() {
+                                    send_agent_step(&writer, step).await?;
+                                }
+                                match result {
                                     Ok(crate::RuntimeResponse::Text(text)) => {
                                         // Record the interaction for history and sync
                                         let _ = runtime
@@ -259,6 +313,28 @@ async fn handle_connection(
                                         w.write_all(json.as_bytes()).await?;
                                         w.flush().await?;
                                     }
+                                    Ok(crate::RuntimeResponse::Code { code, result }) => {
+                                        let _ = runtime
+                                            .record_interaction(
+                                                &session_id,
+                                                message,
+                                                &result.output,
+                                            )
+                                            .await;
+
+                                        // Reported like `ExecuteCode`, so
+                                        // clients can tell a failure apart
+                                        let response = IpcResponse::CodeResult {
+                                            code,
+                                            output: result.output,
+                                            success: result.success,
+                                            exit_code: result.exit_code,
+                                        };
+                                        let json = serde_json::to_string(&response)? + "\n";
+                                        let mut w = writer.lock().await;
+                                        w.write_all(json.as_bytes()).await?;
+                                        w.flush().await?;
+                                    }
                                     Ok(crate::RuntimeResponse::Stream(mut stream)) => {
                                         use futures_util::StreamExt;
                                         let mut full_response = String::new();
@@ -299,9 +375,7 @@ async fn handle_connection(
                                         w.flush().await?;
                                     }
                                     Err(e) => {
-                                        let response = IpcResponse::Error {
-                                            message: e.to_string(),
-                                        };
+                                        let response = IpcResponse::from_error(&e);
                                         let json = serde_json::to_string(&response)? + "\n";
                                         let mut w = writer.lock().await;
                                         w.write_all(json.as_bytes()).await?;
@@ -320,9 +394,7 @@ async fn handle_connection(
                         }
                     }
                     Err(e) => {
-                        let error_response = IpcResponse::Error {
-                            message: format!("Invalid request: {}", e),
-                        };
+                        let error_response = IpcResponse::error(format!("Invalid request: {}", e));
                         let response_json = serde_json::to_string(&error_response)? + "\n";
 
                         let mut w = writer.lock().await;
@@ -342,7 +414,31 @@ async fn handle_connection(
     Ok(())
 }
 
-async fn process_request(
+/// Write one tool progress update to a chat connection
+async fn send_tool_progress(
+    writer: &Mutex<tokio::net::unix::OwnedWriteHalf>,
+    update: crate::mcp::ToolProgress,
+) -> Result<()> {
+    let json = serde_json::to_string(&IpcResponse::ToolProgress { update })? + "\n";
+    let mut w = writer.lock().await;
+    w.write_all(json.as_bytes()).await?;
+    w.flush().await?;
+    Ok(())
+}
+
+/// Write one agentic loop step to a chat connection
+async fn send_agent_step(
+    writer: &Mutex<tokio::net::unix::OwnedWriteHalf>,
+    step: crate::ai::AgentStep,
+) -> Result<()> {
+    let json = serde_json::to_string(&IpcResponse::AgentStep { step })? + "\n";
+    let mut w = writer.lock().await;
+    w.write_all(json.as_bytes()).await?;
+    w.flush().await?;
+    Ok(())
+}
+
+pub(crate) async fn process_request(
     request: &IpcRequest,
     runtime: &MycelRuntime,
     session_id: &mut String,
@@ -356,9 +452,7 @@ async fn process_request(
         }
         IpcRequest::Chat { .. } => {
             // Handled separately in handle_connection for streaming/provider routing
-            IpcResponse::Error {
-                message: "Internal error: Chat should be handled by streaming handler".to_string(),
-            }
+            IpcResponse::error("Internal error: Chat should be handled by streaming handler")
         }
         IpcRequest::SetSession { id } => {
             *session_id = id.clone();
@@ -371,31 +465,214 @@ async fn process_request(
                 working_directory: ctx.working_directory,
                 recent_files: ctx.recent_files,
             },
-            Err(e) => IpcResponse::Error {
-                message: e.to_string(),
-            },
+            Err(e) => IpcResponse::from_error(&e),
         },
-        IpcRequest::Status => {
-            let session_count = runtime.context_manager.session_count().await;
-            IpcResponse::Status {
-                version: env!("CARGO_PKG_VERSION").to_string(),
-                uptime: 0, // TODO: Track uptime
-                sessions: session_count,
-                llm_model: runtime.config.local_model.clone(),
+        IpcRequest::SetProvider { provider } => {
+            let unavailable = match provider {
+                LlmProvider::Local if !runtime.ai_router.is_local_available() => {
+                    Some(Error::LocalUnavailable)
+                }
+                LlmProvider::Cloud if !runtime.ai_router.has_cloud_api() => {
+                    Some(Error::CloudUnconfigured)
+                }
+                _ => None,
+            };
+            match unavailable {
+                Some(e) => IpcResponse::from_error(&e.into()),
+                None => match runtime
+                    .context_manager
+                    .set_provider(session_id, *provider)
+                    .await
+                {
+                    Ok(()) => IpcResponse::Ok {
+                        message: format!("Provider for this session set to {:?}", provider),
+                    },
+                    Err(e) => IpcResponse::from_error(&e),
+                },
+            }
+        }
+        IpcRequest::SetWorkingDirectory { path } => {
+            match runtime.change_working_directory(session_id, path).await {
+                Ok(path) => IpcResponse::WorkingDirectory { path },
+                Err(e) => IpcResponse::from_error(&e),
             }
         }
-        IpcRequest::ExecuteCode { code } => match runtime.executor.run(code).await {
-            Ok(output) => IpcResponse::CodeResult {
+        IpcRequest::SetPreference { key, value } => {
+            match runtime.set_user_preference(key, value).await {
+                Ok(()) => IpcResponse::Ok {
+                    message: format!("Preference '{}' set", key),
+                },
+                Err(e) => IpcResponse::from_error(&e),
+            }
+        }
+        IpcRequest::Status => IpcResponse::Status {
+            status: SystemStatus {
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                uptime_secs: runtime.started_at.elapsed().as_secs(),
+                llm_model: runtime.ai_router.local_model(),
+                local_llm_available: runtime.ai_router.is_local_available(),
+                cloud_configured: runtime.ai_router.has_cloud_api(),
+                mcp_servers: runtime.mcp_manager.get_status().await,
+                active_sessions: runtime.context_manager.session_count().await,
+                peer_count: runtime.sync_service.get_peers().await.len(),
+            },
+        },
+        IpcRequest::SetupStatus => IpcResponse::Setup {
+            status: runtime.ai_router.setup_status(&runtime.executor).await,
+        },
+        IpcRequest::SetupAction { action } => match runtime.run_setup_action(action).await {
+            Ok(message) => IpcResponse::Ok { message },
+            Err(e) => IpcResponse::from_error(&e),
+        },
+        IpcRequest::ValidateCode { code } => {
+            let issue = runtime
+                .policy_evaluator
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .validate_code(code, crate::codegen::CodeLanguage::detect(code))
+                .err();
+            IpcResponse::CodeValidation { issue }
+        }
+        IpcRequest::ExecuteCode { code } => match runtime.run_code(code).await {
+            Ok(result) => IpcResponse::CodeResult {
                 code: code.clone(),
-                output,
-                success: true,
+                output: result.output,
+                success: result.success,
+                exit_code: result.exit_code,
             },
             Err(e) => IpcResponse::CodeResult {
                 code: code.clone(),
                 output: e.to_string(),
                 success: false,
+                exit_code: None,
+            },
+        },
+        IpcRequest::ListModels { backend } => match runtime.ai_router.list_models(*backend).await {
+            Ok(models) => IpcResponse::Models { models },
+            Err(e) => IpcResponse::Error {
+                message: format!("Failed to list models: {}", e),
+                code: error_code(&e),
             },
         },
+        IpcRequest::RecommendModels => match runtime.ai_router.recommend_models().await {
+            Ok(models) => IpcResponse::Models { models },
+            Err(e) => IpcResponse::Error {
+                message: format!("Failed to recommend models: {}", e),
+                code: error_code(&e),
+            },
+        },
+        IpcRequest::Benchmark { runs } => {
+            let runs = (*runs).clamp(1, MAX_BENCHMARK_RUNS);
+            match runtime.ai_router.benchmark(runs).await {
+                Ok(report) => IpcResponse::Benchmark { report },
+                Err(e) => IpcResponse::from_error(&e),
+            }
+        }
+        IpcRequest::UnloadModel => match runtime.ai_router.unload_model().await {
+            Ok(model) => IpcResponse::Ok {
+                message: format!("Unloaded {}", model),
+            },
+            Err(e) => IpcResponse::from_error(&e),
+        },
+        IpcRequest::SwitchModel { id } => match runtime.ai_router.switch_model(id).await {
+            Ok(CompatibilityResult::CompatibleWithWarning { warning }) => IpcResponse::Ok {
+                message: format!("Switched to {} ({})", id, warning),
+            },
+            Ok(_) => IpcResponse::Ok {
+                message: format!("Switched to {}", id),
+            },
+            Err(e) => IpcResponse::from_error(&e),
+        },
+        IpcRequest::GetRoutingLog { limit } => IpcResponse::RoutingLog {
+            decisions: runtime.ai_router.routing_log(*limit),
+        },
+        IpcRequest::SearchHistory { query, limit } => IpcResponse::HistoryResults {
+            matches: runtime.context_manager.search_history(query, *limit).await,
+        },
+        IpcRequest::CreateSurface { spec } => match runtime.ui_factory.create_surface(spec) {
+            Ok(surface) => {
+                runtime.surfaces.register(surface.clone());
+                IpcResponse::Surface { surface }
+            }
+            Err(e) => IpcResponse::Error {
+                message: format!("Failed to create surface: {}", e),
+                code: error_code(&e),
+            },
+        },
+        IpcRequest::ListSurfaces => IpcResponse::Surfaces {
+            surfaces: runtime.surfaces.list(),
+        },
+        IpcRequest::UpdateSurface { id, action } => match runtime.surfaces.apply(id, *action) {
+            Ok(surface) => IpcResponse::Surface { surface },
+            Err(e) => IpcResponse::from_error(&e),
+        },
+        IpcRequest::GetPeers => IpcResponse::Peers {
+            peers: runtime.sync_service.get_peers().await,
+        },
+        IpcRequest::GetSyncStatus => IpcResponse::SyncStatus {
+            status: runtime.sync_service.status().await,
+        },
+        IpcRequest::PreviewSync { limit } => IpcResponse::SyncPreview {
+            events: runtime.sync_service.preview_pending(*limit).await,
+        },
+        IpcRequest::PreviewSyncTurn { input, response } => IpcResponse::SyncPreview {
+            events: vec![runtime
+                .sync_service
+                .preview_turn(session_id, input, response)],
+        },
+        IpcRequest::GetCacheStats => IpcResponse::CacheStats {
+            stats: runtime.mcp_manager.cache_stats().await,
+        },
+        IpcRequest::ClearCache => {
+            runtime.mcp_manager.clear_cache().await;
+            IpcResponse::Ok {
+                message: "Tool result cache cleared".to_string(),
+            }
+        }
+        IpcRequest::ListCapabilities => IpcResponse::Capabilities {
+            capabilities: runtime.mcp_manager.list_capabilities().await,
+        },
+        IpcRequest::ListResources => IpcResponse::Resources {
+            resources: runtime.mcp_manager.list_resources().await,
+        },
+        IpcRequest::ReadResource { uri } => match runtime.mcp_manager.read_resource(uri).await {
+            Ok(contents) => IpcResponse::ResourceContents {
+                uri: uri.clone(),
+                contents,
+            },
+            Err(e) => IpcResponse::from_error(&e),
+        },
+        IpcRequest::RotateDeviceKeys => match runtime.sync_service.rotate_keys().await {
+            Ok(id) => IpcResponse::Ok {
+                message: format!("Device key rotated. New Mycel ID: {}", id),
+            },
+            Err(e) => IpcResponse::Error {
+                message: format!("Key rotation failed: {}", e),
+                code: error_code(&e),
+            },
+        },
+        IpcRequest::ResolveConfirmation { id, approve } => {
+            match runtime.mcp_manager.resolve_confirmation(id, *approve).await {
+                Ok(message) => IpcResponse::Ok { message },
+                Err(e) => IpcResponse::from_error(&e),
+            }
+        }
+        IpcRequest::ListArtifacts => IpcResponse::Artifacts {
+            artifacts: runtime.artifacts.list(),
+        },
+        IpcRequest::GetArtifact { id } => match runtime.artifacts.get(id) {
+            Some(artifact) => match runtime.artifacts.read_code(id) {
+                Ok(code) => IpcResponse::Artifact {
+                    artifact,
+                    code: code.unwrap_or_default(),
+                },
+                Err(e) => IpcResponse::Error {
+                    message: format!("Failed to read artifact: {}", e),
+                    code: error_code(&e),
+                },
+            },
+            None => IpcResponse::error(format!("Unknown artifact: {}", id)),
+        },
         IpcRequest::Ping => IpcResponse::Pong,
     }
 }
@@ -413,6 +690,36 @@ pub enum LlmProvider {
     Cloud,
 }
 
+/// Aggregated subsystem state, for `mycel status` and dashboards
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemStatus {
+    pub version: String,
+    pub uptime_secs: u64,
+    /// Configured local model name
+    pub llm_model: String,
+    pub local_llm_available: bool,
+    /// An OpenRouter API key is set
+    pub cloud_configured: bool,
+    /// MCP server name to state (`ready`, `failed`, ...)
+    pub mcp_servers: std::collections::HashMap<String, String>,
+    pub active_sessions: usize,
+    pub peer_count: usize,
+}
+
+/// A guided first-run setup step
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum SetupAction {
+    /// Pull `model`, or the top recommendation for this machine, and make
+    /// it the local model
+    PullRecommendedModel {
+        #[serde(default)]
+        model: Option<String>,
+    },
+    /// Save an OpenRouter API key to the config file
+    SetCloudKey { api_key: String },
+}
+
 /// Requests that can be sent to the runtime
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -425,19 +732,151 @@ pub enum IpcRequest {
         /// Optional: force a specific LLM provider (local, cloud, or auto)
         #[serde(default)]
         provider: LlmProvider,
+        /// Explain any generated code and wait for confirmation instead of running it
+        #[serde(default)]
+        dry_run: bool,
+        /// Let the model take several tool rounds (up to
+        /// `mcp.agentic_max_iterations`) instead of one
+        #[serde(default)]
+        agentic: bool,
     },
     /// Set the session ID
     SetSession { id: String },
+    /// Pin the current session to an LLM provider (persists with the session)
+    SetProvider { provider: LlmProvider },
     /// Get current context
     GetContext,
-    /// Get system status
+    /// Change the session's working directory (like `cd`)
+    SetWorkingDirectory { path: String },
+    /// Save a user preference (added to chat prompts and synced to peers)
+    SetPreference { key: String, value: String },
+    /// Get the health of every subsystem at once
     Status,
+    /// Report what first-run setup still needs
+    SetupStatus,
+    /// Perform one first-run setup step
+    SetupAction { action: SetupAction },
     /// Direct code execution
     ExecuteCode { code: String },
+    /// Check code against the risk patterns without running it
+    ValidateCode { code: String },
+    /// List models available from a backend (defaults to Ollama)
+    ListModels {
+        #[serde(default)]
+        backend: ModelBackend,
+    },
+    /// Switch the local LLM to an installed model
+    SwitchModel { id: String },
+    /// Free the memory the local model holds; it reloads on the next request
+    UnloadModel,
+    /// List models recommended for this machine's hardware
+    RecommendModels,
+    /// Time a fixed prompt on the local and (if configured) cloud models
+    Benchmark {
+        #[serde(default = "default_benchmark_runs")]
+        runs: usize,
+    },
+    /// Recent AI routing decisions (local vs cloud, fallbacks), newest first
+    GetRoutingLog {
+        #[serde(default = "default_search_limit")]
+        limit: usize,
+    },
+    /// Search past conversation turns across sessions
+    SearchHistory {
+        query: String,
+        #[serde(default = "default_search_limit")]
+        limit: usize,
+    },
+    /// Build a UI surface from a spec and start tracking it
+    CreateSurface { spec: crate::ai::UiSpec },
+    /// List surfaces that haven't been destroyed
+    ListSurfaces,
+    /// Activate, hide or destroy a surface
+    UpdateSurface {
+        id: String,
+        action: crate::ui::SurfaceAction,
+    },
+    /// List mesh peers this device knows about
+    GetPeers,
+    /// Summary of mesh and blockchain sync
+    GetSyncStatus,
+    /// This device's sync events, newest first, with their content redacted
+    PreviewSync {
+        #[serde(default = "default_search_limit")]
+        limit: usize,
+    },
+    /// What recording this exchange would share with peers, without
+    /// recording it
+    PreviewSyncTurn { input: String, response: String },
+    /// Replace this device's mesh key and tell peers about it
+    RotateDeviceKeys,
+    /// Tool result cache hit/miss counters
+    GetCacheStats,
+    /// Drop every cached tool result
+    ClearCache,
+    /// List the tools the assistant can use, with their source server, risk
+    /// and whether they need confirmation
+    ListCapabilities,
+    /// List resources (documents, data) offered by MCP servers
+    ListResources,
+    /// Fetch the contents of an MCP resource
+    ReadResource { uri: String },
+    /// Approve or reject a held tool call, such as a capability awaiting review
+    ResolveConfirmation { id: String, approve: bool },
+    /// List generated code artifacts, newest first
+    ListArtifacts,
+    /// Fetch one artifact with its code (re-run it with ExecuteCode)
+    GetArtifact { id: String },
     /// Ping for health check (allowed without auth)
     Ping,
 }
 
+impl IpcRequest {
+    /// Whether the daemon handling this twice is harmless: it only reads,
+    /// or sets a value to what it already is
+    pub fn is_replayable(&self) -> bool {
+        matches!(
+            self,
+            IpcRequest::Authenticate { .. }
+                | IpcRequest::SetSession { .. }
+                | IpcRequest::SetProvider { .. }
+                | IpcRequest::GetContext
+                | IpcRequest::SetPreference { .. }
+                | IpcRequest::Status
+                | IpcRequest::SetupStatus
+                | IpcRequest::ValidateCode { .. }
+                | IpcRequest::ListModels { .. }
+                | IpcRequest::RecommendModels
+                | IpcRequest::GetRoutingLog { .. }
+                | IpcRequest::SearchHistory { .. }
+                | IpcRequest::ListSurfaces
+                | IpcRequest::GetPeers
+                | IpcRequest::GetSyncStatus
+                | IpcRequest::PreviewSync { .. }
+                | IpcRequest::PreviewSyncTurn { .. }
+                | IpcRequest::GetCacheStats
+                | IpcRequest::ClearCache
+                | IpcRequest::ListCapabilities
+                | IpcRequest::ListResources
+                | IpcRequest::ReadResource { .. }
+                | IpcRequest::ListArtifacts
+                | IpcRequest::GetArtifact { .. }
+                | IpcRequest::Ping
+        )
+    }
+}
+
+fn default_search_limit() -> usize {
+    20
+}
+
+fn default_benchmark_runs() -> usize {
+    3
+}
+
+/// Keeps a benchmark request from tying up the models for long
+pub(crate) const MAX_BENCHMARK_RUNS: usize = 10;
+
 /// Responses from the runtime
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -449,50 +888,240 @@ pub enum IpcResponse {
     },
     /// Chat chunk (for streaming)
     ChatChunk { delta: String },
+    /// Interim progress from a long-running tool, sent before the final `Chat`
+    ToolProgress { update: crate::mcp::ToolProgress },
+    /// A step of an agentic chat (tool call, result, final answer), sent
+    /// before the final `Chat`
+    AgentStep { step: crate::ai::AgentStep },
     /// Code execution result
     CodeResult {
         code: String,
         output: String,
+        /// The code ran and exited with status 0
         success: bool,
+        /// `None` if it never started, timed out or was killed by a signal
+        exit_code: Option<i32>,
     },
     /// Context information
     Context {
         working_directory: String,
         recent_files: Vec<String>,
     },
+    /// The session's new working directory, as an absolute path
+    WorkingDirectory { path: String },
     /// System status
-    Status {
-        version: String,
-        uptime: u64,
-        sessions: usize,
-        llm_model: String,
+    Status { status: SystemStatus },
+    /// What first-run setup still needs
+    Setup { status: crate::ai::SetupStatus },
+    /// Where code trips a risk pattern, if anywhere
+    CodeValidation {
+        issue: Option<crate::policy::ValidationError>,
+    },
+    /// Models with their hardware compatibility verdicts
+    Models { models: Vec<ModelCompatibility> },
+    /// Model latency and throughput on this machine
+    Benchmark { report: crate::ai::BenchmarkReport },
+    /// Recent AI routing decisions, newest first
+    RoutingLog {
+        decisions: Vec<crate::ai::RoutingDecision>,
+    },
+    /// Conversation turns matching a history search
+    HistoryResults {
+        matches: Vec<crate::context::HistoryMatch>,
+    },
+    /// A single surface (created or updated)
+    Surface { surface: crate::ui::Surface },
+    /// Live surfaces
+    Surfaces { surfaces: Vec<crate::ui::Surface> },
+    /// Known mesh peers
+    Peers { peers: Vec<crate::sync::PeerInfo> },
+    /// Mesh and blockchain sync summary
+    SyncStatus { status: crate::sync::SyncStatus },
+    /// What sync events share, content redacted
+    SyncPreview {
+        events: Vec<crate::sync::SyncPreview>,
+    },
+    /// Tool result cache effectiveness
+    CacheStats { stats: crate::mcp::CacheStats },
+    /// Tools the assistant can use
+    Capabilities {
+        capabilities: Vec<crate::mcp::Capability>,
+    },
+    /// Resources offered by MCP servers
+    Resources {
+        resources: Vec<crate::mcp::ServerResource>,
+    },
+    /// Contents of one MCP resource
+    ResourceContents {
+        uri: String,
+        contents: Vec<crate::mcp::ResourceContent>,
+    },
+    /// Stored code artifacts
+    Artifacts {
+        artifacts: Vec<crate::codegen::ArtifactRecord>,
+    },
+    /// A single artifact and its code
+    Artifact {
+        artifact: crate::codegen::ArtifactRecord,
+        code: String,
     },
     /// Generic OK response
     Ok { message: String },
-    /// Error response
-    Error { message: String },
+    /// Error response; `code` is set for failures clients can act on
+    /// (see `crate::error::Error::code`)
+    Error {
+        message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<String>,
+    },
     /// Pong response to ping
     Pong,
 }
 
+impl IpcResponse {
+    /// Error response without a code
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::Error {
+            message: message.into(),
+            code: None,
+        }
+    }
+
+    /// Error response for `err`, carrying its code if it has one
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        Self::Error {
+            message: err.to_string(),
+            code: error_code(err),
+        }
+    }
+}
+
+/// Machine-readable code of a structured error anywhere in `err`'s chain
+fn error_code(err: &anyhow::Error) -> Option<String> {
+    Error::find(err).and_then(Error::code).map(str::to_string)
+}
+
+/// Connection attempts made by `IpcClient::reconnect`
+const RECONNECT_ATTEMPTS: u32 = 6;
+
+/// Delay before the second attempt, doubled after each failure
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_millis(100);
+
 /// IPC Client for connecting to Clay Runtime
 pub struct IpcClient {
-    stream: UnixStream,
+    socket_path: String,
+    /// None once the daemon has gone away
+    stream: Option<UnixStream>,
+    /// Token from the last `authenticate`, replayed after reconnecting
+    token: Option<String>,
+    auto_reconnect: bool,
 }
 
 impl IpcClient {
     pub async fn connect(socket_path: &str) -> Result<Self> {
         let stream = UnixStream::connect(socket_path).await?;
-        Ok(Self { stream })
+        Ok(Self {
+            socket_path: socket_path.to_string(),
+            stream: Some(stream),
+            token: None,
+            auto_reconnect: false,
+        })
+    }
+
+    /// Have `send` reconnect and retry once when the daemon has restarted.
+    /// A request that may have reached the old daemon is only retried if
+    /// it is replayable.
+    pub fn with_auto_reconnect(mut self, enabled: bool) -> Self {
+        self.auto_reconnect = enabled;
+        self
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Authenticate, remembering the token so a reconnect can reuse it
+    pub async fn authenticate(&mut self, token: &str) -> Result<IpcResponse> {
+        self.token = Some(token.to_string());
+        self.send(&IpcRequest::Authenticate {
+            token: token.to_string(),
+        })
+        .await
+    }
+
+    /// Open a fresh connection, backing off between attempts, and
+    /// re-authenticate if a token was stored
+    pub async fn reconnect(&mut self) -> Result<()> {
+        self.stream = None;
+
+        let mut delay = RECONNECT_INITIAL_DELAY;
+        let mut attempt = 1;
+        let stream = loop {
+            match UnixStream::connect(&self.socket_path).await {
+                Ok(stream) => break stream,
+                Err(e) if attempt < RECONNECT_ATTEMPTS => {
+                    debug!("IPC reconnect attempt {} failed: {}", attempt, e);
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        self.stream = Some(stream);
+
+        if let Some(token) = self.token.clone() {
+            if let IpcResponse::Error { message, .. } = self
+                .send_once(&IpcRequest::Authenticate { token }, &mut false)
+                .await?
+            {
+                self.stream = None;
+                return Err(anyhow::anyhow!("Re-authentication failed: {}", message));
+            }
+        }
+        Ok(())
     }
 
     pub async fn send(&mut self, request: &IpcRequest) -> Result<IpcResponse> {
+        let mut written = false;
+        match self.send_once(request, &mut written).await {
+            // Once written, the old daemon may have acted on it before going away
+            Err(e)
+                if self.auto_reconnect
+                    && is_disconnect(&e)
+                    && (!written || request.is_replayable()) =>
+            {
+                warn!("IPC connection lost ({}), reconnecting", e);
+                self.reconnect().await?;
+                self.send_once(request, &mut written).await
+            }
+            result => result,
+        }
+    }
+
+    /// One exchange; `written` is set once the request has been sent
+    async fn send_once(&mut self, request: &IpcRequest, written: &mut bool) -> Result<IpcResponse> {
+        let result = self.exchange(request, written).await;
+        if matches!(&result, Err(e) if is_disconnect(e)) {
+            self.stream = None;
+        }
+        result
+    }
+
+    async fn exchange(&mut self, request: &IpcRequest, written: &mut bool) -> Result<IpcResponse> {
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotConnected))?;
         let request_json = serde_json::to_string(request)? + "\n";
-        self.stream.write_all(request_json.as_bytes()).await?;
+        stream.write_all(request_json.as_bytes()).await?;
+        *written = true;
 
-        let mut reader = BufReader::new(&mut self.stream);
+        let mut reader = BufReader::new(stream);
         let mut response_line = String::new();
-        reader.read_line(&mut response_line).await?;
+        if reader.read_line(&mut response_line).await? == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
 
         Ok(serde_json::from_str(&response_line)?)
     }
@@ -501,19 +1130,58 @@ impl IpcClient {
         self.chat_with_provider(message, LlmProvider::Auto).await
     }
 
+    /// Send a chat message and wait for the final response, skipping
+    /// streamed chunks and tool progress
     pub async fn chat_with_provider(
         &mut self,
         message: &str,
         provider: LlmProvider,
     ) -> Result<IpcResponse> {
-        self.send(&IpcRequest::Chat {
+        let request = IpcRequest::Chat {
             message: message.to_string(),
             provider,
-        })
-        .await
+            dry_run: false,
+            agentic: false,
+        };
+        let request_json = serde_json::to_string(&request)? + "\n";
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotConnected))?;
+        stream.write_all(request_json.as_bytes()).await?;
+
+        let mut reader = BufReader::new(stream);
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await? == 0 {
+                return Err(anyhow::anyhow!("Connection closed before the response"));
+            }
+            match serde_json::from_str(&line)? {
+                IpcResponse::ChatChunk { .. }
+                | IpcResponse::ToolProgress { .. }
+                | IpcResponse::AgentStep { .. } => continue,
+                response => return Ok(response),
+            }
+        }
     }
 }
 
+/// Whether `e` means the connection to the daemon is gone
+fn is_disconnect(e: &anyhow::Error) -> bool {
+    use std::io::ErrorKind;
+
+    matches!(
+        e.downcast_ref::<std::io::Error>().map(|e| e.kind()),
+        Some(
+            ErrorKind::BrokenPipe
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::NotConnected
+                | ErrorKind::UnexpectedEof
+        )
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -581,6 +1249,9 @@ mod tests {
     fn test_chat_request_serialization() {
         let request = IpcRequest::Chat {
             message: "Hello, world!".to_string(),
+            provider: LlmProvider::Auto,
+            dry_run: false,
+            agentic: false,
         };
         let json = serde_json::to_string(&request).unwrap();
         assert!(json.contains("Chat"));
@@ -627,10 +1298,16 @@ mod tests {
     fn test_error_response_serialization() {
         let response = IpcResponse::Error {
             message: "Something went wrong".to_string(),
+            code: None,
         };
         let json = serde_json::to_string(&response).unwrap();
         assert!(json.contains("Error"));
         assert!(json.contains("Something went wrong"));
+        assert!(!json.contains("code"));
+
+        let err = anyhow::Error::from(Error::CloudUnconfigured).context("Chat failed");
+        let json = serde_json::to_string(&IpcResponse::from_error(&err)).unwrap();
+        assert!(json.contains(r#""code":"cloud_unconfigured""#), "{}", json);
     }
 
     #[test]
@@ -643,6 +1320,36 @@ mod tests {
         assert!(json.contains("Success"));
     }
 
+    #[test]
+    fn test_models_response_serialization() {
+        let response = IpcResponse::Models {
+            models: vec![ModelCompatibility {
+                model: crate::models::ModelInfo {
+                    id: "phi3:mini".to_string(),
+                    name: "phi3:mini".to_string(),
+                    description: String::new(),
+                    size_bytes: 0,
+                    backend: ModelBackend::Ollama,
+                    requirements: crate::models::ModelRequirements {
+                        min_ram_bytes: 0,
+                        recommended_ram_bytes: 0,
+                        vram_bytes: 0,
+                        supports_cpu: true,
+                        quantization: None,
+                    },
+                    tags: vec![],
+                },
+                compatibility: CompatibilityResult::Incompatible {
+                    reason: "Insufficient RAM".to_string(),
+                },
+            }],
+        };
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json.contains(r#""type":"Models""#));
+        assert!(json.contains(r#""status":"incompatible""#));
+        assert!(json.contains("Insufficient RAM"));
+    }
+
     #[test]
     fn test_pong_response_serialization() {
         let response = IpcResponse::Pong;
@@ -650,6 +1357,28 @@ mod tests {
         assert!(json.contains("Pong"));
     }
 
+    #[test]
+    fn test_status_response_serialization() {
+        let mut mcp_servers = std::collections::HashMap::new();
+        mcp_servers.insert("void-tools".to_string(), "ready".to_string());
+        let response = IpcResponse::Status {
+            status: SystemStatus {
+                version: "0.1.0".to_string(),
+                uptime_secs: 42,
+                llm_model: "phi3:mini".to_string(),
+                local_llm_available: true,
+                cloud_configured: false,
+                mcp_servers,
+                active_sessions: 2,
+                peer_count: 1,
+            },
+        };
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json.contains(r#""type":"Status""#));
+        assert!(json.contains(r#""void-tools":"ready""#));
+        assert!(json.contains(r#""uptime_secs":42"#));
+    }
+
     // Message size validation tests
 
     #[test]
@@ -670,10 +1399,47 @@ mod tests {
         let test_cases = [
             r#"{"type":"Authenticate","token":"abc"}"#,
             r#"{"type":"Chat","message":"hello"}"#,
+            r#"{"type":"Chat","message":"clean tmp","dry_run":true}"#,
+            r#"{"type":"Chat","message":"tidy up","agentic":true}"#,
             r#"{"type":"SetSession","id":"sess-1"}"#,
+            r#"{"type":"SetProvider","provider":"cloud"}"#,
             r#"{"type":"GetContext"}"#,
+            r#"{"type":"SetWorkingDirectory","path":"~/projects"}"#,
+            r#"{"type":"SetPreference","key":"language","value":"python"}"#,
             r#"{"type":"Status"}"#,
+            r#"{"type":"SetupStatus"}"#,
+            r#"{"type":"SetupAction","action":{"step":"pull_recommended_model"}}"#,
+            r#"{"type":"SetupAction","action":{"step":"pull_recommended_model","model":"phi3:mini"}}"#,
+            r#"{"type":"SetupAction","action":{"step":"set_cloud_key","api_key":"sk-or-1"}}"#,
             r#"{"type":"ExecuteCode","code":"ls"}"#,
+            r#"{"type":"ListModels"}"#,
+            r#"{"type":"ListModels","backend":"HuggingFace"}"#,
+            r#"{"type":"SwitchModel","id":"llama3.2:3b"}"#,
+            r#"{"type":"UnloadModel"}"#,
+            r#"{"type":"RecommendModels"}"#,
+            r#"{"type":"Benchmark"}"#,
+            r#"{"type":"GetRoutingLog"}"#,
+            r#"{"type":"GetRoutingLog","limit":5}"#,
+            r#"{"type":"Benchmark","runs":5}"#,
+            r#"{"type":"SearchHistory","query":"postgres"}"#,
+            r#"{"type":"SearchHistory","query":"postgres","limit":5}"#,
+            r#"{"type":"CreateSurface","spec":{"type":"html","title":"t","width":400,"height":300,"content":"<p>hi</p>","interactive":false}}"#,
+            r#"{"type":"ListSurfaces"}"#,
+            r#"{"type":"UpdateSurface","id":"abc","action":"hide"}"#,
+            r#"{"type":"GetPeers"}"#,
+            r#"{"type":"GetSyncStatus"}"#,
+            r#"{"type":"PreviewSync"}"#,
+            r#"{"type":"PreviewSyncTurn","input":"hi","response":"hello"}"#,
+            r#"{"type":"RotateDeviceKeys"}"#,
+            r#"{"type":"GetCacheStats"}"#,
+            r#"{"type":"ValidateCode","code":"ls"}"#,
+            r#"{"type":"ClearCache"}"#,
+            r#"{"type":"ListCapabilities"}"#,
+            r#"{"type":"ListResources"}"#,
+            r#"{"type":"ReadResource","uri":"docs://readme"}"#,
+            r#"{"type":"ResolveConfirmation","id":"abc","approve":true}"#,
+            r#"{"type":"ListArtifacts"}"#,
+            r#"{"type":"GetArtifact","id":"abc"}"#,
             r#"{"type":"Ping"}"#,
         ];
 
@@ -689,4 +1455,110 @@ mod tests {
         let result: Result<IpcRequest, _> = serde_json::from_str(invalid_json);
         assert!(result.is_err(), "Should fail on invalid request type");
     }
+
+    #[tokio::test]
+    async fn test_send_reconnects_after_disconnect() {
+        let path = std::env::temp_dir().join(format!("mycel-ipc-{}.sock", uuid::Uuid::new_v4()));
+        let listener = UnixListener::bind(&path).unwrap();
+
+        // Stands in for the daemon: the first connection drops after one
+        // request, as if it restarted, and the second stays up
+        let server = tokio::spawn(async move {
+            let mut seen = Vec::new();
+            for max_requests in [1, usize::MAX] {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (read, mut write) = stream.into_split();
+                let mut lines = BufReader::new(read).lines();
+                let mut requests = Vec::new();
+                while requests.len() < max_requests {
+                    let Some(line) = lines.next_line().await.unwrap() else {
+                        break;
+                    };
+                    let response = match serde_json::from_str(&line).unwrap() {
+                        IpcRequest::Authenticate { .. } => IpcResponse::Ok {
+                            message: "Authenticated successfully".to_string(),
+                        },
+                        _ => IpcResponse::Pong,
+                    };
+                    let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+                    requests.push(value["type"].as_str().unwrap().to_string());
+                    let json = serde_json::to_string(&response).unwrap() + "\n";
+                    write.write_all(json.as_bytes()).await.unwrap();
+                }
+                seen.push(requests);
+            }
+            seen
+        });
+
+        let mut client = IpcClient::connect(path.to_str().unwrap())
+            .await
+            .unwrap()
+            .with_auto_reconnect(true);
+        let response = client.authenticate("secret").await.unwrap();
+        assert!(matches!(response, IpcResponse::Ok { .. }));
+
+        let response = client.send(&IpcRequest::Ping).await.unwrap();
+        assert!(matches!(response, IpcResponse::Pong));
+        assert!(client.is_connected());
+
+        drop(client);
+        let seen = server.await.unwrap();
+        assert_eq!(seen[0], ["Authenticate"]);
+        assert_eq!(seen[1], ["Authenticate", "Ping"]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_send_only_replays_read_only_requests() {
+        let path = std::env::temp_dir().join(format!("mycel-ipc-{}.sock", uuid::Uuid::new_v4()));
+        let listener = UnixListener::bind(&path).unwrap();
+
+        // Drops the connection after reading a request without answering
+        // it, as if the daemon crashed while handling it: the first
+        // connection does so straight away, the second after one answer
+        let server = tokio::spawn(async move {
+            let mut seen = Vec::new();
+            for answers in [0, 1] {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (read, mut write) = stream.into_split();
+                let mut lines = BufReader::new(read).lines();
+                for answered in 0..=answers {
+                    let line = lines.next_line().await.unwrap().unwrap();
+                    let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+                    seen.push(value["type"].as_str().unwrap().to_string());
+                    if answered < answers {
+                        let json = serde_json::to_string(&IpcResponse::Pong).unwrap() + "\n";
+                        write.write_all(json.as_bytes()).await.unwrap();
+                    }
+                }
+            }
+            let replayed = tokio::time::timeout(Duration::from_millis(300), listener.accept())
+                .await
+                .is_ok();
+            (seen, replayed)
+        });
+
+        let mut client = IpcClient::connect(path.to_str().unwrap())
+            .await
+            .unwrap()
+            .with_auto_reconnect(true);
+
+        // A read-only request is retried on a fresh connection
+        let response = client.send(&IpcRequest::Status).await.unwrap();
+        assert!(matches!(response, IpcResponse::Pong));
+
+        // The code may already have run, so it is not sent again
+        let result = client
+            .send(&IpcRequest::ExecuteCode {
+                code: "echo once".to_string(),
+            })
+            .await;
+        assert!(result.is_err());
+        assert!(!client.is_connected());
+
+        let (seen, replayed) = server.await.unwrap();
+        assert_eq!(seen, ["Status", "Status", "ExecuteCode"]);
+        assert!(!replayed);
+        let _ = std::fs::remove_file(&path);
+    }
 }
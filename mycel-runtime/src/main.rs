@@ -15,6 +15,9 @@
 use anyhow::Result;
 use clap::Parser;
 use futures::Stream;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
 mod ai;
@@ -22,11 +25,14 @@ mod codegen;
 mod collective;
 mod config;
 mod context;
+mod egress;
+mod error;
 mod events;
 mod executor;
 mod intent;
 mod ipc;
 mod mcp;
+mod metrics;
 mod models;
 mod policy;
 mod sync;
@@ -62,6 +68,10 @@ struct Args {
     /// Run as daemon (no interactive CLI)
     #[arg(long)]
     daemon: bool,
+
+    /// Answer only: no code execution, side-effecting tools, evolution or sync
+    #[arg(long)]
+    safe_mode: bool,
 }
 
 fn print_banner() {
@@ -91,7 +101,10 @@ async fn main() -> Result<()> {
 
     print_banner();
 
-    let config = MycelConfig::load(&args.config, args.dev)?;
+    let mut config = MycelConfig::load(&args.config, args.dev)?;
+    if args.safe_mode {
+        config.enable_safe_mode();
+    }
 
     // Log config status
     tracing::info!(
@@ -100,32 +113,46 @@ async fn main() -> Result<()> {
         !config.openrouter_api_key.is_empty()
     );
 
+    // Create system event bus
+    let (event_bus, _) = tokio::sync::broadcast::channel(100);
+
+    // Held until shutdown; a second daemon on the same state refuses to start
+    let instance_lock = context::InstanceLock::acquire(&config.context_path)?;
+
     let context_manager = context::ContextManager::new(&config).await?;
     let ai_router = if args.no_local_llm {
-        ai::AiRouter::cloud_only(&config).await?
+        ai::AiRouter::cloud_only(&config, event_bus.clone()).await?
     } else {
-        ai::AiRouter::new(&config).await?
+        ai::AiRouter::new(&config, event_bus.clone()).await?
     };
     let executor = executor::CodeExecutor::new(&config)?;
-    let policy_evaluator = policy::PolicyEvaluator::with_defaults();
+    let policy_evaluator = Arc::new(std::sync::RwLock::new(policy::PolicyEvaluator::new(
+        config.policy.clone(),
+    )));
     let ui_factory = ui::UiFactory::new(&config)?;
+    let artifacts = codegen::ArtifactStore::open(&config.code_path)?;
 
-    // Create system event bus
-    let (event_bus, _) = tokio::sync::broadcast::channel(100);
-
-    // Initialize MCP manager with default void-tools config if none specified
+    // Initialize MCP manager with the enabled builtins plus any configured servers
     let runtime_path = std::env::current_dir()
         .map(|p| p.to_string_lossy().to_string())
         .unwrap_or_else(|_| ".".to_string());
 
-    let mcp_config = if config.mcp.servers.is_empty() && config.mcp.enabled {
-        // Use default void-tools configuration
-        mcp::default_void_tools_config(&runtime_path)
-    } else {
-        config.mcp.clone()
-    };
+    let mut mcp_config = config.mcp.clone();
+    if mcp_config.enabled {
+        mcp_config.servers = mcp::resolve_servers(&mcp_config, &runtime_path);
+    }
 
-    let mcp_manager = mcp::McpManager::new(&mcp_config, &runtime_path, event_bus.clone()).await?;
+    let mcp_manager = mcp::McpManager::new(&mcp_config, &runtime_path, event_bus.clone())
+        .await?
+        .with_policy(policy_evaluator.clone());
+    if mcp_config.persist_tool_cache {
+        let cache_path = std::path::Path::new(&config.context_path).join("tool_cache.json");
+        match mcp_manager.load_cache(&cache_path).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("Restored {} cached tool results", n),
+            Err(e) => tracing::warn!("Failed to load tool result cache: {}", e),
+        }
+    }
     // Start MCP servers in the background
     if let Err(e) = mcp_manager.start_servers().await {
         tracing::warn!("Failed to start MCP servers: {}", e);
@@ -133,90 +160,401 @@ async fn main() -> Result<()> {
 
     let sync_service =
         sync::SyncService::new(&config, Some(mcp_manager.clone()), event_bus.clone()).await?;
-    sync_service.start().await?;
+    if config.safe_mode {
+        tracing::info!("Safe mode: sync disabled");
+    } else {
+        sync_service.start().await?;
+    }
+
+    let collective = if config.collective_enabled && !args.no_collective {
+        match collective::CollectiveIntelligence::new(&config).await {
+            Ok(collective) => Some(Arc::new(collective)),
+            Err(e) => {
+                tracing::warn!("Collective intelligence unavailable: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let metrics_bind = config.metrics_bind.clone();
+
+    let package_manager = executor::PackageManager::detect();
+    match package_manager {
+        Some(pm) => tracing::info!("Package manager: {}", pm.name()),
+        None => tracing::warn!("No supported package manager found (xbps, apt, dnf, pacman)"),
+    }
+
+    // Cancelled on SIGINT/SIGTERM; everything long-running watches it
+    let shutdown = CancellationToken::new();
 
     // Create the main runtime
     let runtime = MycelRuntime {
-        config,
+        config: Arc::new(RwLock::new(config)),
+        config_path: args.config.clone(),
+        dev_mode: args.dev,
         context_manager,
-        ai_router,
+        // In-flight inference is aborted on shutdown rather than awaited
+        ai_router: ai_router.with_cancellation(shutdown.clone()),
         executor,
         policy_evaluator,
         ui_factory,
+        surfaces: ui::SurfaceRegistry::new(),
+        artifacts,
         sync_service,
         mcp_manager,
+        collective,
+        started_at: std::time::Instant::now(),
+        package_manager,
+        commands: Arc::new(executor::CommandIndex::new()),
     };
 
     let ipc_server = ipc::IpcServer::new(&runtime).await?;
 
+    // Ctrl-C during a dev CLI reply stops the reply, not the daemon
+    let mut turn_interrupt = None;
     if args.dev {
         // Only spawn interactive CLI if running with a tty and not in daemon mode
         let run_cli = !args.daemon && atty::is(atty::Stream::Stdin);
         if run_cli {
-            tokio::spawn(run_dev_cli(runtime.clone()));
+            let interrupt = TurnInterrupt::default();
+            tokio::spawn(run_dev_cli(runtime.clone(), interrupt.clone()));
+            turn_interrupt = Some(interrupt);
+        }
+    }
+
+    tokio::spawn(wait_for_shutdown_signal(shutdown.clone(), turn_interrupt));
+
+    // Reload the config file on SIGHUP
+    let reload_runtime = runtime.clone();
+    let reload_shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                tracing::warn!("Config reload on SIGHUP unavailable: {}", e);
+                return;
+            }
+        };
+        loop {
+            tokio::select! {
+                _ = reload_shutdown.cancelled() => break,
+                _ = hangup.recv() => {}
+            }
+            if let Err(e) = reload_runtime.reload_config().await {
+                tracing::error!("Config reload failed: {}", e);
+            }
         }
+    });
+
+    // Prometheus metrics, when an address is configured
+    if let Some(bind) = metrics_bind {
+        let metrics = metrics::Metrics::new();
+        metrics.spawn_collector(event_bus.subscribe(), shutdown.clone());
+        let metrics_runtime = runtime.clone();
+        let metrics_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(&bind, metrics, metrics_runtime, metrics_shutdown).await
+            {
+                tracing::error!("Metrics endpoint failed: {}", e);
+            }
+        });
     }
 
-    // Background session cleanup
+    // Background session and confirmation cleanup
     let cleanup_context_manager = runtime.context_manager.clone();
+    let cleanup_mcp_manager = runtime.mcp_manager.clone();
+    let cleanup_shutdown = shutdown.clone();
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
         loop {
-            interval.tick().await;
+            tokio::select! {
+                _ = cleanup_shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
             cleanup_context_manager.cleanup_stale_sessions(None).await;
+            cleanup_mcp_manager.sweep_expired_confirmations().await;
+            cleanup_context_manager
+                .expire_pending_tool_calls(cleanup_mcp_manager.confirmation_ttl())
+                .await;
         }
     });
 
-    ipc_server.run().await?;
+    ipc_server.run(shutdown).await?;
+    runtime.shutdown().await;
+    drop(instance_lock);
 
     Ok(())
 }
 
+/// Resolve once SIGINT or SIGTERM arrives, cancelling `shutdown`. A SIGINT
+/// that interrupts a dev CLI turn in progress doesn't count.
+async fn wait_for_shutdown_signal(shutdown: CancellationToken, turn: Option<TurnInterrupt>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to install SIGTERM handler: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    let interrupt = async {
+        loop {
+            if tokio::signal::ctrl_c().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+            if !turn.as_ref().is_some_and(TurnInterrupt::interrupt) {
+                break;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+
+    tracing::info!("Shutdown signal received");
+    shutdown.cancel();
+}
+
 /// The main runtime struct that ties everything together
 #[derive(Clone)]
 pub struct MycelRuntime {
-    pub config: MycelConfig,
+    /// Live configuration, shared so commands can change it at runtime
+    pub config: Arc<RwLock<MycelConfig>>,
+    /// File the configuration was loaded from
+    pub config_path: String,
+    /// Started with --dev (affects how the config file is loaded)
+    pub dev_mode: bool,
     pub context_manager: context::ContextManager,
     pub ai_router: ai::AiRouter,
     pub executor: executor::CodeExecutor,
-    pub policy_evaluator: policy::PolicyEvaluator,
+    /// Shared so a config reload can swap the rules
+    pub policy_evaluator: Arc<std::sync::RwLock<policy::PolicyEvaluator>>,
     pub ui_factory: ui::UiFactory,
+    /// Surfaces created through IPC and not yet destroyed
+    pub surfaces: ui::SurfaceRegistry,
+    /// Index of generated code saved under `code_path`
+    pub artifacts: codegen::ArtifactStore,
     pub sync_service: sync::SyncService,
     pub mcp_manager: mcp::McpManager,
+    /// Pattern learning and sharing, when `collective_enabled` is set
+    pub collective: Option<Arc<collective::CollectiveIntelligence>>,
+    /// When the runtime came up, for uptime reporting
+    pub started_at: std::time::Instant,
+    /// Host package manager, used to suggest installs for missing commands
+    pub package_manager: Option<executor::PackageManager>,
+    /// Commands on `PATH`, used to correct mistyped ones
+    pub commands: Arc<executor::CommandIndex>,
 }
 
 impl MycelRuntime {
+    /// Ordered shutdown: stop MCP servers, flush the audit log, then persist
+    /// sessions and the sync log. Each step logs and carries on if it fails.
+    pub async fn shutdown(&self) {
+        if let Err(e) = self.mcp_manager.stop_all().await {
+            tracing::warn!("Failed to stop MCP servers: {}", e);
+        }
+
+        let context_path = self.config.read().await.context_path.clone();
+        let audit_path = std::path::Path::new(&context_path).join("tool_audit.jsonl");
+        if let Err(e) = self.mcp_manager.flush_audit_log(&audit_path).await {
+            tracing::warn!("Failed to flush tool audit log: {}", e);
+        }
+        if self.config.read().await.mcp.persist_tool_cache {
+            let cache_path = std::path::Path::new(&context_path).join("tool_cache.json");
+            if let Err(e) = self.mcp_manager.save_cache(&cache_path).await {
+                tracing::warn!("Failed to save tool result cache: {}", e);
+            }
+        }
+
+        if let Err(e) = self.context_manager.persist_sessions().await {
+            tracing::warn!("Failed to persist sessions: {}", e);
+        }
+        if let Err(e) = self.context_manager.save_user_context().await {
+            tracing::warn!("Failed to save user context: {}", e);
+        }
+
+        if let Err(e) = self.sync_service.stop().await {
+            tracing::warn!("Failed to persist sync log: {}", e);
+        }
+
+        tracing::info!("Shutdown complete");
+    }
+
+    /// Re-read the config file and apply the settings that can change live
+    /// (models, cloud settings, policy). Others are logged as needing a restart.
+    pub async fn reload_config(&self) -> Result<()> {
+        let mut new = MycelConfig::load(&self.config_path, self.dev_mode)?;
+
+        let mut config = self.config.write().await;
+        // `--safe-mode` outlasts reloads
+        if config.safe_mode {
+            new.enable_safe_mode();
+        }
+        for field in config.restart_required(&new) {
+            tracing::warn!("Config change to '{}' requires restart", field);
+        }
+
+        let changed = config.apply_live(&new);
+        if changed.is_empty() {
+            tracing::info!("Config reloaded, no live settings changed");
+            return Ok(());
+        }
+
+        self.ai_router.apply_config(&config);
+        *self
+            .policy_evaluator
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) =
+            policy::PolicyEvaluator::new(config.policy.clone());
+
+        tracing::info!("Config reloaded: {}", changed.join(", "));
+        Ok(())
+    }
+
+    /// Change a session's working directory. Relative paths resolve against
+    /// the current one and `~` against home; the result must be an existing
+    /// directory the policy allows. Returns the absolute path.
+    pub async fn change_working_directory(&self, session_id: &str, path: &str) -> Result<String> {
+        let context = self.context_manager.get_context(session_id).await?;
+
+        let requested = if path == "~" || path.starts_with("~/") {
+            dirs::home_dir()
+                .ok_or_else(|| anyhow::anyhow!("No home directory to resolve '~'"))?
+                .join(path[1..].trim_start_matches('/'))
+        } else {
+            std::path::Path::new(&context.working_directory).join(path)
+        };
+
+        let resolved = requested
+            .canonicalize()
+            .map_err(|e| anyhow::anyhow!("Cannot change to '{}': {}", path, e))?;
+        if !resolved.is_dir() {
+            anyhow::bail!("'{}' is not a directory", path);
+        }
+
+        let resolved = resolved.to_string_lossy().to_string();
+        let allowed = self
+            .policy_evaluator
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_path_allowed(&resolved);
+        if !allowed {
+            anyhow::bail!("Access to '{}' is blocked by security policy", resolved);
+        }
+
+        self.context_manager
+            .set_working_directory(session_id, &resolved)
+            .await?;
+        Ok(resolved)
+    }
+
+    /// Save a user preference and share it with the device's mesh peers
+    pub async fn set_user_preference(&self, key: &str, value: &str) -> Result<()> {
+        if key.trim().is_empty() {
+            anyhow::bail!("Preference key must not be empty");
+        }
+        self.context_manager.set_user_preference(key, value).await?;
+        let _ = self
+            .sync_service
+            .create_event(crate::sync::SyncOperation::UpdatePreference {
+                key: key.to_string(),
+                value: value.to_string(),
+            })
+            .await;
+        Ok(())
+    }
+
+    /// Link a NEAR account: persist it to the config file, apply it to the
+    /// running config, and start blockchain sync without a restart
+    pub async fn link_near_account(&self, account_id: &str) -> Result<()> {
+        let account = account_id.to_string();
+        MycelConfig::update_file(&self.config_path, |c| {
+            c.blockchain_sync = true;
+            c.near_account = Some(account.clone());
+        })?;
+
+        {
+            let mut config = self.config.write().await;
+            config.blockchain_sync = true;
+            config.near_account = Some(account.clone());
+        }
+
+        self.sync_service.link_near_account(&account).await
+    }
+
+    /// Run one step of first-run setup, saving what it changes to the
+    /// config file and applying it to the running config
+    pub async fn run_setup_action(&self, action: &ipc::SetupAction) -> Result<String> {
+        let message = match action {
+            ipc::SetupAction::PullRecommendedModel { model } => {
+                let id = self
+                    .ai_router
+                    .pull_recommended_model(model.as_deref())
+                    .await?;
+                let local_model = id.clone();
+                MycelConfig::update_file(&self.config_path, |c| c.local_model = local_model)?;
+                self.config.write().await.local_model = id.clone();
+                format!("Pulled {} and made it the local model", id)
+            }
+            ipc::SetupAction::SetCloudKey { api_key } => {
+                let api_key = api_key.trim().to_string();
+                if api_key.is_empty() {
+                    anyhow::bail!("The API key is empty");
+                }
+                // A reference (`env:`, `file:`, `keyring:`) is saved as given
+                let resolved = config::resolve_secret("openrouter_api_key", &api_key)?;
+                MycelConfig::update_file(&self.config_path, |c| c.openrouter_api_key = api_key)?;
+                self.config.write().await.openrouter_api_key = resolved;
+                format!("Saved the OpenRouter API key to {}", self.config_path)
+            }
+        };
+
+        self.ai_router.apply_config(&*self.config.read().await);
+        // Setup is often what brings the local model up
+        self.ai_router.refresh_local_availability().await;
+        Ok(message)
+    }
+
     /// Process user input - the LLM is the interface between user and OS
     pub async fn process_input(&self, input: &str, session_id: &str) -> Result<RuntimeResponse> {
+        self.process_input_inner(input, session_id, false, false, None)
+            .await
+    }
+
+    /// `process_input`, optionally previewing generated code instead of running
+    /// it, letting the model take several tool rounds (`agentic`), and
+    /// streaming tool progress to `progress`
+    async fn process_input_inner(
+        &self,
+        input: &str,
+        session_id: &str,
+        dry_run: bool,
+        agentic: bool,
+        progress: Option<mcp::ProgressSender>,
+    ) -> Result<RuntimeResponse> {
         let context = self.context_manager.get_context(session_id).await?;
+        if self.safe_mode().await {
+            return self.answer_only(input, context).await;
+        }
 
         // 1. Handle pending confirmations
-        if let Some(pending_code) = &context.pending_command {
-            let input_lower = input.to_lowercase();
-            if input_lower == "yes"
-                || input_lower == "y"
-                || input_lower == "confirm"
-                || input_lower == "ok"
-            {
-                // User confirmed - clear and execute
-                self.context_manager
-                    .clear_pending_command(session_id)
-                    .await?;
-                let output = self.executor.run(pending_code).await?;
-                return Ok(RuntimeResponse::Text(output));
-            } else if input_lower == "no" || input_lower == "n" || input_lower == "cancel" {
-                // User denied - clear and inform
-                self.context_manager
-                    .clear_pending_command(session_id)
-                    .await?;
-                return Ok(RuntimeResponse::Text("action cancelled.".to_string()));
-            } else {
-                // User typed something else - inform them they have a pending action
-                return Ok(RuntimeResponse::Text(format!(
-                    "you have a pending action. type 'yes' to confirm or 'no' to cancel.\ncode: {}",
-                    pending_code
-                )));
-            }
+        if let Some(response) = self
+            .answer_pending(input, session_id, &context, progress.clone())
+            .await?
+        {
+            return Ok(response);
         }
 
         // 2. Normal processing
@@ -243,56 +581,246 @@ impl MycelRuntime {
             }
         }
 
+        // A learned pattern that matches this input answers it directly
+        if let Some(answer) = self.known_pattern_response(input, &context).await {
+            return Ok(RuntimeResponse::Text(answer));
+        }
+
+        // Everything past here needs a model
+        if !self.ai_router.has_backend() {
+            return Err(error::Error::NoBackend.into());
+        }
+
         // The LLM decides what to do - use MCP tools if available
-        let response = self
-            .ai_router
-            .process_with_tools(input, &context, &self.mcp_manager)
-            .await?;
+        let context = self.with_relevant_history(context, input).await;
+        let mcp = self.mcp_for(session_id, progress);
+        let reply = if agentic {
+            self.ai_router
+                .process_with_tools_loop(
+                    input,
+                    &context,
+                    &mcp,
+                    self.agentic_max_iterations().await,
+                    ipc::LlmProvider::Auto,
+                )
+                .await?
+        } else {
+            self.ai_router
+                .process_with_tools(input, &context, &mcp)
+                .await?
+        };
+
+        self.handle_tools_reply(reply, input, session_id, dry_run)
+            .await
+    }
+
+    /// Answer a confirmation the session is waiting on, if `input` is one.
+    /// `None` means `input` is a new request.
+    async fn answer_pending(
+        &self,
+        input: &str,
+        session_id: &str,
+        context: &context::Context,
+        progress: Option<mcp::ProgressSender>,
+    ) -> Result<Option<RuntimeResponse>> {
+        if let Some(pending_code) = &context.pending_command {
+            let answer = confirmation_answer(input);
+            if answer == Some(true) {
+                // User confirmed - clear and execute
+                self.context_manager
+                    .clear_pending_command(session_id)
+                    .await?;
+                let artifact = self.artifacts.find_by_code(pending_code);
+                let result = self
+                    .executor
+                    .run_detailed_as(pending_code, artifact.as_ref().map(|a| a.language))
+                    .await?;
+                if let Some(artifact) = artifact {
+                    self.mark_artifact_executed(&artifact.id);
+                }
+                return Ok(Some(RuntimeResponse::Code {
+                    code: pending_code.clone(),
+                    result,
+                }));
+            } else if answer == Some(false) {
+                // User denied - clear and inform
+                self.context_manager
+                    .clear_pending_command(session_id)
+                    .await?;
+                return Ok(Some(RuntimeResponse::Text("action cancelled.".to_string())));
+            } else {
+                // User typed something else - inform them they have a pending action
+                return Ok(Some(RuntimeResponse::Text(format!(
+                    "you have a pending action. type 'yes' to confirm or 'no' to cancel.\ncode: {}",
+                    pending_code
+                ))));
+            }
+        }
+        if let Some(call) = &context.pending_tool_call {
+            let ttl = self.mcp_manager.confirmation_ttl();
+            if !self
+                .context_manager
+                .pending_tool_call_expired(session_id, ttl)
+                .await
+            {
+                let reply = answer_pending_tool_call(
+                    &self.context_manager,
+                    &self.mcp_for(session_id, progress),
+                    session_id,
+                    call,
+                    input,
+                )
+                .await?;
+                return Ok(Some(RuntimeResponse::Text(reply)));
+            }
+            // Expired: cancelled, and anything but an answer is a new request
+            self.context_manager
+                .clear_pending_tool_call(session_id)
+                .await?;
+            if confirmation_answer(input).is_some() {
+                return Ok(Some(RuntimeResponse::Text(format!(
+                    "that confirmation expired; ask again to re-request it.\ntool: {}",
+                    call.name
+                ))));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Hold a tool call that needs confirmation for the session's answer,
+    /// or handle the model's final response
+    async fn handle_tools_reply(
+        &self,
+        reply: ai::ToolsReply,
+        input: &str,
+        session_id: &str,
+        dry_run: bool,
+    ) -> Result<RuntimeResponse> {
+        match reply {
+            ai::ToolsReply::Text(response) => {
+                self.handle_model_response(response, input, session_id, dry_run)
+                    .await
+            }
+            ai::ToolsReply::Confirm { call, message } => {
+                self.context_manager
+                    .set_pending_tool_call(session_id, Some(call))
+                    .await?;
+                Ok(RuntimeResponse::Text(format!(
+                    "{}\ntype 'yes' to run it or 'no' to cancel.",
+                    message
+                )))
+            }
+        }
+    }
 
-        // Check if LLM wants to execute code
+    /// Run the code in a model response marked `#!exec` (through policy),
+    /// or return the response as is. A fenced answer is only shown: chat
+    /// answers carry code blocks that were never meant to run.
+    async fn handle_model_response(
+        &self,
+        response: String,
+        input: &str,
+        session_id: &str,
+        dry_run: bool,
+    ) -> Result<RuntimeResponse> {
         if response.starts_with("#!exec\n") || response.starts_with("#!exec ") {
-            let code = response.trim_start_matches("#!exec").trim();
-            self.execute_code_with_policy(code, session_id).await
-        } else if response.starts_with("```") {
-            let code = extract_code_block(&response);
-            self.execute_code_with_policy(&code, session_id).await
+            // A fenced body runs as the language its tag names
+            let body = response.trim_start_matches("#!exec");
+            let (language, code) = codegen::extract_code_block(body);
+            self.execute_code_with_policy(&code, language, input, session_id, dry_run)
+                .await
         } else {
-            // Return the response from process_with_tools directly
             Ok(RuntimeResponse::Text(response))
         }
     }
 
-    /// Process user input with a specific LLM provider
+    /// Process user input with a specific LLM provider. With `dry_run`,
+    /// generated code is explained and held for confirmation, not run.
+    /// With `agentic`, the model may take several tool rounds.
+    /// Progress from long-running tools is sent to `progress` as it arrives.
     pub async fn process_input_with_provider(
         &self,
         input: &str,
         session_id: &str,
         provider: ipc::LlmProvider,
+        dry_run: bool,
+        agentic: bool,
+        progress: Option<mcp::ProgressSender>,
     ) -> Result<RuntimeResponse> {
         use ipc::LlmProvider;
 
         // If auto, use normal process_input
-        if provider == LlmProvider::Auto {
-            return self.process_input(input, session_id).await;
+        if provider == LlmProvider::Auto || self.safe_mode().await {
+            return self
+                .process_input_inner(input, session_id, dry_run, agentic, progress)
+                .await;
         }
 
         let context = self.context_manager.get_context(session_id).await?;
+        if let Some(response) = self
+            .answer_pending(input, session_id, &context, progress.clone())
+            .await?
+        {
+            return Ok(response);
+        }
+        let context = self.with_relevant_history(context, input).await;
 
         // Use provider-aware processing
-        let response = self
-            .ai_router
-            .process_with_tools_provider(input, &context, &self.mcp_manager, provider)
-            .await?;
-
-        // Check if LLM wants to execute code
-        if response.starts_with("#!exec\n") || response.starts_with("#!exec ") {
-            let code = response.trim_start_matches("#!exec").trim();
-            self.execute_code_with_policy(code, session_id).await
-        } else if response.starts_with("```") {
-            let code = extract_code_block(&response);
-            self.execute_code_with_policy(&code, session_id).await
+        let mcp = self.mcp_for(session_id, progress);
+        let reply = if agentic {
+            let max_iterations = self.agentic_max_iterations().await;
+            self.ai_router
+                .process_with_tools_loop(input, &context, &mcp, max_iterations, provider)
+                .await?
         } else {
-            Ok(RuntimeResponse::Text(response))
+            self.ai_router
+                .process_with_tools_provider(input, &context, &mcp, provider)
+                .await?
+        };
+
+        self.handle_tools_reply(reply, input, session_id, dry_run)
+            .await
+    }
+
+    async fn safe_mode(&self) -> bool {
+        self.config.read().await.safe_mode
+    }
+
+    /// Safe mode reply: the model's answer as text, never run or acted on
+    async fn answer_only(&self, input: &str, context: context::Context) -> Result<RuntimeResponse> {
+        if !self.ai_router.has_backend() {
+            return Err(error::Error::NoBackend.into());
+        }
+        let context = self.with_relevant_history(context, input).await;
+        let response = self.ai_router.generate_response(input, &context).await?;
+        Ok(RuntimeResponse::Text(response))
+    }
+
+    /// Run code sent directly over IPC, unless safe mode forbids it
+    pub async fn run_code(&self, code: &str) -> error::Result<executor::ExecutionResult> {
+        if self.safe_mode().await {
+            return Err(error::Error::PolicyDenied(
+                "Code execution is disabled in safe mode".to_string(),
+            ));
+        }
+        self.executor.run_detailed(code).await
+    }
+
+    /// Tool rounds allowed in an agentic chat
+    async fn agentic_max_iterations(&self) -> usize {
+        self.config.read().await.mcp.agentic_max_iterations
+    }
+
+    /// The MCP manager for `session_id`, streaming tool progress to
+    /// `progress` when given
+    fn mcp_for(&self, session_id: &str, progress: Option<mcp::ProgressSender>) -> mcp::McpManager {
+        let mcp = self.mcp_manager.with_session(mcp::ToolSession {
+            id: session_id.to_string(),
+            context_manager: self.context_manager.clone(),
+        });
+        match progress {
+            Some(progress) => mcp.with_progress(progress),
+            None => mcp,
         }
     }
 
@@ -308,31 +836,153 @@ impl MycelRuntime {
             .update_session(session_id, user, assistant)
             .await?;
 
-        let _ = self
-            .sync_service
-            .create_event(crate::sync::SyncOperation::AddConversationTurn {
-                session_id: session_id.to_string(),
-                user: turn.user,
-                assistant: turn.assistant,
-            })
-            .await;
+        // Embed the turn in the background so it can be recalled semantically later
+        let ai_router = self.ai_router.clone();
+        let context_manager = self.context_manager.clone();
+        let embed_session = session_id.to_string();
+        let embed_text = format!("{}\n{}", turn.user, turn.assistant);
+        let turn_timestamp = turn.timestamp;
+        tokio::spawn(async move {
+            if let Ok(embedding) = ai_router.embed(&embed_text).await {
+                let _ = context_manager
+                    .attach_embedding(&embed_session, turn_timestamp, embedding)
+                    .await;
+            }
+        });
+
+        if let Some(collective) = self.collective.clone() {
+            let context = self.context_manager.get_context(session_id).await?;
+            let success = interaction_succeeded(&turn.assistant);
+            let interaction = collective::Interaction {
+                id: uuid::Uuid::new_v4().to_string(),
+                timestamp: turn.timestamp,
+                user_input: turn.user.clone(),
+                ai_response: turn.assistant.clone(),
+                context_snapshot: context.clone(),
+                success,
+                // No explicit feedback yet: a successful turn counts as a good one
+                user_rating: success.then_some(4),
+            };
+            tokio::spawn(async move {
+                if let Err(e) = collective
+                    .learn_from_interaction(&interaction, &context)
+                    .await
+                {
+                    tracing::debug!("Pattern learning failed: {}", e);
+                }
+            });
+        }
+
+        if !self.safe_mode().await {
+            let _ = self
+                .sync_service
+                .create_event(crate::sync::SyncOperation::AddConversationTurn {
+                    session_id: session_id.to_string(),
+                    user: turn.user,
+                    assistant: turn.assistant,
+                })
+                .await;
+        }
 
         Ok(())
     }
 
+    /// Answer from a learned prompt pattern, if the collective has one for `input`
+    async fn known_pattern_response(
+        &self,
+        input: &str,
+        context: &context::Context,
+    ) -> Option<String> {
+        let collective = self.collective.as_ref()?;
+        let ranked = match collective.match_pattern(input, context).await {
+            Ok(ranked) => ranked?,
+            Err(e) => {
+                tracing::debug!("Pattern lookup failed: {}", e);
+                return None;
+            }
+        };
+
+        match collective.apply_pattern(&ranked.pattern, context).await {
+            Ok(collective::patterns::PatternResult::Prompt(answer)) => {
+                tracing::debug!("Answered from pattern {}", ranked.pattern.id);
+                Some(answer)
+            }
+            Ok(_) => None,
+            Err(e) => {
+                tracing::debug!("Failed to apply pattern {}: {}", ranked.pattern.id, e);
+                None
+            }
+        }
+    }
+
+    /// Replace the context's history with the turns most relevant to `input`
+    /// (semantic when an embedding model is available, otherwise most recent)
+    async fn with_relevant_history(
+        &self,
+        mut context: context::Context,
+        input: &str,
+    ) -> context::Context {
+        let query_embedding = self.ai_router.embed(input).await.ok();
+        context.conversation_history = self
+            .context_manager
+            .relevant_turns(
+                &context.session_id,
+                query_embedding.as_deref(),
+                context::RELEVANT_TURNS,
+            )
+            .await;
+        context
+    }
+
     /// Execute code after checking with policy (Legacy, needs update if used with streaming)
+    ///
+    /// In dry-run mode (per request or `dry_run_code`), allowed code is
+    /// explained and left pending for a `yes` instead of running.
+    /// `fence_language` comes from the code fence tag, when there was one.
     async fn execute_code_with_policy(
         &self,
         code: &str,
+        fence_language: Option<codegen::CodeLanguage>,
+        description: &str,
         session_id: &str,
+        dry_run: bool,
     ) -> Result<RuntimeResponse> {
         use crate::policy::ActionPolicy;
 
-        match self.policy_evaluator.evaluate_code(code) {
+        let language = fence_language.unwrap_or_else(|| codegen::CodeLanguage::detect(code));
+        let mut artifact =
+            codegen::CodeArtifact::new(language, code.to_string(), description.to_string());
+        if let Err(e) = self.artifacts.add(&mut artifact) {
+            tracing::warn!("Failed to record code artifact: {}", e);
+        }
+
+        let decision = self
+            .policy_evaluator
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .evaluate_generated_code(code, language);
+        let dry_run = dry_run || self.config.read().await.dry_run_code;
+        match decision {
+            ActionPolicy::Allow if dry_run => {
+                let explanation = self.ai_router.explain_code(code).await.unwrap_or_else(|e| {
+                    tracing::warn!("Failed to explain code: {}", e);
+                    "(no explanation available)".to_string()
+                });
+                self.context_manager
+                    .set_pending_command(session_id, Some(code.to_string()))
+                    .await?;
+                Ok(RuntimeResponse::Text(format!(
+                    "dry run: {}\ncode: {}\ntype 'yes' to run it or 'no' to cancel.",
+                    explanation.trim(),
+                    code
+                )))
+            }
             ActionPolicy::Allow => {
-                let output = self.executor.run(code).await?;
+                let result = self.executor.run_detailed_as(code, fence_language).await?;
+                self.mark_artifact_executed(&artifact.id);
 
                 // Check if command not found in the output
+                let output = &result.output;
                 if output.contains("command not found") || output.contains("not found") {
                     let cmd = code.split_whitespace().next().unwrap_or("");
                     if !cmd.is_empty() {
@@ -340,16 +990,30 @@ impl MycelRuntime {
                     }
                 }
 
-                Ok(RuntimeResponse::Text(output))
+                Ok(RuntimeResponse::Code {
+                    code: code.to_string(),
+                    result,
+                })
             }
             ActionPolicy::RequiresConfirmation { message, .. } => {
                 // Store in session and ask user
+                let plan = if self.config.read().await.explain_plan {
+                    match self.ai_router.explain_plan(code).await {
+                        Ok(plan) => format!("\n{}", plan.describe()),
+                        Err(e) => {
+                            tracing::warn!("Failed to plan code: {}", e);
+                            String::new()
+                        }
+                    }
+                } else {
+                    String::new()
+                };
                 self.context_manager
                     .set_pending_command(session_id, Some(code.to_string()))
                     .await?;
                 Ok(RuntimeResponse::Text(format!(
-                    "{}\ncode: {}",
-                    message, code
+                    "{}{}\ncode: {}",
+                    message, plan, code
                 )))
             }
             ActionPolicy::Deny { reason } => {
@@ -358,73 +1022,89 @@ impl MycelRuntime {
         }
     }
 
+    fn mark_artifact_executed(&self, id: &str) {
+        if let Err(e) = self.artifacts.mark_executed(id) {
+            tracing::warn!("Failed to update code artifact {}: {}", id, e);
+        }
+    }
+
     /// Handle missing command - search repos and offer to install
     async fn handle_missing_command(&self, cmd: &str) -> Result<RuntimeResponse> {
-        // Search for package (works on Debian/Ubuntu - devcontainer)
-        let search_result = self.executor.run(&format!(
-            "apt-cache search '^{}$' 2>/dev/null | head -5 || apt-cache search '{}' 2>/dev/null | head -5",
-            cmd, cmd
-        )).await?;
-
-        if search_result.trim().is_empty() {
-            // Try broader search
-            let broad_search = self
-                .executor
-                .run(&format!("apt-cache search '{}' 2>/dev/null | head -5", cmd))
-                .await?;
-
-            if broad_search.trim().is_empty() {
-                return Ok(RuntimeResponse::Text(format!(
-                    "'{}' not found and no package available. check spelling or install manually.",
-                    cmd
-                )));
-            }
-
+        if let Some(command) = self.commands.closest(cmd).await {
             return Ok(RuntimeResponse::Text(format!(
-                "'{}' not installed. related packages:\n{}\ninstall with: sudo apt install <package>",
-                cmd, broad_search.trim()
+                "'{}' not found. did you mean `{}`?",
+                cmd, command
             )));
         }
 
-        // Found exact or close match
-        let first_package = search_result
-            .lines()
-            .next()
-            .and_then(|l| l.split_whitespace().next())
-            .unwrap_or(cmd);
+        let Some(pm) = self.package_manager else {
+            return Ok(RuntimeResponse::Text(format!(
+                "'{}' not installed and no supported package manager (xbps, apt, dnf, pacman) was found.",
+                cmd
+            )));
+        };
 
-        Ok(RuntimeResponse::Text(format!(
-            "'{}' not installed. found: {}\ninstall? run: sudo apt install {}",
-            cmd,
-            search_result.trim(),
-            first_package
-        )))
+        let search_result = self.executor.run(&pm.search_command(cmd)).await?;
+        Ok(RuntimeResponse::Text(pm.suggestion(cmd, &search_result)))
     }
 }
 
-/// Extract code from markdown code block
-fn extract_code_block(text: &str) -> String {
-    let mut lines: Vec<&str> = text.lines().collect();
+/// Rough success check for a response, used when learning from interactions
+fn interaction_succeeded(response: &str) -> bool {
+    let lower = response.trim().to_lowercase();
+    !lower.is_empty()
+        && !lower.starts_with("error")
+        && !lower.contains("command not found")
+        && !lower.contains("action cancelled")
+        && !lower.starts_with("blocked:")
+}
 
-    // Remove opening ``` line
-    if !lines.is_empty() && lines[0].starts_with("```") {
-        lines.remove(0);
+/// The user's answer to a pending confirmation: `Some(true)` to go ahead,
+/// `Some(false)` to cancel, `None` for anything else
+fn confirmation_answer(input: &str) -> Option<bool> {
+    match input.trim().to_lowercase().as_str() {
+        "yes" | "y" | "confirm" | "ok" => Some(true),
+        "no" | "n" | "cancel" => Some(false),
+        _ => None,
     }
+}
 
-    // Remove closing ``` line
-    if !lines.is_empty() && lines.last().map(|l| l.trim()) == Some("```") {
-        lines.pop();
+/// Run or drop the tool call a session is waiting on, depending on `input`
+async fn answer_pending_tool_call(
+    context_manager: &context::ContextManager,
+    mcp_manager: &mcp::McpManager,
+    session_id: &str,
+    call: &mcp::ToolCall,
+    input: &str,
+) -> Result<String> {
+    match confirmation_answer(input) {
+        Some(true) => {
+            context_manager.clear_pending_tool_call(session_id).await?;
+            Ok(mcp_manager.process_tool_call(call).await?)
+        }
+        Some(false) => {
+            context_manager.clear_pending_tool_call(session_id).await?;
+            Ok("action cancelled.".to_string())
+        }
+        None => Ok(format!(
+            "you have a pending action. type 'yes' to confirm or 'no' to cancel.\ntool: {} {}",
+            call.name,
+            serde_json::to_string(&call.arguments)?
+        )),
     }
-
-    lines.join("\n")
 }
 
 use std::pin::Pin;
 
-/// Response from the runtime - text or stream
+/// Response from the runtime - text, stream, or the result of code it ran
 pub enum RuntimeResponse {
     Text(String),
     Stream(Pin<Box<dyn Stream<Item = Result<String>> + Send>>),
+    /// Code run for the request, and how it exited
+    Code {
+        code: String,
+        result: executor::ExecutionResult,
+    },
 }
 
 impl std::fmt::Debug for RuntimeResponse {
@@ -432,12 +1112,82 @@ impl std::fmt::Debug for RuntimeResponse {
         match self {
             Self::Text(t) => f.debug_tuple("Text").field(t).finish(),
             Self::Stream(_) => f.debug_tuple("Stream").finish(),
+            Self::Code { code, result } => f
+                .debug_struct("Code")
+                .field("code", code)
+                .field("result", result)
+                .finish(),
+        }
+    }
+}
+
+/// The dev CLI turn in progress, which Ctrl-C cancels
+#[derive(Clone, Default)]
+struct TurnInterrupt {
+    current: Arc<std::sync::Mutex<Option<CancellationToken>>>,
+}
+
+impl TurnInterrupt {
+    /// Start a turn that `interrupt` cancels through `cancel`
+    fn begin(&self, cancel: CancellationToken) -> CancellationToken {
+        *self.current.lock().unwrap_or_else(|e| e.into_inner()) = Some(cancel.clone());
+        cancel
+    }
+
+    fn end(&self) {
+        self.current
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+    }
+
+    /// Cancel the turn in progress; `false` if there is none
+    fn interrupt(&self) -> bool {
+        match self
+            .current
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+        {
+            Some(cancel) => {
+                cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Print `stream` as it arrives until it ends or `cancel` fires; returns
+/// what was printed and whether it was cut short
+async fn print_stream(
+    mut stream: Pin<Box<dyn Stream<Item = Result<String>> + Send>>,
+    cancel: &CancellationToken,
+    out: &mut impl std::io::Write,
+) -> (String, bool) {
+    use futures_util::StreamExt;
+
+    let mut response = String::new();
+    loop {
+        let chunk = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return (response, true),
+            chunk = stream.next() => chunk,
+        };
+        match chunk {
+            Some(Ok(chunk)) => {
+                let _ = write!(out, "{}", chunk);
+                let _ = out.flush();
+                response.push_str(&chunk);
+            }
+            Some(Err(_)) => {}
+            None => return (response, cancel.is_cancelled()),
         }
     }
 }
 
 /// Development CLI for testing
-async fn run_dev_cli(runtime: MycelRuntime) {
+async fn run_dev_cli(runtime: MycelRuntime, interrupt: TurnInterrupt) {
     use std::io::{self, BufRead, Write};
 
     let session_id = uuid::Uuid::new_v4().to_string();
@@ -466,45 +1216,620 @@ async fn run_dev_cli(runtime: MycelRuntime) {
             let account_id = input.trim_start_matches("near-link ").trim();
             if !account_id.is_empty() {
                 println!("linking to NEAR account: {}...", account_id);
-                // Update config
-                let mut new_config = runtime.config.clone();
-                new_config.blockchain_sync = true;
-                new_config.near_account = Some(account_id.to_string());
+                if let Err(e) = runtime.link_near_account(account_id).await {
+                    eprintln!("error: failed to link: {}", e);
+                    continue;
+                }
 
-                // In a real implementation, we would save this to the config file
-                // and probably restart the sync service polling.
-                // For now, let's just update the runtime's local copy if possible
-                // (though MycelRuntime holds it by value, so we'd need a Mutex if we wanted it truly dynamic)
+                // Read the file back to confirm the link survives a restart
+                let saved = std::fs::read_to_string(&runtime.config_path)
+                    .ok()
+                    .and_then(|c| toml::from_str::<MycelConfig>(&c).ok());
+                match saved {
+                    Some(c) if c.near_account.as_deref() == Some(account_id) => {
+                        println!(
+                            "linked to {} (saved to {}). blockchain sync running.",
+                            account_id, runtime.config_path
+                        );
+                    }
+                    _ => eprintln!(
+                        "warning: linked for this session, but {} does not reflect it",
+                        runtime.config_path
+                    ),
+                }
+            }
+            continue;
+        }
 
-                println!("successfully linked (simulated). please restart to enable polling.");
+        if input == "benchmark" || input.starts_with("benchmark ") {
+            let runs = input["benchmark".len()..]
+                .trim()
+                .parse::<usize>()
+                .unwrap_or(3)
+                .clamp(1, ipc::MAX_BENCHMARK_RUNS);
+            println!("benchmarking ({} runs per model)...", runs);
+            match runtime.ai_router.benchmark(runs).await {
+                Ok(report) => print_benchmark(&report),
+                Err(e) => eprintln!("error: benchmark failed: {}", e),
             }
             continue;
         }
 
-        match runtime.process_input(input, &session_id).await {
+        if input == "capabilities" {
+            print_capabilities(&runtime.mcp_manager.list_capabilities().await);
+            continue;
+        }
+
+        // Ctrl-C from here on aborts this turn's model requests
+        let cancel = interrupt.begin(runtime.ai_router.cancellation().child_token());
+        let turn = MycelRuntime {
+            ai_router: runtime.ai_router.with_cancellation(cancel.clone()),
+            ..runtime.clone()
+        };
+        match turn.process_input(input, &session_id).await {
             Ok(RuntimeResponse::Text(text)) => {
                 if !text.is_empty() {
                     println!("{}", text);
                     let _ = runtime.record_interaction(&session_id, input, &text).await;
                 }
             }
-            Ok(RuntimeResponse::Stream(mut stream)) => {
-                use futures_util::StreamExt;
-                use std::io::{self, Write};
-                let mut full_response = String::new();
-                while let Some(chunk_result) = stream.next().await {
-                    if let Ok(chunk) = chunk_result {
-                        print!("{}", chunk);
-                        full_response.push_str(&chunk);
-                        io::stdout().flush().unwrap();
+            Ok(RuntimeResponse::Code { result, .. }) => {
+                println!("{}", result.output);
+                if !result.success {
+                    match result.exit_code {
+                        Some(code) => println!("(exited with code {})", code),
+                        None => println!("(did not finish)"),
                     }
                 }
-                println!();
                 let _ = runtime
-                    .record_interaction(&session_id, input, &full_response)
+                    .record_interaction(&session_id, input, &result.output)
                     .await;
             }
+            Ok(RuntimeResponse::Stream(stream)) => {
+                let (response, interrupted) =
+                    print_stream(stream, &cancel, &mut io::stdout()).await;
+                println!();
+                if interrupted {
+                    println!("(interrupted)");
+                }
+                // Keep what arrived, so the conversation reads as it happened
+                if !response.is_empty() {
+                    let _ = runtime
+                        .record_interaction(&session_id, input, &response)
+                        .await;
+                }
+            }
+            Err(_) if cancel.is_cancelled() => println!("(interrupted)"),
             Err(e) => eprintln!("error: {}", e),
         }
+        interrupt.end();
+    }
+}
+
+/// Dev CLI rendering of the tools the assistant can use
+fn print_capabilities(capabilities: &[mcp::Capability]) {
+    if capabilities.is_empty() {
+        println!("no tools available.");
+    }
+    for capability in capabilities {
+        let summary = capability.description.lines().next().unwrap_or("");
+        println!(
+            "{} ({}, {:?} risk{}): {}",
+            capability.name,
+            capability.server,
+            capability.risk_level,
+            if capability.requires_confirmation {
+                ", asks first"
+            } else {
+                ""
+            },
+            summary,
+        );
+    }
+}
+
+/// Dev CLI rendering of a benchmark report
+fn print_benchmark(report: &ai::BenchmarkReport) {
+    let hw = &report.hardware;
+    println!(
+        "hardware: {} cores, {:.1} GiB ram, {:.1} GiB vram ({:?})",
+        hw.cpu_cores,
+        hw.total_ram_bytes as f64 / (1u64 << 30) as f64,
+        hw.gpu_vram_bytes as f64 / (1u64 << 30) as f64,
+        hw.gpu_type.unwrap_or(models::GpuType::None),
+    );
+    for result in &report.results {
+        println!(
+            "{:?} {}: first token {} ms, total {} ms, ~{:.1} tokens/s ({} runs)",
+            result.provider,
+            result.model,
+            result.first_token_ms,
+            result.total_ms,
+            result.tokens_per_sec,
+            result.runs,
+        );
+        if let Some(error) = &result.error {
+            println!("  stopped early: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_pending_tool_call_runs_once_confirmed() {
+        let dir = mcp::testing::temp_dir("mycel-confirm");
+        let config = MycelConfig {
+            context_path: dir.join("context").to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let contexts = context::ContextManager::new(&config).await.unwrap();
+
+        let mut server = mcp::testing::write_counting_server(&dir);
+        server.requires_confirmation = vec!["system_info".to_string()];
+        let mcp_config = config::McpConfig {
+            servers: vec![server.clone()],
+            ..Default::default()
+        };
+        let manager = mcp::testing::TestManager::start(&dir, mcp_config, &[server]).await;
+        assert!(manager.requires_confirmation("system_info").await);
+
+        let call = mcp::ToolCall {
+            name: "system_info".to_string(),
+            arguments: HashMap::new(),
+        };
+        contexts.get_context("s").await.unwrap();
+        async fn pending(contexts: &context::ContextManager) -> Option<mcp::ToolCall> {
+            contexts.get_context("s").await.unwrap().pending_tool_call
+        }
+
+        // Declining drops the call without running it
+        contexts
+            .set_pending_tool_call("s", Some(call.clone()))
+            .await
+            .unwrap();
+        let reply = answer_pending_tool_call(&contexts, &manager, "s", &call, "no")
+            .await
+            .unwrap();
+        assert_eq!(reply, "action cancelled.");
+        assert!(pending(&contexts).await.is_none());
+
+        // Anything but yes/no leaves it waiting; yes runs it
+        contexts
+            .set_pending_tool_call("s", Some(call.clone()))
+            .await
+            .unwrap();
+        let reply = answer_pending_tool_call(&contexts, &manager, "s", &call, "hmm")
+            .await
+            .unwrap();
+        assert!(reply.starts_with("you have a pending action"), "{}", reply);
+        assert!(pending(&contexts).await.is_some());
+
+        let reply = answer_pending_tool_call(&contexts, &manager, "s", &call, "yes")
+            .await
+            .unwrap();
+        assert!(reply.contains("hits=1"), "{}", reply);
+        assert!(pending(&contexts).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pinned_provider_tool_calls_wait_for_confirmation() {
+        let dir = mcp::testing::temp_dir("mycel-pinned");
+        let call = r#"<tool_call>{"name": "system_info", "arguments": {}}</tool_call>"#;
+        let (url, _) = ai::testing::fake_ollama(vec![call.to_string(); 8]).await;
+        // Each confirmed call reaches the server
+        let runtime = TestRuntime::start(&dir, url, |c| c.mcp.tool_cache_ttl_secs = 0).await;
+        let mut server = mcp::testing::write_counting_server(&dir);
+        server.requires_confirmation = vec!["system_info".to_string()];
+        runtime.mcp_manager.start_server(&server).await.unwrap();
+
+        // Both the single-round and the agentic path hold the call until
+        // the session says yes
+        for (agentic, hits) in [(false, 1), (true, 2)] {
+            let reply = runtime
+                .process_input_with_provider(
+                    "check the system",
+                    "s",
+                    ipc::LlmProvider::Local,
+                    false,
+                    agentic,
+                    None,
+                )
+                .await
+                .unwrap();
+            match reply {
+                RuntimeResponse::Text(text) => assert!(text.contains("type 'yes'"), "{}", text),
+                other => panic!("expected text, got {:?}", other),
+            }
+            let context = runtime.context_manager.get_context("s").await.unwrap();
+            assert_eq!(context.pending_tool_call.unwrap().name, "system_info");
+
+            let reply = runtime
+                .process_input_with_provider(
+                    "yes",
+                    "s",
+                    ipc::LlmProvider::Local,
+                    false,
+                    agentic,
+                    None,
+                )
+                .await
+                .unwrap();
+            match reply {
+                RuntimeResponse::Text(text) => {
+                    assert!(text.contains(&format!("hits={}", hits)), "{}", text)
+                }
+                other => panic!("expected text, got {:?}", other),
+            }
+            let context = runtime.context_manager.get_context("s").await.unwrap();
+            assert!(context.pending_tool_call.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fenced_chat_answer_is_not_run() {
+        let dir = mcp::testing::temp_dir("mycel-fenced");
+        let marker = dir.join("ran");
+        let reply = format!("```bash\ntouch {}\n```", marker.display());
+        // Enough replies for the embedding lookup as well as the answer
+        let (url, _) = ai::testing::fake_ollama(vec![reply.clone(); 3]).await;
+        let runtime = TestRuntime::start(&dir, url, |_| {}).await;
+
+        match runtime
+            .process_input("what does touch do", "s")
+            .await
+            .unwrap()
+        {
+            RuntimeResponse::Text(text) => assert_eq!(text, reply),
+            other => panic!("expected text, got {:?}", other),
+        }
+        assert!(!marker.exists(), "a chat answer was run as code");
+        let context = runtime.context_manager.get_context("s").await.unwrap();
+        assert!(context.pending_command.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_confirmation_includes_the_plan() {
+        let dir = mcp::testing::temp_dir("mycel-plan");
+        let plan =
+            r#"{"summary": "Deletes the build directory", "side_effects": ["removes ./build"]}"#;
+        let (url, prompts) = ai::testing::fake_ollama(vec![plan.to_string()]).await;
+        let runtime = TestRuntime::start(&dir, url, |c| c.explain_plan = true).await;
+        runtime.context_manager.get_context("s").await.unwrap();
+
+        let code = "rm -rf ./build";
+        let response = runtime
+            .execute_code_with_policy(code, None, "clean up", "s", false)
+            .await
+            .unwrap();
+        match response {
+            RuntimeResponse::Text(text) => {
+                assert!(
+                    text.contains(
+                        "plan: Deletes the build directory\nside effects: removes ./build"
+                    ),
+                    "{}",
+                    text
+                );
+                assert!(text.ends_with(&format!("code: {}", code)), "{}", text);
+            }
+            other => panic!("expected text, got {:?}", other),
+        }
+        assert!(prompts.lock().unwrap()[0].contains(code));
+        let context = runtime.context_manager.get_context("s").await.unwrap();
+        assert_eq!(context.pending_command.as_deref(), Some(code));
+    }
+
+    #[tokio::test]
+    async fn test_chat_code_reports_failure() {
+        let dir = mcp::testing::temp_dir("mycel-chatcode");
+        let (url, _) = ai::testing::fake_ollama(Vec::new()).await;
+        let runtime = TestRuntime::start(&dir, url, |_| {}).await;
+
+        let response = runtime
+            .handle_model_response(
+                "#!exec\necho oops >&2; exit 3".to_string(),
+                "fail",
+                "s",
+                false,
+            )
+            .await
+            .unwrap();
+        match response {
+            RuntimeResponse::Code { code, result } => {
+                assert_eq!(code, "echo oops >&2; exit 3");
+                assert!(!result.success);
+                assert_eq!(result.exit_code, Some(3));
+                assert!(result.output.contains("oops"), "{}", result.output);
+            }
+            other => panic!("expected a code result, got {:?}", other),
+        }
+    }
+
+    /// Runtime keeping its context and code under `dir`, with no MCP
+    /// servers or collective, answering from the fake Ollama at a URL.
+    /// Dropping it stops the sync service and any MCP servers and removes
+    /// `dir`.
+    struct TestRuntime {
+        runtime: MycelRuntime,
+        dir: std::path::PathBuf,
+    }
+
+    impl TestRuntime {
+        /// `configure` adjusts the config before the runtime is built
+        async fn start(
+            dir: &std::path::Path,
+            ollama_url: String,
+            configure: impl FnOnce(&mut MycelConfig),
+        ) -> Self {
+            let mut config = MycelConfig {
+                context_path: dir.join("context").to_string_lossy().to_string(),
+                code_path: dir.join("code").to_string_lossy().to_string(),
+                ..Default::default()
+            };
+            configure(&mut config);
+
+            let (tx, _) = tokio::sync::broadcast::channel(16);
+            let mcp_manager = mcp::McpManager::new(&config.mcp, "/tmp", tx.clone())
+                .await
+                .unwrap();
+            let runtime = MycelRuntime {
+                config_path: String::new(),
+                dev_mode: true,
+                context_manager: context::ContextManager::new(&config).await.unwrap(),
+                ai_router: ai::testing::local_router(ollama_url).await,
+                executor: executor::CodeExecutor::new(&config).unwrap(),
+                policy_evaluator: Arc::new(std::sync::RwLock::new(policy::PolicyEvaluator::new(
+                    config.policy.clone(),
+                ))),
+                ui_factory: ui::UiFactory::new(&config).unwrap(),
+                surfaces: ui::SurfaceRegistry::new(),
+                artifacts: codegen::ArtifactStore::open(&config.code_path).unwrap(),
+                sync_service: sync::SyncService::new(&config, None, tx).await.unwrap(),
+                mcp_manager,
+                collective: None,
+                started_at: std::time::Instant::now(),
+                package_manager: None,
+                commands: Arc::new(executor::CommandIndex::new()),
+                config: Arc::new(RwLock::new(config)),
+            };
+            Self {
+                runtime,
+                dir: dir.to_path_buf(),
+            }
+        }
+    }
+
+    impl std::ops::Deref for TestRuntime {
+        type Target = MycelRuntime;
+
+        fn deref(&self) -> &MycelRuntime {
+            &self.runtime
+        }
+    }
+
+    impl std::ops::DerefMut for TestRuntime {
+        fn deref_mut(&mut self) -> &mut MycelRuntime {
+            &mut self.runtime
+        }
+    }
+
+    impl Drop for TestRuntime {
+        fn drop(&mut self) {
+            self.runtime.sync_service.stop_tasks();
+            mcp::testing::stop_now(&self.runtime.mcp_manager);
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_safe_mode_returns_code_as_text() {
+        let dir = mcp::testing::temp_dir("mycel-safe");
+        let marker = dir.join("ran");
+        let reply = format!("```bash\ntouch {}\n```", marker.display());
+        // Enough replies for the embedding lookup as well as the answer
+        let (url, prompts) = ai::testing::fake_ollama(vec![reply.clone(); 3]).await;
+
+        let runtime = TestRuntime::start(&dir, url, MycelConfig::enable_safe_mode).await;
+        let config = runtime.config.read().await.clone();
+        assert!(!config.policy.allow_code_execution);
+        assert!(config.mcp.read_only_tools && !config.mcp.evolution_enabled);
+
+        let response = runtime
+            .process_input("create the marker file", "s")
+            .await
+            .unwrap();
+        match response {
+            RuntimeResponse::Text(text) => assert_eq!(text, reply),
+            other => panic!("expected text, got {:?}", other),
+        }
+        assert!(!marker.exists(), "safe mode ran generated code");
+        assert!(prompts
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|p| p.contains("user: create the marker file")));
+
+        // Nor can code be run directly
+        assert!(runtime.run_code("true").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_learned_pattern_answers_repeat_input() {
+        let dir = mcp::testing::temp_dir("mycel-collective");
+        let reply = "Run df -h to see how much disk space is free.".to_string();
+        let (url, prompts) = ai::testing::fake_ollama(vec![reply.clone(); 3]).await;
+        let mut runtime = TestRuntime::start(&dir, url, |c| c.collective_enabled = true).await;
+        let config = runtime.config.read().await.clone();
+        let collective = collective::CollectiveIntelligence::new(&config)
+            .await
+            .unwrap();
+        runtime.collective = Some(Arc::new(collective));
+        let collective = runtime.collective.clone().unwrap();
+
+        // The first answer comes from the model; recording it, as the IPC
+        // server does, learns it in the background
+        let input = "how much disk space is free";
+        match runtime.process_input(input, "s").await.unwrap() {
+            RuntimeResponse::Text(text) => assert_eq!(text, reply),
+            other => panic!("expected text, got {:?}", other),
+        }
+        runtime
+            .record_interaction("s", input, &reply)
+            .await
+            .unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while collective.get_stats().await.local_patterns == 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("interaction was not learned");
+
+        // The repeat is answered from the pattern without asking the model
+        match runtime.process_input(input, "s").await.unwrap() {
+            RuntimeResponse::Text(text) => assert_eq!(text, reply),
+            other => panic!("expected text, got {:?}", other),
+        }
+        let asked = prompts
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|p| p.contains(&format!("User: {}", input)))
+            .count();
+        assert_eq!(asked, 1);
+    }
+
+    #[tokio::test]
+    async fn test_later_requests_use_the_new_working_directory() {
+        let dir = mcp::testing::temp_dir("mycel-cwd");
+        std::fs::create_dir_all(dir.join("project/src")).unwrap();
+        let (url, prompts) = ai::testing::fake_ollama(vec!["ok".to_string(); 2]).await;
+        let runtime = TestRuntime::start(&dir, url, |_| {}).await;
+        let project = dir.join("project").canonicalize().unwrap();
+
+        let mut session_id = "s".to_string();
+        let request = ipc::IpcRequest::SetWorkingDirectory {
+            path: project.to_string_lossy().to_string(),
+        };
+        match ipc::process_request(&request, &runtime, &mut session_id).await {
+            ipc::IpcResponse::WorkingDirectory { path } => {
+                assert_eq!(path, project.to_str().unwrap())
+            }
+            other => panic!("expected working directory, got {:?}", other),
+        }
+
+        // A relative path now resolves against the project
+        let src = runtime.change_working_directory("s", "src").await.unwrap();
+        assert_eq!(src, project.join("src").to_str().unwrap());
+
+        // And the next chat prompt carries it
+        runtime.process_input("what is here", "s").await.unwrap();
+        assert!(prompts
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|p| p.contains("what is here") && p.contains(&src)));
+    }
+
+    #[tokio::test]
+    async fn test_expired_confirmation_is_cancelled() {
+        let dir = mcp::testing::temp_dir("mycel-expiry");
+        let (url, _) = ai::testing::fake_ollama(Vec::new()).await;
+        // Every confirmation is already past its TTL
+        let runtime = TestRuntime::start(&dir, url, |c| c.mcp.confirmation_ttl_secs = 0).await;
+
+        // A session's pending tool call isn't run on "yes"
+        runtime.context_manager.get_context("s").await.unwrap();
+        let call = mcp::ToolCall {
+            name: "system_info".to_string(),
+            arguments: HashMap::new(),
+        };
+        runtime
+            .context_manager
+            .set_pending_tool_call("s", Some(call))
+            .await
+            .unwrap();
+        match runtime.process_input("yes", "s").await.unwrap() {
+            RuntimeResponse::Text(text) => assert!(text.contains("expired"), "{}", text),
+            other => panic!("expected text, got {:?}", other),
+        }
+        let context = runtime.context_manager.get_context("s").await.unwrap();
+        assert!(context.pending_tool_call.is_none());
+
+        // Nor is a held capability, which the sweep drops
+        let evolve = mcp::ToolCall {
+            name: "evolve_os_add_capability".to_string(),
+            arguments: HashMap::from([
+                ("name".to_string(), serde_json::json!("hello")),
+                ("language".to_string(), serde_json::json!("python")),
+                ("code".to_string(), serde_json::json!("print('hi')")),
+            ]),
+        };
+        let reply = runtime
+            .mcp_manager
+            .process_tool_call(&evolve)
+            .await
+            .unwrap();
+        let id = reply
+            .split("confirmation ")
+            .nth(1)
+            .and_then(|rest| rest.split(')').next())
+            .unwrap()
+            .to_string();
+        assert!(runtime.mcp_manager.pending_confirmations().await.is_empty());
+        let err = runtime
+            .mcp_manager
+            .resolve_confirmation(&id, true)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error::Error::find(&err).and_then(error::Error::code),
+            Some("confirmation_expired")
+        );
+        runtime
+            .mcp_manager
+            .process_tool_call(&evolve)
+            .await
+            .unwrap();
+        assert_eq!(runtime.mcp_manager.sweep_expired_confirmations().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_ctrl_c_interrupts_stream_not_cli() {
+        use futures_util::StreamExt;
+
+        let interrupt = TurnInterrupt::default();
+        // At the prompt there's no turn, so Ctrl-C stops the daemon
+        assert!(!interrupt.interrupt());
+
+        let cancel = interrupt.begin(CancellationToken::new());
+        // One chunk, then a model that never finishes
+        let stream: Pin<Box<dyn Stream<Item = Result<String>> + Send>> = Box::pin(
+            futures::stream::iter([Ok("partial ".to_string())]).chain(futures::stream::pending()),
+        );
+        let handle = interrupt.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            assert!(handle.interrupt());
+        });
+
+        let mut out = Vec::new();
+        let (response, interrupted) = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            print_stream(stream, &cancel, &mut out),
+        )
+        .await
+        .expect("stream kept the CLI waiting after Ctrl-C");
+        assert!(interrupted);
+        assert_eq!(response, "partial ");
+        assert_eq!(out, b"partial ");
+
+        // The turn is over; the next Ctrl-C is the daemon's again
+        interrupt.end();
+        assert!(!interrupt.interrupt());
     }
 }
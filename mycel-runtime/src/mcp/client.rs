@@ -4,18 +4,26 @@
 //! Includes health monitoring, auto-restart, and configurable timeouts.
 
 use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::process::Stdio;
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::{Child, Command};
 use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
 use tracing::{debug, error, info, warn};
 
 use super::protocol::*;
 
+/// A server that stays healthy this long after a restart gets its restart
+/// budget back
+const RESTART_RESET_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/// Default for [`ServerConfig::max_message_size`]
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
 /// MCP Server connection state
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ServerState {
@@ -53,34 +61,73 @@ pub struct ServerHealth {
 pub struct ServerConfig {
     /// Timeout for individual tool calls (default: 30s)
     pub tool_timeout: Duration,
+    /// Tools allowed more (or less) time than `tool_timeout`
+    pub tool_timeouts: HashMap<String, Duration>,
+    /// Operator overrides of the idempotency tools declare, by tool name
+    pub idempotent_tools: HashMap<String, bool>,
     /// Timeout for initialization (default: 60s)
     pub init_timeout: Duration,
     /// Maximum number of auto-restart attempts (default: 3)
     pub max_restart_attempts: usize,
-    /// Delay between restart attempts (default: 1s)
+    /// Delay before the first restart attempt, doubled for each further
+    /// attempt (default: 1s)
     pub restart_delay: Duration,
+    /// Upper bound on the backoff between restart attempts (default: 60s)
+    pub max_restart_delay: Duration,
     /// Enable automatic health checks (default: true)
     #[allow(dead_code)]
     pub health_check_enabled: bool,
     /// Interval between health checks (default: 60s)
     #[allow(dead_code)]
     pub health_check_interval: Duration,
+    /// Largest JSON-RPC message accepted from the server; longer ones are
+    /// dropped rather than buffered (default: 16 MiB)
+    pub max_message_size: usize,
 }
 
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
             tool_timeout: Duration::from_secs(30),
+            tool_timeouts: HashMap::new(),
+            idempotent_tools: HashMap::new(),
             init_timeout: Duration::from_secs(60),
             max_restart_attempts: 3,
             restart_delay: Duration::from_secs(1),
+            max_restart_delay: Duration::from_secs(60),
             health_check_enabled: true,
             health_check_interval: Duration::from_secs(60),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
         }
     }
 }
 
-/// MCP Server instance
+/// Interim progress a server reported for a running tool call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolProgress {
+    pub tool: String,
+    pub progress: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// Where progress for a tool call is delivered
+pub type ProgressSender = mpsc::UnboundedSender<ToolProgress>;
+
+/// Streaming tool calls by progress token: the tool name and its listener
+type ProgressListeners = HashMap<String, (String, ProgressSender)>;
+
+/// A request queued for the writer task, paired with the channel its response is delivered on
+type PendingRequest = (JsonRpcRequest, oneshot::Sender<Result<JsonRpcResponse>>);
+
+/// In-flight requests by JSON-RPC id, so concurrent calls get their own responses
+type PendingMap = HashMap<RequestId, oneshot::Sender<Result<JsonRpcResponse>>>;
+
+/// MCP Server instance. Cloning gives another handle to the same process, so
+/// calls can run without holding a lock on the server.
+#[derive(Clone)]
 pub struct McpServer {
     pub name: String,
     pub command: String,
@@ -90,12 +137,18 @@ pub struct McpServer {
     pub config: ServerConfig,
     state: Arc<RwLock<ServerState>>,
     process: Arc<Mutex<Option<Child>>>,
-    request_tx: Arc<Mutex<Option<mpsc::Sender<(JsonRpcRequest, oneshot::Sender<Result<JsonRpcResponse>>)>>>>,
-    next_id: AtomicU64,
+    request_tx: Arc<Mutex<Option<mpsc::Sender<PendingRequest>>>>,
+    next_id: Arc<AtomicU64>,
     tools: Arc<RwLock<Vec<McpTool>>>,
     server_info: Arc<RwLock<Option<ServerInfo>>>,
+    /// What the server advertised in `initialize`
+    capabilities: Arc<RwLock<ServerCapabilities>>,
     health: Arc<RwLock<ServerHealth>>,
-    restart_attempts: AtomicUsize,
+    restart_attempts: Arc<AtomicUsize>,
+    last_restart: Arc<RwLock<Option<Instant>>>,
+    /// Set once the restart budget is spent; the server stays `Failed`
+    restarts_exhausted: Arc<AtomicBool>,
+    progress_listeners: Arc<Mutex<ProgressListeners>>,
 }
 
 impl McpServer {
@@ -129,11 +182,15 @@ impl McpServer {
             state: Arc::new(RwLock::new(ServerState::Stopped)),
             process: Arc::new(Mutex::new(None)),
             request_tx: Arc::new(Mutex::new(None)),
-            next_id: AtomicU64::new(1),
+            next_id: Arc::new(AtomicU64::new(1)),
             tools: Arc::new(RwLock::new(Vec::new())),
             server_info: Arc::new(RwLock::new(None)),
+            capabilities: Arc::new(RwLock::new(ServerCapabilities::default())),
             health: Arc::new(RwLock::new(ServerHealth::default())),
-            restart_attempts: AtomicUsize::new(0),
+            restart_attempts: Arc::new(AtomicUsize::new(0)),
+            last_restart: Arc::new(RwLock::new(None)),
+            restarts_exhausted: Arc::new(AtomicBool::new(false)),
+            progress_listeners: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -177,11 +234,10 @@ impl McpServer {
         let stderr = child.stderr.take().ok_or_else(|| anyhow!("Failed to get stderr"))?;
 
         // Create channels for request/response coordination
-        let (request_tx, mut request_rx) = mpsc::channel::<(JsonRpcRequest, oneshot::Sender<Result<JsonRpcResponse>>)>(32);
+        let (request_tx, mut request_rx) = mpsc::channel::<PendingRequest>(32);
 
         // Pending requests map
-        let pending: Arc<Mutex<HashMap<RequestId, oneshot::Sender<Result<JsonRpcResponse>>>>> =
-            Arc::new(Mutex::new(HashMap::new()));
+        let pending: Arc<Mutex<PendingMap>> = Arc::new(Mutex::new(HashMap::new()));
 
         // Track if stdout reader is alive (for health monitoring)
         let reader_alive = Arc::new(std::sync::atomic::AtomicBool::new(true));
@@ -191,24 +247,55 @@ impl McpServer {
         let pending_clone = pending.clone();
         let server_name = self.name.clone();
         let state_clone = self.state.clone();
+        let listeners = self.progress_listeners.clone();
+        let max_message_size = self.config.max_message_size;
         tokio::spawn(async move {
-            let mut reader = BufReader::new(stdout).lines();
-            while let Ok(Some(line)) = reader.next_line().await {
+            let mut reader = BufReader::new(stdout);
+            loop {
+                let line = match read_message(&mut reader, max_message_size).await {
+                    Ok(Message::Line(line)) => line,
+                    Ok(Message::TooLarge(size)) => {
+                        warn!(
+                            "[{}] Dropped a {} byte message (max: {} bytes)",
+                            server_name, size, max_message_size
+                        );
+                        continue;
+                    }
+                    Ok(Message::Eof) | Err(_) => break,
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
                 debug!("[{}] <- {}", server_name, line);
                 match serde_json::from_str::<JsonRpcResponse>(&line) {
                     Ok(response) => {
                         let mut pending = pending_clone.lock().await;
-                        if let Some(sender) = pending.remove(&response.id) {
-                            let _ = sender.send(Ok(response));
+                        match pending.remove(&response.id) {
+                            Some(sender) => {
+                                let _ = sender.send(Ok(response));
+                            }
+                            None => warn!("[{}] Response for unknown request id {:?}", server_name, response.id),
                         }
                     }
-                    Err(e) => {
-                        warn!("[{}] Failed to parse response: {} - {}", server_name, e, line);
-                    }
+                    Err(e) => match serde_json::from_str::<JsonRpcNotification>(&line) {
+                        Ok(notification) => {
+                            dispatch_notification(&server_name, notification, &listeners).await;
+                        }
+                        Err(_) => {
+                            warn!(
+                                "[{}] Failed to parse response: {} - {}",
+                                server_name, e, line
+                            );
+                        }
+                    },
                 }
             }
             debug!("[{}] stdout reader exited", server_name);
             reader_alive_clone.store(false, Ordering::SeqCst);
+            // Fail in-flight requests now rather than letting them time out
+            for (_, sender) in pending_clone.lock().await.drain() {
+                let _ = sender.send(Err(anyhow!("Server process exited")));
+            }
             // Mark server as failed when reader exits unexpectedly
             let current_state = state_clone.read().await.clone();
             if current_state == ServerState::Ready {
@@ -240,8 +327,13 @@ impl McpServer {
                 match serde_json::to_string(&request) {
                     Ok(json) => {
                         debug!("[{}] -> {}", server_name_write, json);
-                        // Store pending request
-                        pending_clone.lock().await.insert(id.clone(), response_sender);
+                        // Store pending request (notifications get no response)
+                        if !request.method.starts_with("notifications/") {
+                            let mut pending = pending_clone.lock().await;
+                            // Drop requests whose caller gave up (timed out)
+                            pending.retain(|_, sender| !sender.is_closed());
+                            pending.insert(id.clone(), response_sender);
+                        }
                         // Write to stdin
                         if let Err(e) = stdin.write_all(format!("{}\n", json).as_bytes()).await {
                             error!("[{}] Write error: {}", server_name_write, e);
@@ -283,10 +375,17 @@ impl McpServer {
         self.refresh_tools().await?;
 
         *self.state.write().await = ServerState::Ready;
-        self.restart_attempts.store(0, Ordering::SeqCst); // Reset restart count on successful start
 
         let tool_count = self.tools.read().await.len();
-        info!("MCP server '{}' is ready with {} tools", self.name, tool_count);
+        let resources = self.supports_resources().await;
+        let prompts = self.supports_prompts().await;
+        info!(
+            resources,
+            prompts,
+            "MCP server '{}' is ready with {} tools",
+            self.name,
+            tool_count
+        );
 
         Ok(())
     }
@@ -298,8 +397,8 @@ impl McpServer {
 
     /// Send a JSON-RPC request with custom timeout
     async fn send_request_with_timeout(&self, request: JsonRpcRequest, timeout: Duration) -> Result<JsonRpcResponse> {
-        let tx_guard = self.request_tx.lock().await;
-        let tx = tx_guard.as_ref()
+        // Clone the sender so the lock isn't held while waiting for the response
+        let tx = self.request_tx.lock().await.clone()
             .ok_or_else(|| anyhow!("Server not started"))?;
 
         let (response_tx, response_rx) = oneshot::channel();
@@ -310,7 +409,7 @@ impl McpServer {
 
         let result = tokio::time::timeout(timeout, response_rx)
             .await
-            .map_err(|_| anyhow!("Request timed out after {:?}", timeout))?
+            .map_err(|_| crate::error::Error::ToolTimeout(timeout))?
             .map_err(|_| anyhow!("Response channel closed - server may have crashed"))?;
 
         // Update health stats
@@ -359,6 +458,7 @@ impl McpServer {
         if let Some(result) = response.result {
             let init_result: InitializeResult = serde_json::from_value(result)?;
             *self.server_info.write().await = Some(init_result.server_info);
+            *self.capabilities.write().await = init_result.capabilities;
         }
 
         // Send initialized notification
@@ -400,14 +500,93 @@ impl McpServer {
         Ok(())
     }
 
+    /// Whether the server offers `resources/list` and `resources/read`
+    pub async fn supports_resources(&self) -> bool {
+        self.capabilities.read().await.resources.is_some()
+    }
+
+    /// Whether the server offers `prompts/list` and `prompts/get`
+    pub async fn supports_prompts(&self) -> bool {
+        self.capabilities.read().await.prompts.is_some()
+    }
+
+    /// Resources the server exposes (`resources/list`)
+    pub async fn list_resources(&self) -> Result<Vec<McpResource>> {
+        let request = JsonRpcRequest::new(
+            self.next_id.fetch_add(1, Ordering::SeqCst),
+            "resources/list",
+            None,
+        );
+
+        let response = self.send_request(request).await?;
+        if let Some(error) = response.error {
+            return Err(anyhow!("resources/list failed: {}", error.message));
+        }
+        let result = response
+            .result
+            .ok_or_else(|| anyhow!("Empty result from resources/list"))?;
+        Ok(serde_json::from_value::<ListResourcesResult>(result)?.resources)
+    }
+
+    /// Contents of the resource at `uri` (`resources/read`)
+    pub async fn read_resource(&self, uri: &str) -> Result<Vec<ResourceContent>> {
+        let request = JsonRpcRequest::new(
+            self.next_id.fetch_add(1, Ordering::SeqCst),
+            "resources/read",
+            Some(serde_json::json!({ "uri": uri })),
+        );
+
+        let response = self.send_request(request).await?;
+        if let Some(error) = response.error {
+            return Err(anyhow!("resources/read failed: {}", error.message));
+        }
+        let result = response
+            .result
+            .ok_or_else(|| anyhow!("Empty result from resources/read"))?;
+        Ok(serde_json::from_value::<ReadResourceResult>(result)?.contents)
+    }
+
     /// Get the list of available tools
     pub async fn get_tools(&self) -> Vec<McpTool> {
         self.tools.read().await.clone()
     }
 
+    /// How long a call to `tool_name` may take
+    pub fn tool_timeout(&self, tool_name: &str) -> Duration {
+        self.config
+            .tool_timeouts
+            .get(tool_name)
+            .copied()
+            .unwrap_or(self.config.tool_timeout)
+    }
+
+    /// Whether `tool_name` is safe to cache and retry: as configured in
+    /// `idempotent_tools`, else as the server declared it, else not
+    pub async fn is_idempotent(&self, tool_name: &str) -> bool {
+        if let Some(&idempotent) = self.config.idempotent_tools.get(tool_name) {
+            return idempotent;
+        }
+        self.tools
+            .read()
+            .await
+            .iter()
+            .find(|tool| tool.name == tool_name)
+            .and_then(|tool| tool.idempotent())
+            .unwrap_or(false)
+    }
+
+    /// Whether the server declared `tool_name` read-only
+    pub async fn is_read_only(&self, tool_name: &str) -> bool {
+        self.tools
+            .read()
+            .await
+            .iter()
+            .any(|tool| tool.name == tool_name && tool.read_only())
+    }
+
     /// Call a tool with configured timeout
     pub async fn call_tool(&self, name: &str, arguments: HashMap<String, serde_json::Value>) -> Result<CallToolResult> {
-        self.call_tool_with_timeout(name, arguments, self.config.tool_timeout).await
+        self.call_tool_with_timeout(name, arguments, self.tool_timeout(name)).await
     }
 
     /// Call a tool with custom timeout
@@ -416,17 +595,52 @@ impl McpServer {
         name: &str,
         arguments: HashMap<String, serde_json::Value>,
         timeout: Duration,
+    ) -> Result<CallToolResult> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.send_tool_call(id, name, arguments, None, timeout)
+            .await
+    }
+
+    /// Call a tool, forwarding any `notifications/progress` the server sends
+    /// for it to `progress`. Servers that don't report progress behave as
+    /// with `call_tool`.
+    pub async fn call_tool_streaming(
+        &self,
+        name: &str,
+        arguments: HashMap<String, serde_json::Value>,
+        progress: ProgressSender,
+    ) -> Result<CallToolResult> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let token = format!("{}-{}", self.name, id);
+        self.progress_listeners
+            .lock()
+            .await
+            .insert(token.clone(), (name.to_string(), progress));
+
+        let meta = serde_json::json!({ "progressToken": token });
+        let result = self
+            .send_tool_call(id, name, arguments, Some(meta), self.tool_timeout(name))
+            .await;
+
+        self.progress_listeners.lock().await.remove(&token);
+        result
+    }
+
+    async fn send_tool_call(
+        &self,
+        id: u64,
+        name: &str,
+        arguments: HashMap<String, serde_json::Value>,
+        meta: Option<serde_json::Value>,
+        timeout: Duration,
     ) -> Result<CallToolResult> {
         let params = CallToolParams {
             name: name.to_string(),
             arguments,
+            meta,
         };
 
-        let request = JsonRpcRequest::new(
-            self.next_id.fetch_add(1, Ordering::SeqCst),
-            "tools/call",
-            Some(serde_json::to_value(params)?),
-        );
+        let request = JsonRpcRequest::new(id, "tools/call", Some(serde_json::to_value(params)?));
 
         let response = self.send_request_with_timeout(request, timeout).await?;
 
@@ -464,7 +678,20 @@ impl McpServer {
 
         // Try to list tools as a health check
         match tokio::time::timeout(Duration::from_secs(5), self.refresh_tools()).await {
-            Ok(Ok(())) => true,
+            Ok(Ok(())) => {
+                // Only a server that has stayed up earns its restart budget back,
+                // so one that crashes shortly after each restart still hits the cap
+                let stable = self
+                    .last_restart
+                    .read()
+                    .await
+                    .map(|at| at.elapsed() >= RESTART_RESET_AFTER)
+                    .unwrap_or(true);
+                if stable {
+                    self.restart_attempts.store(0, Ordering::SeqCst);
+                }
+                true
+            }
             Ok(Err(e)) => {
                 warn!("[{}] Health check failed: {}", self.name, e);
                 false
@@ -476,15 +703,37 @@ impl McpServer {
         }
     }
 
+    /// Whether the server used up its restart attempts and was given up on
+    pub fn restarts_exhausted(&self) -> bool {
+        self.restarts_exhausted.load(Ordering::SeqCst)
+    }
+
+    /// Backoff before the given (zero-based) restart attempt
+    fn restart_backoff(&self, attempt: usize) -> Duration {
+        let factor = 1u32 << attempt.min(16);
+        self.config
+            .restart_delay
+            .saturating_mul(factor)
+            .min(self.config.max_restart_delay)
+    }
+
     /// Restart the server if it's unhealthy
+    ///
+    /// Attempts back off exponentially. Once `max_restart_attempts` is used
+    /// up the server is marked `Failed` for good and further calls return an
+    /// error without touching the process.
     pub async fn restart_if_needed(&mut self) -> Result<bool> {
+        if self.restarts_exhausted() {
+            return Err(anyhow!("Max restart attempts reached"));
+        }
+
         if self.health_check().await {
             return Ok(false); // No restart needed
         }
 
         let attempts = self.restart_attempts.load(Ordering::SeqCst);
         if attempts >= self.config.max_restart_attempts {
-            warn!("[{}] Max restart attempts ({}) reached", self.name, self.config.max_restart_attempts);
+            self.give_up().await;
             return Err(anyhow!("Max restart attempts reached"));
         }
 
@@ -492,19 +741,36 @@ impl McpServer {
         *self.state.write().await = ServerState::Restarting;
         self.restart_attempts.fetch_add(1, Ordering::SeqCst);
         self.health.write().await.restart_count += 1;
+        *self.last_restart.write().await = Some(Instant::now());
 
         // Stop existing process
         self.stop().await?;
 
         // Wait before restarting
-        tokio::time::sleep(self.config.restart_delay).await;
+        tokio::time::sleep(self.restart_backoff(attempts)).await;
 
         // Try to start again
-        self.start().await?;
+        if let Err(e) = self.start().await {
+            if attempts + 1 >= self.config.max_restart_attempts {
+                self.give_up().await;
+            }
+            return Err(e);
+        }
 
         Ok(true)
     }
 
+    /// Stop retrying: kill the process and leave the server `Failed`
+    async fn give_up(&mut self) {
+        warn!("[{}] Max restart attempts ({}) reached, giving up", self.name, self.config.max_restart_attempts);
+        self.restarts_exhausted.store(true, Ordering::SeqCst);
+        let _ = self.stop().await;
+        *self.state.write().await = ServerState::Failed(format!(
+            "Gave up after {} restart attempts",
+            self.config.max_restart_attempts
+        ));
+    }
+
     /// Stop the server
     pub async fn stop(&mut self) -> Result<()> {
         // Clear request channel first
@@ -531,6 +797,86 @@ impl McpServer {
     }
 }
 
+/// One newline-delimited message read from a server
+#[derive(Debug, PartialEq)]
+enum Message {
+    Line(String),
+    /// Over the size limit; its bytes were skipped, not kept
+    TooLarge(usize),
+    Eof,
+}
+
+/// Read the next message however the server's writes split it, holding at
+/// most `max_size` bytes of it in memory
+async fn read_message<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    max_size: usize,
+) -> std::io::Result<Message> {
+    let mut line = Vec::new();
+    let mut size = 0;
+    loop {
+        let available = reader.fill_buf().await?;
+        // A final message without a newline still counts
+        if available.is_empty() && size == 0 {
+            return Ok(Message::Eof);
+        }
+        let newline = available.iter().position(|&b| b == b'\n');
+        let chunk = &available[..newline.unwrap_or(available.len())];
+        size += chunk.len();
+        if size <= max_size {
+            line.extend_from_slice(chunk);
+        } else {
+            line = Vec::new();
+        }
+        let used = chunk.len() + newline.map_or(0, |_| 1);
+        let done = newline.is_some() || available.is_empty();
+        reader.consume(used);
+
+        if done {
+            return Ok(if size > max_size {
+                Message::TooLarge(size)
+            } else {
+                Message::Line(String::from_utf8_lossy(&line).into_owned())
+            });
+        }
+    }
+}
+
+/// Route a server notification; only progress for streaming calls is used
+async fn dispatch_notification(
+    server_name: &str,
+    notification: JsonRpcNotification,
+    listeners: &Mutex<ProgressListeners>,
+) {
+    if notification.method != "notifications/progress" {
+        debug!(
+            "[{}] Ignoring notification {}",
+            server_name, notification.method
+        );
+        return;
+    }
+    let params: ProgressParams = match notification.params.map(serde_json::from_value) {
+        Some(Ok(params)) => params,
+        _ => {
+            warn!("[{}] Malformed progress notification", server_name);
+            return;
+        }
+    };
+    let token = match &params.progress_token {
+        serde_json::Value::String(token) => token.clone(),
+        other => other.to_string(),
+    };
+
+    if let Some((tool, sender)) = listeners.lock().await.get(&token) {
+        let _ = sender.send(ToolProgress {
+            tool: tool.clone(),
+            progress: params.progress,
+            total: params.total,
+            message: params.message,
+        });
+    }
+}
+
 impl Drop for McpServer {
     fn drop(&mut self) {
         // Process will be killed automatically due to kill_on_drop(true)
@@ -567,11 +913,15 @@ mod tests {
     fn test_custom_config() {
         let config = ServerConfig {
             tool_timeout: Duration::from_secs(60),
+            tool_timeouts: HashMap::from([("slow_tool".to_string(), Duration::from_secs(300))]),
+            idempotent_tools: HashMap::new(),
             init_timeout: Duration::from_secs(120),
             max_restart_attempts: 5,
             restart_delay: Duration::from_secs(2),
+            max_restart_delay: Duration::from_secs(30),
             health_check_enabled: false,
             health_check_interval: Duration::from_secs(30),
+            max_message_size: 1024,
         };
 
         let server = McpServer::with_config(
@@ -584,6 +934,181 @@ mod tests {
         );
 
         assert_eq!(server.config.tool_timeout, Duration::from_secs(60));
+        assert_eq!(server.tool_timeout("slow_tool"), Duration::from_secs(300));
+        assert_eq!(server.tool_timeout("other_tool"), Duration::from_secs(60));
         assert_eq!(server.config.max_restart_attempts, 5);
     }
+
+    #[test]
+    fn test_restart_backoff() {
+        let server = McpServer::with_config(
+            "test".to_string(),
+            "echo".to_string(),
+            vec![],
+            HashMap::new(),
+            vec![],
+            ServerConfig {
+                restart_delay: Duration::from_secs(1),
+                max_restart_delay: Duration::from_secs(5),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(server.restart_backoff(0), Duration::from_secs(1));
+        assert_eq!(server.restart_backoff(1), Duration::from_secs(2));
+        assert_eq!(server.restart_backoff(2), Duration::from_secs(4));
+        assert_eq!(server.restart_backoff(3), Duration::from_secs(5));
+        assert_eq!(server.restart_backoff(100), Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_fragmented_message_is_read_whole() {
+        let (mut server, client) = tokio::io::duplex(64);
+        let response =
+            r#"{"jsonrpc":"2.0","id":1,"result":{"content":[{"type":"text","text":"ok"}]}}"#;
+        let (head, tail) = response.split_at(30);
+        let oversized = "x".repeat(100);
+        let writes = vec![
+            head.to_string(),
+            format!("{}\n", tail),
+            format!("{}\n", oversized),
+            "{}".to_string(),
+        ];
+        tokio::spawn(async move {
+            for write in writes {
+                server.write_all(write.as_bytes()).await.unwrap();
+                server.flush().await.unwrap();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+
+        // Tiny buffer so one message spans many reads
+        let mut reader = BufReader::with_capacity(8, client);
+        let Message::Line(line) = read_message(&mut reader, 90).await.unwrap() else {
+            panic!("expected a message");
+        };
+        let parsed: JsonRpcResponse = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed.id, RequestId::Number(1));
+
+        // Too long is skipped, and reading carries on after it
+        assert_eq!(
+            read_message(&mut reader, 90).await.unwrap(),
+            Message::TooLarge(100)
+        );
+        assert_eq!(
+            read_message(&mut reader, 90).await.unwrap(),
+            Message::Line("{}".to_string())
+        );
+        assert_eq!(read_message(&mut reader, 90).await.unwrap(), Message::Eof);
+    }
+
+    #[tokio::test]
+    async fn test_initialize_capabilities_are_kept() {
+        let script = r#"
+import json, sys
+
+for line in sys.stdin:
+    msg = json.loads(line)
+    if "id" not in msg:
+        continue
+    if msg["method"] == "initialize":
+        result = {"protocolVersion": "2024-11-05",
+                  "capabilities": {"tools": {"listChanged": True},
+                                   "resources": {"subscribe": True},
+                                   "logging": {}},
+                  "serverInfo": {"name": "docs", "version": "1.2"}}
+    elif msg["method"] == "tools/list":
+        result = {"tools": []}
+    else:
+        result = {}
+    print(json.dumps({"jsonrpc": "2.0", "id": msg["id"], "result": result}), flush=True)
+"#;
+        let dir = std::env::temp_dir().join(format!("mycel-mcp-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("docs.py");
+        std::fs::write(&path, script).unwrap();
+
+        let mut server = McpServer::new(
+            "docs".to_string(),
+            "python3".to_string(),
+            vec![path.to_string_lossy().to_string()],
+            HashMap::new(),
+            vec![],
+        );
+        assert!(!server.supports_resources().await);
+        server.start().await.unwrap();
+
+        assert!(server.supports_resources().await);
+        assert!(!server.supports_prompts().await);
+        let capabilities = server.capabilities.read().await.clone();
+        assert!(capabilities.resources.unwrap().subscribe);
+        assert!(capabilities.tools.unwrap().list_changed);
+        assert!(capabilities.logging.is_some());
+
+        server.stop().await.unwrap();
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_calls_get_matching_responses() {
+        // Answers tools/call from worker threads after a random delay, so
+        // responses come back out of order
+        let script = r#"
+import json, random, sys, threading, time
+
+lock = threading.Lock()
+
+def reply(msg, result):
+    with lock:
+        print(json.dumps({"jsonrpc": "2.0", "id": msg["id"], "result": result}), flush=True)
+
+def echo(msg):
+    time.sleep(random.random() * 0.05)
+    n = msg["params"]["arguments"]["n"]
+    reply(msg, {"content": [{"type": "text", "text": "echo %d" % n}]})
+
+for line in sys.stdin:
+    msg = json.loads(line)
+    if "id" not in msg or msg["method"].startswith("notifications/"):
+        continue
+    if msg["method"] == "initialize":
+        reply(msg, {"protocolVersion": "2024-11-05", "capabilities": {},
+                    "serverInfo": {"name": "echo", "version": "0.1"}})
+    elif msg["method"] == "tools/list":
+        reply(msg, {"tools": []})
+    elif msg["method"] == "tools/call":
+        threading.Thread(target=echo, args=(msg,)).start()
+"#;
+        let dir = std::env::temp_dir().join(format!("mycel-mcp-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("echo.py");
+        std::fs::write(&path, script).unwrap();
+
+        let mut server = McpServer::new(
+            "echo".to_string(),
+            "python3".to_string(),
+            vec![path.to_string_lossy().to_string()],
+            HashMap::new(),
+            vec![],
+        );
+        server.start().await.unwrap();
+
+        let calls = (0..20).map(|n| {
+            let server = server.clone();
+            async move {
+                let args = HashMap::from([("n".to_string(), serde_json::json!(n))]);
+                (n, server.call_tool("echo", args).await)
+            }
+        });
+
+        for (n, result) in futures::future::join_all(calls).await {
+            match &result.unwrap().content[..] {
+                [ToolContent::Text { text }] => assert_eq!(text, &format!("echo {}", n)),
+                other => panic!("unexpected content: {:?}", other),
+            }
+        }
+
+        server.stop().await.unwrap();
+        let _ = std::fs::remove_dir_all(dir);
+    }
 }
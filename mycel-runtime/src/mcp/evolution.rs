@@ -33,6 +33,8 @@ impl McpEvolver {
         let server_dir = format!("{}/{}", self.dynamic_dir, name);
         fs::create_dir_all(&server_dir).await?;
 
+        self.report_step(name, "writing source");
+
         let (command, args) = match lang.to_lowercase().as_str() {
             "node" | "javascript" | "js" => {
                 let file_path = format!("{}/index.js", server_dir);
@@ -53,6 +55,7 @@ impl McpEvolver {
 
                 // Install dependencies
                 info!("Installing dependencies for dynamic MCP server: {}", name);
+                self.report_step(name, "installing dependencies");
                 let output = tokio::process::Command::new("npm")
                     .arg("install")
                     .current_dir(&server_dir)
@@ -75,6 +78,7 @@ impl McpEvolver {
         };
 
         // Register with the manager
+        self.report_step(name, "starting server");
         self.manager.add_dynamic_server(name, &command, args).await?;
 
         // Broadcast to mesh if requested
@@ -92,4 +96,14 @@ impl McpEvolver {
             name
         ))
     }
+
+    fn report_step(&self, name: &str, step: &str) {
+        let _ = self
+            .manager
+            .event_bus
+            .send(crate::events::SystemEvent::CapabilityInstalling {
+                name: name.to_string(),
+                step: step.to_string(),
+            });
+    }
 }
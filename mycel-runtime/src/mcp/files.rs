@@ -0,0 +1,305 @@
+//! Built-in file tools
+//!
+//! `file_read`, `file_write` and `file_list` run inside the runtime rather
+//! than on an MCP server, so every path is checked against the policy's
+//! blocked file patterns and the model never has to generate code to
+//! touch a file.
+
+use super::protocol::{CallToolResult, McpTool, ToolAnnotations, ToolContent};
+use crate::error::Error;
+use crate::policy::PolicyEvaluator;
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use tokio::io::AsyncWriteExt;
+
+/// Reported as the server in audit entries and tool events
+pub const SERVER_NAME: &str = "builtin-files";
+
+const TOOL_NAMES: &[&str] = &["file_read", "file_write", "file_list"];
+
+/// Whether `name` is one of the built-in file tools
+pub fn is_file_tool(name: &str) -> bool {
+    TOOL_NAMES.contains(&name)
+}
+
+/// Only writes change anything, so only they wait for the user
+pub fn requires_confirmation(name: &str) -> bool {
+    name == "file_write"
+}
+
+/// Definitions offered to the model
+pub fn tools() -> Vec<McpTool> {
+    vec![
+        McpTool {
+            name: "file_read".to_string(),
+            description: "Read a text file. Relative paths are resolved against the working directory."
+                .to_string(),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "File to read"}
+                },
+                "required": ["path"]
+            }),
+            annotations: Some(read_only(true)),
+        },
+        McpTool {
+            name: "file_write".to_string(),
+            description: "Write text to a file, replacing it unless 'append' is set. The user confirms every write."
+                .to_string(),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "File to write"},
+                    "content": {"type": "string", "description": "Text to write"},
+                    "append": {"type": "boolean", "description": "Add to the end of the file instead of replacing it"}
+                },
+                "required": ["path", "content"]
+            }),
+            annotations: Some(read_only(false)),
+        },
+        McpTool {
+            name: "file_list".to_string(),
+            description: "List a directory. Subdirectories end with '/'.".to_string(),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "Directory to list (default: the working directory)"}
+                }
+            }),
+            annotations: Some(read_only(true)),
+        },
+    ]
+}
+
+fn read_only(read_only: bool) -> ToolAnnotations {
+    ToolAnnotations {
+        read_only_hint: Some(read_only),
+        idempotent_hint: Some(read_only),
+    }
+}
+
+/// Run a file tool, returning its result and the absolute path it used.
+/// Relative paths resolve against `working_dir`; paths the policy blocks
+/// fail with [`Error::PolicyDenied`].
+pub async fn call(
+    policy: &RwLock<PolicyEvaluator>,
+    working_dir: &Path,
+    name: &str,
+    arguments: &HashMap<String, serde_json::Value>,
+) -> Result<(CallToolResult, PathBuf)> {
+    let arg = |key: &str| arguments.get(key).and_then(|v| v.as_str());
+    let raw = match (name, arg("path")) {
+        (_, Some(path)) => path,
+        ("file_list", None) => ".",
+        _ => bail!("Tool '{}' needs a 'path' argument", name),
+    };
+    let (path, max_bytes) = {
+        let policy = policy.read().unwrap_or_else(|e| e.into_inner());
+        (
+            allowed_path(&policy, working_dir, raw)?,
+            policy.max_file_size_bytes(),
+        )
+    };
+
+    let text = match name {
+        "file_read" => read(&path, max_bytes).await?,
+        "file_write" => {
+            let content =
+                arg("content").ok_or_else(|| anyhow!("Tool 'file_write' needs 'content'"))?;
+            if content.len() as u64 > max_bytes {
+                bail!(
+                    "Refusing to write {} bytes (limit {})",
+                    content.len(),
+                    max_bytes
+                );
+            }
+            let append = arguments
+                .get("append")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false);
+            write(&path, content, append).await?
+        }
+        "file_list" => list(&path).await?,
+        _ => bail!("Unknown file tool '{}'", name),
+    };
+
+    let result = CallToolResult {
+        content: vec![ToolContent::Text { text }],
+        is_error: false,
+    };
+    Ok((result, path))
+}
+
+/// `raw` made absolute (`~` is home, relative paths start at `working_dir`)
+/// once both it and its symlink-free form pass the policy
+fn allowed_path(policy: &PolicyEvaluator, working_dir: &Path, raw: &str) -> Result<PathBuf> {
+    let requested = if raw == "~" || raw.starts_with("~/") {
+        dirs::home_dir()
+            .ok_or_else(|| anyhow!("No home directory to resolve '~'"))?
+            .join(raw[1..].trim_start_matches('/'))
+    } else {
+        working_dir.join(raw)
+    };
+
+    // A file about to be created has no canonical form; its directory does
+    let resolved = requested.canonicalize().unwrap_or_else(|_| {
+        match (requested.parent(), requested.file_name()) {
+            (Some(parent), Some(name)) => parent
+                .canonicalize()
+                .map(|p| p.join(name))
+                .unwrap_or_else(|_| requested.clone()),
+            _ => requested.clone(),
+        }
+    });
+
+    for candidate in [&requested, &resolved] {
+        if !policy.is_path_allowed(&candidate.to_string_lossy()) {
+            return Err(Error::PolicyDenied(format!(
+                "Access to '{}' is blocked by security policy",
+                raw
+            ))
+            .into());
+        }
+    }
+    Ok(resolved)
+}
+
+async fn read(path: &Path, max_bytes: u64) -> Result<String> {
+    let size = tokio::fs::metadata(path)
+        .await
+        .map_err(|e| anyhow!("Cannot read '{}': {}", path.display(), e))?
+        .len();
+    if size > max_bytes {
+        bail!(
+            "'{}' is {} bytes, over the {} byte limit",
+            path.display(),
+            size,
+            max_bytes
+        );
+    }
+    let bytes = tokio::fs::read(path).await?;
+    String::from_utf8(bytes).map_err(|_| anyhow!("'{}' is not a text file", path.display()))
+}
+
+async fn write(path: &Path, content: &str, append: bool) -> Result<String> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .await
+        .map_err(|e| anyhow!("Cannot write '{}': {}", path.display(), e))?;
+    file.write_all(content.as_bytes()).await?;
+    file.flush().await?;
+    Ok(format!(
+        "Wrote {} bytes to {}",
+        content.len(),
+        path.display()
+    ))
+}
+
+async fn list(path: &Path) -> Result<String> {
+    let mut entries = tokio::fs::read_dir(path)
+        .await
+        .map_err(|e| anyhow!("Cannot list '{}': {}", path.display(), e))?;
+    let mut names = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let mut name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type().await?.is_dir() {
+            name.push('/');
+        }
+        names.push(name);
+    }
+    if names.is_empty() {
+        return Ok(format!("{} is empty", path.display()));
+    }
+    names.sort();
+    Ok(names.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::PolicyConfig;
+
+    #[tokio::test]
+    async fn test_file_tools_respect_blocked_paths() {
+        let dir = std::env::temp_dir().join(format!("mycel-files-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("secret")).unwrap();
+        std::fs::write(dir.join("secret/key"), "hunter2").unwrap();
+        let policy = RwLock::new(PolicyEvaluator::new(PolicyConfig {
+            blocked_file_patterns: vec![
+                "/etc/shadow".to_string(),
+                format!("{}/secret/*", dir.display()),
+            ],
+            ..PolicyConfig::default()
+        }));
+        let args = |pairs: &[(&str, &str)]| -> HashMap<String, serde_json::Value> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), serde_json::json!(v)))
+                .collect()
+        };
+        let text = |(result, _): (CallToolResult, PathBuf)| match &result.content[..] {
+            [ToolContent::Text { text }] => text.clone(),
+            other => panic!("unexpected content {:?}", other),
+        };
+
+        // Project files can be written, read back and listed
+        let notes = dir.join("notes.txt").to_string_lossy().to_string();
+        let written = [("path", notes.as_str()), ("content", "hello")];
+        call(&policy, &dir, "file_write", &args(&written))
+            .await
+            .unwrap();
+        let read = call(&policy, &dir, "file_read", &args(&[("path", &notes)])).await;
+        assert_eq!(text(read.unwrap()), "hello");
+        let listing = call(
+            &policy,
+            &dir,
+            "file_list",
+            &args(&[("path", &dir.to_string_lossy())]),
+        )
+        .await;
+        assert_eq!(text(listing.unwrap()), "notes.txt\nsecret/");
+
+        // Relative paths start at the working directory
+        let read = call(&policy, &dir, "file_read", &args(&[("path", "notes.txt")])).await;
+        assert_eq!(text(read.unwrap()), "hello");
+
+        // Blocked paths are refused, including through a symlink
+        std::os::unix::fs::symlink(dir.join("secret/key"), dir.join("link")).unwrap();
+        let link = dir.join("link").to_string_lossy().to_string();
+        for path in ["/etc/shadow", link.as_str()] {
+            let err = call(&policy, &dir, "file_read", &args(&[("path", path)]))
+                .await
+                .unwrap_err();
+            assert_eq!(
+                Error::find(&err).and_then(Error::code),
+                Some("policy_denied")
+            );
+        }
+
+        // As is listing the blocked directory itself
+        let err = call(&policy, &dir, "file_list", &args(&[("path", "secret")]))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            Error::find(&err).and_then(Error::code),
+            Some("policy_denied")
+        );
+        let overwrite = [("path", link.as_str()), ("content", "")];
+        assert!(call(&policy, &dir, "file_write", &args(&overwrite))
+            .await
+            .is_err());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("secret/key")).unwrap(),
+            "hunter2"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
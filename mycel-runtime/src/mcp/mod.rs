@@ -13,27 +13,69 @@
 
 pub mod client;
 pub mod evolution;
+pub mod files;
 pub mod protocol;
 pub mod tool_parser;
 
+use crate::error::Error;
 use crate::events::SystemEvent;
+use crate::policy::{ActionPolicy, PolicyEvaluator};
 use anyhow::{anyhow, Result};
 use std::collections::HashMap;
+use std::future::Future;
 use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
 
-pub use client::{McpServer, ServerHealth, ServerState};
+pub use client::{McpServer, ProgressSender, ServerHealth, ServerState, ToolProgress};
 pub use evolution::McpEvolver;
-pub use protocol::McpTool;
-pub use tool_parser::{format_tool_result, format_tools_for_prompt, parse_tool_calls, ToolCall};
+pub use protocol::{McpResource, McpTool, ResourceContent};
+pub use tool_parser::{
+    format_tool_result, format_tools_for_prompt, parse_tool_calls_as,
+    StreamSegment, ToolCallFormatHint, ToolCallStreamParser, ToolCall,
+};
+#[cfg(test)]
+pub use tool_parser::parse_tool_calls;
 
 use crate::config::{McpConfig, McpServerConfig};
 
+/// Packages whose removal can leave the system unbootable or unmanageable
+const CRITICAL_PACKAGES: &[&str] = &[
+    "base-system",
+    "base-files",
+    "glibc",
+    "musl",
+    "linux",
+    "xbps",
+    "runit",
+    "runit-void",
+    "bash",
+    "coreutils",
+    "util-linux",
+    "shadow",
+    "sudo",
+    "eudev",
+];
+
+/// Services whose loss cuts off login, networking or device handling
+const CRITICAL_SERVICES: &[&str] = &[
+    "sshd",
+    "dbus",
+    "udevd",
+    "elogind",
+    "NetworkManager",
+    "dhcpcd",
+    "wpa_supplicant",
+];
+
 /// Risk level for tool operations
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RiskLevel {
     /// Safe, read-only operations
     Low,
@@ -46,6 +88,8 @@ pub enum RiskLevel {
 /// Pending confirmation for a tool call
 #[derive(Debug, Clone)]
 pub struct PendingConfirmation {
+    /// Identifies the confirmation when it is answered
+    pub id: String,
     /// Tool name
     pub tool_name: String,
     /// Tool arguments
@@ -58,11 +102,62 @@ pub struct PendingConfirmation {
     pub created_at: Instant,
 }
 
+impl PendingConfirmation {
+    /// Whether the confirmation has waited `ttl` or longer
+    pub fn is_expired(&self, ttl: Duration) -> bool {
+        self.created_at.elapsed() >= ttl
+    }
+}
+
 /// Cached tool result
 #[derive(Debug, Clone)]
 struct CachedResult {
     result: String,
     expires_at: Instant,
+    /// Read-only tool, so the result may be saved across restarts
+    persist: bool,
+}
+
+/// On-disk form of a cached result; an `Instant` means nothing after a
+/// restart, so expiry is stored as wall-clock time
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedResult {
+    result: String,
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Tool result cache effectiveness, since startup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Unexpired cached results
+    pub entries: usize,
+    /// `hits / (hits + misses)`, or 0 before any lookup
+    pub hit_rate: f64,
+}
+
+/// A resource and the server offering it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerResource {
+    pub server: String,
+    #[serde(flatten)]
+    pub resource: McpResource,
+}
+
+/// A tool the model is offered, with where it comes from and how it is
+/// guarded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Capability {
+    pub name: String,
+    pub description: String,
+    /// Server providing the tool; `files` for the built-in file tools and
+    /// `mycel` for the evolution meta-tools
+    pub server: String,
+    /// Risk of a call without arguments; arguments can raise it
+    pub risk_level: RiskLevel,
+    /// Calls wait for the user's approval before they run
+    pub requires_confirmation: bool,
 }
 
 /// Audit log entry for tool calls
@@ -84,6 +179,14 @@ pub struct ToolAuditEntry {
     pub server_name: String,
 }
 
+/// The chat session a handle's tool calls are made for
+#[derive(Clone)]
+pub struct ToolSession {
+    pub id: String,
+    /// Told about the files the file tools read and write
+    pub context_manager: crate::context::ContextManager,
+}
+
 /// Manages multiple MCP servers and provides unified tool access
 #[derive(Clone)]
 pub struct McpManager {
@@ -93,10 +196,24 @@ pub struct McpManager {
     event_bus: broadcast::Sender<SystemEvent>,
     /// Cache for tool results (tool_name:args_hash -> result)
     cache: Arc<RwLock<HashMap<String, CachedResult>>>,
+    cache_hits: Arc<AtomicU64>,
+    cache_misses: Arc<AtomicU64>,
     /// Audit log (bounded circular buffer)
     audit_log: Arc<RwLock<Vec<ToolAuditEntry>>>,
     /// Maximum audit log entries
     max_audit_entries: usize,
+    /// Cancelled by `stop_all` so background tasks wind down
+    shutdown: CancellationToken,
+    /// Where `process_tool_call` streams tool progress, if anywhere
+    progress: Option<ProgressSender>,
+    /// The session calls are made for, if any
+    session: Option<ToolSession>,
+    /// Calls held for review, by confirmation id
+    pending: Arc<RwLock<HashMap<String, PendingConfirmation>>>,
+    /// Tool description embeddings, keyed by the embedded text
+    tool_embeddings: Arc<RwLock<HashMap<String, Vec<f32>>>>,
+    /// Checks the paths given to the built-in file tools
+    policy: Arc<std::sync::RwLock<PolicyEvaluator>>,
 }
 
 impl McpManager {
@@ -112,8 +229,16 @@ impl McpManager {
             runtime_path: runtime_path.to_string(),
             event_bus,
             cache: Arc::new(RwLock::new(HashMap::new())),
+            cache_hits: Arc::new(AtomicU64::new(0)),
+            cache_misses: Arc::new(AtomicU64::new(0)),
             audit_log: Arc::new(RwLock::new(Vec::new())),
             max_audit_entries: 1000,
+            shutdown: CancellationToken::new(),
+            progress: None,
+            session: None,
+            pending: Arc::new(RwLock::new(HashMap::new())),
+            tool_embeddings: Arc::new(RwLock::new(HashMap::new())),
+            policy: Arc::new(std::sync::RwLock::new(PolicyEvaluator::with_defaults())),
         };
 
         Ok(manager)
@@ -165,51 +290,62 @@ impl McpManager {
     }
 
     /// Spawn a background task to periodically check server health
-    fn spawn_health_check_task(&self) {
+    fn spawn_health_check_task(&self) -> JoinHandle<()> {
         let servers = self.servers.clone();
         let event_bus = self.event_bus.clone();
+        let shutdown = self.shutdown.clone();
 
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(Duration::from_secs(60));
 
             loop {
-                interval.tick().await;
-
-                let mut servers_guard = servers.lock().await;
-                for (name, server) in servers_guard.iter_mut() {
-                    if !server.health_check().await {
-                        warn!("[{}] Health check failed, attempting restart", name);
-                        match server.restart_if_needed().await {
-                            Ok(true) => {
-                                info!("[{}] Server restarted successfully", name);
-                                let _ = event_bus.send(SystemEvent::McpServerRestarted {
-                                    name: name.clone(),
-                                });
-                            }
-                            Ok(false) => {}
-                            Err(e) => {
-                                warn!("[{}] Failed to restart: {}", name, e);
-                            }
-                        }
-                    }
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+
+                // Work on handles so a restart's backoff doesn't block tool calls
+                let snapshot: Vec<(String, McpServer)> = servers
+                    .lock()
+                    .await
+                    .iter()
+                    .map(|(name, server)| (name.clone(), server.clone()))
+                    .collect();
+                for (name, mut server) in snapshot {
+                    check_server_health(&name, &mut server, &event_bus).await;
                 }
             }
-        });
+        })
     }
 
     /// Start a single MCP server
     pub async fn start_server(&self, config: &McpServerConfig) -> Result<()> {
         // Resolve the command path
         let command = self.resolve_command(&config.command);
-        let args = self.resolve_args(&config.args);
+        let args = self.resolve_args(&config.args)
+            .map_err(|e| anyhow!("[{}] {}", config.name, e))?;
+        let env = config.env.iter()
+            .map(|(key, value)| Ok((key.clone(), self.expand_env(value)?)))
+            .collect::<Result<HashMap<_, _>>>()
+            .map_err(|e| anyhow!("[{}] {}", config.name, e))?;
 
         let mut server = McpServer::new(
             config.name.clone(),
             command,
             args,
-            config.env.clone(),
+            env,
             config.requires_confirmation.clone(),
         );
+        if let Some(secs) = config.tool_timeout_secs {
+            server.config.tool_timeout = Duration::from_secs(secs);
+        }
+        server.config.tool_timeouts = config
+            .tool_timeouts
+            .iter()
+            .map(|(tool, secs)| (tool.clone(), Duration::from_secs(*secs)))
+            .collect();
+        server.config.idempotent_tools = config.idempotent_tools.clone();
+        server.config.max_message_size = self.config.max_message_bytes;
 
         server.start().await?;
 
@@ -226,6 +362,9 @@ impl McpManager {
             args,
             env: HashMap::new(),
             requires_confirmation: Vec::new(),
+            tool_timeout_secs: None,
+            tool_timeouts: HashMap::new(),
+            idempotent_tools: HashMap::new(),
         };
 
         self.start_server(&config).await
@@ -245,35 +384,126 @@ impl McpManager {
         command.to_string()
     }
 
-    /// Resolve arguments (handle relative paths)
-    fn resolve_args(&self, args: &[String]) -> Vec<String> {
+    /// Resolve arguments (expand environment variables, handle relative paths)
+    fn resolve_args(&self, args: &[String]) -> Result<Vec<String>> {
         args.iter()
             .map(|arg| {
+                let arg = self.expand_env(arg)?;
                 if arg.contains('/') && !arg.starts_with('/') && !arg.starts_with("--") {
-                    let full_path = Path::new(&self.runtime_path).join(arg);
+                    let full_path = Path::new(&self.runtime_path).join(&arg);
                     if full_path.exists() || full_path.parent().map(|p| p.exists()).unwrap_or(false) {
-                        return full_path.to_string_lossy().to_string();
+                        return Ok(full_path.to_string_lossy().to_string());
                     }
                 }
-                arg.clone()
+                Ok(arg)
             })
             .collect()
     }
 
-    /// Get all available tools from all servers
+    /// Expand environment variables in a config value against the process environment
+    fn expand_env(&self, value: &str) -> Result<String> {
+        expand_env_vars(value, |name| std::env::var(name).ok(), self.config.allow_undefined_env)
+    }
+
+    /// A handle whose built-in file tools check paths against `policy`
+    /// (shared, so reloading the policy applies here too)
+    pub fn with_policy(&self, policy: Arc<std::sync::RwLock<PolicyEvaluator>>) -> Self {
+        Self {
+            policy,
+            ..self.clone()
+        }
+    }
+
+    /// Get all tools the model may use from all servers
     pub async fn get_all_tools(&self) -> Vec<McpTool> {
         let mut all_tools = Vec::new();
+        let read_only = self.config.read_only_tools;
+        if self.config.file_tools_enabled {
+            all_tools.extend(
+                files::tools()
+                    .into_iter()
+                    .filter(|t| self.tool_exposed(&t.name) && (!read_only || t.read_only())),
+            );
+        }
         let servers = self.servers.lock().await;
 
         for server in servers.values() {
             if server.state().await == ServerState::Ready {
-                all_tools.extend(server.get_tools().await);
+                for tool in server.get_tools().await {
+                    if self.tool_exposed(&tool.name)
+                        && (!read_only || server.is_read_only(&tool.name).await)
+                    {
+                        all_tools.push(tool);
+                    }
+                }
             }
         }
 
         all_tools
     }
 
+    /// Whether `mcp.tool_allowlist`/`mcp.tool_denylist` let the model use a tool
+    pub fn tool_exposed(&self, tool_name: &str) -> bool {
+        let allowed = self.config.tool_allowlist.is_empty()
+            || self.config.tool_allowlist.iter().any(|t| t == tool_name);
+        allowed && !self.config.tool_denylist.iter().any(|t| t == tool_name)
+    }
+
+    /// Resources from every ready server that advertised the resources
+    /// capability. A server that fails to list them is skipped.
+    pub async fn list_resources(&self) -> Vec<ServerResource> {
+        let mut resources = Vec::new();
+        for (name, server) in self.resource_servers().await {
+            match server.list_resources().await {
+                Ok(listed) => resources.extend(listed.into_iter().map(|resource| {
+                    ServerResource {
+                        server: name.clone(),
+                        resource,
+                    }
+                })),
+                Err(e) => warn!("Failed to list resources of '{}': {}", name, e),
+            }
+        }
+        resources
+    }
+
+    /// Read the resource at `uri` from the server that lists it
+    pub async fn read_resource(&self, uri: &str) -> Result<Vec<ResourceContent>> {
+        for (name, server) in self.resource_servers().await {
+            let listed = match server.list_resources().await {
+                Ok(listed) => listed,
+                Err(e) => {
+                    debug!("Skipping '{}' for resource {}: {}", name, uri, e);
+                    continue;
+                }
+            };
+            if listed.iter().any(|r| r.uri == uri) {
+                return server.read_resource(uri).await;
+            }
+        }
+        Err(anyhow!("No server provides resource '{}'", uri))
+    }
+
+    /// Handles to the ready servers that support resources
+    async fn resource_servers(&self) -> Vec<(String, McpServer)> {
+        let servers: Vec<(String, McpServer)> = self
+            .servers
+            .lock()
+            .await
+            .iter()
+            .map(|(name, server)| (name.clone(), server.clone()))
+            .collect();
+
+        let mut supporting = Vec::new();
+        for (name, server) in servers {
+            if server.state().await == ServerState::Ready && server.supports_resources().await {
+                supporting.push((name, server));
+            }
+        }
+        supporting.sort_by(|a, b| a.0.cmp(&b.0));
+        supporting
+    }
+
     /// Find which server provides a specific tool
     async fn find_tool_server(&self, tool_name: &str) -> Option<String> {
         let servers = self.servers.lock().await;
@@ -291,28 +521,140 @@ impl McpManager {
         None
     }
 
-    /// Generate a cache key for a tool call
-    fn cache_key(tool_name: &str, arguments: &HashMap<String, serde_json::Value>) -> String {
-        let args_json = serde_json::to_string(arguments).unwrap_or_default();
+    /// Generate a cache key for a tool call (argument order doesn't matter)
+    pub(crate) fn cache_key(tool_name: &str, arguments: &HashMap<String, serde_json::Value>) -> String {
+        let sorted: std::collections::BTreeMap<_, _> = arguments.iter().collect();
+        let args_json = serde_json::to_string(&sorted).unwrap_or_default();
         format!("{}:{}", tool_name, args_json)
     }
 
+    /// A handle whose `process_tool_call` streams tool progress to `progress`
+    pub fn with_progress(&self, progress: ProgressSender) -> Self {
+        Self {
+            progress: Some(progress),
+            ..self.clone()
+        }
+    }
+
+    /// A handle whose file tools record what they touch in `session`
+    pub fn with_session(&self, session: ToolSession) -> Self {
+        Self {
+            session: Some(session),
+            ..self.clone()
+        }
+    }
+
+    /// Where the file tools resolve relative paths: the session's working
+    /// directory, or the daemon's own without a session
+    async fn working_directory(&self) -> Result<std::path::PathBuf> {
+        let session_dir = match &self.session {
+            Some(session) => session.context_manager.working_directory(&session.id).await,
+            None => None,
+        };
+        match session_dir {
+            Some(dir) => Ok(dir.into()),
+            None => Ok(std::env::current_dir()?),
+        }
+    }
+
+    /// Note a file that a file tool read or wrote in the session's recent
+    /// and frequently used files
+    async fn record_file_access(&self, tool_name: &str, path: &std::path::Path) {
+        let Some(session) = &self.session else {
+            return;
+        };
+        if tool_name == "file_list" {
+            return;
+        }
+        if let Err(e) = session
+            .context_manager
+            .record_file_access(&session.id, &path.to_string_lossy())
+            .await
+        {
+            debug!("Failed to record access to {}: {}", path.display(), e);
+        }
+    }
+
     /// Call a tool by name
     pub async fn call_tool(
         &self,
         tool_name: &str,
         arguments: HashMap<String, serde_json::Value>,
     ) -> Result<protocol::CallToolResult> {
-        let start = Instant::now();
-        let server_name = self.find_tool_server(tool_name).await
-            .ok_or_else(|| anyhow!("No server provides tool '{}'", tool_name))?;
+        self.call_tool_inner(tool_name, arguments, None).await
+    }
 
-        let result = {
-            let mut servers = self.servers.lock().await;
-            let server = servers.get_mut(&server_name)
-                .ok_or_else(|| anyhow!("Server '{}' not found", server_name))?;
+    /// Call a tool by name, forwarding interim progress from servers that
+    /// report it; others just return the final result
+    pub async fn call_tool_streaming(
+        &self,
+        tool_name: &str,
+        arguments: HashMap<String, serde_json::Value>,
+        progress: ProgressSender,
+    ) -> Result<protocol::CallToolResult> {
+        self.call_tool_inner(tool_name, arguments, Some(progress))
+            .await
+    }
 
-            server.call_tool(tool_name, arguments.clone()).await
+    async fn call_tool_inner(
+        &self,
+        tool_name: &str,
+        arguments: HashMap<String, serde_json::Value>,
+        progress: Option<ProgressSender>,
+    ) -> Result<protocol::CallToolResult> {
+        validate_tool_arguments(tool_name, &arguments)?;
+        let start = Instant::now();
+        let (server_name, result) = if self.is_file_tool(tool_name) {
+            let working_dir = self.working_directory().await?;
+            let result = match files::call(&self.policy, &working_dir, tool_name, &arguments).await
+            {
+                Ok((result, path)) => {
+                    self.record_file_access(tool_name, &path).await;
+                    Ok(result)
+                }
+                Err(e) => Err(e),
+            };
+            (files::SERVER_NAME.to_string(), result)
+        } else {
+            let server_name = self.find_tool_server(tool_name).await
+                .ok_or_else(|| anyhow!("No server provides tool '{}'", tool_name))?;
+
+            // Take a handle and release the lock so calls to the same or other
+            // servers can run concurrently (responses are matched by request id)
+            let server = self.servers.lock().await.get(&server_name).cloned()
+                .ok_or_else(|| anyhow!("Server '{}' not found", server_name))?;
+            // The client's own deadline only starts once the request is
+            // queued; this one also covers a server that stopped reading
+            let timeout = server.tool_timeout(tool_name);
+            let (server, arguments) = (&server, &arguments);
+            let attempt = |progress: Option<ProgressSender>| async move {
+                let call = async {
+                    match progress {
+                        Some(progress) => {
+                            server
+                                .call_tool_streaming(tool_name, arguments.clone(), progress)
+                                .await
+                        }
+                        None => server.call_tool(tool_name, arguments.clone()).await,
+                    }
+                };
+                tokio::time::timeout(timeout, call)
+                    .await
+                    .unwrap_or_else(|_| Err(Error::ToolTimeout(timeout).into()))
+            };
+            let mut result = attempt(progress.clone()).await;
+
+            // A timeout may be a passing stall; only a tool that is safe to
+            // run twice gets a second try
+            let timed_out = matches!(
+                result.as_ref().err().and_then(Error::find),
+                Some(Error::ToolTimeout(_))
+            );
+            if timed_out && server.is_idempotent(tool_name).await {
+                debug!("Retrying idempotent tool '{}' after a timeout", tool_name);
+                result = attempt(progress).await;
+            }
+            (server_name, result)
         };
 
         // Record audit entry
@@ -338,7 +680,48 @@ impl McpManager {
         result
     }
 
-    /// Call a tool with caching
+    /// Whether `tool_name` is safe to cache and retry. Tools that don't
+    /// say are treated as not.
+    pub async fn is_idempotent(&self, tool_name: &str) -> bool {
+        if is_evolution_tool(tool_name) {
+            return false;
+        }
+        if self.is_file_tool(tool_name) {
+            return files::tools()
+                .iter()
+                .any(|tool| tool.name == tool_name && tool.idempotent() == Some(true));
+        }
+        if let Some(server_name) = self.find_tool_server(tool_name).await {
+            let server = self.servers.lock().await.get(&server_name).cloned();
+            if let Some(server) = server {
+                return server.is_idempotent(tool_name).await;
+            }
+        }
+        false
+    }
+
+    /// Whether `tool_name` is declared read-only, as `mcp.read_only_tools`
+    /// requires. Repeating a call having no further effect isn't enough.
+    pub async fn is_read_only(&self, tool_name: &str) -> bool {
+        if is_evolution_tool(tool_name) {
+            return false;
+        }
+        if self.is_file_tool(tool_name) {
+            return files::tools()
+                .iter()
+                .any(|tool| tool.name == tool_name && tool.read_only());
+        }
+        if let Some(server_name) = self.find_tool_server(tool_name).await {
+            let server = self.servers.lock().await.get(&server_name).cloned();
+            if let Some(server) = server {
+                return server.is_read_only(tool_name).await;
+            }
+        }
+        false
+    }
+
+    /// Call a tool with caching. Only idempotent tools are cached; others,
+    /// and the file tools, are called every time.
     pub async fn call_tool_cached(
         &self,
         tool_name: &str,
@@ -353,13 +736,28 @@ impl McpManager {
             if let Some(cached) = cache.get(&cache_key) {
                 if cached.expires_at > Instant::now() {
                     debug!("Cache hit for tool '{}'", tool_name);
+                    self.cache_hits.fetch_add(1, Ordering::Relaxed);
                     return Ok(cached.result.clone());
                 }
             }
         }
 
+        // Results of tools that may change something aren't reused, nor are
+        // file contents, which change underneath the cache and would be
+        // written to disk with it
+        if self.is_file_tool(tool_name) || !self.is_idempotent(tool_name).await {
+            let result = self
+                .call_tool_inner(tool_name, arguments, self.progress.clone())
+                .await?;
+            return Ok(format_tool_result(tool_name, &result));
+        }
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+
         // Call the tool
-        let result = self.call_tool(tool_name, arguments).await?;
+        let persist = self.assess_risk_level(tool_name, &arguments) == RiskLevel::Low;
+        let result = self
+            .call_tool_inner(tool_name, arguments, self.progress.clone())
+            .await?;
         let formatted = format_tool_result(tool_name, &result);
 
         // Store in cache
@@ -368,6 +766,7 @@ impl McpManager {
             cache.insert(cache_key, CachedResult {
                 result: formatted.clone(),
                 expires_at: Instant::now() + ttl,
+                persist,
             });
 
             // Cleanup expired entries periodically
@@ -380,22 +779,51 @@ impl McpManager {
         Ok(formatted)
     }
 
-    /// Execute multiple tool calls in parallel
+    /// Execute multiple tool calls in parallel. Identical calls (same name
+    /// and arguments) run once and every position gets the shared result.
     pub async fn call_tools_parallel(
         &self,
         calls: &[ToolCall],
     ) -> Vec<Result<String>> {
-        let futures: Vec<_> = calls.iter()
+        let mut unique: Vec<&ToolCall> = Vec::new();
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        let slots: Vec<usize> = calls
+            .iter()
+            .map(|call| {
+                *seen
+                    .entry(Self::cache_key(&call.name, &call.arguments))
+                    .or_insert_with(|| {
+                        unique.push(call);
+                        unique.len() - 1
+                    })
+            })
+            .collect();
+
+        if unique.len() < calls.len() {
+            debug!(
+                "Skipping {} duplicate tool call(s)",
+                calls.len() - unique.len()
+            );
+        }
+
+        let futures: Vec<_> = unique.iter()
             .map(|call| {
                 let manager = self.clone();
-                let call = call.clone();
+                let call = (*call).clone();
                 async move {
                     manager.process_tool_call(&call).await
                 }
             })
             .collect();
+        let results = futures::future::join_all(futures).await;
 
-        futures::future::join_all(futures).await
+        slots
+            .into_iter()
+            .map(|slot| match &results[slot] {
+                Ok(output) => Ok(output.clone()),
+                Err(e) => Err(anyhow!("{}", e)),
+            })
+            .collect()
     }
 
     /// Record an audit log entry
@@ -410,6 +838,106 @@ impl McpManager {
         }
     }
 
+    /// Append the audit log to `path` as JSON lines and clear it from memory
+    pub async fn flush_audit_log(&self, path: &Path) -> Result<usize> {
+        use tokio::io::AsyncWriteExt;
+
+        let entries: Vec<ToolAuditEntry> = self.audit_log.write().await.drain(..).collect();
+        if entries.is_empty() {
+            return Ok(0);
+        }
+
+        let now = Instant::now();
+        let wall_now = chrono::Utc::now();
+        let mut lines = String::new();
+        for entry in &entries {
+            let age = chrono::Duration::from_std(now.duration_since(entry.timestamp))
+                .unwrap_or_else(|_| chrono::Duration::zero());
+            let line = serde_json::json!({
+                "timestamp": (wall_now - age).to_rfc3339(),
+                "tool_name": entry.tool_name,
+                "server_name": entry.server_name,
+                "success": entry.success,
+                "response_time_ms": entry.response_time_ms,
+                "error": entry.error,
+            });
+            lines.push_str(&line.to_string());
+            lines.push('\n');
+        }
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        file.write_all(lines.as_bytes()).await?;
+        file.flush().await?;
+
+        Ok(entries.len())
+    }
+
+    /// Write unexpired results of read-only tools to `path`, replacing it.
+    ///
+    /// Returns the number of results saved.
+    pub async fn save_cache(&self, path: &Path) -> Result<usize> {
+        let now = Instant::now();
+        let wall_now = chrono::Utc::now();
+        let saved: HashMap<String, PersistedResult> = self
+            .cache
+            .read()
+            .await
+            .iter()
+            .filter(|(_, cached)| cached.persist && cached.expires_at > now)
+            .filter_map(|(key, cached)| {
+                let ttl = chrono::Duration::from_std(cached.expires_at - now).ok()?;
+                let persisted = PersistedResult {
+                    result: cached.result.clone(),
+                    expires_at: wall_now + ttl,
+                };
+                Some((key.clone(), persisted))
+            })
+            .collect();
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, serde_json::to_vec(&saved)?).await?;
+        Ok(saved.len())
+    }
+
+    /// Restore results saved by `save_cache`, skipping those that have
+    /// expired since. A missing file restores nothing.
+    ///
+    /// Returns the number of results restored.
+    pub async fn load_cache(&self, path: &Path) -> Result<usize> {
+        let data = match tokio::fs::read(path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let saved: HashMap<String, PersistedResult> = serde_json::from_slice(&data)?;
+
+        let now = Instant::now();
+        let wall_now = chrono::Utc::now();
+        let mut cache = self.cache.write().await;
+        let mut restored = 0;
+        for (key, persisted) in saved {
+            let Ok(ttl) = (persisted.expires_at - wall_now).to_std() else {
+                continue;
+            };
+            cache.insert(key, CachedResult {
+                result: persisted.result,
+                expires_at: now + ttl,
+                persist: true,
+            });
+            restored += 1;
+        }
+        Ok(restored)
+    }
+
     /// Get recent audit log entries
     pub async fn get_audit_log(&self, limit: usize) -> Vec<ToolAuditEntry> {
         let log = self.audit_log.read().await;
@@ -420,13 +948,46 @@ impl McpManager {
             .collect()
     }
 
-    /// Clear the cache
+    /// Clear the cache (hit/miss counters are kept)
     pub async fn clear_cache(&self) {
         self.cache.write().await.clear();
     }
 
+    /// Hit/miss counts for `call_tool_cached` and the current cache size
+    pub async fn cache_stats(&self) -> CacheStats {
+        let now = Instant::now();
+        let entries = self
+            .cache
+            .read()
+            .await
+            .values()
+            .filter(|cached| cached.expires_at > now)
+            .count();
+        let hits = self.cache_hits.load(Ordering::Relaxed);
+        let misses = self.cache_misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+
+        CacheStats {
+            hits,
+            misses,
+            entries,
+            hit_rate: if lookups == 0 {
+                0.0
+            } else {
+                hits as f64 / lookups as f64
+            },
+        }
+    }
+
     /// Check if a tool requires user confirmation
     pub async fn requires_confirmation(&self, tool_name: &str) -> bool {
+        // Evolution calls hold themselves for review in `process_tool_call`
+        if is_evolution_tool(tool_name) {
+            return false;
+        }
+        if self.is_file_tool(tool_name) {
+            return files::requires_confirmation(tool_name);
+        }
         if let Some(server_name) = self.find_tool_server(tool_name).await {
             let servers = self.servers.lock().await;
             if let Some(server) = servers.get(&server_name) {
@@ -437,11 +998,14 @@ impl McpManager {
     }
 
     /// Create a pending confirmation for a tool call
+    ///
+    /// Fails if the arguments don't pass [`validate_tool_arguments`].
     pub fn create_pending_confirmation(
         &self,
         tool_name: &str,
         arguments: HashMap<String, serde_json::Value>,
-    ) -> PendingConfirmation {
+    ) -> Result<PendingConfirmation> {
+        validate_tool_arguments(tool_name, &arguments)?;
         let risk_level = self.assess_risk_level(tool_name, &arguments);
 
         let description = match tool_name {
@@ -458,39 +1022,234 @@ impl McpManager {
                 arguments.get("action").and_then(|v| v.as_str()).unwrap_or("control"),
                 arguments.get("service").and_then(|v| v.as_str()).unwrap_or("unknown")
             ),
+            "file_write" => format!(
+                "Write file: {}",
+                arguments.get("path").and_then(|v| v.as_str()).unwrap_or("unknown")
+            ),
+            "evolve_os_add_capability" | "evolve_os_install_capability" => format!(
+                "Create MCP server '{}' ({})",
+                arguments.get("name").and_then(|v| v.as_str()).unwrap_or("unknown"),
+                arguments.get("language").and_then(|v| v.as_str()).unwrap_or("unknown")
+            ),
             _ => format!("Execute tool '{}' with arguments", tool_name),
         };
 
-        PendingConfirmation {
+        Ok(PendingConfirmation {
+            id: uuid::Uuid::new_v4().to_string(),
             tool_name: tool_name.to_string(),
             arguments,
             description,
             risk_level,
             created_at: Instant::now(),
-        }
+        })
     }
 
-    /// Assess the risk level of a tool call
-    fn assess_risk_level(&self, tool_name: &str, _arguments: &HashMap<String, serde_json::Value>) -> RiskLevel {
+    /// Assess the risk level of a tool call from its name and arguments
+    pub fn assess_risk_level(
+        &self,
+        tool_name: &str,
+        arguments: &HashMap<String, serde_json::Value>,
+    ) -> RiskLevel {
+        let arg = |key: &str| arguments.get(key).and_then(|v| v.as_str()).unwrap_or("");
+
+        // Destructive shell fragments escalate any tool
+        let destructive = ["command", "package", "service"].iter().any(|key| {
+            let value = arg(key).to_lowercase();
+            crate::policy::DESTRUCTIVE_COMMAND_PATTERNS
+                .iter()
+                .any(|pattern| value.contains(pattern))
+        });
+        if destructive {
+            return RiskLevel::High;
+        }
+
         match tool_name {
             // Read-only operations
-            "xbps_search" | "xbps_info" | "service_status" | "system_info" => RiskLevel::Low,
+            "xbps_search" | "xbps_info" | "service_status" | "system_info" | "file_read"
+            | "file_list" => RiskLevel::Low,
 
             // System modifications
-            "xbps_install" | "service_control" => RiskLevel::Medium,
+            "xbps_install" | "shell_command" => RiskLevel::Medium,
+
+            // Overwrites user data
+            "file_write" => RiskLevel::Medium,
+
+            // Stopping core services can cut off the machine
+            "service_control" => {
+                let stopping = matches!(arg("action"), "stop" | "disable" | "restart");
+                if stopping && CRITICAL_SERVICES.contains(&arg("service")) {
+                    RiskLevel::High
+                } else {
+                    RiskLevel::Medium
+                }
+            }
 
-            // Destructive operations
-            "xbps_remove" => RiskLevel::High,
+            // Removing an ordinary package is recoverable; removing the base system is not
+            "xbps_remove" => {
+                if CRITICAL_PACKAGES.contains(&arg("package")) {
+                    RiskLevel::High
+                } else {
+                    RiskLevel::Medium
+                }
+            }
 
             // Unknown tools default to high risk
             _ => RiskLevel::High,
         }
     }
 
+    /// Whether `tool_name` is a built-in file tool and those are enabled
+    fn is_file_tool(&self, tool_name: &str) -> bool {
+        self.config.file_tools_enabled && files::is_file_tool(tool_name)
+    }
+
+    /// Whether the `evolve_os_*` meta-tools are offered and accepted
+    pub fn evolution_enabled(&self) -> bool {
+        self.config.evolution_enabled
+    }
+
     /// Get the tools formatted for LLM prompt injection
     pub async fn get_tools_prompt(&self) -> String {
+        format_tools_for_prompt(&self.prompt_tools().await)
+    }
+
+    /// Like `get_tools_prompt`, but with `mcp.tool_selection_top_k` set only
+    /// the tools most similar to `input` are listed. Every tool is listed when
+    /// there are few of them or `embed` fails.
+    pub async fn get_tools_prompt_for<F, Fut>(&self, input: &str, embed: F) -> String
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<Vec<f32>>>,
+    {
+        let tools = self.prompt_tools().await;
+        let top_k = self.config.tool_selection_top_k;
+        if top_k == 0 || tools.len() <= top_k {
+            return format_tools_for_prompt(&tools);
+        }
+
+        match self.select_tools(&tools, input, top_k, embed).await {
+            Ok(selected) => format_tools_for_prompt(&selected),
+            Err(e) => {
+                debug!("Tool selection unavailable, offering every tool: {}", e);
+                format_tools_for_prompt(&tools)
+            }
+        }
+    }
+
+    /// The `top_k` tools most similar to `input`, in their original order.
+    /// Tool embeddings are cached and only computed for new or changed tools.
+    async fn select_tools<F, Fut>(
+        &self,
+        tools: &[McpTool],
+        input: &str,
+        top_k: usize,
+        embed: F,
+    ) -> Result<Vec<McpTool>>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<Vec<f32>>>,
+    {
+        let query = embed(input.to_string()).await?;
+        let texts: Vec<String> = tools
+            .iter()
+            .map(|t| format!("{}: {}", t.name, t.description))
+            .collect();
+
+        let missing: Vec<String> = {
+            let cache = self.tool_embeddings.read().await;
+            texts
+                .iter()
+                .filter(|t| !cache.contains_key(*t))
+                .cloned()
+                .collect()
+        };
+        let mut computed = Vec::with_capacity(missing.len());
+        for text in missing {
+            let embedding = embed(text.clone()).await?;
+            computed.push((text, embedding));
+        }
+
+        let cache = if computed.is_empty() {
+            self.tool_embeddings.read().await
+        } else {
+            // The tool set changed; drop embeddings of tools that are gone
+            let mut cache = self.tool_embeddings.write().await;
+            cache.retain(|text, _| texts.contains(text));
+            cache.extend(computed);
+            cache.downgrade()
+        };
+
+        let mut scored: Vec<(usize, f32)> = texts
+            .iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let score = cache
+                    .get(text)
+                    .map_or(0.0, |e| crate::context::cosine_similarity(&query, e));
+                (i, score)
+            })
+            .collect();
+        drop(cache);
+
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let mut keep: Vec<usize> = scored.into_iter().take(top_k).map(|(i, _)| i).collect();
+        keep.sort_unstable();
+        Ok(keep.into_iter().map(|i| tools[i].clone()).collect())
+    }
+
+    /// Every tool the model is offered, sorted by server and name. Tools
+    /// the security policy denies are left out, since they never run.
+    pub async fn list_capabilities(&self) -> Vec<Capability> {
+        let mut sources = HashMap::new();
+        for (name, server) in self.servers.lock().await.iter() {
+            for tool in server.get_tools().await {
+                sources.entry(tool.name).or_insert_with(|| name.clone());
+            }
+        }
+
+        let mut capabilities = Vec::new();
+        for tool in self.prompt_tools().await {
+            let server = if is_evolution_tool(&tool.name) {
+                "mycel".to_string()
+            } else if self.is_file_tool(&tool.name) {
+                "files".to_string()
+            } else {
+                sources.get(&tool.name).cloned().unwrap_or_default()
+            };
+            let risk_level = self.assess_risk_level(&tool.name, &HashMap::new());
+            let decision = self
+                .policy
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .evaluate_tool_call(&tool.name, &HashMap::new(), risk_level);
+            let requires_confirmation = match decision {
+                ActionPolicy::Deny { .. } => continue,
+                ActionPolicy::RequiresConfirmation { .. } => true,
+                ActionPolicy::Allow if is_evolution_tool(&tool.name) => {
+                    self.config.evolution_require_confirmation
+                }
+                ActionPolicy::Allow => self.requires_confirmation(&tool.name).await,
+            };
+            capabilities.push(Capability {
+                name: tool.name,
+                description: tool.description,
+                server,
+                risk_level,
+                requires_confirmation,
+            });
+        }
+        capabilities.sort_by(|a, b| (&a.server, &a.name).cmp(&(&b.server, &b.name)));
+        capabilities
+    }
+
+    /// Tools offered to the model: server tools plus the enabled meta-tools
+    async fn prompt_tools(&self) -> Vec<McpTool> {
         let mut tools = self.get_all_tools().await;
 
+        if !self.config.evolution_enabled {
+            return tools;
+        }
+
         // Add meta-tools for evolution
         let meta_tools = vec![
             McpTool {
@@ -514,6 +1273,7 @@ server.connect(transport);".to_string(),
                     },
                     "required": ["name", "language", "code"]
                 }),
+                annotations: None,
             },
             McpTool {
                 name: "evolve_os_install_capability".to_string(),
@@ -527,38 +1287,169 @@ server.connect(transport);".to_string(),
                     },
                     "required": ["name", "language", "code"]
                 }),
+                annotations: None,
             }
         ];
 
-        tools.extend(meta_tools);
-        format_tools_for_prompt(&tools)
+        tools.extend(
+            meta_tools
+                .into_iter()
+                .filter(|t| self.tool_exposed(&t.name)),
+        );
+        tools
     }
 
     /// Process a tool call from parsed LLM response
-    pub async fn process_tool_call(&self, call: &ToolCall) -> Result<String> {
+    pub async fn process_tool_call(&self, call: &ToolCall) -> crate::error::Result<String> {
         info!(
             tool = %call.name,
             args = ?call.arguments,
             "Processing MCP tool call"
         );
 
-        if call.name == "evolve_os_add_capability" || call.name == "evolve_os_install_capability" {
-            let name = call.arguments.get("name").and_then(|v| v.as_str())
-                .ok_or_else(|| anyhow!("Missing 'name' argument"))?;
-            let lang = call.arguments.get("language").and_then(|v| v.as_str())
-                .ok_or_else(|| anyhow!("Missing 'language' argument"))?;
-            let code = call.arguments.get("code").and_then(|v| v.as_str())
-                .ok_or_else(|| anyhow!("Missing 'code' argument"))?;
+        if !self.tool_exposed(&call.name) {
+            return Err(Error::PolicyDenied(format!(
+                "Tool '{}' is not available (excluded by mcp.tool_allowlist/tool_denylist)",
+                call.name
+            )));
+        }
+
+        if self.config.read_only_tools && !self.is_read_only(&call.name).await {
+            return Err(Error::PolicyDenied(format!(
+                "Tool '{}' is unavailable: only read-only tools are allowed (mcp.read_only_tools = true)",
+                call.name
+            )));
+        }
 
-            let evolver = McpEvolver::new(self.clone(), &self.runtime_path);
-            evolver.init().await?;
-            return evolver.create_server(name, lang, code, true).await;
+        if is_evolution_tool(&call.name) {
+            if !self.config.evolution_enabled {
+                return Err(Error::PolicyDenied(format!(
+                    "Tool '{}' is unavailable: evolution is disabled (mcp.evolution_enabled = false)",
+                    call.name
+                )));
+            }
+            if self.config.evolution_require_confirmation {
+                return Ok(self.hold_for_review(call).await?);
+            }
+            return Ok(self.evolve(&call.arguments).await?);
         }
 
-        let result = self.call_tool(&call.name, call.arguments.clone()).await?;
+        let ttl = Duration::from_secs(self.config.tool_cache_ttl_secs);
+        if !ttl.is_zero() {
+            return Ok(self
+                .call_tool_cached(&call.name, call.arguments.clone(), ttl)
+                .await?);
+        }
+        let result = self
+            .call_tool_inner(&call.name, call.arguments.clone(), self.progress.clone())
+            .await?;
         Ok(format_tool_result(&call.name, &result))
     }
 
+    /// Create and start the MCP server described by an evolution call
+    async fn evolve(&self, arguments: &HashMap<String, serde_json::Value>) -> Result<String> {
+        let (name, lang, code) = evolution_arguments(arguments)?;
+        let evolver = McpEvolver::new(self.clone(), &self.runtime_path);
+        evolver.init().await?;
+        evolver.create_server(name, lang, code, true).await
+    }
+
+    /// Store an evolution call as a pending confirmation and return the
+    /// proposed code for the user to review
+    async fn hold_for_review(&self, call: &ToolCall) -> Result<String> {
+        let (name, lang, code) = evolution_arguments(&call.arguments)?;
+        let mut confirmation =
+            self.create_pending_confirmation(&call.name, call.arguments.clone())?;
+        confirmation.risk_level = RiskLevel::High;
+        let id = confirmation.id.clone();
+
+        info!("Holding new capability '{}' for review ({})", name, id);
+        let reply = format!(
+            "New capability '{}' is waiting for review (confirmation {}). Nothing has been \
+             installed yet. Proposed {} code:\n```{}\n{}\n```",
+            name, id, lang, lang, code
+        );
+        self.pending.write().await.insert(id, confirmation);
+        Ok(reply)
+    }
+
+    /// Hold `call` until the user answers it with `resolve_confirmation`
+    pub async fn hold_for_confirmation(&self, call: &ToolCall) -> Result<PendingConfirmation> {
+        let confirmation = self.create_pending_confirmation(&call.name, call.arguments.clone())?;
+        info!(
+            "Holding {} for confirmation ({})",
+            call.name, confirmation.id
+        );
+        self.pending
+            .write()
+            .await
+            .insert(confirmation.id.clone(), confirmation.clone());
+        Ok(confirmation)
+    }
+
+    /// How long a call waits for confirmation before it is cancelled
+    pub fn confirmation_ttl(&self) -> Duration {
+        Duration::from_secs(self.config.confirmation_ttl_secs)
+    }
+
+    /// Calls waiting for the user to confirm or reject them
+    pub async fn pending_confirmations(&self) -> Vec<PendingConfirmation> {
+        let ttl = self.confirmation_ttl();
+        self.pending
+            .read()
+            .await
+            .values()
+            .filter(|c| !c.is_expired(ttl))
+            .cloned()
+            .collect()
+    }
+
+    /// Drop confirmations past `confirmation_ttl_secs`, returning how many
+    pub async fn sweep_expired_confirmations(&self) -> usize {
+        let ttl = self.confirmation_ttl();
+        let mut pending = self.pending.write().await;
+        let before = pending.len();
+        pending.retain(|_, c| !c.is_expired(ttl));
+        let removed = before - pending.len();
+        if removed > 0 {
+            info!("Cancelled {} expired confirmations", removed);
+        }
+        removed
+    }
+
+    /// Answer a pending confirmation: run the held call if `approve`,
+    /// otherwise drop it. One past its TTL is cancelled either way.
+    pub async fn resolve_confirmation(&self, id: &str, approve: bool) -> Result<String> {
+        let confirmation = self
+            .pending
+            .write()
+            .await
+            .remove(id)
+            .ok_or_else(|| anyhow!("No pending confirmation with id '{}'", id))?;
+
+        if confirmation.is_expired(self.confirmation_ttl()) {
+            info!("Confirmation expired: {}", confirmation.description);
+            return Err(Error::ConfirmationExpired(confirmation.description).into());
+        }
+        if !approve {
+            info!("Rejected {}", confirmation.description);
+            return Ok(format!("Cancelled: {}", confirmation.description));
+        }
+
+        info!("Confirmed {}", confirmation.description);
+        if is_evolution_tool(&confirmation.tool_name) {
+            return self.evolve(&confirmation.arguments).await;
+        }
+        let result = self
+            .call_tool_inner(
+                &confirmation.tool_name,
+                confirmation.arguments,
+                self.progress.clone(),
+            )
+            .await?;
+        Ok(format_tool_result(&confirmation.tool_name, &result))
+    }
+
     /// Process multiple tool calls, handling confirmations
     pub async fn process_tool_calls_with_confirmation(
         &self,
@@ -569,21 +1460,27 @@ server.connect(transport);".to_string(),
 
         for call in calls {
             if self.requires_confirmation(&call.name).await {
-                pending.push(self.create_pending_confirmation(&call.name, call.arguments.clone()));
-                results.push(Ok(format!(
-                    "Tool '{}' requires confirmation before execution.",
-                    call.name
-                )));
+                match self.create_pending_confirmation(&call.name, call.arguments.clone()) {
+                    Ok(confirmation) => {
+                        pending.push(confirmation);
+                        results.push(Ok(format!(
+                            "Tool '{}' requires confirmation before execution.",
+                            call.name
+                        )));
+                    }
+                    Err(e) => results.push(Err(e)),
+                }
             } else {
-                results.push(self.process_tool_call(call).await);
+                results.push(self.process_tool_call(call).await.map_err(Into::into));
             }
         }
 
         (results, pending)
     }
 
-    /// Stop all MCP servers
+    /// Stop all MCP servers and background tasks
     pub async fn stop_all(&self) -> Result<()> {
+        self.shutdown.cancel();
         let mut servers = self.servers.lock().await;
 
         for (name, server) in servers.iter_mut() {
@@ -596,6 +1493,11 @@ server.connect(transport);".to_string(),
         Ok(())
     }
 
+    /// Whether `stop_all` has been called
+    pub fn is_stopped(&self) -> bool {
+        self.shutdown.is_cancelled()
+    }
+
     /// Check if MCP is enabled and has active servers
     pub async fn is_active(&self) -> bool {
         if !self.config.enabled {
@@ -638,47 +1540,377 @@ server.connect(transport);".to_string(),
     }
 }
 
-/// Create default MCP configuration for Void Linux tools
-pub fn default_void_tools_config(runtime_path: &str) -> McpConfig {
-    McpConfig {
-        enabled: true,
-        servers: vec![
-            McpServerConfig {
-                name: "void-tools".to_string(),
-                command: "python3".to_string(),
-                args: vec![format!("{}/mcp-servers/void-tools/void_tools.py", runtime_path)],
-                env: HashMap::new(),
-                requires_confirmation: vec![
-                    "xbps_install".to_string(),
-                    "xbps_remove".to_string(),
-                    "service_control".to_string(),
-                ],
-            },
-            // TODO: Add near-identity server when implemented
-            // TODO: Add web-tools server when implemented
-        ],
+/// Expand `${VAR}` and `$VAR` in `value` using `lookup`. `$$` is a literal `$`,
+/// and substituted values are not expanded again. An undefined variable is an
+/// error unless `allow_undefined` is set, in which case it expands to "".
+fn expand_env_vars(
+    value: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+    allow_undefined: bool,
+) -> Result<String> {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+
+        let (name, remainder) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => return Err(anyhow!("Unterminated '${{' in '{}'", value)),
+            }
+        } else if let Some(escaped) = after.strip_prefix('$') {
+            out.push('$');
+            rest = escaped;
+            continue;
+        } else {
+            let end = after.find(|c: char| !is_name_char(c)).unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            // Not a variable reference, keep the '$' as written
+            out.push('$');
+            rest = after;
+            continue;
+        }
+
+        match lookup(name) {
+            Some(val) => out.push_str(&val),
+            None if allow_undefined => {}
+            None => return Err(anyhow!("Environment variable '{}' is not set", name)),
+        }
+        rest = remainder;
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Health-check one server, restarting it if needed.
+///
+/// Servers that have used up their restart attempts are skipped; the
+/// moment that happens `McpServerFailed` is emitted once.
+async fn check_server_health(
+    name: &str,
+    server: &mut McpServer,
+    event_bus: &broadcast::Sender<SystemEvent>,
+) {
+    if server.restarts_exhausted() || server.health_check().await {
+        return;
+    }
+
+    warn!("[{}] Health check failed, attempting restart", name);
+    match server.restart_if_needed().await {
+        Ok(true) => {
+            info!("[{}] Server restarted successfully", name);
+            let _ = event_bus.send(SystemEvent::McpServerRestarted {
+                name: name.to_string(),
+            });
+        }
+        Ok(false) => {}
+        Err(e) => {
+            warn!("[{}] Failed to restart: {}", name, e);
+            if server.restarts_exhausted() {
+                let _ = event_bus.send(SystemEvent::McpServerFailed {
+                    name: name.to_string(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+    }
+}
+
+/// Check that an xbps package name is safe to hand to the package manager.
+///
+/// The void-tools server builds shell commands from it, so only
+/// `[a-zA-Z0-9._+-]` is allowed, and a leading `-` is refused so the name
+/// can't be read as an option.
+pub fn validate_package_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(anyhow!("Package name is empty"));
+    }
+    if name.starts_with('-') {
+        return Err(anyhow!(
+            "Invalid package name '{}': must not start with '-'",
+            name
+        ));
+    }
+    if let Some(bad) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '+' | '-')))
+    {
+        return Err(anyhow!(
+            "Invalid package name '{}': character {:?} is not allowed",
+            name,
+            bad
+        ));
+    }
+    Ok(())
+}
+
+/// Validate the arguments of tools that pass them on to a shell
+fn validate_tool_arguments(
+    tool_name: &str,
+    arguments: &HashMap<String, serde_json::Value>,
+) -> Result<()> {
+    if let "xbps_install" | "xbps_remove" | "xbps_info" = tool_name {
+        let package = arguments
+            .get("package")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("Tool '{}' requires a 'package' argument", tool_name))?;
+        validate_package_name(package)?;
+    }
+    Ok(())
+}
+
+fn is_evolution_tool(name: &str) -> bool {
+    matches!(
+        name,
+        "evolve_os_add_capability" | "evolve_os_install_capability"
+    )
+}
+
+/// The `name`, `language` and `code` arguments of an evolution call
+fn evolution_arguments(
+    arguments: &HashMap<String, serde_json::Value>,
+) -> Result<(&str, &str, &str)> {
+    let arg = |key: &str| {
+        arguments
+            .get(key)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("Missing '{}' argument", key))
+    };
+    Ok((arg("name")?, arg("language")?, arg("code")?))
+}
+
+/// Servers bundled with Mycel that `mcp.enabled_builtins` can turn on by name
+pub const BUILTIN_SERVERS: &[&str] = &["void-tools", "near-identity", "web-tools", "filesystem"];
+
+/// Server config for a bundled server, or `None` if `name` isn't one
+pub fn builtin_server_config(name: &str, runtime_path: &str) -> Option<McpServerConfig> {
+    let strings = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let bundled = |script: &str| vec![format!("{}/mcp-servers/{}", runtime_path, script)];
+    let (command, args, requires_confirmation) = match name {
+        "void-tools" => (
+            "python3",
+            bundled("void-tools/void_tools.py"),
+            strings(&["xbps_install", "xbps_remove", "service_control"]),
+        ),
+        "near-identity" => (
+            "node",
+            bundled("near-identity/index.js"),
+            strings(&["near_register_device", "near_publish_capability"]),
+        ),
+        // Search and page reads only; nothing to confirm
+        "web-tools" => (
+            "python3",
+            bundled("web-tools/web_tools.py"),
+            Vec::new(),
+        ),
+        "filesystem" => (
+            "npx",
+            strings(&["-y", "@modelcontextprotocol/server-filesystem", "$HOME"]),
+            strings(&["write_file", "edit_file", "move_file", "create_directory"]),
+        ),
+        _ => return None,
+    };
+
+    Some(McpServerConfig {
+        name: name.to_string(),
+        command: command.to_string(),
+        args,
+        env: HashMap::new(),
+        requires_confirmation,
+        tool_timeout_secs: None,
+        tool_timeouts: HashMap::new(),
+        idempotent_tools: HashMap::new(),
+    })
+}
+
+/// Servers to run: the enabled builtins followed by the user's own servers.
+///
+/// A user server with a builtin's name replaces that builtin. With nothing
+/// configured at all, void-tools is started as before.
+pub fn resolve_servers(config: &McpConfig, runtime_path: &str) -> Vec<McpServerConfig> {
+    let mut names: Vec<&str> = config.enabled_builtins.iter().map(String::as_str).collect();
+    if names.is_empty() && config.servers.is_empty() {
+        names.push("void-tools");
+    }
+
+    let mut servers: Vec<McpServerConfig> = names
+        .into_iter()
+        .filter(|name| !config.servers.iter().any(|s| s.name == *name))
+        .filter_map(|name| {
+            let server = builtin_server_config(name, runtime_path);
+            if server.is_none() {
+                warn!("Unknown builtin MCP server '{}'", name);
+            }
+            server
+        })
+        .collect();
+    servers.extend(config.servers.iter().cloned());
+    servers
+}
+
+/// Test MCP servers, shared with tests in other modules
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// MCP server counting its `tools/call` requests
+    pub(crate) const COUNTING_SERVER: &str = r#"
+import json, sys
+
+hits = 0
+for line in sys.stdin:
+    msg = json.loads(line)
+    if "id" not in msg:
+        continue
+    method = msg["method"]
+    if method == "initialize":
+        result = {"protocolVersion": "2024-11-05", "capabilities": {"tools": {}},
+                  "serverInfo": {"name": "counter", "version": "0.1"}}
+    elif method == "tools/list":
+        result = {"tools": [{"name": "system_info", "description": "info",
+                             "inputSchema": {"type": "object"},
+                             "annotations": {"readOnlyHint": True}}]}
+    elif method == "tools/call":
+        hits += 1
+        result = {"content": [{"type": "text", "text": "hits=%d" % hits}]}
+    else:
+        result = {}
+    print(json.dumps({"jsonrpc": "2.0", "id": msg["id"], "result": result}), flush=True)
+"#;
+
+    /// Write a minimal stdio MCP server exposing `system_info`, which reports
+    /// how many times it has been called
+    pub(crate) fn write_counting_server(dir: &Path) -> McpServerConfig {
+        write_server(dir, "counter", COUNTING_SERVER)
+    }
+
+    /// Write the Python `script` into `dir` as the stdio MCP server `name`,
+    /// with default settings
+    pub(crate) fn write_server(dir: &Path, name: &str, script: &str) -> McpServerConfig {
+        std::fs::create_dir_all(dir).unwrap();
+        let path = dir.join(format!("{}.py", name));
+        std::fs::write(&path, script).unwrap();
+
+        McpServerConfig {
+            name: name.to_string(),
+            command: "python3".to_string(),
+            args: vec![path.to_string_lossy().to_string()],
+            env: HashMap::new(),
+            requires_confirmation: Vec::new(),
+            tool_timeout_secs: None,
+            tool_timeouts: HashMap::new(),
+            idempotent_tools: HashMap::new(),
+        }
+    }
+
+    /// A fresh directory path under the system temp dir
+    pub(crate) fn temp_dir(prefix: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}", prefix, uuid::Uuid::new_v4()))
+    }
+
+    /// Manager over `config` with `servers` started, their scripts written
+    /// under `dir`. Dropping it stops the servers and removes `dir`.
+    pub(crate) struct TestManager {
+        manager: McpManager,
+        dir: PathBuf,
+    }
+
+    impl TestManager {
+        pub(crate) async fn start(
+            dir: &Path,
+            config: McpConfig,
+            servers: &[McpServerConfig],
+        ) -> Self {
+            let (tx, _) = broadcast::channel(16);
+            let manager = McpManager::new(&config, "/tmp", tx).await.unwrap();
+            for server in servers {
+                manager.start_server(server).await.unwrap();
+            }
+            Self {
+                manager,
+                dir: dir.to_path_buf(),
+            }
+        }
+    }
+
+    impl std::ops::Deref for TestManager {
+        type Target = McpManager;
+
+        fn deref(&self) -> &McpManager {
+            &self.manager
+        }
+    }
+
+    impl Drop for TestManager {
+        fn drop(&mut self) {
+            stop_now(&self.manager);
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Synchronous `stop_all` for use from `Drop`: ends the background
+    /// tasks, and each server process is killed as its handle is dropped
+    pub(crate) fn stop_now(manager: &McpManager) {
+        manager.shutdown.cancel();
+        if let Ok(mut servers) = manager.servers.try_lock() {
+            servers.clear();
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::testing::{
+        temp_dir, write_counting_server, write_server, TestManager, COUNTING_SERVER,
+    };
     use super::*;
 
     #[test]
     fn test_default_config() {
-        let config = default_void_tools_config("/path/to/runtime");
+        // Nothing configured starts void-tools alone
+        let config = McpConfig::default();
+        let servers = resolve_servers(&config, "/path/to/runtime");
 
         assert!(config.enabled);
-        assert_eq!(config.servers.len(), 1);
-        assert_eq!(config.servers[0].name, "void-tools");
-        assert!(config.servers[0].requires_confirmation.contains(&"xbps_install".to_string()));
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].name, "void-tools");
+        assert!(servers[0].requires_confirmation.contains(&"xbps_install".to_string()));
+    }
+
+    #[test]
+    fn test_enabled_builtins() {
+        let config = McpConfig {
+            enabled_builtins: vec!["web-tools".to_string(), "near-identity".to_string()],
+            ..Default::default()
+        };
+
+        let servers = resolve_servers(&config, "/opt/mycel");
+        let names: Vec<&str> = servers.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["web-tools", "near-identity"]);
+        assert!(servers[0].requires_confirmation.is_empty());
+        assert_eq!(
+            servers[1].requires_confirmation,
+            vec!["near_register_device", "near_publish_capability"]
+        );
+        assert_eq!(
+            servers[1].args,
+            vec!["/opt/mycel/mcp-servers/near-identity/index.js"]
+        );
     }
 
     #[tokio::test]
     async fn test_manager_creation() {
         let config = McpConfig {
             enabled: false,
-            servers: vec![],
+            ..Default::default()
         };
 
         let (tx, _) = tokio::sync::broadcast::channel(1);
@@ -686,6 +1918,321 @@ mod tests {
         assert!(!manager.is_active().await);
     }
 
+    #[tokio::test]
+    async fn test_risk_level_considers_arguments() {
+        let (tx, _) = tokio::sync::broadcast::channel(1);
+        let manager = McpManager::new(&McpConfig::default(), "/tmp", tx)
+            .await
+            .unwrap();
+        let args = |pairs: &[(&str, &str)]| -> HashMap<String, serde_json::Value> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), serde_json::json!(v)))
+                .collect()
+        };
+
+        assert_eq!(
+            manager.assess_risk_level("shell_command", &args(&[("command", "ls -la")])),
+            RiskLevel::Medium
+        );
+        assert_eq!(
+            manager.assess_risk_level(
+                "shell_command",
+                &args(&[("command", "dd if=/dev/zero of=/dev/sda")])
+            ),
+            RiskLevel::High
+        );
+        assert_eq!(
+            manager.assess_risk_level("shell_command", &args(&[("command", "RM -RF /home")])),
+            RiskLevel::High
+        );
+
+        assert_eq!(
+            manager.assess_risk_level("xbps_remove", &args(&[("package", "cowsay")])),
+            RiskLevel::Medium
+        );
+        assert_eq!(
+            manager.assess_risk_level("xbps_remove", &args(&[("package", "glibc")])),
+            RiskLevel::High
+        );
+        assert_eq!(
+            manager.assess_risk_level(
+                "service_control",
+                &args(&[("service", "sshd"), ("action", "stop")])
+            ),
+            RiskLevel::High
+        );
+        assert_eq!(
+            manager.assess_risk_level(
+                "service_control",
+                &args(&[("service", "cupsd"), ("action", "stop")])
+            ),
+            RiskLevel::Medium
+        );
+    }
+
+    #[test]
+    fn test_package_name_validation() {
+        assert!(validate_package_name("firefox").is_ok());
+        assert!(validate_package_name("gtk+3-devel_1.2").is_ok());
+
+        assert!(validate_package_name("vim;rm -rf /").is_err());
+        assert!(validate_package_name("vim rm").is_err());
+        assert!(validate_package_name("$(reboot)").is_err());
+        assert!(validate_package_name("--yes").is_err());
+        assert!(validate_package_name("").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_package_blocks_confirmation() {
+        let (tx, _) = tokio::sync::broadcast::channel(1);
+        let manager = McpManager::new(&McpConfig::default(), "/tmp", tx).await.unwrap();
+
+        let args =
+            |package: &str| HashMap::from([("package".to_string(), serde_json::json!(package))]);
+
+        let confirmation = manager
+            .create_pending_confirmation("xbps_install", args("htop"))
+            .unwrap();
+        assert_eq!(confirmation.description, "Install package: htop");
+
+        assert!(manager
+            .create_pending_confirmation("xbps_install", args("htop;rm -rf /"))
+            .is_err());
+        assert!(manager
+            .create_pending_confirmation("xbps_remove", args("htop vim"))
+            .is_err());
+
+        let err = manager
+            .call_tool("xbps_install", args("htop;rm -rf /"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid package name"));
+    }
+
+    #[tokio::test]
+    async fn test_evolution_disabled() {
+        let (tx, _) = tokio::sync::broadcast::channel(1);
+
+        let enabled = McpManager::new(&McpConfig::default(), "/tmp", tx.clone())
+            .await
+            .unwrap();
+        assert!(enabled
+            .get_tools_prompt()
+            .await
+            .contains("evolve_os_add_capability"));
+
+        let config = McpConfig {
+            evolution_enabled: false,
+            ..Default::default()
+        };
+        let manager = McpManager::new(&config, "/tmp", tx).await.unwrap();
+
+        let prompt = manager.get_tools_prompt().await;
+        assert!(!prompt.contains("evolve_os_add_capability"));
+        assert!(!prompt.contains("evolve_os_install_capability"));
+
+        let call = ToolCall {
+            name: "evolve_os_add_capability".to_string(),
+            arguments: HashMap::from([
+                ("name".to_string(), serde_json::json!("weather-tools")),
+                ("language".to_string(), serde_json::json!("python")),
+                ("code".to_string(), serde_json::json!("print('hi')")),
+            ]),
+        };
+        let err = manager.process_tool_call(&call).await.unwrap_err();
+        assert!(err.to_string().contains("evolution is disabled"));
+    }
+
+    #[tokio::test]
+    async fn test_read_only_tools() {
+        let (tx, _) = tokio::sync::broadcast::channel(1);
+        let config = McpConfig {
+            read_only_tools: true,
+            ..Default::default()
+        };
+        let manager = McpManager::new(&config, "/tmp", tx).await.unwrap();
+
+        let names: Vec<String> = manager
+            .get_all_tools()
+            .await
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(names, vec!["file_read", "file_list"]);
+
+        let call = ToolCall {
+            name: "file_write".to_string(),
+            arguments: HashMap::from([
+                ("path".to_string(), serde_json::json!("/tmp/mycel-read-only")),
+                ("content".to_string(), serde_json::json!("x")),
+            ]),
+        };
+        let err = manager.process_tool_call(&call).await.unwrap_err();
+        assert!(err.to_string().contains("only read-only tools"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_file_tools_record_access_in_session() {
+        let dir = std::env::temp_dir().join(format!("mycel-access-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("notes.txt"), "hello").unwrap();
+        let notes = dir.join("notes.txt").canonicalize().unwrap();
+        let notes = notes.to_string_lossy().to_string();
+
+        let config = crate::config::MycelConfig {
+            context_path: dir.join("context").to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let context_manager = crate::context::ContextManager::new(&config).await.unwrap();
+        context_manager.get_context("s").await.unwrap();
+        context_manager
+            .set_working_directory("s", &dir.to_string_lossy())
+            .await
+            .unwrap();
+        let (tx, _) = tokio::sync::broadcast::channel(16);
+        let manager = McpManager::new(&config.mcp, "/tmp", tx)
+            .await
+            .unwrap()
+            .with_session(ToolSession {
+                id: "s".to_string(),
+                context_manager: context_manager.clone(),
+            });
+
+        // Relative to the session's directory, not the daemon's
+        let args = |path: &str| HashMap::from([("path".to_string(), serde_json::json!(path))]);
+        let read = manager
+            .call_tool("file_read", args("notes.txt"))
+            .await
+            .unwrap();
+        assert!(matches!(&read.content[0], protocol::ToolContent::Text { text } if text == "hello"));
+        manager
+            .call_tool("file_list", args(&dir.to_string_lossy()))
+            .await
+            .unwrap();
+        assert!(manager
+            .call_tool("file_read", args("/nonexistent/x"))
+            .await
+            .is_err());
+
+        // Only the successful read counts
+        let context = context_manager.get_context("s").await.unwrap();
+        assert_eq!(context.recent_files, vec![notes]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_new_capability_waits_for_confirmation() {
+        let runtime = std::env::temp_dir().join(format!("mycel-evolve-{}", uuid::Uuid::new_v4()));
+        let (tx, _) = tokio::sync::broadcast::channel(16);
+        let manager = McpManager::new(&McpConfig::default(), &runtime.to_string_lossy(), tx)
+            .await
+            .unwrap();
+
+        let call = ToolCall {
+            name: "evolve_os_add_capability".to_string(),
+            arguments: HashMap::from([
+                ("name".to_string(), serde_json::json!("counter")),
+                ("language".to_string(), serde_json::json!("python")),
+                ("code".to_string(), serde_json::json!(COUNTING_SERVER)),
+            ]),
+        };
+        let reply = manager.process_tool_call(&call).await.unwrap();
+        assert!(reply.contains("waiting for review"));
+        assert!(reply.contains("tools/call"));
+
+        let server_dir = runtime.join("mcp-servers/dynamic/counter");
+        assert!(!server_dir.exists());
+
+        let pending = manager.pending_confirmations().await;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].risk_level, RiskLevel::High);
+
+        manager
+            .resolve_confirmation(&pending[0].id, true)
+            .await
+            .unwrap();
+        assert!(server_dir.join("server.py").exists());
+        assert!(manager.pending_confirmations().await.is_empty());
+        assert!(manager
+            .resolve_confirmation(&pending[0].id, true)
+            .await
+            .is_err());
+
+        manager.stop_all().await.unwrap();
+        let _ = std::fs::remove_dir_all(runtime);
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_server_tool_is_listed() {
+        let dir = temp_dir("mycel-caps");
+        let server = write_counting_server(&dir);
+        let config = McpConfig {
+            file_tools_enabled: false,
+            ..Default::default()
+        };
+        let manager = TestManager::start(&dir, config, &[]).await;
+
+        let names = |caps: &[Capability]| caps.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+        let before = manager.list_capabilities().await;
+        assert_eq!(
+            names(&before),
+            ["evolve_os_add_capability", "evolve_os_install_capability"]
+        );
+        assert!(before
+            .iter()
+            .all(|c| c.server == "mycel" && c.requires_confirmation));
+
+        manager
+            .add_dynamic_server("counter", &server.command, server.args.clone())
+            .await
+            .unwrap();
+        let after = manager.list_capabilities().await;
+        let tool = after.iter().find(|c| c.name == "system_info").unwrap();
+        assert_eq!(tool.server, "counter");
+        assert_eq!(tool.description, "info");
+        assert_eq!(tool.risk_level, RiskLevel::Low);
+        assert!(!tool.requires_confirmation);
+    }
+
+    #[tokio::test]
+    async fn test_stop_all_ends_background_tasks() {
+        let config = McpConfig::default();
+        let (tx, _) = tokio::sync::broadcast::channel(1);
+        let manager = McpManager::new(&config, "/tmp", tx).await.unwrap();
+
+        let mut health_check = manager.spawn_health_check_task();
+        let shutdown = CancellationToken::new();
+
+        // Mirrors main: the signal handler cancels, then shutdown stops MCP
+        let waiter = {
+            let manager = manager.clone();
+            let shutdown = shutdown.clone();
+            tokio::spawn(async move {
+                shutdown.cancelled().await;
+                manager.stop_all().await
+            })
+        };
+
+        // The task keeps running across its first tick until stop_all
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), &mut health_check)
+                .await
+                .is_err(),
+            "health check task ended before stop_all"
+        );
+        assert!(!health_check.is_finished());
+
+        shutdown.cancel();
+        waiter.await.unwrap().unwrap();
+
+        tokio::time::timeout(Duration::from_secs(1), health_check)
+            .await
+            .expect("health check task should exit after stop_all")
+            .unwrap();
+    }
+
     #[test]
     fn test_cache_key() {
         let mut args = HashMap::new();
@@ -700,13 +2247,546 @@ mod tests {
         assert_ne!(key1, key3);
     }
 
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/mycel".to_string()),
+            "MY_KEY" => Some("secret".to_string()),
+            "NESTED" => Some("$HOME".to_string()),
+            _ => None,
+        }
+    }
+
     #[test]
-    fn test_risk_assessment() {
+    fn test_expand_env_defined() {
+        assert_eq!(
+            expand_env_vars("$HOME/data", lookup, false).unwrap(),
+            "/home/mycel/data"
+        );
+        assert_eq!(
+            expand_env_vars("key=${MY_KEY}!", lookup, false).unwrap(),
+            "key=secret!"
+        );
+        assert_eq!(expand_env_vars("--verbose", lookup, false).unwrap(), "--verbose");
+    }
+
+    #[test]
+    fn test_expand_env_undefined() {
+        let err = expand_env_vars("${MISSING}", lookup, false).unwrap_err();
+        assert!(err.to_string().contains("MISSING"));
+        assert_eq!(expand_env_vars("a${MISSING}b", lookup, true).unwrap(), "ab");
+        assert!(expand_env_vars("${MY_KEY", lookup, false).is_err());
+    }
+
+    #[test]
+    fn test_expand_env_literals() {
+        // Substituted values are not expanded again
+        assert_eq!(expand_env_vars("${NESTED}", lookup, false).unwrap(), "$HOME");
+        assert_eq!(expand_env_vars("$$HOME", lookup, false).unwrap(), "$HOME");
+        assert_eq!(expand_env_vars("cost: $5 $", lookup, false).unwrap(), "cost: $5 $");
+    }
+
+    #[tokio::test]
+    async fn test_failing_server_stops_restarting_after_cap() {
+        let mut server = McpServer::with_config(
+            "broken".to_string(),
+            "python3".to_string(),
+            vec!["-c".to_string(), "import sys; sys.exit(1)".to_string()],
+            HashMap::new(),
+            vec![],
+            client::ServerConfig {
+                init_timeout: Duration::from_secs(5),
+                max_restart_attempts: 2,
+                restart_delay: Duration::from_millis(10),
+                ..Default::default()
+            },
+        );
+        assert!(server.start().await.is_err());
+
+        let (tx, mut rx) = broadcast::channel(16);
+        for _ in 0..5 {
+            check_server_health("broken", &mut server, &tx).await;
+        }
+
+        assert!(server.restarts_exhausted());
+        assert_eq!(server.health().await.restart_count, 2);
+        assert!(matches!(server.state().await, ServerState::Failed(_)));
+
+        let mut failed = 0;
+        while let Ok(event) = rx.try_recv() {
+            match event {
+                SystemEvent::McpServerFailed { name, .. } => {
+                    assert_eq!(name, "broken");
+                    failed += 1;
+                }
+                other => panic!("unexpected event: {:?}", other),
+            }
+        }
+        assert_eq!(failed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_parallel_calls_deduplicated() {
+        let dir = temp_dir("mycel-mcp");
+        let server = write_counting_server(&dir);
         let config = McpConfig {
-            enabled: false,
-            servers: vec![],
+            servers: vec![server.clone()],
+            ..Default::default()
+        };
+        let manager = TestManager::start(&dir, config, &[server]).await;
+
+        let call = ToolCall {
+            name: "system_info".to_string(),
+            arguments: HashMap::new(),
+        };
+        let results = manager.call_tools_parallel(&[call.clone(), call]).await;
+
+        assert_eq!(results.len(), 2);
+        for result in &results {
+            assert!(result.as_ref().unwrap().contains("hits=1"));
+        }
+        let log = manager.audit_log.read().await;
+        assert_eq!(log.iter().filter(|e| e.tool_name == "system_info").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_tool_progress_is_streamed() {
+        let script = r#"
+import json, sys
+
+for line in sys.stdin:
+    msg = json.loads(line)
+    if "id" not in msg:
+        continue
+    method = msg["method"]
+    if method == "initialize":
+        result = {"protocolVersion": "2024-11-05", "capabilities": {"tools": {}},
+                  "serverInfo": {"name": "builder", "version": "0.1"}}
+    elif method == "tools/list":
+        result = {"tools": [{"name": "build", "description": "build",
+                             "inputSchema": {"type": "object"}}]}
+    elif method == "tools/call":
+        token = msg["params"].get("_meta", {}).get("progressToken")
+        if token is not None:
+            for step in (1, 2):
+                print(json.dumps({"jsonrpc": "2.0", "method": "notifications/progress",
+                                  "params": {"progressToken": token, "progress": step,
+                                             "total": 2, "message": "step %d" % step}}),
+                      flush=True)
+        result = {"content": [{"type": "text", "text": "built"}]}
+    else:
+        result = {}
+    print(json.dumps({"jsonrpc": "2.0", "id": msg["id"], "result": result}), flush=True)
+"#;
+        let dir = temp_dir("mycel-mcp");
+        let server = write_server(&dir, "builder", script);
+        let manager = TestManager::start(&dir, McpConfig::default(), &[server]).await;
+
+        // The chat path: tool calls through a progress-carrying handle
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel();
+        let call = ToolCall {
+            name: "build".to_string(),
+            arguments: HashMap::new(),
+        };
+        let output = manager
+            .with_progress(progress_tx)
+            .process_tool_call(&call)
+            .await
+            .unwrap();
+        assert!(output.contains("built"));
+
+        let mut messages = Vec::new();
+        while let Ok(update) = progress_rx.try_recv() {
+            assert_eq!(update.tool, "build");
+            assert_eq!(update.total, Some(2.0));
+            messages.push(update.message.unwrap());
+        }
+        assert_eq!(messages, vec!["step 1", "step 2"]);
+
+        // Without a listener the call behaves as before
+        let result = manager.call_tool("build", HashMap::new()).await.unwrap();
+        assert!(!result.is_error);
+    }
+
+    #[tokio::test]
+    async fn test_tool_allow_and_deny_lists() {
+        let dir = temp_dir("mycel-mcp");
+        let server = write_counting_server(&dir);
+
+        let config = McpConfig {
+            servers: vec![server.clone()],
+            tool_denylist: vec!["system_info".to_string()],
+            ..Default::default()
+        };
+        let manager = TestManager::start(&dir, config, std::slice::from_ref(&server)).await;
+
+        assert!(!manager.get_tools_prompt().await.contains("system_info"));
+        let call = ToolCall {
+            name: "system_info".to_string(),
+            arguments: HashMap::new(),
+        };
+        let err = manager.process_tool_call(&call).await.unwrap_err();
+        assert!(err.to_string().contains("not available"), "{}", err);
+
+        // An allowlist hides everything else, meta-tools included
+        let config = McpConfig {
+            servers: vec![server.clone()],
+            tool_allowlist: vec!["system_info".to_string()],
+            ..Default::default()
+        };
+        let manager = TestManager::start(&dir, config, &[server]).await;
+
+        let prompt = manager.get_tools_prompt().await;
+        assert!(prompt.contains("system_info"));
+        assert!(!prompt.contains("evolve_os_add_capability"));
+        assert!(manager.process_tool_call(&call).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_resources_from_supporting_servers() {
+        let script = r#"
+import json, sys
+
+for line in sys.stdin:
+    msg = json.loads(line)
+    if "id" not in msg:
+        continue
+    method = msg["method"]
+    if method == "initialize":
+        result = {"protocolVersion": "2024-11-05",
+                  "capabilities": {"tools": {}, "resources": {}},
+                  "serverInfo": {"name": "docs", "version": "0.1"}}
+    elif method == "tools/list":
+        result = {"tools": []}
+    elif method == "resources/list":
+        result = {"resources": [{"uri": "docs://readme", "name": "README",
+                                 "mimeType": "text/markdown"}]}
+    elif method == "resources/read":
+        result = {"contents": [{"uri": msg["params"]["uri"], "mimeType": "text/markdown",
+                                "text": "Mycel docs"}]}
+    else:
+        result = {}
+    print(json.dumps({"jsonrpc": "2.0", "id": msg["id"], "result": result}), flush=True)
+"#;
+        let dir = temp_dir("mycel-mcp");
+        let docs = write_server(&dir, "docs", script);
+        // Advertises no resources, so it is never asked for any
+        let counter = write_counting_server(&dir);
+        let manager = TestManager::start(&dir, McpConfig::default(), &[docs, counter]).await;
+
+        let resources = manager.list_resources().await;
+        assert_eq!(resources.len(), 1);
+        assert_eq!(resources[0].server, "docs");
+        assert_eq!(resources[0].resource.uri, "docs://readme");
+
+        let contents = manager.read_resource("docs://readme").await.unwrap();
+        assert_eq!(contents[0].text.as_deref(), Some("Mycel docs"));
+        assert!(manager.read_resource("docs://missing").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_hung_tool_times_out() {
+        let script = r#"
+import json, sys, time
+
+for line in sys.stdin:
+    msg = json.loads(line)
+    if "id" not in msg:
+        continue
+    method = msg["method"]
+    if method == "initialize":
+        result = {"protocolVersion": "2024-11-05", "capabilities": {"tools": {}},
+                  "serverInfo": {"name": "sleeper", "version": "0.1"}}
+    elif method == "tools/list":
+        result = {"tools": [{"name": "hang", "description": "never answers",
+                             "inputSchema": {"type": "object"}}]}
+    elif method == "tools/call":
+        time.sleep(60)
+        result = {"content": []}
+    else:
+        result = {}
+    print(json.dumps({"jsonrpc": "2.0", "id": msg["id"], "result": result}), flush=True)
+"#;
+        let dir = temp_dir("mycel-mcp");
+        let server = McpServerConfig {
+            tool_timeout_secs: Some(30),
+            // The per-tool override wins over the server's 30s
+            tool_timeouts: HashMap::from([("hang".to_string(), 1)]),
+            ..write_server(&dir, "sleeper", script)
+        };
+        let manager = TestManager::start(&dir, McpConfig::default(), &[server]).await;
+
+        let start = Instant::now();
+        let call = ToolCall {
+            name: "hang".to_string(),
+            arguments: HashMap::new(),
+        };
+        let err = manager.process_tool_call(&call).await.unwrap_err();
+        assert!(matches!(err, Error::ToolTimeout(d) if d == Duration::from_secs(1)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let audit = manager.get_audit_log(1).await;
+        assert!(!audit[0].success);
+        assert_eq!(audit[0].server_name, "sleeper");
+    }
+
+    #[tokio::test]
+    async fn test_only_idempotent_tools_are_cached_and_retried() {
+        // Each tool's first call stalls past its 1s timeout
+        let script = r#"
+import json, sys, time
+
+hits = {}
+for line in sys.stdin:
+    msg = json.loads(line)
+    if "id" not in msg:
+        continue
+    method = msg["method"]
+    if method == "initialize":
+        result = {"protocolVersion": "2024-11-05", "capabilities": {"tools": {}},
+                  "serverInfo": {"name": "flaky", "version": "0.1"}}
+    elif method == "tools/list":
+        result = {"tools": [
+            {"name": "lookup", "description": "read", "inputSchema": {"type": "object"},
+             "annotations": {"idempotentHint": True}},
+            {"name": "append", "description": "write", "inputSchema": {"type": "object"}}]}
+    elif method == "tools/call":
+        name = msg["params"]["name"]
+        hits[name] = hits.get(name, 0) + 1
+        if hits[name] == 1:
+            time.sleep(1.5)
+        result = {"content": [{"type": "text", "text": "%s=%d" % (name, hits[name])}]}
+    else:
+        result = {}
+    print(json.dumps({"jsonrpc": "2.0", "id": msg["id"], "result": result}), flush=True)
+"#;
+        let dir = temp_dir("mycel-mcp");
+        let server = McpServerConfig {
+            tool_timeout_secs: Some(1),
+            ..write_server(&dir, "flaky", script)
+        };
+        let manager = TestManager::start(&dir, McpConfig::default(), &[server]).await;
+        assert!(manager.is_idempotent("lookup").await);
+        assert!(!manager.is_idempotent("append").await);
+        // Idempotent doesn't make it read-only
+        assert!(!manager.is_read_only("lookup").await);
+
+        // The stalled call is retried, then the answer is cached
+        let ttl = Duration::from_secs(60);
+        let first = manager
+            .call_tool_cached("lookup", HashMap::new(), ttl)
+            .await
+            .unwrap();
+        assert!(first.contains("lookup=2"), "{}", first);
+        let repeat = manager
+            .call_tool_cached("lookup", HashMap::new(), ttl)
+            .await
+            .unwrap();
+        assert_eq!(first, repeat);
+
+        // The stalled call fails outright, and every call reaches the server
+        let err = manager
+            .call_tool_cached("append", HashMap::new(), ttl)
+            .await
+            .unwrap_err();
+        assert!(matches!(Error::find(&err), Some(Error::ToolTimeout(_))));
+        for expected in ["append=2", "append=3"] {
+            let output = manager
+                .call_tool_cached("append", HashMap::new(), ttl)
+                .await
+                .unwrap();
+            assert!(output.contains(expected), "{}", output);
+        }
+        assert_eq!(manager.cache_stats().await.entries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_cache_stats() {
+        let dir = temp_dir("mycel-mcp");
+        let server = write_counting_server(&dir);
+        let config = McpConfig {
+            servers: vec![server.clone()],
+            ..Default::default()
+        };
+        let manager = TestManager::start(&dir, config, &[server]).await;
+
+        let ttl = Duration::from_secs(60);
+        let args = |n: i64| HashMap::from([("n".to_string(), serde_json::json!(n))]);
+
+        let first = manager
+            .call_tool_cached("system_info", args(1), ttl)
+            .await
+            .unwrap();
+        let repeat = manager
+            .call_tool_cached("system_info", args(1), ttl)
+            .await
+            .unwrap();
+        assert_eq!(first, repeat);
+        let stats = manager.cache_stats().await;
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+
+        manager
+            .call_tool_cached("system_info", args(2), ttl)
+            .await
+            .unwrap();
+        let stats = manager.cache_stats().await;
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 2, 2));
+        assert!((stats.hit_rate - 1.0 / 3.0).abs() < 1e-9);
+
+        manager.clear_cache().await;
+        assert_eq!(manager.cache_stats().await.entries, 0);
+    }
+
+    #[tokio::test]
+    async fn test_tool_calls_go_through_the_cache() {
+        let dir = temp_dir("mycel-mcp");
+        let server = write_counting_server(&dir);
+        let config = McpConfig {
+            servers: vec![server.clone()],
+            ..Default::default()
+        };
+        let manager = TestManager::start(&dir, config, &[server]).await;
+
+        // A repeated idempotent call is answered from the cache
+        let info = ToolCall {
+            name: "system_info".to_string(),
+            arguments: HashMap::new(),
         };
+        let first = manager.process_tool_call(&info).await.unwrap();
+        assert_eq!(manager.process_tool_call(&info).await.unwrap(), first);
+        assert!(first.contains("hits=1"));
+        assert_eq!(manager.cache_stats().await.hits, 1);
+
+        // File contents are read afresh and never saved with the cache
+        let notes = dir.join("notes.txt");
+        let read = ToolCall {
+            name: "file_read".to_string(),
+            arguments: HashMap::from([(
+                "path".to_string(),
+                serde_json::json!(notes.to_string_lossy()),
+            )]),
+        };
+        std::fs::write(&notes, "before").unwrap();
+        assert!(manager
+            .process_tool_call(&read)
+            .await
+            .unwrap()
+            .contains("before"));
+        std::fs::write(&notes, "after").unwrap();
+        assert!(manager
+            .process_tool_call(&read)
+            .await
+            .unwrap()
+            .contains("after"));
+        let path = dir.join("tool_cache.json");
+        assert_eq!(manager.save_cache(&path).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cache_survives_restart_until_ttl() {
+        let dir = temp_dir("mycel-mcp");
+        let server = write_counting_server(&dir);
+        let manager = TestManager::start(&dir, McpConfig::default(), &[server]).await;
+
+        let ttl = Duration::from_secs(1);
+        let first = manager
+            .call_tool_cached("system_info", HashMap::new(), ttl)
+            .await
+            .unwrap();
+        // Results of tools that change things are never written out
+        manager.cache.write().await.insert(
+            "shell_command:{}".to_string(),
+            CachedResult {
+                result: "done".to_string(),
+                expires_at: Instant::now() + ttl,
+                persist: false,
+            },
+        );
+        let path = dir.join("tool_cache.json");
+        assert_eq!(manager.save_cache(&path).await.unwrap(), 1);
+        manager.stop_all().await.unwrap();
+
+        // After a restart the result is served without any server running
+        let restarted = TestManager::start(&dir, McpConfig::default(), &[]).await;
+        assert_eq!(restarted.load_cache(&path).await.unwrap(), 1);
+        let cached = restarted
+            .call_tool_cached("system_info", HashMap::new(), ttl)
+            .await
+            .unwrap();
+        assert_eq!(cached, first);
+
+        // Until its TTL passes, on the wall clock too
+        tokio::time::sleep(ttl).await;
+        assert!(restarted
+            .call_tool_cached("system_info", HashMap::new(), ttl)
+            .await
+            .is_err());
+        assert_eq!(restarted.load_cache(&path).await.unwrap(), 0);
+    }
 
+    #[tokio::test]
+    async fn test_tool_selection_excludes_unrelated_tools() {
+        const VOCAB: [&str; 6] = ["file", "read", "directory", "weather", "forecast", "city"];
+        let embeds = Arc::new(AtomicU64::new(0));
+        let embed = |text: String| {
+            let embeds = Arc::clone(&embeds);
+            async move {
+                embeds.fetch_add(1, Ordering::SeqCst);
+                let text = text.to_lowercase();
+                Ok(VOCAB
+                    .iter()
+                    .map(|w| text.matches(w).count() as f32)
+                    .collect::<Vec<f32>>())
+            }
+        };
+        let tool = |name: &str, description: &str| McpTool {
+            name: name.to_string(),
+            description: description.to_string(),
+            input_schema: serde_json::json!({}),
+            annotations: None,
+        };
+        let tools = vec![
+            tool("read_file", "Read a file"),
+            tool("get_weather", "Weather forecast for a city"),
+            tool("list_directory", "List the files in a directory"),
+        ];
+
+        let (tx, _) = tokio::sync::broadcast::channel(16);
+        let manager = McpManager::new(&McpConfig::default(), "/tmp", tx)
+            .await
+            .unwrap();
+
+        let selected = manager
+            .select_tools(&tools, "read the file notes.txt", 2, embed)
+            .await
+            .unwrap();
+        let names: Vec<&str> = selected.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["read_file", "list_directory"]);
+        assert_eq!(embeds.load(Ordering::SeqCst), 4);
+
+        // Tool embeddings are reused; only the query is embedded again
+        manager
+            .select_tools(&tools, "weather in this city", 1, embed)
+            .await
+            .unwrap();
+        assert_eq!(embeds.load(Ordering::SeqCst), 5);
+
+        // Without embeddings every tool is offered
+        let config = McpConfig {
+            tool_selection_top_k: 1,
+            ..Default::default()
+        };
+        let (tx, _) = tokio::sync::broadcast::channel(16);
+        let manager = McpManager::new(&config, "/tmp", tx).await.unwrap();
+        let prompt = manager
+            .get_tools_prompt_for("add a capability", |_| async {
+                Err(anyhow!("no embedding model"))
+            })
+            .await;
+        assert_eq!(prompt, manager.get_tools_prompt().await);
+    }
+
+    #[test]
+    fn test_risk_assessment() {
         // Can't easily test without async, but the logic is straightforward
         assert_eq!(
             match "xbps_search" {
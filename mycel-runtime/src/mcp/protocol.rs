@@ -37,6 +37,15 @@ pub struct JsonRpcResponse {
     pub error: Option<JsonRpcError>,
 }
 
+/// JSON-RPC 2.0 notification sent by a server (no id, no response expected)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonRpcNotification {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<serde_json::Value>,
+}
+
 /// JSON-RPC 2.0 Error
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JsonRpcError {
@@ -108,6 +117,9 @@ pub struct ServerCapabilities {
     pub resources: Option<ResourcesCapability>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub prompts: Option<PromptsCapability>,
+    /// Present when the server accepts `logging/setLevel`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logging: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -147,6 +159,42 @@ pub struct McpTool {
     pub description: String,
     #[serde(rename = "inputSchema")]
     pub input_schema: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<ToolAnnotations>,
+}
+
+impl McpTool {
+    /// Whether calling it again with the same arguments has no further
+    /// effect, so results may be cached and failed calls retried. A tool
+    /// that only reads is; unknown counts as no.
+    pub fn idempotent(&self) -> Option<bool> {
+        let annotations = self.annotations.as_ref()?;
+        if annotations.read_only_hint == Some(true) {
+            return Some(true);
+        }
+        annotations.idempotent_hint
+    }
+
+    /// Whether the server declares that it doesn't change anything
+    pub fn read_only(&self) -> bool {
+        self.annotations
+            .as_ref()
+            .and_then(|annotations| annotations.read_only_hint)
+            == Some(true)
+    }
+}
+
+/// What a server says about how a tool behaves. These are hints from the
+/// server, not guarantees.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolAnnotations {
+    /// It doesn't change anything
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only_hint: Option<bool>,
+    /// Repeating a call with the same arguments has no further effect
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotent_hint: Option<bool>,
 }
 
 /// List tools response
@@ -161,6 +209,22 @@ pub struct CallToolParams {
     pub name: String,
     #[serde(default)]
     pub arguments: HashMap<String, serde_json::Value>,
+    /// Request metadata, e.g. the `progressToken` to report progress under
+    #[serde(rename = "_meta", default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<serde_json::Value>,
+}
+
+/// `notifications/progress` parameters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgressParams {
+    #[serde(rename = "progressToken")]
+    pub progress_token: serde_json::Value,
+    pub progress: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<f64>,
+    /// Human-readable status, e.g. the latest line of output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
 }
 
 /// Call tool response
@@ -192,6 +256,33 @@ pub struct ResourceContent {
     pub mime_type: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub text: Option<String>,
+    /// Base64 data, for binary resources
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+}
+
+/// A resource (document, data) a server exposes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct McpResource {
+    pub uri: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(rename = "mimeType")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+/// List resources response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListResourcesResult {
+    pub resources: Vec<McpResource>,
+}
+
+/// Read resource response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadResourceResult {
+    pub contents: Vec<ResourceContent>,
 }
 
 /// Standard JSON-RPC error codes
@@ -239,5 +330,36 @@ mod tests {
         }"#;
         let tool: McpTool = serde_json::from_str(json).unwrap();
         assert_eq!(tool.name, "xbps_search");
+        assert_eq!(tool.idempotent(), None);
+    }
+
+    #[test]
+    fn test_tool_annotations() {
+        let tool = |extra: &str| -> McpTool {
+            serde_json::from_str(&format!(
+                r#"{{"name": "t", "description": "", "inputSchema": {{}}{}}}"#,
+                extra
+            ))
+            .unwrap()
+        };
+        let annotated = |annotations: &str| tool(&format!(r#", "annotations": {}"#, annotations));
+
+        assert_eq!(
+            annotated(r#"{"idempotentHint": true}"#).idempotent(),
+            Some(true)
+        );
+        assert_eq!(
+            annotated(r#"{"readOnlyHint": true}"#).idempotent(),
+            Some(true)
+        );
+        assert_eq!(
+            annotated(r#"{"readOnlyHint": false, "idempotentHint": false}"#).idempotent(),
+            Some(false)
+        );
+        assert_eq!(annotated(r#"{"title": "Lookup"}"#).idempotent(), None);
+        assert!(annotated(r#"{"readOnlyHint": true}"#).read_only());
+        assert!(!annotated(r#"{"idempotentHint": true}"#).read_only());
+        // Only the standard annotations count
+        assert_eq!(tool(r#", "idempotent": true"#).idempotent(), None);
     }
 }
@@ -3,6 +3,7 @@
 //! Parses tool calls from various formats that LLMs might output:
 //! - XML tags: `<tool_call>{"name": "...", "arguments": {...}}</tool_call>`
 //! - JSON blocks: ```json\n{"tool_call": {"name": "...", ...}}```
+//! - OpenAI style: `{"tool_calls": [{"function": {"name": "...", "arguments": "{...}"}}]}`
 //! - Function syntax: `tool_name({"arg": "value"})`
 //! - Direct JSON with name/arguments fields
 
@@ -38,11 +39,43 @@ pub struct ParsedResponse {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ToolCallFormat {
     XmlTags,          // <tool_call>...</tool_call>
+    OpenAiToolCalls,  // {"tool_calls": [{"function": {...}}]}
     JsonCodeBlock,    // ```json ... ```
     FunctionSyntax,   // tool_name({...})
     DirectJson,       // {"name": "...", "arguments": {...}}
 }
 
+/// Which format `parse_tool_calls_as` looks for (`mcp.tool_call_format`).
+/// Pinning the format a model always uses skips the looser parsers and
+/// the false positives they can produce.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolCallFormatHint {
+    /// Try every format, most explicit first
+    #[default]
+    Auto,
+    XmlTags,
+    #[serde(rename = "openai")]
+    OpenAiToolCalls,
+    JsonCodeBlock,
+    FunctionSyntax,
+    DirectJson,
+}
+
+impl ToolCallFormatHint {
+    /// Whether responses in `format` are parsed under this hint
+    fn accepts(self, format: ToolCallFormat) -> bool {
+        match self {
+            Self::Auto => true,
+            Self::XmlTags => format == ToolCallFormat::XmlTags,
+            Self::OpenAiToolCalls => format == ToolCallFormat::OpenAiToolCalls,
+            Self::JsonCodeBlock => format == ToolCallFormat::JsonCodeBlock,
+            Self::FunctionSyntax => format == ToolCallFormat::FunctionSyntax,
+            Self::DirectJson => format == ToolCallFormat::DirectJson,
+        }
+    }
+}
+
 impl ParsedResponse {
     /// Check if this response contains any tool calls
     pub fn has_tool_calls(&self) -> bool {
@@ -63,35 +96,40 @@ impl ParsedResponse {
     }
 }
 
-/// Parse tool calls from an LLM response, trying multiple formats
+/// Parser for one format; `None` when the format isn't present at all
+type FormatParser = fn(&str) -> Option<ParsedResponse>;
+
+/// Parse tool calls from an LLM response, trying multiple formats. The
+/// runtime parses with the configured `mcp.tool_call_format` instead.
+#[cfg(test)]
 pub fn parse_tool_calls(response: &str) -> ParsedResponse {
-    // Try formats in order of specificity
+    parse_tool_calls_as(response, ToolCallFormatHint::Auto)
+}
 
+/// Parse tool calls in the formats `hint` allows
+pub fn parse_tool_calls_as(response: &str, hint: ToolCallFormatHint) -> ParsedResponse {
+    // Formats in order of specificity:
     // 1. XML tags (most explicit)
-    if let Some(parsed) = try_parse_xml_tags(response) {
-        if parsed.has_tool_calls() {
-            return parsed;
-        }
-    }
-
-    // 2. JSON code blocks
-    if let Some(parsed) = try_parse_json_code_blocks(response) {
-        if parsed.has_tool_calls() {
-            return parsed;
-        }
-    }
-
-    // 3. Function call syntax
-    if let Some(parsed) = try_parse_function_syntax(response) {
-        if parsed.has_tool_calls() {
-            return parsed;
+    // 2. OpenAI-style tool_calls array (cloud providers)
+    // 3. JSON code blocks
+    // 4. Function call syntax
+    // 5. Direct JSON in text
+    let parsers: [(ToolCallFormat, FormatParser); 5] = [
+        (ToolCallFormat::XmlTags, try_parse_xml_tags),
+        (ToolCallFormat::OpenAiToolCalls, try_parse_openai_tool_calls),
+        (ToolCallFormat::JsonCodeBlock, try_parse_json_code_blocks),
+        (ToolCallFormat::FunctionSyntax, try_parse_function_syntax),
+        (ToolCallFormat::DirectJson, try_parse_direct_json),
+    ];
+
+    for (format, parse) in parsers {
+        if !hint.accepts(format) {
+            continue;
         }
-    }
-
-    // 4. Direct JSON in text
-    if let Some(parsed) = try_parse_direct_json(response) {
-        if parsed.has_tool_calls() {
-            return parsed;
+        if let Some(parsed) = parse(response) {
+            if parsed.has_tool_calls() {
+                return parsed;
+            }
         }
     }
 
@@ -182,6 +220,88 @@ fn try_parse_xml_tags(response: &str) -> Option<ParsedResponse> {
     })
 }
 
+/// Parse the OpenAI/cloud format:
+/// `{"tool_calls": [{"function": {"name": "...", "arguments": "<json string>"}}]}`
+fn try_parse_openai_tool_calls(response: &str) -> Option<ParsedResponse> {
+    if !response.contains("\"tool_calls\"") {
+        return None;
+    }
+
+    let mut search_from = 0;
+    while let Some(offset) = response[search_from..].find('{') {
+        let start = search_from + offset;
+        let Some(end) = balanced_object_end(response, start) else {
+            break;
+        };
+
+        if let Ok(value) = serde_json::from_str::<serde_json::Value>(&response[start..end]) {
+            if let Some(entries) = value.get("tool_calls").and_then(|v| v.as_array()) {
+                let tool_calls: Vec<ToolCall> =
+                    entries.iter().filter_map(parse_openai_tool_call).collect();
+                if !tool_calls.is_empty() {
+                    return Some(ParsedResponse {
+                        prefix_text: response[..start].to_string(),
+                        tool_calls,
+                        suffix_text: response[end..].to_string(),
+                        format_detected: Some(ToolCallFormat::OpenAiToolCalls),
+                    });
+                }
+            }
+        }
+        search_from = start + 1;
+    }
+
+    None
+}
+
+/// Parse one `tool_calls` entry; `arguments` is usually a JSON-encoded string
+fn parse_openai_tool_call(entry: &serde_json::Value) -> Option<ToolCall> {
+    let function = entry.get("function")?;
+    let name = function.get("name")?.as_str()?;
+
+    let arguments = match function.get("arguments") {
+        Some(serde_json::Value::String(encoded)) if encoded.trim().is_empty() => HashMap::new(),
+        Some(serde_json::Value::String(encoded)) => serde_json::from_str(encoded).ok()?,
+        Some(serde_json::Value::Object(obj)) => {
+            obj.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+        }
+        _ => HashMap::new(),
+    };
+
+    Some(ToolCall {
+        name: name.to_string(),
+        arguments,
+    })
+}
+
+/// Byte index just past the `}` closing the object that opens at `start`
+fn balanced_object_end(text: &str, start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escape = false;
+
+    for (i, b) in text.bytes().enumerate().skip(start) {
+        if escape {
+            escape = false;
+        } else if b == b'\\' && in_string {
+            escape = true;
+        } else if b == b'"' {
+            in_string = !in_string;
+        } else if !in_string {
+            if b == b'{' {
+                depth += 1;
+            } else if b == b'}' {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+        }
+    }
+
+    None
+}
+
 /// Parse JSON code blocks that contain tool calls
 fn try_parse_json_code_blocks(response: &str) -> Option<ParsedResponse> {
     let re = Regex::new(r"```(?:json)?\s*\n?([\s\S]*?)```").ok()?;
@@ -236,25 +356,41 @@ fn try_parse_json_code_blocks(response: &str) -> Option<ParsedResponse> {
 
 /// Parse function-call syntax: tool_name({"arg": "value"})
 fn try_parse_function_syntax(response: &str) -> Option<ParsedResponse> {
-    // Match patterns like: tool_name({"key": "value"}) or tool_name({...})
-    let re = Regex::new(r"([a-zA-Z_][a-zA-Z0-9_]*)\s*\(\s*(\{[\s\S]*?\})\s*\)").ok()?;
+    // Find `tool_name({`; the argument object is then scanned with brace
+    // balancing so nested objects and braces inside strings are kept whole
+    let re = Regex::new(r"([a-zA-Z_][a-zA-Z0-9_]*)\s*\(\s*\{").ok()?;
 
     let mut tool_calls = Vec::new();
     let mut prefix_text = String::new();
     let mut last_end = 0;
     let mut found_any = false;
+    let mut pos = 0;
 
-    for cap in re.captures_iter(response) {
+    while let Some(cap) = re.captures_at(response, pos) {
         let full_match = cap.get(0)?;
         let func_name = cap.get(1)?.as_str();
-        let args_json = cap.get(2)?.as_str();
+        // Resume after the name by default, so an object that doesn't close
+        // properly doesn't hide later calls
+        pos = cap.get(1)?.end();
 
         // Skip common false positives
         if ["if", "while", "for", "function", "return", "var", "let", "const"].contains(&func_name) {
             continue;
         }
 
+        let args_start = full_match.end() - 1;
+        let Some(args_end) = balanced_object_end(response, args_start) else {
+            continue;
+        };
+        let rest = &response[args_end..];
+        let trimmed = rest.trim_start();
+        if !trimmed.starts_with(')') {
+            continue;
+        }
+        let call_end = args_end + (rest.len() - trimmed.len()) + 1;
+
         // Try to parse the arguments as JSON
+        let args_json = &response[args_start..args_end];
         if let Ok(args) = serde_json::from_str::<HashMap<String, serde_json::Value>>(args_json) {
             if !found_any {
                 prefix_text = response[..full_match.start()].to_string();
@@ -264,7 +400,8 @@ fn try_parse_function_syntax(response: &str) -> Option<ParsedResponse> {
                 name: func_name.to_string(),
                 arguments: args,
             });
-            last_end = full_match.end();
+            last_end = call_end;
+            pos = call_end;
         }
     }
 
@@ -284,73 +421,42 @@ fn try_parse_function_syntax(response: &str) -> Option<ParsedResponse> {
 fn try_parse_direct_json(response: &str) -> Option<ParsedResponse> {
     // Find balanced JSON objects that contain "name" field
     let mut tool_calls = Vec::new();
-    let mut prefix_text = String::new();
-    let mut found_any = false;
+    let mut first_start = None;
     let mut last_end = 0;
+    let mut pos = 0;
 
-    // Find all potential JSON objects by tracking brace depth
-    let chars: Vec<char> = response.chars().collect();
-    let mut i = 0;
-
-    while i < chars.len() {
-        if chars[i] == '{' {
-            let start = i;
-            let mut depth = 1;
-            let mut in_string = false;
-            let mut escape = false;
-            i += 1;
-
-            while i < chars.len() && depth > 0 {
-                let c = chars[i];
-                if escape {
-                    escape = false;
-                } else if c == '\\' && in_string {
-                    escape = true;
-                } else if c == '"' {
-                    in_string = !in_string;
-                } else if !in_string {
-                    if c == '{' {
-                        depth += 1;
-                    } else if c == '}' {
-                        depth -= 1;
-                    }
-                }
-                i += 1;
-            }
-
-            if depth == 0 {
-                let json_str: String = chars[start..i].iter().collect();
-
-                // Check if it contains "name" field (could be a tool call)
-                if json_str.contains("\"name\"") {
-                    if let Ok(call) = serde_json::from_str::<ToolCall>(&json_str) {
-                        // Validate it looks like a real tool call
-                        if !call.name.is_empty()
-                            && call.name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
-                        {
-                            if !found_any {
-                                prefix_text = chars[..start].iter().collect();
-                                found_any = true;
-                            }
-                            tool_calls.push(call);
-                            last_end = i;
-                        }
-                    }
+    // Walk the objects by byte index; braces are ASCII, so every index
+    // found here is a valid slice boundary
+    while let Some(offset) = response[pos..].find('{') {
+        let start = pos + offset;
+        let Some(end) = balanced_object_end(response, start) else {
+            break;
+        };
+        let json_str = &response[start..end];
+
+        // Check if it contains "name" field (could be a tool call)
+        if json_str.contains("\"name\"") {
+            if let Ok(call) = serde_json::from_str::<ToolCall>(json_str) {
+                // Validate it looks like a real tool call
+                if !call.name.is_empty()
+                    && call.name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+                {
+                    first_start.get_or_insert(start);
+                    tool_calls.push(call);
+                    last_end = end;
                 }
             }
-        } else {
-            i += 1;
         }
-    }
 
-    if !found_any {
-        return None;
+        pos = end;
     }
 
+    let first_start = first_start?;
+
     Some(ParsedResponse {
-        prefix_text,
+        prefix_text: response[..first_start].to_string(),
         tool_calls,
-        suffix_text: chars[last_end..].iter().collect(),
+        suffix_text: response[last_end..].to_string(),
         format_detected: Some(ToolCallFormat::DirectJson),
     })
 }
@@ -439,6 +545,87 @@ fn strip_markdown_code_block(s: &str) -> String {
     result.trim().to_string()
 }
 
+const TOOL_CALL_OPEN: &str = "<tool_call>";
+const TOOL_CALL_CLOSE: &str = "</tool_call>";
+
+/// A piece of streamed output, split by [`ToolCallStreamParser`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamSegment {
+    /// Plain text, safe to show immediately
+    Text(String),
+    /// A complete `<tool_call>...</tool_call>` block
+    ToolCall(String),
+}
+
+/// Incremental `<tool_call>` detection over a token stream.
+///
+/// Text passes straight through until an opening tag appears; from there the
+/// output is buffered until the closing tag completes the block. A trailing
+/// fragment that could be the start of a tag is held back between chunks.
+#[derive(Debug, Default)]
+pub struct ToolCallStreamParser {
+    pending: String,
+    in_tool_call: bool,
+}
+
+impl ToolCallStreamParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next chunk, returning whatever can be emitted so far
+    pub fn push(&mut self, chunk: &str) -> Vec<StreamSegment> {
+        self.pending.push_str(chunk);
+        let mut segments = Vec::new();
+
+        loop {
+            if self.in_tool_call {
+                let Some(pos) = self.pending.find(TOOL_CALL_CLOSE) else {
+                    break;
+                };
+                let end = pos + TOOL_CALL_CLOSE.len();
+                segments.push(StreamSegment::ToolCall(self.pending[..end].to_string()));
+                self.pending.drain(..end);
+                self.in_tool_call = false;
+            } else if let Some(pos) = self.pending.find(TOOL_CALL_OPEN) {
+                if pos > 0 {
+                    segments.push(StreamSegment::Text(self.pending[..pos].to_string()));
+                }
+                self.pending.drain(..pos);
+                self.in_tool_call = true;
+            } else {
+                let keep = partial_tag_len(&self.pending, TOOL_CALL_OPEN);
+                let emit = self.pending.len() - keep;
+                if emit > 0 {
+                    segments.push(StreamSegment::Text(self.pending[..emit].to_string()));
+                    self.pending.drain(..emit);
+                }
+                break;
+            }
+        }
+
+        segments
+    }
+
+    /// End of stream: flush anything held back (an unterminated tool call
+    /// is returned as text)
+    pub fn finish(self) -> Option<StreamSegment> {
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some(StreamSegment::Text(self.pending))
+        }
+    }
+}
+
+/// Length of the longest suffix of `text` that is a proper prefix of `tag`
+fn partial_tag_len(text: &str, tag: &str) -> usize {
+    (1..tag.len())
+        .rev()
+        .find(|&n| text.ends_with(&tag[..n]))
+        .unwrap_or(0)
+}
+
 /// Format tools for injection into LLM prompts
 pub fn format_tools_for_prompt(tools: &[super::protocol::McpTool]) -> String {
     if tools.is_empty() {
@@ -524,6 +711,108 @@ Here are the results."#;
         assert!(parsed.prefix_text.contains("Let me search"));
     }
 
+    #[test]
+    fn test_parse_openai_tool_call() {
+        let response = r#"{"tool_calls": [{"id": "call_1", "type": "function", "function": {"name": "xbps_search", "arguments": "{\"query\": \"htop\"}"}}]}"#;
+
+        let parsed = parse_tool_calls(response);
+
+        assert_eq!(
+            parsed.format_detected,
+            Some(ToolCallFormat::OpenAiToolCalls)
+        );
+        assert_eq!(parsed.tool_calls.len(), 1);
+        assert_eq!(parsed.tool_calls[0].name, "xbps_search");
+        assert_eq!(
+            parsed.tool_calls[0].arguments.get("query"),
+            Some(&serde_json::json!("htop"))
+        );
+    }
+
+    #[test]
+    fn test_parse_openai_multiple_tool_calls() {
+        let response = r#"Checking both.
+{"tool_calls": [
+  {"function": {"name": "read_file", "arguments": "{\"path\": \"/etc/hosts\", \"opts\": {\"lines\": 10}}"}},
+  {"function": {"name": "system_info", "arguments": ""}}
+]}"#;
+
+        let parsed = parse_tool_calls(response);
+
+        assert_eq!(
+            parsed.format_detected,
+            Some(ToolCallFormat::OpenAiToolCalls)
+        );
+        assert_eq!(parsed.tool_calls.len(), 2);
+        assert_eq!(parsed.tool_calls[0].name, "read_file");
+        assert_eq!(
+            parsed.tool_calls[0].arguments.get("opts"),
+            Some(&serde_json::json!({"lines": 10}))
+        );
+        assert_eq!(parsed.tool_calls[1].name, "system_info");
+        assert!(parsed.tool_calls[1].arguments.is_empty());
+        assert!(parsed.prefix_text.contains("Checking both."));
+    }
+
+    #[test]
+    fn test_stream_parser_passes_plain_text() {
+        let mut parser = ToolCallStreamParser::new();
+        assert_eq!(
+            parser.push("Hello "),
+            vec![StreamSegment::Text("Hello ".to_string())]
+        );
+        assert_eq!(
+            parser.push("world"),
+            vec![StreamSegment::Text("world".to_string())]
+        );
+        assert_eq!(parser.finish(), None);
+    }
+
+    #[test]
+    fn test_stream_parser_tag_split_across_chunks() {
+        let mut parser = ToolCallStreamParser::new();
+        let mut segments = Vec::new();
+        for chunk in [
+            "Let me check. <tool",
+            "_call>{\"name\": \"system_info\",",
+            " \"arguments\": {}}</tool_",
+            "call> trailing",
+        ] {
+            segments.extend(parser.push(chunk));
+        }
+        segments.extend(parser.finish());
+
+        assert_eq!(
+            segments,
+            vec![
+                StreamSegment::Text("Let me check. ".to_string()),
+                StreamSegment::ToolCall(
+                    "<tool_call>{\"name\": \"system_info\", \"arguments\": {}}</tool_call>"
+                        .to_string()
+                ),
+                StreamSegment::Text(" trailing".to_string()),
+            ]
+        );
+
+        let StreamSegment::ToolCall(block) = &segments[1] else {
+            unreachable!()
+        };
+        assert_eq!(parse_tool_calls(block).tool_calls[0].name, "system_info");
+    }
+
+    #[test]
+    fn test_stream_parser_releases_false_tag_prefix() {
+        let mut parser = ToolCallStreamParser::new();
+        assert_eq!(
+            parser.push("a <to"),
+            vec![StreamSegment::Text("a ".to_string())]
+        );
+        assert_eq!(
+            parser.push("p> b"),
+            vec![StreamSegment::Text("<top> b".to_string())]
+        );
+    }
+
     #[test]
     fn test_parse_function_call_tag() {
         let response = r#"<function_call>
@@ -595,6 +884,41 @@ Let me know what you find."#;
         assert_eq!(parsed.format_detected, Some(ToolCallFormat::FunctionSyntax));
     }
 
+    #[test]
+    fn test_parse_function_syntax_nested_object() {
+        let response = r#"Searching now:
+search({
+    "filter": {"nested": true, "tags": {"any": ["a"]}},
+    "note": "closing text like }) inside a string"
+})
+Done."#;
+
+        let parsed = parse_tool_calls(response);
+
+        assert_eq!(parsed.tool_calls.len(), 1);
+        assert_eq!(parsed.format_detected, Some(ToolCallFormat::FunctionSyntax));
+        let args = &parsed.tool_calls[0].arguments;
+        assert_eq!(args["filter"]["nested"], serde_json::json!(true));
+        assert_eq!(args["filter"]["tags"]["any"], serde_json::json!(["a"]));
+        assert_eq!(args["note"], "closing text like }) inside a string");
+        assert_eq!(parsed.prefix_text, "Searching now:\n");
+        assert_eq!(parsed.suffix_text, "\nDone.");
+    }
+
+    #[test]
+    fn test_parse_function_syntax_array_argument() {
+        let response = r#"read_files({"paths": ["/tmp/a", "/tmp/b"]}) then if({"x": 1})"#;
+
+        let parsed = parse_tool_calls(response);
+
+        assert_eq!(parsed.tool_calls.len(), 1);
+        assert_eq!(parsed.tool_calls[0].name, "read_files");
+        assert_eq!(
+            parsed.tool_calls[0].arguments["paths"],
+            serde_json::json!(["/tmp/a", "/tmp/b"])
+        );
+    }
+
     #[test]
     fn test_parse_direct_json() {
         let response = r#"I'll use this tool: {"name": "system_info", "arguments": {}}"#;
@@ -606,6 +930,47 @@ Let me know what you find."#;
         assert_eq!(parsed.format_detected, Some(ToolCallFormat::DirectJson));
     }
 
+    #[test]
+    fn test_parse_direct_json_large_response() {
+        let filler = "Här är lite text {\"note\": \"not a call\", \"n\": {\"x\": 1}} och mer. ";
+        let mut response = filler.repeat(4000);
+        let prefix_len = response.len();
+        response.push_str(r#"{"name": "system_info", "arguments": {}}"#);
+        response.push_str(&filler.repeat(4000));
+        response.push_str(r#"{"name": "xbps_search", "arguments": {"query": "vim {x}"}}"#);
+        response.push_str(" done");
+        assert!(response.len() > 500_000);
+
+        let started = std::time::Instant::now();
+        let parsed = try_parse_direct_json(&response).unwrap();
+        // Generous, so a loaded machine doesn't fail it; a linear scan needs
+        // milliseconds here, a quadratic one far longer
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+        let names: Vec<_> = parsed.tool_calls.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["system_info", "xbps_search"]);
+        assert_eq!(parsed.tool_calls[1].arguments["query"], "vim {x}");
+        assert_eq!(parsed.prefix_text, response[..prefix_len]);
+        assert_eq!(parsed.suffix_text, " done");
+    }
+
+    #[test]
+    fn test_format_hint_limits_parsing() {
+        let response = r#"The config looks like {"name": "system_info", "arguments": {}}"#;
+        assert!(parse_tool_calls(response).has_tool_calls());
+
+        let parsed = parse_tool_calls_as(response, ToolCallFormatHint::XmlTags);
+        assert!(!parsed.has_tool_calls());
+        assert_eq!(parsed.prefix_text, response);
+
+        let tagged = format!("<tool_call>{}</tool_call>", &response[22..]);
+        let parsed = parse_tool_calls_as(&tagged, ToolCallFormatHint::XmlTags);
+        assert_eq!(parsed.tool_calls[0].name, "system_info");
+
+        let hint: ToolCallFormatHint = serde_json::from_str("\"openai\"").unwrap();
+        assert_eq!(hint, ToolCallFormatHint::OpenAiToolCalls);
+    }
+
     #[test]
     fn test_parse_no_tool_calls() {
         let response = "Just a normal response without any tools.";
@@ -671,6 +1036,7 @@ for (item in items) {
                     "query": {"type": "string"}
                 }
             }),
+            annotations: None,
         }];
 
         let formatted = format_tools_for_prompt(&tools);
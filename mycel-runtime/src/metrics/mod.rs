@@ -0,0 +1,432 @@
+//! Prometheus metrics endpoint
+//!
+//! Counters (inferences, tool calls) are fed from the system event bus;
+//! gauges (MCP servers, tool cache, sessions, peers) are read from the
+//! runtime on each scrape. Served as plain HTTP on `metrics_bind`.
+
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::events::SystemEvent;
+use crate::mcp::CacheStats;
+use crate::MycelRuntime;
+
+/// Upper bounds (seconds) of the inference latency buckets
+const LATENCY_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// Largest request head accepted from a scraper
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/// Cumulative latency histogram in the Prometheus layout
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations at or below each of `LATENCY_BUCKETS`
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; LATENCY_BUCKETS.len()];
+        }
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if secs <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    /// Inference latency by source (`local` / `cloud`)
+    inference: BTreeMap<String, Histogram>,
+    /// Tool calls by (tool, success)
+    tool_calls: BTreeMap<(String, bool), u64>,
+}
+
+/// Point-in-time values read from the runtime when scraped
+pub struct Gauges {
+    /// MCP server name to state (`ready`, `failed`, ...)
+    pub mcp_servers: HashMap<String, String>,
+    pub cache: CacheStats,
+    pub active_sessions: usize,
+    pub peer_count: usize,
+    pub uptime_secs: u64,
+}
+
+impl Gauges {
+    async fn read(runtime: &MycelRuntime) -> Self {
+        Self {
+            mcp_servers: runtime.mcp_manager.get_status().await,
+            cache: runtime.mcp_manager.cache_stats().await,
+            active_sessions: runtime.context_manager.session_count().await,
+            peer_count: runtime.sync_service.get_peers().await.len(),
+            uptime_secs: runtime.started_at.elapsed().as_secs(),
+        }
+    }
+}
+
+/// Counters accumulated from system events
+#[derive(Clone, Default)]
+pub struct Metrics {
+    counters: Arc<Mutex<Counters>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count an event, if it is one we track
+    pub fn record(&self, event: &SystemEvent) {
+        let mut counters = self.counters.lock().unwrap_or_else(|e| e.into_inner());
+        match event {
+            SystemEvent::InferenceCompleted {
+                source,
+                duration_ms,
+            } => {
+                counters
+                    .inference
+                    .entry(source.clone())
+                    .or_default()
+                    .observe(*duration_ms as f64 / 1000.0);
+            }
+            SystemEvent::ToolCalled {
+                tool_name, success, ..
+            } => {
+                *counters
+                    .tool_calls
+                    .entry((tool_name.clone(), *success))
+                    .or_default() += 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Record events from the bus until shutdown
+    pub fn spawn_collector(
+        &self,
+        mut events: broadcast::Receiver<SystemEvent>,
+        shutdown: CancellationToken,
+    ) -> tokio::task::JoinHandle<()> {
+        let metrics = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    event = events.recv() => event,
+                };
+                match event {
+                    Ok(event) => metrics.record(&event),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        debug!("Metrics collector missed {} events", missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let counters = self.counters.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out = String::new();
+
+        out.push_str("# HELP mycel_inference_duration_seconds LLM request latency by source.\n");
+        out.push_str("# TYPE mycel_inference_duration_seconds histogram\n");
+        for (source, hist) in &counters.inference {
+            let source = escape_label(source);
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(&hist.buckets) {
+                let _ = writeln!(
+                    out,
+                    "mycel_inference_duration_seconds_bucket{{source=\"{}\",le=\"{}\"}} {}",
+                    source, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "mycel_inference_duration_seconds_bucket{{source=\"{}\",le=\"+Inf\"}} {}",
+                source, hist.count
+            );
+            let _ = writeln!(
+                out,
+                "mycel_inference_duration_seconds_sum{{source=\"{}\"}} {}",
+                source, hist.sum
+            );
+            let _ = writeln!(
+                out,
+                "mycel_inference_duration_seconds_count{{source=\"{}\"}} {}",
+                source, hist.count
+            );
+        }
+
+        out.push_str("# HELP mycel_tool_calls_total MCP tool calls by tool and outcome.\n");
+        out.push_str("# TYPE mycel_tool_calls_total counter\n");
+        for ((tool, success), count) in &counters.tool_calls {
+            let _ = writeln!(
+                out,
+                "mycel_tool_calls_total{{tool=\"{}\",success=\"{}\"}} {}",
+                escape_label(tool),
+                success,
+                count
+            );
+        }
+
+        out.push_str("# HELP mycel_mcp_server_up Whether an MCP server is ready (1) or not (0).\n");
+        out.push_str("# TYPE mycel_mcp_server_up gauge\n");
+        let servers: BTreeMap<_, _> = gauges.mcp_servers.iter().collect();
+        for (name, state) in servers {
+            let _ = writeln!(
+                out,
+                "mycel_mcp_server_up{{server=\"{}\"}} {}",
+                escape_label(name),
+                u8::from(state == "ready")
+            );
+        }
+
+        let mut simple = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        simple(
+            "mycel_tool_cache_hits_total",
+            "counter",
+            "Tool result cache hits.",
+            gauges.cache.hits.to_string(),
+        );
+        simple(
+            "mycel_tool_cache_misses_total",
+            "counter",
+            "Tool result cache misses.",
+            gauges.cache.misses.to_string(),
+        );
+        simple(
+            "mycel_tool_cache_hit_ratio",
+            "gauge",
+            "Share of tool cache lookups that hit.",
+            gauges.cache.hit_rate.to_string(),
+        );
+        simple(
+            "mycel_active_sessions",
+            "gauge",
+            "Sessions held in memory.",
+            gauges.active_sessions.to_string(),
+        );
+        simple(
+            "mycel_peers",
+            "gauge",
+            "Known sync peers.",
+            gauges.peer_count.to_string(),
+        );
+        simple(
+            "mycel_uptime_seconds",
+            "gauge",
+            "Seconds since the runtime started.",
+            gauges.uptime_secs.to_string(),
+        );
+
+        out
+    }
+}
+
+/// Escape a label value for the exposition format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serve `GET /metrics` for `runtime` on `bind` until shutdown
+pub async fn serve(
+    bind: &str,
+    metrics: Metrics,
+    runtime: MycelRuntime,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let listener = TcpListener::bind(bind).await?;
+    info!(
+        "📈 Metrics available at http://{}/metrics",
+        listener.local_addr()?
+    );
+
+    let runtime = Arc::new(runtime);
+    serve_on(listener, shutdown, move || {
+        let metrics = metrics.clone();
+        let runtime = Arc::clone(&runtime);
+        async move { metrics.render(&Gauges::read(&runtime).await) }
+    })
+    .await
+}
+
+/// Accept scrapes on `listener`, answering `/metrics` with `render()`
+async fn serve_on<F, Fut>(
+    listener: TcpListener,
+    shutdown: CancellationToken,
+    render: F,
+) -> Result<()>
+where
+    F: Fn() -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = String> + Send,
+{
+    loop {
+        let accepted = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            accepted = listener.accept() => accepted,
+        };
+        match accepted {
+            Ok((stream, _)) => {
+                let render = render.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_scrape(stream, render).await {
+                        debug!("Metrics request failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("Metrics accept error: {}", e),
+        }
+    }
+    Ok(())
+}
+
+async fn handle_scrape<F, Fut>(mut stream: TcpStream, render: F) -> Result<()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = String>,
+{
+    // Only the request line matters; read until the end of the head
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_REQUEST_BYTES {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next().unwrap_or("").split_whitespace();
+    let method = request_line.next().unwrap_or("");
+    let path = request_line.next().unwrap_or("");
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => (
+            "200 OK",
+            "text/plain; version=0.0.4; charset=utf-8",
+            render().await,
+        ),
+        ("GET", _) => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method not allowed\n".to_string(),
+        ),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gauges() -> Gauges {
+        Gauges {
+            mcp_servers: HashMap::from([
+                ("void-tools".to_string(), "ready".to_string()),
+                ("broken".to_string(), "failed".to_string()),
+            ]),
+            cache: CacheStats {
+                hits: 3,
+                misses: 1,
+                entries: 2,
+                hit_rate: 0.75,
+            },
+            active_sessions: 2,
+            peer_count: 1,
+            uptime_secs: 60,
+        }
+    }
+
+    #[test]
+    fn test_render_counts_events() {
+        let metrics = Metrics::new();
+        for duration_ms in [200, 3_000] {
+            metrics.record(&SystemEvent::InferenceCompleted {
+                source: "local".to_string(),
+                duration_ms,
+            });
+        }
+        metrics.record(&SystemEvent::ToolCalled {
+            tool_name: "system_info".to_string(),
+            server_name: "void-tools".to_string(),
+            success: false,
+            response_time_ms: 5,
+        });
+
+        let text = metrics.render(&gauges());
+        assert!(text
+            .contains("mycel_inference_duration_seconds_bucket{source=\"local\",le=\"0.25\"} 1"));
+        assert!(text
+            .contains("mycel_inference_duration_seconds_bucket{source=\"local\",le=\"+Inf\"} 2"));
+        assert!(text.contains("mycel_inference_duration_seconds_count{source=\"local\"} 2"));
+        assert!(text.contains("mycel_tool_calls_total{tool=\"system_info\",success=\"false\"} 1"));
+        assert!(text.contains("mycel_mcp_server_up{server=\"void-tools\"} 1"));
+        assert!(text.contains("mycel_mcp_server_up{server=\"broken\"} 0"));
+        assert!(text.contains("mycel_tool_cache_hit_ratio 0.75"));
+        assert!(text.contains("mycel_peers 1"));
+    }
+
+    #[tokio::test]
+    async fn test_serves_metrics_over_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let server = tokio::spawn(serve_on(listener, shutdown.clone(), || async {
+            "mycel_peers 0\n".to_string()
+        }));
+
+        let client = reqwest::Client::new();
+        let response = client
+            .get(format!("http://{}/metrics", addr))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(response.text().await.unwrap(), "mycel_peers 0\n");
+
+        let missing = client
+            .get(format!("http://{}/other", addr))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -14,8 +14,14 @@
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use tokio::sync::broadcast;
 use tracing::{info, warn};
 
+use crate::config::MycelConfig;
+use crate::egress::{EgressPolicy, HttpClient};
+use crate::error::Error;
+use crate::events::SystemEvent;
+
 /// Model provider backends
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub enum ModelBackend {
@@ -88,6 +94,27 @@ pub struct ModelInfo {
     pub tags: Vec<String>,
 }
 
+/// Percent complete from an Ollama pull status line, if it carries sizes
+fn pull_progress_percent(status: &serde_json::Value) -> Option<u8> {
+    let total = status.get("total")?.as_u64()?;
+    let completed = status.get("completed")?.as_u64()?;
+    if total == 0 {
+        return None;
+    }
+    Some((completed.min(total) * 100 / total) as u8)
+}
+
+/// The registry Ollama downloads `model_id` from: the host it is prefixed
+/// with (`hf.co/user/model`), else the Ollama library
+fn ollama_registry(model_id: &str) -> String {
+    match model_id.split_once('/') {
+        Some((host, _)) if host.contains('.') || host.contains(':') || host == "localhost" => {
+            format!("https://{}/", host)
+        }
+        _ => "https://registry.ollama.ai/".to_string(),
+    }
+}
+
 /// Model manager configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelManagerConfig {
@@ -101,6 +128,9 @@ pub struct ModelManagerConfig {
     pub hf_token: Option<String>,
     /// Maximum model size to auto-download (bytes)
     pub max_auto_download_bytes: u64,
+    /// Hosts model listing and downloads may reach
+    #[serde(default)]
+    pub egress: EgressPolicy,
 }
 
 impl Default for ModelManagerConfig {
@@ -113,6 +143,18 @@ impl Default for ModelManagerConfig {
             ollama_url: "http://localhost:11434".to_string(),
             hf_token: None,
             max_auto_download_bytes: 10 * 1024 * 1024 * 1024, // 10GB
+            egress: EgressPolicy::default(),
+        }
+    }
+}
+
+impl ModelManagerConfig {
+    /// Derive model manager settings from the main runtime config
+    pub fn from_mycel_config(config: &MycelConfig) -> Self {
+        Self {
+            ollama_url: config.ollama_url.clone(),
+            egress: EgressPolicy::from_config(config),
+            ..Default::default()
         }
     }
 }
@@ -121,7 +163,9 @@ impl Default for ModelManagerConfig {
 pub struct ModelManager {
     config: ModelManagerConfig,
     hardware: HardwareInfo,
-    http_client: reqwest::Client,
+    http_client: HttpClient,
+    /// Where download progress is reported, if anywhere
+    event_bus: Option<broadcast::Sender<SystemEvent>>,
 }
 
 impl ModelManager {
@@ -134,11 +178,31 @@ impl ModelManager {
             "Hardware detected"
         );
 
-        Ok(Self {
+        Ok(Self::with_hardware(config, hardware))
+    }
+
+    /// Create a model manager for already-known hardware
+    pub fn with_hardware(config: ModelManagerConfig, hardware: HardwareInfo) -> Self {
+        // Like reqwest::Client::new, panics only if TLS can't be set up
+        let http_client = HttpClient::new(reqwest::Client::builder(), config.egress.clone())
+            .expect("failed to build HTTP client");
+        Self {
             config,
             hardware,
-            http_client: reqwest::Client::new(),
-        })
+            http_client,
+            event_bus: None,
+        }
+    }
+
+    /// Report download progress on the system event bus
+    pub fn with_event_bus(mut self, event_bus: broadcast::Sender<SystemEvent>) -> Self {
+        self.event_bus = Some(event_bus);
+        self
+    }
+
+    /// Hardware this manager checks compatibility against
+    pub fn hardware(&self) -> &HardwareInfo {
+        &self.hardware
     }
 
     /// Detect system hardware capabilities
@@ -249,7 +313,7 @@ impl ModelManager {
 
     async fn list_ollama_models(&self) -> Result<Vec<ModelInfo>> {
         let url = format!("{}/api/tags", self.config.ollama_url);
-        let response: serde_json::Value = self.http_client.get(&url).send().await?.json().await?;
+        let response: serde_json::Value = self.http_client.get(&url)?.send().await?.json().await?;
 
         let models = response["models"]
             .as_array()
@@ -292,7 +356,7 @@ impl ModelManager {
         let url =
             "https://huggingface.co/api/models?filter=gguf&sort=downloads&direction=-1&limit=50";
 
-        let mut request = self.http_client.get(url);
+        let mut request = self.http_client.get(url)?;
         if let Some(token) = &self.config.hf_token {
             request = request.header("Authorization", format!("Bearer {}", token));
         }
@@ -358,6 +422,17 @@ impl ModelManager {
         Ok(models)
     }
 
+    /// Pair each model with its compatibility verdict for this machine
+    pub fn annotate(&self, models: Vec<ModelInfo>) -> Vec<ModelCompatibility> {
+        models
+            .into_iter()
+            .map(|model| ModelCompatibility {
+                compatibility: self.check_compatibility(&model),
+                model,
+            })
+            .collect()
+    }
+
     /// Download a model
     pub async fn download(&self, model: &ModelInfo) -> Result<PathBuf> {
         // Check compatibility first
@@ -381,11 +456,18 @@ impl ModelManager {
     async fn download_ollama(&self, model_id: &str) -> Result<PathBuf> {
         info!(model = model_id, "Pulling model from Ollama");
 
+        // Ollama is local, but the pull makes it fetch from a registry the
+        // egress policy has to allow too
+        let registry = reqwest::Url::parse(&ollama_registry(model_id))?;
+        if let Some(reason) = self.config.egress.check(&registry) {
+            return Err(Error::EgressDenied(format!("can't pull {}: {}", model_id, reason)).into());
+        }
+
         let url = format!("{}/api/pull", self.config.ollama_url);
-        let response = self
+        let mut response = self
             .http_client
-            .post(&url)
-            .json(&serde_json::json!({ "name": model_id }))
+            .post(&url)?
+            .json(&serde_json::json!({ "name": model_id, "stream": true }))
             .send()
             .await?;
 
@@ -393,10 +475,42 @@ impl ModelManager {
             return Err(anyhow!("Failed to pull model: {}", response.status()));
         }
 
+        // Ollama streams one JSON status object per line
+        let mut buffer = Vec::new();
+        let mut last_percent = None;
+        while let Some(chunk) = response.chunk().await? {
+            buffer.extend_from_slice(&chunk);
+            while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                let Ok(status) = serde_json::from_slice::<serde_json::Value>(&line) else {
+                    continue;
+                };
+                if let Some(error) = status.get("error").and_then(|e| e.as_str()) {
+                    return Err(anyhow!("Failed to pull model: {}", error));
+                }
+                if let Some(percent) = pull_progress_percent(&status) {
+                    if last_percent != Some(percent) {
+                        last_percent = Some(percent);
+                        self.report_progress(model_id, percent);
+                    }
+                }
+            }
+        }
+        self.report_progress(model_id, 100);
+
         // Ollama manages its own model storage
         Ok(PathBuf::from(format!("ollama://{}", model_id)))
     }
 
+    fn report_progress(&self, model_id: &str, percent: u8) {
+        if let Some(ref event_bus) = self.event_bus {
+            let _ = event_bus.send(SystemEvent::ModelDownloadProgress {
+                model: model_id.to_string(),
+                percent,
+            });
+        }
+    }
+
     async fn download_huggingface(&self, model_id: &str) -> Result<PathBuf> {
         info!(model = model_id, "Downloading model from Hugging Face");
 
@@ -465,13 +579,21 @@ impl ModelManager {
 }
 
 /// Result of hardware compatibility check
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
 pub enum CompatibilityResult {
     Compatible,
     CompatibleWithWarning { warning: String },
     Incompatible { reason: String },
 }
 
+/// A model together with its compatibility verdict for this machine
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelCompatibility {
+    pub model: ModelInfo,
+    pub compatibility: CompatibilityResult,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -482,6 +604,53 @@ mod tests {
         assert_eq!(config.default_backend, ModelBackend::Ollama);
     }
 
+    #[test]
+    fn test_pull_progress_percent() {
+        let status = serde_json::json!({"status": "pulling", "total": 200, "completed": 50});
+        assert_eq!(pull_progress_percent(&status), Some(25));
+
+        let status = serde_json::json!({"status": "verifying sha256 digest"});
+        assert_eq!(pull_progress_percent(&status), None);
+
+        let status = serde_json::json!({"status": "pulling", "total": 0, "completed": 0});
+        assert_eq!(pull_progress_percent(&status), None);
+    }
+
+    #[tokio::test]
+    async fn test_pull_respects_egress() {
+        assert_eq!(ollama_registry("phi3:mini"), "https://registry.ollama.ai/");
+        assert_eq!(
+            ollama_registry("library/phi3"),
+            "https://registry.ollama.ai/"
+        );
+        assert_eq!(ollama_registry("hf.co/user/model"), "https://hf.co/");
+
+        // Pure local mode: Ollama itself is reachable, its registry isn't
+        let config = ModelManagerConfig {
+            ollama_url: "http://127.0.0.1:9".to_string(),
+            egress: EgressPolicy {
+                allowlist: Some(Vec::new()),
+                denylist: Vec::new(),
+            },
+            ..Default::default()
+        };
+        let hardware = HardwareInfo {
+            total_ram_bytes: 16 * 1024 * 1024 * 1024,
+            available_ram_bytes: 12 * 1024 * 1024 * 1024,
+            gpu_vram_bytes: 0,
+            gpu_type: Some(GpuType::None),
+            cpu_cores: 8,
+            has_avx2: true,
+        };
+        let manager = ModelManager::with_hardware(config, hardware);
+        let err = manager.download_ollama("phi3:mini").await.unwrap_err();
+        assert!(
+            matches!(Error::find(&err), Some(Error::EgressDenied(_))),
+            "{}",
+            err
+        );
+    }
+
     #[test]
     fn test_compatibility_check() {
         let hardware = HardwareInfo {
@@ -509,8 +678,41 @@ mod tests {
             tags: vec![],
         };
 
-        // This would need a ModelManager instance to test properly
-        // For now, just verify the struct compiles
-        assert!(hardware.available_ram_bytes >= model.requirements.min_ram_bytes);
+        let manager = ModelManager::with_hardware(ModelManagerConfig::default(), hardware);
+        assert!(matches!(
+            manager.check_compatibility(&model),
+            CompatibilityResult::CompatibleWithWarning { .. }
+        ));
+    }
+
+    #[test]
+    fn test_incompatible_reports_reason() {
+        let hardware = HardwareInfo {
+            total_ram_bytes: 4 * 1024 * 1024 * 1024,
+            available_ram_bytes: 2 * 1024 * 1024 * 1024,
+            gpu_vram_bytes: 0,
+            gpu_type: Some(GpuType::None),
+            cpu_cores: 4,
+            has_avx2: true,
+        };
+        let manager = ModelManager::with_hardware(ModelManagerConfig::default(), hardware);
+
+        let models = vec![ModelInfo {
+            id: "llama3.1:70b".to_string(),
+            name: "llama3.1:70b".to_string(),
+            description: "Large".to_string(),
+            size_bytes: 40 * 1024 * 1024 * 1024,
+            backend: ModelBackend::Ollama,
+            requirements: ModelManager::estimate_ollama_requirements(40 * 1024 * 1024 * 1024),
+            tags: vec![],
+        }];
+
+        let annotated = manager.annotate(models);
+        match &annotated[0].compatibility {
+            CompatibilityResult::Incompatible { reason } => {
+                assert!(reason.contains("Insufficient RAM"))
+            }
+            other => panic!("expected incompatible, got {:?}", other),
+        }
     }
 }
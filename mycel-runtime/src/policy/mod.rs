@@ -6,11 +6,15 @@
 
 #![allow(dead_code)]
 
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tracing::{debug, warn};
 
+use crate::codegen::CodeLanguage;
 use crate::context::Context;
 use crate::intent::{ActionType, Intent};
+use crate::mcp;
 
 /// Result of policy evaluation
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,6 +30,86 @@ pub enum ActionPolicy {
     },
 }
 
+/// Why a piece of code was held back, pointing at the offending line
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ValidationError {
+    /// The risk pattern that matched
+    pub pattern: String,
+    /// 1-based line of the first match
+    pub line: usize,
+    /// The offending line, trimmed
+    pub snippet: String,
+    /// What to do instead
+    pub suggestion: String,
+    pub risk_level: RiskLevel,
+}
+
+impl ValidationError {
+    /// Locate the first occurrence of `pattern` (case-insensitive) in `code`
+    fn locate(code: &str, pattern: &str, risk_level: RiskLevel) -> Self {
+        /// Keep one-line minified code from flooding the message
+        const MAX_SNIPPET_CHARS: usize = 120;
+
+        let pattern_lower = pattern.to_lowercase();
+        let line_index = code
+            .lines()
+            .position(|line| line.to_lowercase().contains(&pattern_lower))
+            .unwrap_or(0);
+        let line = code.lines().nth(line_index).unwrap_or("").trim();
+        let snippet = if line.chars().count() > MAX_SNIPPET_CHARS {
+            let clipped: String = line.chars().take(MAX_SNIPPET_CHARS).collect();
+            format!("{}...", clipped)
+        } else {
+            line.to_string()
+        };
+
+        Self {
+            pattern: pattern.trim().to_string(),
+            line: line_index + 1,
+            snippet,
+            suggestion: suggestion_for(&pattern_lower).to_string(),
+            risk_level,
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "'{}' on line {}: {}",
+            self.pattern, self.line, self.snippet
+        )
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// A hint for code caught by a risk pattern (`pattern` is lowercase)
+fn suggestion_for(pattern: &str) -> &'static str {
+    let has = |needles: &[&str]| needles.iter().any(|n| pattern.contains(n));
+
+    if has(&["sudo"]) {
+        "Root access is restricted; ask for the system change directly so it goes through a confirmed tool."
+    } else if has(&["mkfs", "dd if=", "/dev/", "format disk"]) {
+        "Writing to devices can wipe disks; double-check the target before running."
+    } else if has(&["apt", "uninstall"]) {
+        "Removing packages changes the system; use the xbps tools instead."
+    } else if has(&["/etc/", "/boot/"]) {
+        "Changing system files can leave the machine unbootable; edit them through a confirmed tool."
+    } else if has(&["child_process", "exec.command", "system(", "%x("]) {
+        "Spawning processes is restricted; use the shell_command tool instead."
+    } else if has(&["chmod", "chown"]) {
+        "Changing permissions is restricted; use the file tools instead."
+    } else if has(&["rm", "unlink", "remove", "delete", "truncate"]) {
+        "Deleting files is restricted; use the file tools instead so the change can be confirmed."
+    } else if has(&["drop database"]) {
+        "This destroys data permanently; make a backup first."
+    } else {
+        "Review this line before running the code."
+    }
+}
+
 /// Risk level for actions requiring confirmation
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum RiskLevel {
@@ -39,10 +123,13 @@ pub enum RiskLevel {
 #[derive(Clone)]
 pub struct PolicyEvaluator {
     config: PolicyConfig,
+    /// Compiled form of `config.blocked_file_patterns`
+    blocked_globs: GlobSet,
 }
 
-/// Policy configuration
-#[derive(Clone, Debug)]
+/// Policy configuration (the `[policy]` section of the runtime config)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PolicyConfig {
     /// Require confirmation for destructive file operations
     pub confirm_destructive_file_ops: bool,
@@ -56,6 +143,28 @@ pub struct PolicyConfig {
     pub max_file_size_bytes: u64,
     /// Blocked file patterns (glob patterns)
     pub blocked_file_patterns: Vec<String>,
+    /// Requested actions that always need confirmation (critical risk).
+    /// This and the other risk pattern lists add to the built-in ones
+    /// rather than replacing them.
+    pub critical_action_patterns: Vec<String>,
+    /// Requested actions that need confirmation (high risk)
+    pub high_risk_action_patterns: Vec<String>,
+    /// Code snippets that always need confirmation (critical risk)
+    pub critical_code_patterns: Vec<String>,
+    /// Code snippets that need confirmation (high risk)
+    pub high_risk_code_patterns: Vec<String>,
+    /// System actions treated as system modifications
+    pub system_modification_patterns: Vec<String>,
+    /// System actions treated as destructive file operations
+    pub destructive_file_patterns: Vec<String>,
+    /// MCP tools that may never be called
+    pub denied_tools: Vec<String>,
+    /// MCP tools that always need confirmation, whatever their server config says
+    pub confirm_tools: Vec<String>,
+}
+
+fn strings(items: &[&str]) -> Vec<String> {
+    items.iter().map(|s| s.to_string()).collect()
 }
 
 impl Default for PolicyConfig {
@@ -73,13 +182,167 @@ impl Default for PolicyConfig {
                 "~/.gnupg/*".to_string(),
                 "/root/*".to_string(),
             ],
+            critical_action_patterns: strings(&[
+                "rm -rf",
+                "delete all",
+                "drop database",
+                "format disk",
+                "sudo rm",
+                "dd if=",
+            ]),
+            high_risk_action_patterns: strings(&[
+                "delete",
+                "remove",
+                "uninstall",
+                "modify system",
+                "change config",
+            ]),
+            critical_code_patterns: strings(DESTRUCTIVE_COMMAND_PATTERNS),
+            high_risk_code_patterns: strings(&[
+                "chmod -r 777",
+                "chown -r",
+                "mv /etc/",
+                "cp /etc/",
+                "mv /boot/",
+                "rm ",
+                "apt-get remove",
+                "apt remove",
+                "uninstall",
+            ]),
+            system_modification_patterns: strings(&[
+                "install",
+                "uninstall",
+                "update system",
+                "change setting",
+                "modify config",
+                "create user",
+                "delete user",
+                "chmod",
+                "chown",
+            ]),
+            destructive_file_patterns: strings(&[
+                "delete",
+                "remove",
+                "overwrite",
+                "truncate",
+                "rm ",
+            ]),
+            denied_tools: Vec::new(),
+            confirm_tools: Vec::new(),
+        }
+    }
+}
+
+/// Shell fragments that can wreck a system (matched lowercase); the default
+/// `critical_code_patterns` and the MCP tool risk assessment share this list
+pub const DESTRUCTIVE_COMMAND_PATTERNS: &[&str] = &[
+    "rm -rf",
+    "sudo ",
+    "mkfs",
+    "dd if=",
+    "> /dev/",
+    "drop database",
+    "format disk",
+    ":(){ :|:& };:", // Fork bomb
+];
+
+/// Python APIs that delete or rewrite files (matched lowercase)
+const PYTHON_RISK_PATTERNS: &[&str] = &[
+    "shutil.rmtree",
+    "os.remove(",
+    "os.unlink(",
+    "os.rmdir(",
+    "os.chmod(",
+    ".unlink(",
+];
+
+/// Node.js APIs that delete files or spawn processes (matched lowercase)
+const JS_RISK_PATTERNS: &[&str] = &[
+    "fs.rmsync",
+    "fs.rm(",
+    "fs.unlinksync",
+    "fs.unlink(",
+    "fs.rmdirsync",
+    "child_process",
+];
+
+/// Go APIs that delete files or spawn processes (matched lowercase)
+const GO_RISK_PATTERNS: &[&str] = &[
+    "os.remove(",
+    "os.removeall(",
+    "os.truncate(",
+    "os.chmod(",
+    "exec.command(",
+];
+
+/// Ruby APIs that delete files or spawn processes (matched lowercase)
+const RUBY_RISK_PATTERNS: &[&str] = &[
+    "fileutils.rm",
+    "file.delete(",
+    "file.unlink(",
+    "dir.rmdir(",
+    "file.chmod(",
+    "system(",
+    "exec(",
+    "spawn(",
+    "io.popen",
+    "open3",
+    "%x",
+    "`",
+];
+
+/// Find the first pattern contained in `text` (patterns compare case-insensitively)
+fn find_pattern<'a>(text: &str, patterns: &'a [String]) -> Option<&'a str> {
+    patterns
+        .iter()
+        .find(|p| text.contains(p.to_lowercase().as_str()))
+        .map(|p| p.as_str())
+}
+
+/// Append the `builtin` patterns `patterns` doesn't already have
+fn add_missing(patterns: &mut Vec<String>, builtin: Vec<String>) {
+    for pattern in builtin {
+        if !patterns.contains(&pattern) {
+            patterns.push(pattern);
         }
     }
 }
 
 impl PolicyEvaluator {
-    pub fn new(config: PolicyConfig) -> Self {
-        Self { config }
+    pub fn new(mut config: PolicyConfig) -> Self {
+        // The built-in risk patterns are a floor; a configured list adds to
+        // them, so naming one new pattern can't drop `rm -rf`
+        let defaults = PolicyConfig::default();
+        add_missing(
+            &mut config.critical_action_patterns,
+            defaults.critical_action_patterns,
+        );
+        add_missing(
+            &mut config.high_risk_action_patterns,
+            defaults.high_risk_action_patterns,
+        );
+        add_missing(
+            &mut config.critical_code_patterns,
+            defaults.critical_code_patterns,
+        );
+        add_missing(
+            &mut config.high_risk_code_patterns,
+            defaults.high_risk_code_patterns,
+        );
+        add_missing(
+            &mut config.system_modification_patterns,
+            defaults.system_modification_patterns,
+        );
+        add_missing(
+            &mut config.destructive_file_patterns,
+            defaults.destructive_file_patterns,
+        );
+
+        let blocked_globs = build_glob_set(&config.blocked_file_patterns);
+        Self {
+            config,
+            blocked_globs,
+        }
     }
 
     pub fn with_defaults() -> Self {
@@ -107,56 +370,96 @@ impl PolicyEvaluator {
             };
         }
 
-        let code_lower = code.to_lowercase();
-
-        // Critical patterns - always require confirmation
-        let critical_patterns = [
-            "rm -rf",
-            "sudo ",
-            "mkfs",
-            "dd if=",
-            "> /dev/",
-            "drop database",
-            "format disk",
-            ":(){ :|:& };:", // Fork bomb
-        ];
-
-        for pattern in critical_patterns {
-            if code_lower.contains(pattern) {
-                warn!(pattern = pattern, "Critical code pattern detected");
-                return ActionPolicy::RequiresConfirmation {
+        match self.find_code_issue(code) {
+            Some(issue) if issue.risk_level == RiskLevel::Critical => {
+                warn!(pattern = %issue.pattern, line = issue.line, "Critical code pattern detected");
+                ActionPolicy::RequiresConfirmation {
                     message: format!(
-                        "Dangerous command detected: '{}'. This could cause permanent data loss. Proceed?",
-                        pattern.trim()
+                        "Dangerous command detected: {}. This could cause permanent data loss. {} Proceed?",
+                        issue, issue.suggestion
                     ),
                     risk_level: RiskLevel::Critical,
-                };
+                }
             }
+            Some(issue) => ActionPolicy::RequiresConfirmation {
+                message: format!(
+                    "Potentially risky command: {}. {} Proceed?",
+                    issue, issue.suggestion
+                ),
+                risk_level: issue.risk_level,
+            },
+            None => ActionPolicy::Allow,
         }
+    }
 
-        // High-risk patterns
-        let high_risk_patterns = [
-            "chmod -r 777",
-            "chown -r",
-            "mv /etc/",
-            "cp /etc/",
-            "mv /boot/",
-            "rm ",
-            "apt-get remove",
-            "apt remove",
-            "uninstall",
-        ];
-
-        for pattern in high_risk_patterns {
-            if code_lower.contains(pattern) {
-                return ActionPolicy::RequiresConfirmation {
-                    message: format!("Potentially risky command: '{}'. Proceed?", pattern.trim()),
-                    risk_level: RiskLevel::High,
-                };
-            }
+    /// First shell-level risk pattern in `code`, critical patterns first
+    fn find_code_issue(&self, code: &str) -> Option<ValidationError> {
+        let code_lower = code.to_lowercase();
+
+        if let Some(pattern) = find_pattern(&code_lower, &self.config.critical_code_patterns) {
+            return Some(ValidationError::locate(code, pattern, RiskLevel::Critical));
         }
+        find_pattern(&code_lower, &self.config.high_risk_code_patterns)
+            .map(|pattern| ValidationError::locate(code, pattern, RiskLevel::High))
+    }
 
-        ActionPolicy::Allow
+    /// Check code against the shell and language risk patterns.
+    ///
+    /// Unlike `evaluate_generated_code` this ignores whether execution is
+    /// allowed at all, and reports where the problem is so a client can
+    /// show it next to the code.
+    pub fn validate_code(
+        &self,
+        code: &str,
+        language: CodeLanguage,
+    ) -> std::result::Result<(), ValidationError> {
+        if let Some(issue) = self.find_code_issue(code) {
+            return Err(issue);
+        }
+
+        let code_lower = code.to_lowercase();
+        let language_patterns: &[&str] = match language {
+            CodeLanguage::Python => PYTHON_RISK_PATTERNS,
+            CodeLanguage::JavaScript | CodeLanguage::TypeScript => JS_RISK_PATTERNS,
+            CodeLanguage::Go => GO_RISK_PATTERNS,
+            CodeLanguage::Ruby => RUBY_RISK_PATTERNS,
+            // Detection is heuristic, so check everything when unsure
+            CodeLanguage::Unknown => &[PYTHON_RISK_PATTERNS, JS_RISK_PATTERNS].concat(),
+            _ => &[],
+        };
+
+        match language_patterns.iter().find(|p| code_lower.contains(*p)) {
+            Some(pattern) => Err(ValidationError::locate(code, pattern, RiskLevel::High)),
+            None => Ok(()),
+        }
+    }
+
+    /// Evaluate the body of generated code before it runs.
+    ///
+    /// Applies the shell patterns from `evaluate_code` (scripts can shell out)
+    /// plus file-destroying APIs of the detected language.
+    pub fn evaluate_generated_code(&self, code: &str, language: CodeLanguage) -> ActionPolicy {
+        let policy = self.evaluate_code(code);
+        if !matches!(policy, ActionPolicy::Allow) {
+            return policy;
+        }
+
+        match self.validate_code(code, language) {
+            Ok(()) => ActionPolicy::Allow,
+            Err(issue) => {
+                warn!(pattern = %issue.pattern, line = issue.line, "Risky API in generated code");
+                ActionPolicy::RequiresConfirmation {
+                    message: format!(
+                        "Generated code uses '{}' on line {}: {}. It can delete or change files. {} Proceed?",
+                        issue.pattern.trim_end_matches('('),
+                        issue.line,
+                        issue.snippet,
+                        issue.suggestion
+                    ),
+                    risk_level: issue.risk_level,
+                }
+            }
+        }
     }
 
     fn evaluate_code_execution(&self, intent: &Intent, _context: &Context) -> ActionPolicy {
@@ -170,47 +473,26 @@ impl PolicyEvaluator {
         let action_lower = intent.action.to_lowercase();
 
         // Critical patterns - always require confirmation
-        let critical_patterns = [
-            "rm -rf",
-            "delete all",
-            "drop database",
-            "format disk",
-            "sudo rm",
-            "dd if=",
-        ];
-
-        for pattern in critical_patterns {
-            if action_lower.contains(pattern) {
-                warn!(pattern = pattern, "Critical action pattern detected");
-                return ActionPolicy::RequiresConfirmation {
-                    message: format!(
-                        "This action contains a potentially dangerous pattern: '{}'. Are you sure you want to proceed?",
-                        pattern
-                    ),
-                    risk_level: RiskLevel::Critical,
-                };
-            }
+        if let Some(pattern) = find_pattern(&action_lower, &self.config.critical_action_patterns) {
+            warn!(pattern = pattern, "Critical action pattern detected");
+            return ActionPolicy::RequiresConfirmation {
+                message: format!(
+                    "This action contains a potentially dangerous pattern: '{}'. Are you sure you want to proceed?",
+                    pattern
+                ),
+                risk_level: RiskLevel::Critical,
+            };
         }
 
         // High-risk patterns
-        let high_risk_patterns = [
-            "delete",
-            "remove",
-            "uninstall",
-            "modify system",
-            "change config",
-        ];
-
-        for pattern in high_risk_patterns {
-            if action_lower.contains(pattern) {
-                return ActionPolicy::RequiresConfirmation {
-                    message: format!(
-                        "This action will {}: Please confirm you want to proceed.",
-                        pattern
-                    ),
-                    risk_level: RiskLevel::High,
-                };
-            }
+        if let Some(pattern) = find_pattern(&action_lower, &self.config.high_risk_action_patterns) {
+            return ActionPolicy::RequiresConfirmation {
+                message: format!(
+                    "This action will {}: Please confirm you want to proceed.",
+                    pattern
+                ),
+                risk_level: RiskLevel::High,
+            };
         }
 
         ActionPolicy::Allow
@@ -219,11 +501,13 @@ impl PolicyEvaluator {
     fn evaluate_system_action(&self, intent: &Intent, _context: &Context) -> ActionPolicy {
         let action_lower = intent.action.to_lowercase();
 
-        // Check for blocked file patterns
-        for blocked in &self.config.blocked_file_patterns {
-            let pattern_lower = blocked.to_lowercase();
-            // Simple check - in production would use glob matching
-            if action_lower.contains(&pattern_lower.replace("*", "")) {
+        // Check every path-like word of the action against the blocked patterns
+        for word in intent.action.split_whitespace() {
+            let candidate = word.trim_matches(|c| matches!(c, '"' | '\'' | '`' | ',' | ';'));
+            if !candidate.starts_with('/') && !candidate.starts_with('~') {
+                continue;
+            }
+            if let Some(blocked) = self.blocked_pattern_for(candidate) {
                 return ActionPolicy::Deny {
                     reason: format!("Access to '{}' is blocked by security policy", blocked),
                 };
@@ -232,65 +516,132 @@ impl PolicyEvaluator {
 
         // System modification checks
         if self.config.confirm_system_modifications {
-            let system_patterns = [
-                "install",
-                "uninstall",
-                "update system",
-                "change setting",
-                "modify config",
-                "create user",
-                "delete user",
-                "chmod",
-                "chown",
-            ];
-
-            for pattern in system_patterns {
-                if action_lower.contains(pattern) {
-                    return ActionPolicy::RequiresConfirmation {
-                        message: format!(
-                            "This will modify system settings ({}). Continue?",
-                            pattern
-                        ),
-                        risk_level: RiskLevel::Medium,
-                    };
-                }
+            if let Some(pattern) =
+                find_pattern(&action_lower, &self.config.system_modification_patterns)
+            {
+                return ActionPolicy::RequiresConfirmation {
+                    message: format!("This will modify system settings ({}). Continue?", pattern),
+                    risk_level: RiskLevel::Medium,
+                };
             }
         }
 
         // Destructive file operations
-        if self.config.confirm_destructive_file_ops {
-            let destructive_patterns = ["delete", "remove", "overwrite", "truncate", "rm "];
-
-            for pattern in destructive_patterns {
-                if action_lower.contains(pattern) {
-                    return ActionPolicy::RequiresConfirmation {
-                        message: "This will delete or modify files. Continue?".to_string(),
-                        risk_level: RiskLevel::Medium,
+        if self.config.confirm_destructive_file_ops
+            && find_pattern(&action_lower, &self.config.destructive_file_patterns).is_some()
+        {
+            return ActionPolicy::RequiresConfirmation {
+                message: "This will delete or modify files. Continue?".to_string(),
+                risk_level: RiskLevel::Medium,
+            };
+        }
+
+        ActionPolicy::Allow
+    }
+
+    /// Evaluate an MCP tool call before it is dispatched to its server
+    pub fn evaluate_tool_call(
+        &self,
+        tool_name: &str,
+        arguments: &HashMap<String, serde_json::Value>,
+        risk_level: mcp::RiskLevel,
+    ) -> ActionPolicy {
+        if self.config.denied_tools.iter().any(|t| t == tool_name) {
+            return ActionPolicy::Deny {
+                reason: format!("Tool '{}' is disabled by security policy", tool_name),
+            };
+        }
+
+        for value in arguments.values() {
+            let Some(text) = value.as_str() else {
+                continue;
+            };
+            // Path-like arguments must not touch blocked files
+            if text.starts_with('/') || text.starts_with('~') {
+                if let Some(blocked) = self.blocked_pattern_for(text) {
+                    return ActionPolicy::Deny {
+                        reason: format!("Access to '{}' is blocked by security policy", blocked),
                     };
                 }
             }
         }
 
+        // Commands and scripts passed to a tool get the same scrutiny as generated code
+        for key in ["command", "code", "script"] {
+            if let Some(code) = arguments.get(key).and_then(|v| v.as_str()) {
+                let policy = self.evaluate_code(code);
+                if !matches!(policy, ActionPolicy::Allow) {
+                    return policy;
+                }
+            }
+        }
+
+        if self.config.confirm_tools.iter().any(|t| t == tool_name) {
+            return ActionPolicy::RequiresConfirmation {
+                message: format!(
+                    "Tool '{}' requires confirmation by policy. Proceed?",
+                    tool_name
+                ),
+                risk_level: match risk_level {
+                    mcp::RiskLevel::Low => RiskLevel::Low,
+                    mcp::RiskLevel::Medium => RiskLevel::Medium,
+                    mcp::RiskLevel::High => RiskLevel::High,
+                },
+            };
+        }
+
         ActionPolicy::Allow
     }
 
     /// Check if a specific file path is allowed
     pub fn is_path_allowed(&self, path: &str) -> bool {
-        for blocked in &self.config.blocked_file_patterns {
-            // Simple substring match - would use glob in production
-            let normalized_blocked = blocked.replace(
-                "~",
-                &dirs::home_dir()
-                    .map(|p| p.to_string_lossy().to_string())
-                    .unwrap_or_default(),
-            );
+        self.blocked_pattern_for(path).is_none()
+    }
 
-            if path.starts_with(&normalized_blocked.replace("*", "")) {
-                return false;
-            }
+    /// Largest file a built-in file tool may read or write
+    pub fn max_file_size_bytes(&self) -> u64 {
+        self.config.max_file_size_bytes
+    }
+
+    /// The first blocked pattern matching `path`, if any. A `dir/*`
+    /// pattern also blocks `dir` itself, whose listing would show what's in it.
+    fn blocked_pattern_for(&self, path: &str) -> Option<&str> {
+        let expanded = expand_home(path);
+        let as_dir = format!("{}/", expanded.trim_end_matches('/'));
+        let index = [expanded.as_str(), as_dir.as_str()]
+            .into_iter()
+            .find_map(|p| self.blocked_globs.matches(p).first().copied())?;
+        Some(self.config.blocked_file_patterns[index].as_str())
+    }
+}
+
+/// Expand a leading `~` to the user's home directory
+fn expand_home(path: &str) -> String {
+    if path == "~" || path.starts_with("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return format!("{}{}", home.to_string_lossy(), &path[1..]);
         }
-        true
     }
+    path.to_string()
+}
+
+/// Compile blocked file patterns, skipping (and logging) invalid ones.
+///
+/// Indices in the resulting set line up with `patterns`; an invalid pattern
+/// is replaced by one that can never match so the mapping stays intact.
+fn build_glob_set(patterns: &[String]) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(&expand_home(pattern)).unwrap_or_else(|e| {
+            warn!(pattern = %pattern, error = %e, "Ignoring invalid blocked file pattern");
+            Glob::new("\0").expect("literal glob is valid")
+        });
+        builder.add(glob);
+    }
+    builder.build().unwrap_or_else(|e| {
+        warn!(error = %e, "Failed to compile blocked file patterns");
+        GlobSet::empty()
+    })
 }
 
 #[cfg(test)]
@@ -316,7 +667,9 @@ mod tests {
             timestamp: chrono::Utc::now(),
             user_name: None,
             user_preferences: std::collections::HashMap::new(),
+            frequently_used: vec![],
             pending_command: None,
+            pending_tool_call: None,
         }
     }
 
@@ -366,6 +719,53 @@ mod tests {
         assert!(evaluator.is_path_allowed("/tmp/test.txt"));
     }
 
+    fn evaluator_blocking(patterns: &[&str]) -> PolicyEvaluator {
+        PolicyEvaluator::new(PolicyConfig {
+            blocked_file_patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_blocked_home_glob() {
+        let evaluator = evaluator_blocking(&["~/.ssh/*"]);
+        assert!(!evaluator.is_path_allowed("~/.ssh/id_rsa"));
+        assert!(!evaluator.is_path_allowed(&expand_home("~/.ssh/id_rsa")));
+        assert!(evaluator.is_path_allowed("~/.sshconfig"));
+        assert!(evaluator.is_path_allowed(&expand_home("~/.sshconfig")));
+
+        // The directory itself, with or without a trailing slash
+        assert!(!evaluator.is_path_allowed("~/.ssh"));
+        assert!(!evaluator.is_path_allowed(&expand_home("~/.ssh/")));
+        assert!(evaluator.is_path_allowed("~"));
+    }
+
+    #[test]
+    fn test_blocked_root_glob() {
+        let evaluator = evaluator_blocking(&["/root/*"]);
+        assert!(!evaluator.is_path_allowed("/root/anything"));
+        assert!(evaluator.is_path_allowed("/rootfs"));
+        assert!(evaluator.is_path_allowed("/rootfs/etc/hosts"));
+    }
+
+    #[test]
+    fn test_system_action_blocked_path() {
+        let evaluator = evaluator_blocking(&["~/.ssh/*", "/root/*"]);
+        let context = test_context();
+
+        let intent = test_intent("show me ~/.ssh/id_rsa", ActionType::SystemAction);
+        assert!(matches!(
+            evaluator.evaluate(&intent, &context),
+            ActionPolicy::Deny { .. }
+        ));
+
+        let intent = test_intent("list files in /rootfs", ActionType::SystemAction);
+        assert!(matches!(
+            evaluator.evaluate(&intent, &context),
+            ActionPolicy::Allow
+        ));
+    }
+
     #[test]
     fn test_code_execution_disabled() {
         let config = PolicyConfig {
@@ -382,6 +782,233 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_harmless_intent_dangerous_code() {
+        let evaluator = PolicyEvaluator::with_defaults();
+        let intent = test_intent("tidy up my downloads folder", ActionType::GenerateCode);
+
+        // The description alone passes the intent-level gate...
+        assert!(matches!(
+            evaluator.evaluate(&intent, &test_context()),
+            ActionPolicy::Allow
+        ));
+
+        // ...but the code it produced does not
+        let code = "import shutil\nshutil.rmtree('/home/user')";
+        assert!(matches!(
+            evaluator.evaluate_generated_code(code, CodeLanguage::detect(code)),
+            ActionPolicy::RequiresConfirmation {
+                risk_level: RiskLevel::High,
+                ..
+            }
+        ));
+
+        let code = "import os\nos.system('rm -rf ~/Downloads')";
+        assert!(matches!(
+            evaluator.evaluate_generated_code(code, CodeLanguage::Python),
+            ActionPolicy::RequiresConfirmation {
+                risk_level: RiskLevel::Critical,
+                ..
+            }
+        ));
+
+        let code = "const fs = require('fs');\nfs.rmSync('/tmp/x', { recursive: true });";
+        assert!(matches!(
+            evaluator.evaluate_generated_code(code, CodeLanguage::JavaScript),
+            ActionPolicy::RequiresConfirmation { .. }
+        ));
+
+        let code = "print(sum(range(10)))";
+        assert!(matches!(
+            evaluator.evaluate_generated_code(code, CodeLanguage::Python),
+            ActionPolicy::Allow
+        ));
+    }
+
+    #[test]
+    fn test_validation_error_points_at_line() {
+        let evaluator = PolicyEvaluator::with_defaults();
+
+        let code = "import os\n\nnames = os.listdir('/tmp')\nos.remove('/tmp/old.log')\n";
+        let issue = evaluator
+            .validate_code(code, CodeLanguage::Python)
+            .unwrap_err();
+        assert_eq!(issue.pattern, "os.remove(");
+        assert_eq!(issue.line, 4);
+        assert_eq!(issue.snippet, "os.remove('/tmp/old.log')");
+        assert!(issue.suggestion.contains("file tools"));
+
+        let code = "echo start\n  sudo reboot";
+        let issue = evaluator
+            .validate_code(code, CodeLanguage::Shell)
+            .unwrap_err();
+        assert_eq!((issue.line, issue.risk_level), (2, RiskLevel::Critical));
+        assert_eq!(issue.snippet, "sudo reboot");
+
+        match evaluator.evaluate_generated_code(
+            "x = 1\nimport shutil\nshutil.rmtree('/data')",
+            CodeLanguage::Python,
+        ) {
+            ActionPolicy::RequiresConfirmation { message, .. } => {
+                assert!(message.contains("line 3"), "{}", message);
+                assert!(message.contains("shutil.rmtree('/data')"), "{}", message);
+            }
+            other => panic!("Expected RequiresConfirmation, got {:?}", other),
+        }
+
+        assert!(evaluator
+            .validate_code("print(sum(range(10)))", CodeLanguage::Python)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_generated_go_and_ruby_policy() {
+        let evaluator = PolicyEvaluator::with_defaults();
+
+        let code =
+            "package main\n\nimport \"os\"\n\nfunc main() {\n\tos.RemoveAll(\"/tmp/cache\")\n}";
+        assert_eq!(CodeLanguage::detect(code), CodeLanguage::Go);
+        assert!(matches!(
+            evaluator.evaluate_generated_code(code, CodeLanguage::Go),
+            ActionPolicy::RequiresConfirmation { .. }
+        ));
+
+        let code = "require 'fileutils'\nFileUtils.rm_r('/tmp/cache')";
+        assert_eq!(CodeLanguage::detect(code), CodeLanguage::Ruby);
+        assert!(matches!(
+            evaluator.evaluate_generated_code(code, CodeLanguage::Ruby),
+            ActionPolicy::RequiresConfirmation { .. }
+        ));
+
+        let code = "package main\n\nimport \"fmt\"\n\nfunc main() {\n\tfmt.Println(42)\n}";
+        assert!(matches!(
+            evaluator.evaluate_generated_code(code, CodeLanguage::Go),
+            ActionPolicy::Allow
+        ));
+        assert!(matches!(
+            evaluator.evaluate_generated_code("puts [1, 2].sum", CodeLanguage::Ruby),
+            ActionPolicy::Allow
+        ));
+
+        // Every way Ruby spawns a process needs confirmation
+        for code in [
+            "puts `whoami`",
+            "puts %x{whoami}",
+            "exec('whoami')",
+            "pid = spawn('whoami')",
+            "IO.popen('whoami').read",
+            "require 'open3'\nOpen3.capture2('whoami')",
+        ] {
+            assert!(
+                matches!(
+                    evaluator.evaluate_generated_code(code, CodeLanguage::Ruby),
+                    ActionPolicy::RequiresConfirmation { .. }
+                ),
+                "{}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn test_tool_call_policy() {
+        let evaluator = PolicyEvaluator::new(PolicyConfig {
+            denied_tools: vec!["xbps_remove".to_string()],
+            confirm_tools: vec!["xbps_install".to_string()],
+            blocked_file_patterns: vec!["/etc/shadow".to_string()],
+            ..Default::default()
+        });
+        let args = |pairs: &[(&str, &str)]| -> HashMap<String, serde_json::Value> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), serde_json::json!(v)))
+                .collect()
+        };
+
+        assert!(matches!(
+            evaluator.evaluate_tool_call(
+                "xbps_remove",
+                &args(&[("package", "vim")]),
+                mcp::RiskLevel::High
+            ),
+            ActionPolicy::Deny { .. }
+        ));
+        assert!(matches!(
+            evaluator.evaluate_tool_call(
+                "xbps_install",
+                &args(&[("package", "vim")]),
+                mcp::RiskLevel::Medium
+            ),
+            ActionPolicy::RequiresConfirmation {
+                risk_level: RiskLevel::Medium,
+                ..
+            }
+        ));
+        assert!(matches!(
+            evaluator.evaluate_tool_call(
+                "file_read",
+                &args(&[("path", "/etc/shadow")]),
+                mcp::RiskLevel::Low
+            ),
+            ActionPolicy::Deny { .. }
+        ));
+        assert!(matches!(
+            evaluator.evaluate_tool_call(
+                "shell_command",
+                &args(&[("command", "rm -rf /home")]),
+                mcp::RiskLevel::High
+            ),
+            ActionPolicy::RequiresConfirmation {
+                risk_level: RiskLevel::Critical,
+                ..
+            }
+        ));
+        assert!(matches!(
+            evaluator.evaluate_tool_call(
+                "shell_command",
+                &args(&[("command", "ls -la")]),
+                mcp::RiskLevel::High
+            ),
+            ActionPolicy::Allow
+        ));
+    }
+
+    #[test]
+    fn test_policy_from_toml() {
+        let config: crate::config::MycelConfig = toml::from_str(
+            r#"
+            [policy]
+            critical_code_patterns = ["curl | sh"]
+            "#,
+        )
+        .unwrap();
+        let evaluator = PolicyEvaluator::new(config.policy);
+
+        match evaluator.evaluate_code("curl | sh") {
+            ActionPolicy::RequiresConfirmation { risk_level, .. } => {
+                assert_eq!(risk_level, RiskLevel::Critical);
+            }
+            _ => panic!("Expected RequiresConfirmation for custom pattern"),
+        }
+
+        // The built-in patterns still apply alongside the custom one
+        for code in ["rm -rf /", "mkfs.ext4 /dev/sda"] {
+            match evaluator.evaluate_code(code) {
+                ActionPolicy::RequiresConfirmation { risk_level, .. } => {
+                    assert_eq!(risk_level, RiskLevel::Critical, "{}", code);
+                }
+                _ => panic!("Expected RequiresConfirmation for {}", code),
+            }
+        }
+
+        // Lists not mentioned in the section keep their defaults
+        assert!(matches!(
+            evaluator.evaluate_code("chmod -R 777 /home/user"),
+            ActionPolicy::RequiresConfirmation { .. }
+        ));
+        assert!(!evaluator.is_path_allowed("/etc/shadow"));
+    }
+
     #[test]
     fn test_evaluate_dangerous_code() {
         let evaluator = PolicyEvaluator::with_defaults();
@@ -10,11 +10,14 @@ use anyhow::{anyhow, Result};
 use chrono::{DateTime, Utc};
 use mdns_sd::{ServiceDaemon, ServiceInfo};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::net::UdpSocket;
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 use x25519_dalek::{PublicKey, StaticSecret};
 use chacha20poly1305::{
@@ -99,6 +102,144 @@ pub enum SyncOperation {
         language: String,
         code: String,
     },
+    /// The sender replaced its device key; peers should re-key it. Sent
+    /// encrypted under the old key, which is what authenticates it.
+    RotateKey {
+        old_key: String,
+        new_key: String,
+    },
+}
+
+impl SyncOperation {
+    /// Variant name, e.g. `AddConversationTurn`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::AddConversationTurn { .. } => "AddConversationTurn",
+            Self::UpdatePreference { .. } => "UpdatePreference",
+            Self::AddLearnedPattern { .. } => "AddLearnedPattern",
+            Self::AddCapability { .. } => "AddCapability",
+            Self::RotateKey { .. } => "RotateKey",
+        }
+    }
+
+    /// What the operation carries, with free text reduced to its length
+    fn redacted_summary(&self) -> String {
+        match self {
+            Self::AddConversationTurn {
+                session_id,
+                user,
+                assistant,
+            } => format!(
+                "conversation turn in session {}: your message ({} chars) and the reply ({} chars)",
+                session_id.chars().take(8).collect::<String>(),
+                user.chars().count(),
+                assistant.chars().count()
+            ),
+            Self::UpdatePreference { key, .. } => format!("preference '{}' and its value", key),
+            Self::AddLearnedPattern { trigger, action } => format!(
+                "learned pattern: a trigger ({} chars) and an action ({} chars)",
+                trigger.chars().count(),
+                action.chars().count()
+            ),
+            Self::AddCapability {
+                name,
+                language,
+                code,
+            } => format!(
+                "capability '{}' ({} code, {} bytes)",
+                name,
+                language,
+                code.len()
+            ),
+            Self::RotateKey { .. } => "device key rotation (public keys only)".to_string(),
+        }
+    }
+}
+
+/// What one sync event shares with peers, without the content itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncPreview {
+    /// `None` for an event that hasn't been created
+    pub event_id: Option<String>,
+    pub timestamp: Option<DateTime<Utc>>,
+    /// [`SyncOperation::kind`]
+    pub operation: String,
+    pub summary: String,
+    /// Carries what the user said to the assistant or what it answered
+    pub contains_conversation: bool,
+}
+
+impl SyncPreview {
+    fn new(operation: &SyncOperation, event: Option<&SyncEvent>) -> Self {
+        Self {
+            event_id: event.map(|e| e.id.clone()),
+            timestamp: event.map(|e| e.timestamp),
+            operation: operation.kind().to_string(),
+            summary: operation.redacted_summary(),
+            contains_conversation: matches!(operation, SyncOperation::AddConversationTurn { .. }),
+        }
+    }
+}
+
+/// Where a sync operation can be sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncDestination {
+    /// This user's devices on the LAN, over the encrypted mesh
+    Mesh,
+    /// The NEAR registry, published by blockchain sync
+    Blockchain,
+}
+
+/// Which destinations each kind of operation is sent to (`[sync_policy]`
+/// in the config). An empty list keeps that kind on this device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncPolicy {
+    /// What the user said and what the assistant answered; mesh only by
+    /// default, since anything published to the blockchain is public
+    #[serde(default = "mesh_only")]
+    pub conversation_turns: Vec<SyncDestination>,
+    #[serde(default = "everywhere")]
+    pub preferences: Vec<SyncDestination>,
+    #[serde(default = "everywhere")]
+    pub learned_patterns: Vec<SyncDestination>,
+    #[serde(default = "everywhere")]
+    pub capabilities: Vec<SyncDestination>,
+}
+
+fn mesh_only() -> Vec<SyncDestination> {
+    vec![SyncDestination::Mesh]
+}
+
+fn everywhere() -> Vec<SyncDestination> {
+    vec![SyncDestination::Mesh, SyncDestination::Blockchain]
+}
+
+impl Default for SyncPolicy {
+    fn default() -> Self {
+        Self {
+            conversation_turns: mesh_only(),
+            preferences: everywhere(),
+            learned_patterns: everywhere(),
+            capabilities: everywhere(),
+        }
+    }
+}
+
+impl SyncPolicy {
+    /// Whether `operation` may be sent to `destination`. Key rotations
+    /// carry only public keys and always go out, or peers lose track of
+    /// this device.
+    pub fn allows(&self, operation: &SyncOperation, destination: SyncDestination) -> bool {
+        let destinations = match operation {
+            SyncOperation::AddConversationTurn { .. } => &self.conversation_turns,
+            SyncOperation::UpdatePreference { .. } => &self.preferences,
+            SyncOperation::AddLearnedPattern { .. } => &self.learned_patterns,
+            SyncOperation::AddCapability { .. } => &self.capabilities,
+            SyncOperation::RotateKey { .. } => return true,
+        };
+        destinations.contains(&destination)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -108,6 +249,15 @@ pub struct SyncConfig {
     pub device_name: String,
     pub blockchain_sync: bool,
     pub near_account: Option<String>,
+    pub policy: SyncPolicy,
+    /// Days conversation turns stay in the log before compaction drops them
+    pub retention_days: u32,
+}
+
+impl SyncConfig {
+    fn retention(&self) -> chrono::Duration {
+        chrono::Duration::days(self.retention_days.into())
+    }
 }
 
 impl Default for SyncConfig {
@@ -118,6 +268,8 @@ impl Default for SyncConfig {
             device_name: "mycel-device".to_string(),
             blockchain_sync: false,
             near_account: None,
+            policy: SyncPolicy::default(),
+            retention_days: 90,
         }
     }
 }
@@ -141,23 +293,258 @@ impl DeviceKeys {
     pub fn load_or_generate(path: &str) -> Result<Self> {
         let key_path = std::path::Path::new(path).join("device_key");
         if key_path.exists() {
-            let bytes = std::fs::read(&key_path)?;
-            if bytes.len() != 32 {
-                return Err(anyhow!("Invalid key file length"));
-            }
-            let mut key_bytes = [0u8; 32];
-            key_bytes.copy_from_slice(&bytes);
-            let private = StaticSecret::from(key_bytes);
-            let public = PublicKey::from(&private);
-            Ok(Self { private, public })
+            secure_key_permissions(&key_path)?;
+            Self::from_bytes(&std::fs::read(&key_path)?)
         } else {
             info!("Generating new WireGuard device keys...");
-            let mut rng = rand::thread_rng();
-            let private = StaticSecret::random_from_rng(&mut rng);
-            let public = PublicKey::from(&private);
+            let keys = Self::generate();
             let _ = std::fs::create_dir_all(path);
-            std::fs::write(&key_path, private.to_bytes())?;
-            Ok(Self { private, public })
+            write_key_file(&key_path, &keys.private.to_bytes())?;
+            Ok(keys)
+        }
+    }
+
+    fn generate() -> Self {
+        let mut rng = rand::thread_rng();
+        let private = StaticSecret::random_from_rng(&mut rng);
+        let public = PublicKey::from(&private);
+        Self { private, public }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let key_bytes: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| anyhow!("Invalid key file length"))?;
+        let private = StaticSecret::from(key_bytes);
+        let public = PublicKey::from(&private);
+        Ok(Self { private, public })
+    }
+
+    /// The base64 public key, which doubles as the device id on the mesh
+    fn id(&self) -> String {
+        base64::Engine::encode(
+            &base64::engine::general_purpose::STANDARD,
+            self.public.as_bytes(),
+        )
+    }
+}
+
+/// Write a private key file, readable by the owner only
+fn write_key_file(path: &Path, bytes: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.create(true).truncate(true).write(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    // The mode only applies to a new file; tighten an existing one before
+    // the key goes into it
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(bytes)?;
+    Ok(())
+}
+
+/// Make sure an existing key file isn't readable by group or others,
+/// tightening it to 0600 if it is. Refuses the key if that fails.
+fn secure_key_permissions(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            warn!(
+                "Key file {} had permissions {:o}, restricting to 0600",
+                path.display(),
+                mode & 0o777
+            );
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).map_err(|e| {
+                anyhow!(
+                    "Refusing to use {}: it is readable by other users and could not be restricted: {}",
+                    path.display(),
+                    e
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// How many retired keys are kept after rotation
+const KEY_HISTORY_LEN: usize = 3;
+
+/// How long a retired key can still decrypt incoming events
+const KEY_GRACE_HOURS: i64 = 24;
+
+/// A key replaced by rotation, kept so events peers encrypted to it before
+/// they learned the new key can still be read
+#[derive(Clone)]
+struct RetiredKey {
+    keys: DeviceKeys,
+    retired_at: DateTime<Utc>,
+}
+
+/// On-disk form of a retired key (`device_key_history.json`)
+#[derive(Serialize, Deserialize)]
+struct PersistedRetiredKey {
+    key: String,
+    retired_at: DateTime<Utc>,
+}
+
+/// The current device key plus a short history of retired ones
+#[derive(Clone)]
+struct KeyRing {
+    current: DeviceKeys,
+    retired: Vec<RetiredKey>,
+}
+
+impl KeyRing {
+    fn load_or_generate(path: &str) -> Result<Self> {
+        let current = DeviceKeys::load_or_generate(path)?;
+        let history_path = Path::new(path).join("device_key_history.json");
+        if history_path.exists() {
+            secure_key_permissions(&history_path)?;
+        }
+        let retired = match std::fs::read_to_string(&history_path) {
+            Ok(content) => serde_json::from_str::<Vec<PersistedRetiredKey>>(&content)
+                .unwrap_or_else(|e| {
+                    warn!(
+                        "Ignoring unreadable key history {}: {}",
+                        history_path.display(),
+                        e
+                    );
+                    Vec::new()
+                })
+                .into_iter()
+                .filter_map(|entry| {
+                    let bytes = base64::Engine::decode(
+                        &base64::engine::general_purpose::STANDARD,
+                        &entry.key,
+                    )
+                    .ok()?;
+                    Some(RetiredKey {
+                        keys: DeviceKeys::from_bytes(&bytes).ok()?,
+                        retired_at: entry.retired_at,
+                    })
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        let mut ring = Self { current, retired };
+        ring.prune(Utc::now());
+        Ok(ring)
+    }
+
+    /// Make `keys` current, retiring the previous key
+    fn install(&mut self, keys: DeviceKeys, now: DateTime<Utc>) {
+        let old = std::mem::replace(&mut self.current, keys);
+        self.retired.insert(
+            0,
+            RetiredKey {
+                keys: old,
+                retired_at: now,
+            },
+        );
+        self.prune(now);
+    }
+
+    /// Drop retired keys past the grace window or beyond the history length
+    fn prune(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - chrono::Duration::hours(KEY_GRACE_HOURS);
+        self.retired.retain(|r| r.retired_at > cutoff);
+        self.retired.truncate(KEY_HISTORY_LEN);
+    }
+
+    /// Write the current key and the retired history to `path`
+    fn save(&self, path: &str) -> Result<()> {
+        std::fs::create_dir_all(path)?;
+        let dir = Path::new(path);
+        write_key_file(&dir.join("device_key"), &self.current.private.to_bytes())?;
+
+        let history: Vec<PersistedRetiredKey> = self
+            .retired
+            .iter()
+            .map(|r| PersistedRetiredKey {
+                key: base64::Engine::encode(
+                    &base64::engine::general_purpose::STANDARD,
+                    r.keys.private.to_bytes(),
+                ),
+                retired_at: r.retired_at,
+            })
+            .collect();
+        write_key_file(
+            &dir.join("device_key_history.json"),
+            &serde_json::to_vec(&history)?,
+        )
+    }
+
+    /// Decrypt an event from `peer`, trying the current key and then any
+    /// retired key still inside the grace window
+    fn decrypt(
+        &self,
+        peer: &PublicKey,
+        nonce: &[u8; 12],
+        data: &[u8],
+        now: DateTime<Utc>,
+    ) -> Option<Vec<u8>> {
+        let cutoff = now - chrono::Duration::hours(KEY_GRACE_HOURS);
+        std::iter::once(&self.current)
+            .chain(
+                self.retired
+                    .iter()
+                    .filter(|r| r.retired_at > cutoff)
+                    .map(|r| &r.keys),
+            )
+            .find_map(|keys| {
+                cipher_for(&keys.private, peer)
+                    .decrypt(
+                        nonce.into(),
+                        Payload {
+                            msg: data,
+                            aad: &[],
+                        },
+                    )
+                    .ok()
+            })
+    }
+}
+
+/// Cipher for traffic between our key and a peer's public key
+fn cipher_for(private: &StaticSecret, peer: &PublicKey) -> ChaCha20Poly1305 {
+    let shared_secret = private.diffie_hellman(peer);
+    ChaCha20Poly1305::new(shared_secret.as_bytes().into())
+}
+
+/// Parse a peer id (base64 public key)
+fn peer_public_key(peer_id: &str) -> Result<PublicKey> {
+    let bytes = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, peer_id)?;
+    let key_bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| anyhow!("Invalid peer public key"))?;
+    Ok(PublicKey::from(key_bytes))
+}
+
+/// Where publishing to the blockchain got to. Events are published in
+/// timestamp order, with the id breaking ties between equal timestamps.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct BlockchainCursor {
+    timestamp: DateTime<Utc>,
+    id: String,
+}
+
+impl BlockchainCursor {
+    fn at(event: &SyncEvent) -> Self {
+        Self {
+            timestamp: event.timestamp,
+            id: event.id.clone(),
         }
     }
 }
@@ -167,18 +554,280 @@ struct SyncState {
     peers: HashMap<String, PeerInfo>,
     event_log: Vec<SyncEvent>,
     local_clock: VectorClock,
+    /// The last event published to the blockchain
+    blockchain_cursor: Option<BlockchainCursor>,
+    /// Ids of the events compaction dropped, with when, kept for a
+    /// retention window
+    dropped: HashMap<String, DateTime<Utc>>,
+}
+
+impl SyncState {
+    /// Record a sighting of a peer, merging it with any entry for the same key
+    ///
+    /// mDNS, NEAR and handshakes can all report the same device; the freshest
+    /// addresses go first and the oldest drop off past `MAX_PEER_ADDRESSES`.
+    fn upsert_peer(&mut self, sighting: PeerInfo) {
+        let now = Utc::now();
+        let Some(peer) = self.peers.get_mut(&sighting.id) else {
+            let mut peer = sighting;
+            peer.addresses.dedup();
+            peer.last_seen = Some(now);
+            self.peers.insert(peer.id.clone(), peer);
+            return;
+        };
+
+        let mut addresses = sighting.addresses;
+        for addr in peer.addresses.drain(..) {
+            if !addresses.contains(&addr) {
+                addresses.push(addr);
+            }
+        }
+        addresses.truncate(MAX_PEER_ADDRESSES);
+        peer.addresses = addresses;
+
+        // Handshake-only entries carry a placeholder name; take a real one when offered
+        if peer.name.starts_with(PLACEHOLDER_PEER_PREFIX)
+            && !sighting.name.starts_with(PLACEHOLDER_PEER_PREFIX)
+        {
+            peer.name = sighting.name;
+        }
+        if sighting.os.is_some() {
+            peer.os = sighting.os;
+        }
+        if sighting.version.is_some() {
+            peer.version = sighting.version;
+        }
+        peer.status = sighting.status;
+        peer.last_seen = Some(now);
+    }
+
+    /// Move a peer to the id announced in its key rotation
+    fn rekey_peer(&mut self, old_key: &str, new_key: &str) {
+        // A handshake with the new key may already have added a bare entry
+        let fresh = self.peers.remove(new_key);
+        if let Some(mut peer) = self.peers.remove(old_key).or(fresh) {
+            info!("Peer {} rotated its key", peer.name);
+            peer.id = new_key.to_string();
+            self.peers.insert(new_key.to_string(), peer);
+        }
+    }
+
+    /// Log a new event of this device's, returning the peers `policy`
+    /// lets it be sent to
+    fn record(&mut self, event: SyncEvent, policy: &SyncPolicy) -> Vec<PeerInfo> {
+        let peers = if policy.allows(&event.operation, SyncDestination::Mesh) {
+            self.peers.values().cloned().collect()
+        } else {
+            Vec::new()
+        };
+        self.event_log.push(event);
+        peers
+    }
+
+    /// Whether `event` is in the log, or was until compaction dropped it
+    fn has_seen(&self, event: &SyncEvent) -> bool {
+        self.dropped.contains_key(&event.id) || self.event_log.iter().any(|e| e.id == event.id)
+    }
+
+    /// Drop superseded events: all but the latest `UpdatePreference` per
+    /// key, and conversation turns older than `retention`. The local clock
+    /// is left alone, and the dropped events' ids go into `dropped` for
+    /// another `retention` so a peer re-sending one doesn't add it back.
+    /// Returns how many events were dropped.
+    fn compact(&mut self, retention: chrono::Duration, now: DateTime<Utc>) -> usize {
+        let cutoff = now - retention;
+        // Past that, a re-sent event sorts before what superseded it and is
+        // dropped again
+        self.dropped.retain(|_, dropped_at| *dropped_at >= cutoff);
+        // The log is in causal order, so the last update to a key wins
+        let mut keys = HashSet::new();
+        let mut keep: Vec<bool> = self
+            .event_log
+            .iter()
+            .rev()
+            .map(|event| match &event.operation {
+                SyncOperation::UpdatePreference { key, .. } => keys.insert(key.clone()),
+                SyncOperation::AddConversationTurn { .. } => event.timestamp >= cutoff,
+                _ => true,
+            })
+            .collect();
+        keep.reverse();
+
+        let before = self.event_log.len();
+        let mut keep = keep.into_iter();
+        let dropped = &mut self.dropped;
+        self.event_log.retain(|event| {
+            let kept = keep.next().unwrap_or(true);
+            if !kept {
+                dropped.insert(event.id.clone(), now);
+            }
+            kept
+        });
+        before - self.event_log.len()
+    }
+
+    /// This device's capabilities not yet published to the blockchain that
+    /// `policy` allows there, oldest first. The registry only takes
+    /// capabilities, so nothing else is ever published.
+    fn blockchain_outbox(&self, own_ids: &[String], policy: &SyncPolicy) -> Vec<SyncEvent> {
+        let mut outbox: Vec<SyncEvent> = self
+            .event_log
+            .iter()
+            .filter(|event| own_ids.contains(&event.device_id))
+            .filter(|event| matches!(event.operation, SyncOperation::AddCapability { .. }))
+            .filter(|event| {
+                self.blockchain_cursor
+                    .as_ref()
+                    .is_none_or(|cursor| BlockchainCursor::at(event) > *cursor)
+            })
+            .filter(|event| policy.allows(&event.operation, SyncDestination::Blockchain))
+            .cloned()
+            .collect();
+        outbox.sort_by_key(BlockchainCursor::at);
+        outbox
+    }
+
+    /// This device's events (made under any of `own_ids`), newest first
+    fn preview(&self, own_ids: &[String], limit: usize) -> Vec<SyncPreview> {
+        self.event_log
+            .iter()
+            .rev()
+            .filter(|event| own_ids.contains(&event.device_id))
+            .take(limit)
+            .map(|event| SyncPreview::new(&event.operation, Some(event)))
+            .collect()
+    }
+
+    fn status(&self, blockchain_sync: bool, near_account: Option<String>) -> SyncStatus {
+        SyncStatus {
+            events_synced: self.event_log.len(),
+            peer_count: self.peers.len(),
+            connected_peers: self
+                .peers
+                .values()
+                .filter(|p| matches!(p.status, PeerStatus::Connected))
+                .count(),
+            last_event: self.event_log.iter().map(|e| e.timestamp).max(),
+            blockchain_sync,
+            near_account,
+        }
+    }
+}
+
+/// On-disk form of the sync log, written on shutdown and loaded on startup
+#[derive(Default, Serialize, Deserialize)]
+struct PersistedSyncLog {
+    event_log: Vec<SyncEvent>,
+    local_clock: VectorClock,
+    #[serde(default)]
+    blockchain_cursor: Option<BlockchainCursor>,
+    #[serde(default)]
+    dropped: HashMap<String, DateTime<Utc>>,
+}
+
+/// Arguments for the NEAR registry's `near_publish_capability`, for a
+/// capability event
+fn capability_publication(operation: &SyncOperation) -> Option<HashMap<String, serde_json::Value>> {
+    let SyncOperation::AddCapability {
+        name,
+        language,
+        code,
+    } = operation
+    else {
+        return None;
+    };
+    Some(HashMap::from([
+        ("name".to_string(), serde_json::json!(name)),
+        (
+            "description".to_string(),
+            serde_json::json!(format!("Mycel capability '{}'", name)),
+        ),
+        ("sourceCode".to_string(), serde_json::json!(code)),
+        ("language".to_string(), serde_json::json!(language)),
+    ]))
+}
+
+/// mDNS service type Mycel devices announce themselves under
+const MDNS_SERVICE_TYPE: &str = "_mycel._udp.local.";
+
+/// Addresses kept per peer across all discovery channels
+const MAX_PEER_ADDRESSES: usize = 8;
+
+/// How often the sync log is compacted while running
+const COMPACTION_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Name prefix for peers known only from a handshake
+const PLACEHOLDER_PEER_PREFIX: &str = "peer-";
+
+/// Longest TXT property value we advertise; a whole `key=value` entry must
+/// stay under the 255 bytes DNS allows
+const MAX_TXT_VALUE_BYTES: usize = 200;
+
+/// Human-readable OS name, e.g. "Void Linux", falling back to the target OS
+fn os_name() -> String {
+    std::fs::read_to_string("/etc/os-release")
+        .ok()
+        .and_then(|release| {
+            release.lines().find_map(|line| {
+                line.strip_prefix("PRETTY_NAME=")
+                    .map(|v| v.trim_matches('"').to_string())
+            })
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| std::env::consts::OS.to_string())
+}
+
+/// Cut `value` to fit in a TXT property, on a character boundary
+fn txt_value(value: &str) -> String {
+    let mut end = value.len().min(MAX_TXT_VALUE_BYTES);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    value[..end].to_string()
+}
+
+/// The peer a resolved mDNS service describes, read from its TXT properties.
+/// Services without a `pubkey` aren't Mycel devices we can talk to.
+fn peer_from_mdns<'a>(
+    fullname: &str,
+    addresses: Vec<String>,
+    property: impl Fn(&str) -> Option<&'a str>,
+) -> Option<PeerInfo> {
+    let text = |key: &str| property(key).filter(|v| !v.is_empty()).map(str::to_string);
+    Some(PeerInfo {
+        id: text("pubkey")?,
+        name: text("name").unwrap_or_else(|| fullname.to_string()),
+        status: PeerStatus::Connected,
+        addresses,
+        last_seen: None,
+        os: text("os"),
+        version: text("version"),
+    })
 }
 
 #[derive(Clone)]
 pub struct SyncService {
     sync_config: SyncConfig,
     state: Arc<RwLock<SyncState>>,
-    keys: Arc<DeviceKeys>,
+    /// Device keys; replaced by `rotate_keys`
+    keys: Arc<std::sync::RwLock<KeyRing>>,
+    /// Directory the device key files live in
+    key_dir: String,
     mdns: Option<ServiceDaemon>,
+    /// mDNS instance name, kept so a key rotation re-announces the same instance
+    mdns_instance: String,
     mcp_manager: Arc<Option<McpManager>>,
     socket: Arc<UdpSocket>,
     event_bus: broadcast::Sender<SystemEvent>,
     runtime_path: String,
+    /// Where the sync log is persisted
+    log_path: PathBuf,
+    /// Cancelled by `stop` so background loops wind down
+    shutdown: CancellationToken,
+    /// NEAR account polled by blockchain sync (can be linked at runtime)
+    near_account: Arc<RwLock<Option<String>>>,
+    /// Whether the blockchain polling loop has been spawned
+    blockchain_sync_running: Arc<AtomicBool>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -189,22 +838,109 @@ enum MeshPacket {
     Event {
         nonce: [u8; 12],
         encrypted_data: Vec<u8>,
+        /// The plaintext is zlib-compressed JSON rather than plain JSON
+        #[serde(default)]
+        compressed: bool,
     },
 }
 
+/// Bus events waiting for the sync worker
+const OUTBOUND_QUEUE: usize = 32;
+
+/// Queue the bus events that go to the mesh until shutdown or the bus
+/// closes. Only new capabilities are shared; tool calls, restarts and
+/// progress events stay local. Falling behind the bus skips the missed
+/// events rather than ending the loop.
+async fn forward_bus_events(
+    mut receiver: broadcast::Receiver<SystemEvent>,
+    outbound: mpsc::Sender<SyncOperation>,
+    shutdown: CancellationToken,
+) {
+    loop {
+        let event = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            event = receiver.recv() => match event {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("Sync fell behind the event bus; {} events were not shared", missed);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+        if let SystemEvent::CapabilityCreated {
+            name,
+            language,
+            source_code,
+        } = event
+        {
+            info!("Broadcasting new capability to mesh: {}", name);
+            let operation = SyncOperation::AddCapability {
+                name,
+                language,
+                code: source_code,
+            };
+            if outbound.send(operation).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Event JSON at least this large is compressed before encryption
+const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Largest event we'll inflate, so a peer can't send a decompression bomb
+const MAX_EVENT_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Serialize an event for the wire, compressing it when that's worthwhile.
+/// Returns the payload and whether it was compressed.
+fn encode_event(event: &SyncEvent) -> Result<(Vec<u8>, bool)> {
+    use std::io::Write;
+
+    let json = serde_json::to_vec(event)?;
+    if json.len() < COMPRESSION_THRESHOLD {
+        return Ok((json, false));
+    }
+
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&json)?;
+    Ok((encoder.finish()?, true))
+}
+
+/// Inverse of [`encode_event`]
+fn decode_event(payload: &[u8], compressed: bool) -> Result<SyncEvent> {
+    use std::io::Read;
+
+    if !compressed {
+        return Ok(serde_json::from_slice(payload)?);
+    }
+
+    let mut json = Vec::new();
+    flate2::read::ZlibDecoder::new(payload)
+        .take(MAX_EVENT_BYTES + 1)
+        .read_to_end(&mut json)?;
+    if json.len() as u64 > MAX_EVENT_BYTES {
+        return Err(anyhow!("Sync event exceeds {} bytes", MAX_EVENT_BYTES));
+    }
+    Ok(serde_json::from_slice(&json)?)
+}
+
 impl SyncService {
     pub async fn new(
         config: &MycelConfig,
         mcp_manager: Option<McpManager>,
         event_bus: broadcast::Sender<SystemEvent>,
     ) -> Result<Self> {
-        let keys = DeviceKeys::load_or_generate(&config.context_path)?;
+        let keys = KeyRing::load_or_generate(&config.context_path)?;
         let sync_config = SyncConfig {
             mesh_port: 51820,
             discovery_enabled: true,
             device_name: "mycel-device".to_string(),
             blockchain_sync: config.blockchain_sync,
             near_account: config.near_account.clone(),
+            policy: config.sync_policy.clone(),
+            retention_days: config.sync_retention_days,
         };
 
         let runtime_path = std::env::current_dir()?
@@ -222,10 +958,32 @@ impl SyncService {
             }
         };
 
+        let log_path = Path::new(&config.context_path).join("sync_log.json");
+        let persisted = match std::fs::read_to_string(&log_path) {
+            Ok(content) => serde_json::from_str::<PersistedSyncLog>(&content).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable sync log {}: {}", log_path.display(), e);
+                PersistedSyncLog::default()
+            }),
+            Err(_) => PersistedSyncLog::default(),
+        };
+        let mut state = SyncState {
+            event_log: persisted.event_log,
+            local_clock: persisted.local_clock,
+            blockchain_cursor: persisted.blockchain_cursor,
+            dropped: persisted.dropped,
+            ..Default::default()
+        };
+        let dropped = state.compact(sync_config.retention(), Utc::now());
+        if dropped > 0 {
+            info!("Compacted {} superseded events from the sync log", dropped);
+        }
+
         Ok(Self {
             sync_config: sync_config.clone(),
-            state: Arc::new(RwLock::new(SyncState::default())),
-            keys: Arc::new(keys),
+            state: Arc::new(RwLock::new(state)),
+            keys: Arc::new(std::sync::RwLock::new(keys)),
+            key_dir: config.context_path.clone(),
+            mdns_instance: format!("{}.{}", sync_config.device_name, uuid::Uuid::new_v4()),
             mdns: if sync_config.discovery_enabled {
                 Some(ServiceDaemon::new()?)
             } else {
@@ -235,14 +993,99 @@ impl SyncService {
             socket: Arc::new(socket),
             event_bus,
             runtime_path,
+            log_path,
+            shutdown: CancellationToken::new(),
+            near_account: Arc::new(RwLock::new(config.near_account.clone())),
+            blockchain_sync_running: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Link a NEAR account and start blockchain polling without a restart
+    pub async fn link_near_account(&self, account: &str) -> Result<()> {
+        *self.near_account.write().await = Some(account.to_string());
+        self.start_blockchain_sync().await
+    }
+
+    /// Stop background loops and mDNS, then persist the sync log
+    pub async fn stop(&self) -> Result<()> {
+        self.stop_tasks();
+        self.save_log().await
+    }
+
+    /// Stop background loops and mDNS without saving anything
+    pub fn stop_tasks(&self) {
+        self.shutdown.cancel();
+        if let Some(mdns) = &self.mdns {
+            let _ = mdns.shutdown();
+        }
+    }
+
+    /// Write the event log and local clock to disk
+    pub async fn save_log(&self) -> Result<()> {
+        let state = self.state.read().await;
+        let persisted = PersistedSyncLog {
+            event_log: state.event_log.clone(),
+            local_clock: state.local_clock.clone(),
+            blockchain_cursor: state.blockchain_cursor.clone(),
+            dropped: state.dropped.clone(),
+        };
+        drop(state);
+
+        if let Some(parent) = self.log_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&self.log_path, serde_json::to_vec(&persisted)?).await?;
+        Ok(())
+    }
+
+    /// Current device keys
+    fn current_keys(&self) -> DeviceKeys {
+        self.keys
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .current
+            .clone()
+    }
+
+    /// Replace the device key. Peers are told over the old key (so they can
+    /// trust the announcement), mDNS is updated, and the old key keeps
+    /// decrypting incoming events for a grace window. Returns the new id.
+    pub async fn rotate_keys(&self) -> Result<String> {
+        let new_keys = DeviceKeys::generate();
+        let old_id = self.current_keys().id();
+        let new_id = new_keys.id();
+
+        self.create_event(SyncOperation::RotateKey {
+            old_key: old_id,
+            new_key: new_id.clone(),
         })
+        .await?;
+
+        {
+            let mut ring = self.keys.write().unwrap_or_else(|e| e.into_inner());
+            ring.install(new_keys, Utc::now());
+            ring.save(&self.key_dir)?;
+        }
+
+        if let Some(mdns) = &self.mdns {
+            self.announce(mdns)?;
+        }
+
+        // Peers that missed the event still learn the new key this way
+        for peer in self.get_peers().await {
+            for addr_str in &peer.addresses {
+                if let Ok(addr) = addr_str.parse::<SocketAddr>() {
+                    let _ = self.send_handshake(addr).await;
+                }
+            }
+        }
+
+        info!("Device key rotated. New Mycel ID: {}", new_id);
+        Ok(new_id)
     }
 
     pub async fn start(&self) -> Result<()> {
-        let pubkey_b64 = base64::Engine::encode(
-            &base64::engine::general_purpose::STANDARD,
-            self.keys.public.as_bytes(),
-        );
+        let pubkey_b64 = self.current_keys().id();
         let port = self.socket.local_addr()?.port();
         info!(
             "Sync service starting on port {}. Mycel ID: {}",
@@ -264,41 +1107,58 @@ impl SyncService {
             }
         });
 
-        // Start event bus listener
+        // Bus events are queued for a single worker, so they reach the mesh
+        // in order and a slow send doesn't hold up the bus
+        let (outbound, mut queue) = mpsc::channel(OUTBOUND_QUEUE);
+        tokio::spawn(forward_bus_events(
+            self.event_bus.subscribe(),
+            outbound,
+            self.shutdown.clone(),
+        ));
         let service = self.clone();
-        let mut receiver = self.event_bus.subscribe();
         tokio::spawn(async move {
-            while let Ok(event) = receiver.recv().await {
-                match event {
-                    SystemEvent::CapabilityCreated {
-                        name,
-                        language,
-                        source_code,
-                    } => {
-                        info!("Broadcasting new capability to mesh: {}", name);
-                        let _ = service
-                            .create_event(SyncOperation::AddCapability {
-                                name,
-                                language,
-                                code: source_code,
-                            })
-                            .await;
-                    }
-                    // Tool call events are logged but not synced to mesh
-                    SystemEvent::ToolCalled { .. } => {}
-                    // Server restart events are logged but not synced to mesh
-                    SystemEvent::McpServerRestarted { .. } => {}
-                }
+            while let Some(operation) = queue.recv().await {
+                let _ = service.create_event(operation).await;
             }
         });
 
+        let service = self.clone();
+        tokio::spawn(async move { service.compaction_loop().await });
+
         Ok(())
     }
 
+    /// Compact the log every `COMPACTION_INTERVAL` until shutdown, saving
+    /// it when anything was dropped. Startup compaction happens in `new`.
+    async fn compaction_loop(&self) {
+        let start = tokio::time::Instant::now() + COMPACTION_INTERVAL;
+        let mut interval = tokio::time::interval_at(start, COMPACTION_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            let dropped = self
+                .state
+                .write()
+                .await
+                .compact(self.sync_config.retention(), Utc::now());
+            if dropped > 0 {
+                info!("Compacted {} superseded events from the sync log", dropped);
+                if let Err(e) = self.save_log().await {
+                    warn!("Failed to save compacted sync log: {}", e);
+                }
+            }
+        }
+    }
+
     async fn listen_loop(&self) -> Result<()> {
         let mut buf = [0u8; 65535];
         loop {
-            let (len, addr) = self.socket.recv_from(&mut buf).await?;
+            let (len, addr) = tokio::select! {
+                _ = self.shutdown.cancelled() => return Ok(()),
+                received = self.socket.recv_from(&mut buf) => received?,
+            };
             let data = &buf[..len];
 
             match serde_json::from_slice::<MeshPacket>(data) {
@@ -309,12 +1169,14 @@ impl SyncService {
                             &public_key,
                         );
 
-                        let mut state = self.state.write().await;
-                        state.peers.entry(peer_id.clone()).or_insert_with(|| PeerInfo {
+                        self.state.write().await.upsert_peer(PeerInfo {
                             id: peer_id,
-                            name: format!("peer-{}", addr),
+                            name: format!("{}{}", PLACEHOLDER_PEER_PREFIX, addr),
                             status: PeerStatus::Connected,
                             addresses: vec![addr.to_string()],
+                            last_seen: None,
+                            os: None,
+                            version: None,
                         });
                         debug!("Received handshake from {}", addr);
                     }
@@ -322,34 +1184,33 @@ impl SyncService {
                 Ok(MeshPacket::Event {
                     nonce,
                     encrypted_data,
+                    compressed,
                 }) => {
                     let peers = self.state.read().await.peers.clone();
-                    for (peer_id, _info) in peers {
-                        if let Ok(peer_pk_bytes) = base64::Engine::decode(
-                            &base64::engine::general_purpose::STANDARD,
-                            &peer_id,
-                        ) {
-                            if peer_pk_bytes.len() == 32 {
-                                let mut pk_bytes = [0u8; 32];
-                                pk_bytes.copy_from_slice(&peer_pk_bytes);
-                                let peer_pk = PublicKey::from(pk_bytes);
-
-                                let shared_secret = self.keys.private.diffie_hellman(&peer_pk);
-                                let cipher = ChaCha20Poly1305::new(shared_secret.as_bytes().into());
-
-                                if let Ok(decrypted) = cipher.decrypt(
-                                    &nonce.into(),
-                                    Payload {
-                                        msg: &encrypted_data,
-                                        aad: &[],
-                                    },
-                                ) {
-                                    if let Ok(event) = serde_json::from_slice::<SyncEvent>(&decrypted)
-                                    {
-                                        let _ = self.apply_event(event).await;
-                                        break;
+                    let keys = self.keys.read().unwrap_or_else(|e| e.into_inner()).clone();
+                    for peer_id in peers.keys() {
+                        let Ok(peer_pk) = peer_public_key(peer_id) else {
+                            continue;
+                        };
+                        if let Some(decrypted) =
+                            keys.decrypt(&peer_pk, &nonce, &encrypted_data, Utc::now())
+                        {
+                            if let Ok(event) = decode_event(&decrypted, compressed) {
+                                if let SyncOperation::RotateKey { old_key, new_key } =
+                                    &event.operation
+                                {
+                                    // Only the holder of the old key may move it
+                                    if old_key == peer_id {
+                                        self.state.write().await.rekey_peer(old_key, new_key);
+                                    } else {
+                                        warn!(
+                                            "Ignoring key rotation for {} sent by {}",
+                                            old_key, peer_id
+                                        );
                                     }
                                 }
+                                let _ = self.apply_event(event).await;
+                                break;
                             }
                         }
                     }
@@ -361,22 +1222,21 @@ impl SyncService {
         }
     }
 
-    async fn start_discovery(&self, mdns: &ServiceDaemon) -> Result<()> {
-        let service_type = "_mycel._udp.local.";
-        let instance_name = format!("{}.{}", self.sync_config.device_name, uuid::Uuid::new_v4());
+    /// Register (or re-register after a key rotation) our mDNS service
+    fn announce(&self, mdns: &ServiceDaemon) -> Result<()> {
         let host_name = format!("{}.local.", self.sync_config.device_name);
         let port = self.socket.local_addr()?.port();
 
-        let pub_key_base64 = base64::Engine::encode(
-            &base64::engine::general_purpose::STANDARD,
-            self.keys.public.as_bytes(),
-        );
-
-        let properties = [("pubkey", pub_key_base64)];
+        let properties = [
+            ("pubkey", self.current_keys().id()),
+            ("name", txt_value(&self.sync_config.device_name)),
+            ("os", txt_value(&os_name())),
+            ("version", env!("CARGO_PKG_VERSION").to_string()),
+        ];
 
         let my_service = ServiceInfo::new(
-            service_type,
-            &instance_name,
+            MDNS_SERVICE_TYPE,
+            &self.mdns_instance,
             &host_name,
             "",
             port,
@@ -384,39 +1244,38 @@ impl SyncService {
         )?;
 
         mdns.register(my_service)?;
-        info!("mDNS discovery active: {}", instance_name);
+        Ok(())
+    }
+
+    async fn start_discovery(&self, mdns: &ServiceDaemon) -> Result<()> {
+        self.announce(mdns)?;
+        info!("mDNS discovery active: {}", self.mdns_instance);
 
-        let receiver = mdns.browse(service_type)?;
+        let receiver = mdns.browse(MDNS_SERVICE_TYPE)?;
         let service = self.clone();
 
         tokio::spawn(async move {
             while let Ok(event) = receiver.recv_async().await {
-                match event {
-                    mdns_sd::ServiceEvent::ServiceResolved(info) => {
-                        debug!("Found Mycel device via mDNS: {:?}", info.get_fullname());
-                        if let Some(pubkey) = info.get_property_val_str("pubkey") {
-                            let mut state = service.state.write().await;
-                            let addresses: Vec<String> = info
-                                .get_addresses()
-                                .iter()
-                                .map(|a| format!("{}:{}", a, info.get_port()))
-                                .collect();
-
-                            state.peers.entry(pubkey.to_string()).or_insert_with(|| PeerInfo {
-                                id: pubkey.to_string(),
-                                name: info.get_fullname().to_string(),
-                                status: PeerStatus::Connected,
-                                addresses: addresses.clone(),
-                            });
-
-                            for addr_str in addresses {
-                                if let Ok(addr) = addr_str.parse::<SocketAddr>() {
-                                    let _ = service.send_handshake(addr).await;
-                                }
+                if let mdns_sd::ServiceEvent::ServiceResolved(info) = event {
+                    debug!("Found Mycel device via mDNS: {:?}", info.get_fullname());
+                    let addresses: Vec<String> = info
+                        .get_addresses()
+                        .iter()
+                        .map(|a| format!("{}:{}", a, info.get_port()))
+                        .collect();
+                    let peer = peer_from_mdns(info.get_fullname(), addresses, |key| {
+                        info.get_property_val_str(key)
+                    });
+                    if let Some(peer) = peer {
+                        let addresses = peer.addresses.clone();
+                        service.state.write().await.upsert_peer(peer);
+
+                        for addr_str in addresses {
+                            if let Ok(addr) = addr_str.parse::<SocketAddr>() {
+                                let _ = service.send_handshake(addr).await;
                             }
                         }
                     }
-                    _ => {}
                 }
             }
         });
@@ -426,7 +1285,7 @@ impl SyncService {
 
     async fn send_handshake(&self, addr: SocketAddr) -> Result<()> {
         let packet = MeshPacket::Handshake {
-            public_key: self.keys.public.as_bytes().to_vec(),
+            public_key: self.current_keys().public.as_bytes().to_vec(),
         };
         let data = serde_json::to_vec(&packet)?;
         self.socket.send_to(&data, addr).await?;
@@ -434,14 +1293,22 @@ impl SyncService {
     }
 
     async fn start_blockchain_sync(&self) -> Result<()> {
+        if self.blockchain_sync_running.swap(true, Ordering::SeqCst) {
+            // Already polling; the loop picks up account changes on its next tick
+            return Ok(());
+        }
+
         let mcp = self.mcp_manager.clone();
-        let account = self.sync_config.near_account.clone();
         let service = self.clone();
 
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
             loop {
-                interval.tick().await;
+                tokio::select! {
+                    _ = service.shutdown.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                let account = service.near_account.read().await.clone();
                 if let (Some(acc), Some(mcp)) = (&account, &*mcp) {
                     debug!("Polling NEAR for global updates for {}", acc);
 
@@ -451,10 +1318,10 @@ impl SyncService {
                         for content in result.content {
                             if let crate::mcp::protocol::ToolContent::Text { text } = content {
                                 if let Ok(peers) = serde_json::from_str::<Vec<PeerInfo>>(&text) {
-                                    let mut state = service.state.write().await;
                                     for peer in peers {
-                                        state.peers.entry(peer.id.clone()).or_insert(peer.clone());
-                                        for addr_str in &peer.addresses {
+                                        let addresses = peer.addresses.clone();
+                                        service.state.write().await.upsert_peer(peer);
+                                        for addr_str in &addresses {
                                             if let Ok(addr) = addr_str.parse::<SocketAddr>() {
                                                 let _ = service.send_handshake(addr).await;
                                             }
@@ -481,6 +1348,9 @@ impl SyncService {
                             }
                         }
                     }
+
+                    // 3. Publish this device's new events
+                    service.publish_to_blockchain(mcp).await;
                 }
             }
         });
@@ -488,13 +1358,36 @@ impl SyncService {
         Ok(())
     }
 
+    /// Publish this device's capabilities that the sync policy allows on
+    /// the blockchain to the NEAR registry, stopping at the first failure
+    /// so it's retried next poll
+    async fn publish_to_blockchain(&self, mcp: &McpManager) {
+        let outbox = self
+            .state
+            .read()
+            .await
+            .blockchain_outbox(&self.own_ids(), &self.sync_config.policy);
+
+        for event in outbox {
+            let Some(args) = capability_publication(&event.operation) else {
+                continue;
+            };
+            match mcp.call_tool("near_publish_capability", args).await {
+                Ok(result) if !result.is_error => {
+                    self.state.write().await.blockchain_cursor = Some(BlockchainCursor::at(&event));
+                }
+                _ => {
+                    debug!("Could not publish sync event {} to NEAR", event.id);
+                    break;
+                }
+            }
+        }
+    }
+
     pub async fn create_event(&self, operation: SyncOperation) -> Result<SyncEvent> {
         let mut state = self.state.write().await;
 
-        let device_id = base64::Engine::encode(
-            &base64::engine::general_purpose::STANDARD,
-            self.keys.public.as_bytes(),
-        );
+        let device_id = self.current_keys().id();
 
         state.local_clock.increment(&device_id);
 
@@ -507,12 +1400,10 @@ impl SyncService {
             signature: Vec::new(),
         };
 
-        state.event_log.push(event.clone());
-
-        let peers = state.peers.clone();
+        let peers = state.record(event.clone(), &self.sync_config.policy);
         drop(state);
 
-        for peer in peers.values() {
+        for peer in &peers {
             let _ = self.send_event(peer, &event).await;
         }
 
@@ -520,31 +1411,21 @@ impl SyncService {
     }
 
     async fn send_event(&self, peer: &PeerInfo, event: &SyncEvent) -> Result<()> {
-        let peer_pk_bytes =
-            base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &peer.id)?;
-        if peer_pk_bytes.len() != 32 {
-            return Err(anyhow!("Invalid peer public key"));
-        }
-
-        let mut pk_bytes = [0u8; 32];
-        pk_bytes.copy_from_slice(&peer_pk_bytes);
-        let peer_pk = PublicKey::from(pk_bytes);
-
-        let shared_secret = self.keys.private.diffie_hellman(&peer_pk);
-        let cipher = ChaCha20Poly1305::new(shared_secret.as_bytes().into());
+        let peer_pk = peer_public_key(&peer.id)?;
+        let cipher = cipher_for(&self.current_keys().private, &peer_pk);
 
+        let (payload, compressed) = encode_event(event)?;
         let (nonce_bytes, encrypted) = {
             let mut nonce_bytes = [0u8; 12];
             let mut rng = rand::thread_rng();
             use rand::RngCore;
             rng.fill_bytes(&mut nonce_bytes);
 
-            let event_json = serde_json::to_vec(event)?;
             let encrypted = cipher
                 .encrypt(
                     &nonce_bytes.into(),
                     Payload {
-                        msg: &event_json,
+                        msg: &payload,
                         aad: &[],
                     },
                 )
@@ -555,6 +1436,7 @@ impl SyncService {
         let packet = MeshPacket::Event {
             nonce: nonce_bytes,
             encrypted_data: encrypted,
+            compressed,
         };
 
         let packet_data = serde_json::to_vec(&packet)?;
@@ -568,16 +1450,53 @@ impl SyncService {
         Ok(())
     }
 
+    /// Up to `limit` of this device's events, newest first: what it has
+    /// shared with mesh peers, or will share with those it meets
+    pub async fn preview_pending(&self, limit: usize) -> Vec<SyncPreview> {
+        self.state.read().await.preview(&self.own_ids(), limit)
+    }
+
+    /// Ids this device has made events under: the current key and the
+    /// retired ones
+    fn own_ids(&self) -> Vec<String> {
+        let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+        std::iter::once(&keys.current)
+            .chain(keys.retired.iter().map(|r| &r.keys))
+            .map(DeviceKeys::id)
+            .collect()
+    }
+
+    /// What recording a conversation turn would share, without creating or
+    /// sending the event
+    pub fn preview_turn(&self, session_id: &str, user: &str, assistant: &str) -> SyncPreview {
+        let operation = SyncOperation::AddConversationTurn {
+            session_id: session_id.to_string(),
+            user: user.to_string(),
+            assistant: assistant.to_string(),
+        };
+        SyncPreview::new(&operation, None)
+    }
+
     pub async fn get_peers(&self) -> Vec<PeerInfo> {
         self.state.read().await.peers.values().cloned().collect()
     }
 
+    /// Summary of the mesh and sync log for status displays
+    pub async fn status(&self) -> SyncStatus {
+        let near_account = self.near_account.read().await.clone();
+        let blockchain_sync = self.blockchain_sync_running.load(Ordering::SeqCst);
+        self.state
+            .read()
+            .await
+            .status(blockchain_sync, near_account)
+    }
+
     pub async fn apply_event(&self, event: SyncEvent) -> Result<()> {
         debug!(event_id = %event.id, device = %event.device_id, "Applying sync event");
 
         let mut state = self.state.write().await;
 
-        if state.event_log.iter().any(|e| e.id == event.id) {
+        if state.has_seen(&event) {
             return Ok(());
         }
 
@@ -597,19 +1516,17 @@ impl SyncService {
         info!(event_id = %event.id, "Event integrated into local mesh log");
 
         // 5. React to the event
-        match event.operation {
-            SyncOperation::AddCapability {
-                name,
-                language,
-                code,
-            } => {
-                if let Some(mcp) = &*self.mcp_manager {
-                    info!("Installing shared capability from mesh: {}", name);
-                    let evolver = McpEvolver::new(mcp.clone(), &self.runtime_path);
-                    let _ = evolver.create_server(&name, &language, &code, false).await;
-                }
+        if let SyncOperation::AddCapability {
+            name,
+            language,
+            code,
+        } = event.operation
+        {
+            if let Some(mcp) = &*self.mcp_manager {
+                info!("Installing shared capability from mesh: {}", name);
+                let evolver = McpEvolver::new(mcp.clone(), &self.runtime_path);
+                let _ = evolver.create_server(&name, &language, &code, false).await;
             }
-            _ => {}
         }
 
         Ok(())
@@ -622,6 +1539,15 @@ pub struct PeerInfo {
     pub name: String,
     pub status: PeerStatus,
     pub addresses: Vec<String>,
+    /// When any discovery channel last reported this peer
+    #[serde(default)]
+    pub last_seen: Option<DateTime<Utc>>,
+    /// Operating system the peer advertises, e.g. "Void Linux"
+    #[serde(default)]
+    pub os: Option<String>,
+    /// Mycel version the peer runs
+    #[serde(default)]
+    pub version: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -632,11 +1558,16 @@ pub enum PeerStatus {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[allow(dead_code)]
 pub struct SyncStatus {
-    pub files_synced: usize,
-    pub conflicts: usize,
-    pub last_sync: chrono::DateTime<chrono::Utc>,
+    /// Events in the local log, ours and peers'
+    pub events_synced: usize,
+    pub peer_count: usize,
+    pub connected_peers: usize,
+    /// Timestamp of the newest event in the log
+    pub last_event: Option<DateTime<Utc>>,
+    /// Whether the blockchain polling loop is running
+    pub blockchain_sync: bool,
+    pub near_account: Option<String>,
 }
 
 #[cfg(test)]
@@ -675,4 +1606,500 @@ mod tests {
         assert!(!v1.is_ahead_of(&v2));
         assert!(!v2.is_ahead_of(&v1));
     }
+
+    fn test_event() -> SyncEvent {
+        SyncEvent {
+            id: uuid::Uuid::new_v4().to_string(),
+            device_id: "a".to_string(),
+            timestamp: Utc::now(),
+            clock: VectorClock::default(),
+            operation: SyncOperation::UpdatePreference {
+                key: "theme".to_string(),
+                value: "dark".to_string(),
+            },
+            signature: vec![],
+        }
+    }
+
+    #[test]
+    fn test_event_to_rotated_key_still_decrypts() {
+        let mut ring = KeyRing {
+            current: DeviceKeys::generate(),
+            retired: Vec::new(),
+        };
+        let peer = DeviceKeys::generate();
+
+        // The peer encrypts to our key as it knew it before rotation
+        let nonce = [7u8; 12];
+        let event = test_event();
+        let plaintext = serde_json::to_vec(&event).unwrap();
+        let encrypted = cipher_for(&peer.private, &ring.current.public)
+            .encrypt(
+                &nonce.into(),
+                Payload {
+                    msg: &plaintext,
+                    aad: &[],
+                },
+            )
+            .unwrap();
+
+        let now = Utc::now();
+        ring.install(DeviceKeys::generate(), now);
+        let decrypted = ring.decrypt(&peer.public, &nonce, &encrypted, now).unwrap();
+        let decoded: SyncEvent = serde_json::from_slice(&decrypted).unwrap();
+        assert_eq!(decoded.id, event.id);
+
+        // Past the grace window the old key no longer applies
+        let later = now + chrono::Duration::hours(KEY_GRACE_HOURS + 1);
+        assert!(ring
+            .decrypt(&peer.public, &nonce, &encrypted, later)
+            .is_none());
+    }
+
+    #[test]
+    fn test_key_history_is_bounded_and_persisted() {
+        let dir = std::env::temp_dir().join(format!("mycel-keys-{}", uuid::Uuid::new_v4()));
+        let dir = dir.to_string_lossy().to_string();
+
+        let mut ring = KeyRing::load_or_generate(&dir).unwrap();
+        for _ in 0..KEY_HISTORY_LEN + 2 {
+            ring.install(DeviceKeys::generate(), Utc::now());
+        }
+        assert_eq!(ring.retired.len(), KEY_HISTORY_LEN);
+        ring.save(&dir).unwrap();
+
+        let reloaded = KeyRing::load_or_generate(&dir).unwrap();
+        assert_eq!(reloaded.current.id(), ring.current.id());
+        assert_eq!(reloaded.retired.len(), KEY_HISTORY_LEN);
+        assert_eq!(reloaded.retired[0].keys.id(), ring.retired[0].keys.id());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_device_key_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("mycel-keys-{}", uuid::Uuid::new_v4()));
+        let key_path = dir.join("device_key");
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        DeviceKeys::load_or_generate(&dir.to_string_lossy()).unwrap();
+        assert_eq!(mode(&key_path), 0o600);
+
+        // A key left world-readable is tightened on load
+        std::fs::set_permissions(&key_path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        DeviceKeys::load_or_generate(&dir.to_string_lossy()).unwrap();
+        assert_eq!(mode(&key_path), 0o600);
+
+        // Rewriting a world-readable file tightens it too
+        std::fs::set_permissions(&key_path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        write_key_file(&key_path, b"key").unwrap();
+        assert_eq!(mode(&key_path), 0o600);
+        assert_eq!(std::fs::read(&key_path).unwrap(), b"key");
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_rekey_peer() {
+        let mut state = SyncState::default();
+        state.peers.insert(
+            "old".to_string(),
+            PeerInfo {
+                id: "old".to_string(),
+                name: "laptop".to_string(),
+                status: PeerStatus::Connected,
+                addresses: vec!["10.0.0.2:51820".to_string()],
+                last_seen: None,
+                os: None,
+                version: None,
+            },
+        );
+
+        state.rekey_peer("old", "new");
+        assert!(!state.peers.contains_key("old"));
+        let peer = &state.peers["new"];
+        assert_eq!(peer.id, "new");
+        assert_eq!(peer.name, "laptop");
+    }
+
+    #[test]
+    fn test_upsert_merges_peer_sightings() {
+        let mut state = SyncState::default();
+        let sighting = |name: &str, addr: &str| PeerInfo {
+            id: "pubkey".to_string(),
+            name: name.to_string(),
+            status: PeerStatus::Connected,
+            addresses: vec![addr.to_string()],
+            last_seen: None,
+            os: None,
+            version: None,
+        };
+
+        state.upsert_peer(sighting("laptop._mycel._udp.local.", "192.168.1.5:51820"));
+        let first_seen = state.peers["pubkey"].last_seen.unwrap();
+        state.upsert_peer(sighting("peer-203.0.113.7:51820", "203.0.113.7:51820"));
+        state.upsert_peer(sighting("peer-203.0.113.7:51820", "203.0.113.7:51820"));
+
+        assert_eq!(state.peers.len(), 1);
+        let peer = &state.peers["pubkey"];
+        assert_eq!(peer.name, "laptop._mycel._udp.local.");
+        assert_eq!(
+            peer.addresses,
+            vec![
+                "203.0.113.7:51820".to_string(),
+                "192.168.1.5:51820".to_string()
+            ]
+        );
+        assert!(peer.last_seen.unwrap() >= first_seen);
+    }
+
+    #[test]
+    fn test_peer_from_mdns_properties() {
+        let properties = HashMap::from([
+            ("pubkey", "cGVlcg=="),
+            ("name", "Alice's laptop"),
+            ("os", "Void Linux"),
+            ("version", "0.3.0"),
+        ]);
+        let peer = peer_from_mdns(
+            "mycel-device.1234._mycel._udp.local.",
+            vec!["192.168.1.5:51820".to_string()],
+            |key| properties.get(key).copied(),
+        )
+        .unwrap();
+
+        assert_eq!(peer.id, "cGVlcg==");
+        assert_eq!(peer.name, "Alice's laptop");
+        assert_eq!(peer.os.as_deref(), Some("Void Linux"));
+        assert_eq!(peer.version.as_deref(), Some("0.3.0"));
+        assert_eq!(peer.addresses, vec!["192.168.1.5:51820".to_string()]);
+
+        // Older devices only advertise their key
+        let peer = peer_from_mdns("old._mycel._udp.local.", vec![], |key| {
+            (key == "pubkey").then_some("b2xk")
+        })
+        .unwrap();
+        assert_eq!(peer.name, "old._mycel._udp.local.");
+        assert!(peer.os.is_none());
+        assert!(peer_from_mdns("x", vec![], |_| None).is_none());
+
+        let long = "é".repeat(200);
+        assert!(txt_value(&long).len() <= MAX_TXT_VALUE_BYTES);
+    }
+
+    #[test]
+    fn test_large_event_compression_round_trip() {
+        let mut event = test_event();
+        event.operation = SyncOperation::AddCapability {
+            name: "csv_report".to_string(),
+            language: "python".to_string(),
+            code: "import csv\nprint('row')\n".repeat(500),
+        };
+        let json_len = serde_json::to_vec(&event).unwrap().len();
+
+        let (payload, compressed) = encode_event(&event).unwrap();
+        assert!(compressed);
+        assert!(payload.len() < json_len);
+        let decoded = decode_event(&payload, compressed).unwrap();
+        assert_eq!(decoded.id, event.id);
+        assert_eq!(
+            serde_json::to_value(&decoded.operation).unwrap(),
+            serde_json::to_value(&event.operation).unwrap()
+        );
+
+        // Small events go out as plain JSON
+        let (payload, compressed) = encode_event(&test_event()).unwrap();
+        assert!(!compressed);
+        assert!(decode_event(&payload, compressed).is_ok());
+    }
+
+    #[test]
+    fn test_sync_status() {
+        let mut state = SyncState::default();
+        let status = state.status(false, None);
+        assert_eq!(status.events_synced, 0);
+        assert!(status.last_event.is_none());
+
+        for (id, status) in [
+            ("a", PeerStatus::Connected),
+            ("b", PeerStatus::Disconnected),
+        ] {
+            state.peers.insert(
+                id.to_string(),
+                PeerInfo {
+                    id: id.to_string(),
+                    name: id.to_string(),
+                    status,
+                    addresses: vec![],
+                    last_seen: None,
+                    os: None,
+                    version: None,
+                },
+            );
+        }
+        let newest = Utc::now();
+        for timestamp in [newest - chrono::Duration::minutes(5), newest] {
+            state.event_log.push(SyncEvent {
+                id: uuid::Uuid::new_v4().to_string(),
+                device_id: "a".to_string(),
+                timestamp,
+                clock: VectorClock::default(),
+                operation: SyncOperation::UpdatePreference {
+                    key: "theme".to_string(),
+                    value: "dark".to_string(),
+                },
+                signature: vec![],
+            });
+        }
+
+        let status = state.status(true, Some("alice.near".to_string()));
+        assert_eq!(status.events_synced, 2);
+        assert_eq!(status.peer_count, 2);
+        assert_eq!(status.connected_peers, 1);
+        assert_eq!(status.last_event, Some(newest));
+        assert!(status.blockchain_sync);
+    }
+
+    #[tokio::test]
+    async fn test_lagged_bus_keeps_forwarding() {
+        let capability = |name: &str| SystemEvent::CapabilityCreated {
+            name: name.to_string(),
+            language: "python".to_string(),
+            source_code: "print('hi')".to_string(),
+        };
+        let (bus, receiver) = broadcast::channel(2);
+        // Overflow the receiver before it reads anything
+        for name in ["a", "b", "c", "d"] {
+            bus.send(capability(name)).unwrap();
+        }
+
+        let (outbound, mut queue) = mpsc::channel(8);
+        let shutdown = CancellationToken::new();
+        let forwarder = tokio::spawn(forward_bus_events(receiver, outbound, shutdown.clone()));
+        async fn next(queue: &mut mpsc::Receiver<SyncOperation>) -> String {
+            match queue.recv().await.unwrap() {
+                SyncOperation::AddCapability { name, .. } => name,
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+
+        // The oldest two were missed; the rest, and later events, still arrive
+        assert_eq!(next(&mut queue).await, "c");
+        assert_eq!(next(&mut queue).await, "d");
+        bus.send(capability("e")).unwrap();
+        assert_eq!(next(&mut queue).await, "e");
+
+        shutdown.cancel();
+        forwarder.await.unwrap();
+    }
+
+    #[test]
+    fn test_preview_flags_conversation_without_content() {
+        let mut state = SyncState::default();
+        for (device_id, operation) in [
+            (
+                "me",
+                SyncOperation::AddConversationTurn {
+                    session_id: "session-1234567890".to_string(),
+                    user: "my bank pin is 4321".to_string(),
+                    assistant: "Please don't share that.".to_string(),
+                },
+            ),
+            (
+                "peer",
+                SyncOperation::UpdatePreference {
+                    key: "theme".to_string(),
+                    value: "dark".to_string(),
+                },
+            ),
+        ] {
+            let mut event = test_event();
+            event.device_id = device_id.to_string();
+            event.operation = operation;
+            state.event_log.push(event);
+        }
+
+        // Only our own events are listed; peers' were shared with us
+        let preview = state.preview(&["me".to_string()], 10);
+        assert_eq!(preview.len(), 1);
+        let turn = &preview[0];
+        assert_eq!(turn.operation, "AddConversationTurn");
+        assert!(turn.contains_conversation);
+        assert_eq!(turn.event_id.as_ref(), Some(&state.event_log[0].id));
+        assert!(turn.summary.contains("19 chars"), "{}", turn.summary);
+        assert!(!turn.summary.contains("4321"));
+
+        assert!(state.preview(&["me".to_string()], 0).is_empty());
+    }
+
+    #[test]
+    fn test_policy_keeps_operation_off_blockchain_but_on_mesh() {
+        let config: MycelConfig = toml::from_str(
+            r#"
+            [sync_policy]
+            preferences = ["mesh"]
+            "#,
+        )
+        .unwrap();
+        let policy = config.sync_policy;
+
+        let mut state = SyncState::default();
+        state.peers.insert(
+            "laptop-key".to_string(),
+            PeerInfo {
+                id: "laptop-key".to_string(),
+                name: "laptop".to_string(),
+                status: PeerStatus::Connected,
+                addresses: vec!["10.0.0.2:51820".to_string()],
+                last_seen: None,
+                os: None,
+                version: None,
+            },
+        );
+
+        let mut preference = test_event();
+        preference.device_id = "me".to_string();
+        let mut turn = preference.clone();
+        turn.id = uuid::Uuid::new_v4().to_string();
+        turn.operation = SyncOperation::AddConversationTurn {
+            session_id: "s".to_string(),
+            user: "hi".to_string(),
+            assistant: "hello".to_string(),
+        };
+        let mut capability = preference.clone();
+        capability.id = uuid::Uuid::new_v4().to_string();
+        capability.operation = SyncOperation::AddCapability {
+            name: "weather".to_string(),
+            language: "python".to_string(),
+            code: "print('sunny')".to_string(),
+        };
+
+        // All three still reach LAN peers
+        for event in [preference, turn, capability.clone()] {
+            let peers = state.record(event, &policy);
+            assert_eq!(peers.len(), 1);
+            assert_eq!(peers[0].name, "laptop");
+        }
+
+        // Only the capability is published; conversation turns stay off the
+        // blockchain by default and preferences were turned off for it
+        let me = ["me".to_string()];
+        let outbox = state.blockchain_outbox(&me, &policy);
+        assert_eq!(outbox.len(), 1);
+        assert_eq!(outbox[0].id, capability.id);
+
+        state.blockchain_cursor = Some(BlockchainCursor::at(&capability));
+        assert!(state.blockchain_outbox(&me, &policy).is_empty());
+
+        // One made in the same instant is still published after it
+        let mut second = capability.clone();
+        second.id = format!("{}~", capability.id);
+        state.event_log.push(second.clone());
+        let outbox = state.blockchain_outbox(&me, &policy);
+        assert_eq!(outbox.len(), 1);
+        assert_eq!(outbox[0].id, second.id);
+
+        // As a registry entry
+        let args = capability_publication(&second.operation).unwrap();
+        assert_eq!(args["name"], "weather");
+        assert_eq!(args["sourceCode"], "print('sunny')");
+        assert_eq!(args["language"], "python");
+        let pattern = SyncOperation::AddLearnedPattern {
+            trigger: "hi".to_string(),
+            action: "wave".to_string(),
+        };
+        assert!(capability_publication(&pattern).is_none());
+
+        // Nothing at all leaves the device for an empty list
+        let local_only = SyncPolicy {
+            capabilities: Vec::new(),
+            ..SyncPolicy::default()
+        };
+        assert!(state.record(capability, &local_only).is_empty());
+    }
+
+    #[test]
+    fn test_compaction_keeps_latest_preference() {
+        let mut state = SyncState::default();
+        let now = Utc::now();
+        let mut event = |operation, minutes_ago| {
+            state.local_clock.increment("me");
+            state.event_log.push(SyncEvent {
+                id: uuid::Uuid::new_v4().to_string(),
+                device_id: "me".to_string(),
+                timestamp: now - chrono::Duration::minutes(minutes_ago),
+                clock: state.local_clock.clone(),
+                operation,
+                signature: vec![],
+            });
+        };
+        let theme = |value: &str| SyncOperation::UpdatePreference {
+            key: "theme".to_string(),
+            value: value.to_string(),
+        };
+        event(theme("dark"), 30);
+        event(theme("light"), 20);
+        event(
+            SyncOperation::UpdatePreference {
+                key: "editor".to_string(),
+                value: "helix".to_string(),
+            },
+            15,
+        );
+        event(theme("solarized"), 10);
+        event(
+            SyncOperation::AddConversationTurn {
+                session_id: "s".to_string(),
+                user: "hi".to_string(),
+                assistant: "hello".to_string(),
+            },
+            5,
+        );
+        let superseded = state.event_log[0].clone();
+        let clock = state.local_clock.clone();
+
+        assert_eq!(state.compact(chrono::Duration::days(1), now), 2);
+        let themes: Vec<&str> = state
+            .event_log
+            .iter()
+            .filter_map(|e| match &e.operation {
+                SyncOperation::UpdatePreference { key, value } if key == "theme" => {
+                    Some(value.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(themes, ["solarized"]);
+        assert_eq!(state.event_log.len(), 3);
+
+        // The clock is untouched and a re-sent dropped event counts as seen
+        assert_eq!(state.local_clock, clock);
+        assert!(state.has_seen(&superseded));
+        assert!(!state.has_seen(&test_event()));
+
+        // An event lost on the way still gets in, though its counter is
+        // below those of events compaction dropped
+        let late = SyncEvent {
+            id: uuid::Uuid::new_v4().to_string(),
+            device_id: "me".to_string(),
+            timestamp: now - chrono::Duration::minutes(40),
+            clock: VectorClock {
+                map: HashMap::from([("me".to_string(), 1)]),
+            },
+            operation: SyncOperation::AddCapability {
+                name: "weather".to_string(),
+                language: "python".to_string(),
+                code: "print('sunny')".to_string(),
+            },
+            signature: vec![],
+        };
+        assert!(!state.has_seen(&late));
+
+        // Turns go once they're past the retention window
+        assert_eq!(state.compact(chrono::Duration::minutes(1), now), 1);
+        assert_eq!(state.event_log.len(), 2);
+    }
 }
